@@ -1,7 +1,10 @@
-use clap::{Parser, Subcommand};
+use clap::{Args, Parser, Subcommand, ValueEnum};
 use std::path::PathBuf;
 
-use crate::trellis::constants::containers;
+use crate::{
+    config::Layering,
+    trellis::{constants::containers, run_lock::LockMode},
+};
 
 #[derive(Parser)]
 #[command(name = "trellis")]
@@ -23,6 +26,18 @@ pub struct Cli {
     #[arg(long)]
     pub auto_clean: bool,
 
+    /// Enable/Disable reuse of unchanged stage images between builds
+    #[arg(long)]
+    pub incremental: Option<bool>,
+
+    /// How rootfs stage images are layered
+    #[arg(long, value_enum)]
+    pub layering: Option<Layering>,
+
+    /// Enable/Disable splitting the final rootfs image into content-based layers
+    #[arg(long)]
+    pub rechunk: Option<bool>,
+
     /// Path to a persistent pacman package cache
     #[arg(long)]
     pub pacman_cache: Option<PathBuf>,
@@ -43,6 +58,10 @@ pub struct Cli {
     #[arg(long, value_delimiter = ',')]
     pub extra_mounts: Vec<PathBuf>,
 
+    /// A build secret as `id=ID,src=PATH`, passed to `podman build --secret` (repeatable)
+    #[arg(long = "secret", value_name = "id=ID,src=PATH")]
+    pub secrets: Vec<String>,
+
     /// A comma delimited list of the image stages to build
     #[arg(long, value_delimiter = ',')]
     pub rootfs_stages: Vec<String>,
@@ -63,10 +82,30 @@ pub struct Cli {
     #[arg(short, long)]
     pub quiet: bool,
 
+    /// Print the commands that would run without executing anything
+    #[arg(long)]
+    pub dry_run: bool,
+
+    /// Wait for another trls run holding the run lock to finish
+    #[arg(long, overrides_with = "no_wait")]
+    pub wait: bool,
+
+    /// Fail right away if another trls run holds the run lock (default)
+    #[arg(long, overrides_with = "wait")]
+    pub no_wait: bool,
+
+    /// Start builds and image generation even if the disk space check fails
+    #[arg(long)]
+    pub force: bool,
+
     /// Path to configuration file (overrides default /etc/trellis/trellis.toml)
     #[arg(long)]
     pub config_path: Option<PathBuf>,
 
+    /// Name of a [profiles.<name>] table in the configuration file to apply
+    #[arg(long)]
+    pub profile: Option<String>,
+
     /// Skip root user check (for testing purposes)
     #[arg(long)]
     pub skip_root_check: bool,
@@ -77,7 +116,7 @@ pub enum Commands {
     /// (Re-)Build pacstrap container that's used by the other commands
     BuildBuilder,
     /// Build all requested stages from files in --stages-dir
-    Build,
+    Build(BuildArgs),
     /// Remove unused container images
     Clean,
     /// Run cmd in the latest --rootfs-tag container
@@ -116,4 +155,170 @@ pub enum Commands {
         #[arg(long)]
         root_password: Option<String>,
     },
+    /// Show the layers of a built image
+    Inspect {
+        /// Image to inspect (default: the rootfs image)
+        image: Option<String>,
+    },
+    /// List the versions of the rootfs image
+    Images,
+    /// Point the rootfs image's latest tag at an earlier version
+    Retag {
+        /// Version as listed by `images`, or a unique prefix of it
+        version: String,
+    },
+    /// Push the rootfs image and its versions to the registry in [registry]
+    Push {
+        /// Destination overriding the configured URL, a registry host or oci:<directory>
+        #[arg(long, value_name = "URL")]
+        to: Option<String>,
+    },
+    /// Check or pin the digest of the rootfs base image
+    Base {
+        #[command(subcommand)]
+        command: BaseCommands,
+    },
+    /// Inspect the configured rootfs stages
+    Stages {
+        #[command(subcommand)]
+        command: StagesCommands,
+    },
+    /// Manage the git repositories configured in [[sources]]
+    Sources {
+        #[command(subcommand)]
+        command: SourcesCommands,
+    },
+    /// Check stage Containerfiles for mistakes before building
+    Lint {
+        /// Stages to check, in build order (default: the builder and rootfs stages)
+        stages: Vec<String>,
+    },
+    /// Print a stage's Containerfile after preprocessing
+    Render {
+        /// Stage name, as `stage` or `group:stage`
+        stage: String,
+    },
+    /// List recorded builds, updates and image generations
+    History,
+    /// Show the details of a recorded build
+    Show {
+        /// History ID or unique ID prefix
+        id: String,
+    },
+    /// List builds with stage logs, or show the log of a stage
+    Logs {
+        /// Build ID or unique ID prefix (default: most recent build)
+        #[arg(long, value_name = "ID")]
+        build: Option<String>,
+
+        /// Stage whose log to print
+        #[arg(long, value_name = "STAGE")]
+        stage: Option<String>,
+    },
+}
+
+impl Commands {
+    /// Whether the command writes its result to stdout, in which case no status
+    /// message should be printed after it succeeds.
+    pub fn writes_stdout(&self) -> bool {
+        matches!(
+            self,
+            Commands::Inspect { .. }
+                | Commands::Images
+                | Commands::Stages { .. }
+                | Commands::Render { .. }
+                | Commands::History
+                | Commands::Show { .. }
+                | Commands::Logs { .. }
+        )
+    }
+
+    /// How the command takes the run lock, `None` for commands that neither read
+    /// nor change images, containers or caches.
+    pub fn lock_mode(&self) -> Option<LockMode> {
+        match self {
+            Commands::BuildBuilder
+            | Commands::Clean
+            | Commands::Update
+            | Commands::QuickUpdate
+            | Commands::Image { .. }
+            | Commands::Retag { .. }
+            | Commands::Push { .. }
+            | Commands::Base {
+                command: BaseCommands::Pin,
+            }
+            | Commands::Sources { .. } => Some(LockMode::Exclusive),
+            Commands::Build(args) if !args.check => Some(LockMode::Exclusive),
+            Commands::Build(_)
+            | Commands::Run { .. }
+            | Commands::Inspect { .. }
+            | Commands::Images
+            | Commands::Base {
+                command: BaseCommands::Check,
+            } => Some(LockMode::Shared),
+            Commands::Stages { .. }
+            | Commands::Lint { .. }
+            | Commands::Render { .. }
+            | Commands::History
+            | Commands::Show { .. }
+            | Commands::Logs { .. } => None,
+        }
+    }
+}
+
+/// Subcommands of `stages`.
+#[derive(Subcommand, Clone, Debug)]
+pub enum StagesCommands {
+    /// Render the dependency graph declared in Containerfile headers
+    Graph {
+        /// Output format
+        #[arg(long, value_enum, default_value_t = GraphFormat::Dot)]
+        format: GraphFormat,
+    },
+}
+
+/// Subcommands of `base`.
+#[derive(Subcommand, Clone, Debug)]
+pub enum BaseCommands {
+    /// Compare the pinned digest of rootfs_base with the newest available one
+    Check,
+    /// Pin rootfs_base to the newest digest in the configuration file
+    Pin,
+}
+
+/// Subcommands of `sources`.
+#[derive(Subcommand, Clone, Debug)]
+pub enum SourcesCommands {
+    /// Fetch sources and check out their refs again
+    Update {
+        /// Update only this source
+        name: Option<String>,
+    },
+}
+
+/// Output formats for `stages graph`.
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GraphFormat {
+    /// Graphviz DOT
+    Dot,
+}
+
+/// Options for the `build` command.
+#[derive(Args, Clone, Debug, Default)]
+pub struct BuildArgs {
+    /// Report which stages are stale without building anything
+    #[arg(long)]
+    pub check: bool,
+
+    /// Start the build at this stage, reusing the image of the stage before it
+    #[arg(long, value_name = "STAGE", conflicts_with_all = ["check", "resume"])]
+    pub from: Option<String>,
+
+    /// Continue the last failed build after its last completed stage
+    #[arg(long, conflicts_with = "check")]
+    pub resume: bool,
+
+    /// Fail if any build input differs from trellis.lock
+    #[arg(long)]
+    pub locked: bool,
 }
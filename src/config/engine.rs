@@ -0,0 +1,61 @@
+//! Container engine selection.
+//!
+//! The `[engine]` section picks the tool that builds and runs images. Podman is the
+//! default. Buildah builds into the same container storage, with podman running
+//! the containers, and docker builds and runs in its own image store.
+//!
+//! With `api = true`, image queries go to podman's REST API on its socket instead
+//! of running a `podman` command for each.
+
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+/// The `[engine]` section of the configuration file.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct EngineConfig {
+    pub kind: Option<EngineKind>,
+    /// Query images through the libpod REST API
+    pub api: Option<bool>,
+    /// Socket of the API, a path or `unix://<path>`
+    pub socket: Option<String>,
+}
+
+impl EngineConfig {
+    /// Returns the API socket if the API is enabled, `default` unless configured.
+    pub fn api_socket(&self, default: &Path) -> Option<PathBuf> {
+        if !self.api.unwrap_or(false) {
+            return None;
+        }
+        Some(self.socket.as_deref().map_or_else(
+            || default.to_path_buf(),
+            |socket| PathBuf::from(socket.strip_prefix("unix://").unwrap_or(socket)),
+        ))
+    }
+}
+
+/// Container engine driving builds and containers.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EngineKind {
+    #[default]
+    Podman,
+    Buildah,
+    Docker,
+}
+
+impl EngineKind {
+    /// Returns the configuration value of the engine.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            EngineKind::Podman => "podman",
+            EngineKind::Buildah => "buildah",
+            EngineKind::Docker => "docker",
+        }
+    }
+}
+
+impl std::fmt::Display for EngineKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
@@ -1,33 +1,209 @@
-use std::{fs, path::PathBuf};
+use std::{collections::BTreeMap, fs, path::PathBuf};
 
-use anyhow::{Context, Result};
+use anyhow::{anyhow, Context, Result};
+use clap::ValueEnum;
 use serde::{Deserialize, Serialize};
 
 use crate::{
     cli::Cli,
-    trellis::constants::{containers, paths},
+    trellis::{
+        constants::{containers, paths},
+        rootless::{self, UserDirs},
+    },
 };
 
+use super::engine::{EngineConfig, EngineKind};
 use super::merger::{BoolMerger, ConfigMerger};
+use super::registry::{Registry, RegistryConfig};
+use super::security::{SecurityConfig, SecurityProfile};
+use super::sources::{SourceConfig, StageSource};
 use super::validator::ConfigValidator;
 
 #[derive(Debug, Deserialize, Serialize)]
 pub struct Config {
     pub build: Option<BuildConfig>,
     pub environment: Option<EnvironmentConfig>,
+    pub profiles: Option<BTreeMap<String, ProfileConfig>>,
+    pub stages: Option<BTreeMap<String, StageConfig>>,
+    pub vars: Option<BTreeMap<String, String>>,
+    pub security: Option<SecurityConfig>,
+    pub registry: Option<RegistryConfig>,
+    pub sources: Option<Vec<SourceConfig>>,
+    pub engine: Option<EngineConfig>,
 }
 
 #[derive(Debug, Deserialize, Serialize)]
 pub struct BuildConfig {
     pub builder_stages: Option<Vec<String>>,
     pub rootfs_stages: Option<Vec<String>>,
+    /// Base image of the first rootfs stage, optionally pinned as `image@sha256:...`
     pub rootfs_base: Option<String>,
+    pub pull_policy: Option<PullPolicy>,
     pub builder_tag: Option<String>,
     pub rootfs_tag: Option<String>,
+    /// Number of versioned rootfs tags kept besides `latest`, 0 disables them
+    pub keep_versions: Option<usize>,
     pub podman_build_cache: Option<bool>,
     pub auto_clean: Option<bool>,
+    pub incremental: Option<bool>,
+    pub preprocess: Option<bool>,
+    pub layering: Option<Layering>,
+    pub rechunk: Option<bool>,
+    /// Image providing `rpm-ostree compose build-chunked-oci` for rechunking
+    pub rechunk_image: Option<String>,
     pub extra_contexts: Option<Vec<String>>,
     pub extra_mounts: Option<Vec<PathBuf>>,
+    /// Build secrets by ID, mapped to the file holding the secret
+    pub secrets: Option<BTreeMap<String, PathBuf>>,
+}
+
+/// Named set of overrides selected with `--profile`.
+///
+/// Profile values take precedence over `[build]` values but not over CLI arguments.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct ProfileConfig {
+    pub rootfs_stages: Option<Vec<String>>,
+    pub rootfs_base: Option<String>,
+    pub rootfs_tag: Option<String>,
+    pub extra_contexts: Option<Vec<String>>,
+    pub extra_mounts: Option<Vec<PathBuf>>,
+}
+
+/// Build settings applied only to a single stage, from a `[stages.<name>]` table.
+///
+/// The table name matches either the full stage name as listed in the stage lists
+/// (e.g. `"features:gpu"`) or just the stage part (`gpu`).
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct StageConfig {
+    pub build_args: Option<BTreeMap<String, String>>,
+    pub mounts: Option<Vec<String>>,
+    pub contexts: Option<Vec<String>>,
+    pub network: Option<NetworkMode>,
+    pub no_cache: Option<bool>,
+    pub target: Option<String>,
+}
+
+/// Network mode of the build container for a stage.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum NetworkMode {
+    /// Share the host network namespace
+    #[default]
+    Host,
+    /// No network access
+    None,
+}
+
+impl NetworkMode {
+    /// Returns the value passed to `podman build --net`.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            NetworkMode::Host => "host",
+            NetworkMode::None => "none",
+        }
+    }
+}
+
+/// When `podman build` pulls the base image, passed as `--pull`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PullPolicy {
+    /// Pull the image even if it exists locally
+    Always,
+    /// Pull the image only if it does not exist locally
+    Missing,
+    /// Never pull, fail if the image does not exist locally
+    Never,
+}
+
+impl PullPolicy {
+    /// Returns the value passed to `podman build --pull`.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PullPolicy::Always => "always",
+            PullPolicy::Missing => "missing",
+            PullPolicy::Never => "never",
+        }
+    }
+}
+
+/// How the layers of rootfs stage images are produced.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum Layering {
+    /// Squash the layers each stage adds into one layer on top of its parent (`--squash`)
+    #[default]
+    Squash,
+    /// Squash every stage image into a single layer, including its parent (`--squash-all`)
+    SquashAll,
+    /// Keep one layer per Containerfile instruction
+    Layered,
+}
+
+impl Layering {
+    /// Returns the configuration value of the mode.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Layering::Squash => "squash",
+            Layering::SquashAll => "squash-all",
+            Layering::Layered => "layered",
+        }
+    }
+
+    /// Returns the `podman build` flag for the mode, if any.
+    pub fn podman_flag(&self) -> Option<&'static str> {
+        match self {
+            Layering::Squash => Some("--squash"),
+            Layering::SquashAll => Some("--squash-all"),
+            Layering::Layered => None,
+        }
+    }
+}
+
+/// Whether trls drives rootless podman, from `rootless` in `[environment]`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(try_from = "RootlessValue", into = "RootlessValue")]
+pub enum Rootless {
+    /// Drive root's podman (`false`)
+    #[default]
+    Off,
+    /// Drive the invoking user's podman (`true`)
+    On,
+    /// Rootless unless trls runs as root (`"auto"`)
+    Auto,
+}
+
+/// `rootless` as written in the configuration file: a boolean or `"auto"`.
+#[derive(Deserialize, Serialize)]
+#[serde(untagged)]
+enum RootlessValue {
+    Bool(bool),
+    Keyword(String),
+}
+
+impl TryFrom<RootlessValue> for Rootless {
+    type Error = String;
+
+    fn try_from(value: RootlessValue) -> std::result::Result<Self, Self::Error> {
+        match value {
+            RootlessValue::Bool(false) => Ok(Rootless::Off),
+            RootlessValue::Bool(true) => Ok(Rootless::On),
+            RootlessValue::Keyword(keyword) if keyword == "auto" => Ok(Rootless::Auto),
+            RootlessValue::Keyword(keyword) => Err(format!(
+                "invalid rootless value '{keyword}': expected true, false or \"auto\""
+            )),
+        }
+    }
+}
+
+impl From<Rootless> for RootlessValue {
+    fn from(rootless: Rootless) -> Self {
+        match rootless {
+            Rootless::Off => RootlessValue::Bool(false),
+            Rootless::On => RootlessValue::Bool(true),
+            Rootless::Auto => RootlessValue::Keyword("auto".to_string()),
+        }
+    }
 }
 
 #[derive(Debug, Deserialize, Serialize)]
@@ -35,7 +211,18 @@ pub struct EnvironmentConfig {
     pub pacman_cache: Option<PathBuf>,
     pub aur_cache: Option<PathBuf>,
     pub stages_dir: Option<PathBuf>,
+    /// Directory holding the checkouts of `[[sources]]` repositories
+    pub sources_dir: Option<PathBuf>,
     pub hooks_dir: Option<PathBuf>,
+    pub state_dir: Option<PathBuf>,
+    pub log_dir: Option<PathBuf>,
+    pub log_retention: Option<usize>,
+    /// Directory holding the lock that serializes trls runs
+    pub lock_dir: Option<PathBuf>,
+    /// Drive the invoking user's podman instead of root's
+    pub rootless: Option<Rootless>,
+    /// Command running operations that need root in rootless mode, `sudo` or `run0` if unset
+    pub privilege_command: Option<String>,
 }
 
 impl Default for Config {
@@ -45,51 +232,116 @@ impl Default for Config {
                 builder_stages: None,
                 rootfs_stages: None,
                 rootfs_base: Some("scratch".to_string()),
+                pull_policy: None,
                 builder_tag: Some(containers::DEFAULT_BUILDER_TAG.to_string()),
                 rootfs_tag: Some(containers::DEFAULT_ROOTFS_TAG.to_string()),
+                keep_versions: Some(containers::DEFAULT_KEEP_VERSIONS),
                 podman_build_cache: Some(false),
                 auto_clean: Some(false),
+                incremental: Some(false),
+                preprocess: Some(false),
+                layering: Some(Layering::Squash),
+                rechunk: Some(false),
+                rechunk_image: Some(containers::DEFAULT_RECHUNK_IMAGE.to_string()),
                 extra_contexts: None,
                 extra_mounts: None,
+                secrets: None,
             }),
             environment: Some(EnvironmentConfig {
                 pacman_cache: Some(PathBuf::from(paths::DEFAULT_PACMAN_CACHE)),
                 aur_cache: Some(PathBuf::from(paths::DEFAULT_AUR_CACHE)),
                 stages_dir: None,
+                sources_dir: Some(PathBuf::from(paths::DEFAULT_SOURCES_DIR)),
                 hooks_dir: Some(PathBuf::from(paths::DEFAULT_HOOKS_DIR)),
+                state_dir: Some(PathBuf::from(paths::DEFAULT_STATE_DIR)),
+                log_dir: Some(PathBuf::from(paths::DEFAULT_LOG_DIR)),
+                log_retention: Some(paths::DEFAULT_LOG_RETENTION),
+                lock_dir: Some(PathBuf::from(paths::DEFAULT_LOCK_DIR)),
+                rootless: Some(Rootless::Off),
+                privilege_command: None,
             }),
+            profiles: None,
+            stages: None,
+            vars: None,
+            security: None,
+            registry: None,
+            sources: None,
+            engine: None,
         }
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Serialize)]
 pub struct TrellisConfig {
     pub builder_stages: Vec<String>,
     pub builder_tag: String,
     pub podman_build_cache: bool,
     pub auto_clean: bool,
+    pub incremental: bool,
+    pub preprocess: bool,
+    pub layering: Layering,
+    pub rechunk: bool,
+    pub rechunk_image: String,
     pub pacman_cache: Option<PathBuf>,
     pub aur_cache: Option<PathBuf>,
     pub stages_dir: PathBuf,
+    /// Git repositories searched for Containerfiles after `stages_dir`
+    pub sources: Vec<StageSource>,
+    pub sources_dir: PathBuf,
     pub rootfs_stages: Vec<String>,
     pub rootfs_base: String,
+    /// Pull policy for stages built on an image outside local storage, podman's default unless set
+    pub pull_policy: Option<PullPolicy>,
     pub extra_contexts: Vec<String>,
     pub extra_mounts: Vec<PathBuf>,
+    /// Build secrets by ID, mapped to the file holding the secret
+    pub secrets: BTreeMap<String, PathBuf>,
     pub rootfs_tag: String,
+    /// Number of versioned rootfs tags kept besides `latest`, 0 disables them
+    pub keep_versions: usize,
     pub hooks_dir: Option<PathBuf>,
+    pub state_dir: PathBuf,
+    pub log_dir: PathBuf,
+    pub log_retention: usize,
+    pub lock_dir: PathBuf,
+    /// Drive the invoking user's podman, resolved from `rootless` in `[environment]`
+    pub rootless: bool,
+    /// Container storage of the podman trls drives
+    pub storage_dir: PathBuf,
+    /// Command running operations that need root in rootless mode
+    pub privilege_command: Option<String>,
+    /// Container engine building and running images, from the `[engine]` section
+    pub engine: EngineKind,
+    /// Socket of the libpod REST API answering image queries, if enabled
+    pub podman_socket: Option<PathBuf>,
+    pub stage_configs: BTreeMap<String, StageConfig>,
+    pub vars: BTreeMap<String, String>,
+    pub security: SecurityProfile,
+    /// Destination of `trls push`, from the `[registry]` section
+    pub registry: Option<Registry>,
+    pub profile: Option<String>,
+    /// Configuration file the settings were read from, which may not exist
+    pub config_path: PathBuf,
     pub quiet: bool,
+    pub dry_run: bool,
+    /// Wait for other trls runs holding the run lock instead of failing
+    pub wait: bool,
+    /// Start builds and image generation despite too little free disk space
+    pub force: bool,
 }
 
 impl TrellisConfig {
     /// Creates a new TrellisConfig by merging CLI arguments with configuration file values.
     ///
     /// CLI arguments take precedence over configuration file values, which take precedence
-    /// over default values. The configuration file path can be overridden with the
-    /// `--config-path` CLI argument.
+    /// over default values. Values from the profile selected with `--profile` take
+    /// precedence over the `[build]` section. The configuration file path can be overridden
+    /// with the `--config-path` CLI argument.
     ///
     /// # Errors
     ///
-    /// Returns an error if the configuration file exists but cannot be read or parsed.
+    /// Returns an error if the configuration file exists but cannot be read or parsed, or
+    /// if the selected profile is not defined.
     pub fn new(cli: Cli) -> Result<Self> {
         let config_file = cli
             .config_path
@@ -106,6 +358,17 @@ impl TrellisConfig {
 
         let build_config = file_config.build.as_ref();
         let env_config = file_config.environment.as_ref();
+        let profile = Self::resolve_profile(&file_config, cli.profile.as_deref())?;
+        let rootless = rootless::resolve(
+            Self::get_env_field(env_config, |e| &e.rootless).unwrap_or_default(),
+        )?;
+        let user_dirs = rootless.then(UserDirs::from_env).transpose()?;
+        // Directories root uses are not writable for other users
+        let default_dir = |rootless: fn(&UserDirs) -> PathBuf, rootful: &str| {
+            user_dirs
+                .as_ref()
+                .map_or_else(|| PathBuf::from(rootful), rootless)
+        };
 
         let config = TrellisConfig {
             builder_stages: Vec::merge(
@@ -115,24 +378,30 @@ impl TrellisConfig {
             ),
             rootfs_stages: Vec::merge(
                 cli.rootfs_stages,
-                Self::get_build_field(build_config, |b| &b.rootfs_stages),
+                Self::get_profile_field(profile, |p| &p.rootfs_stages)
+                    .or_else(|| Self::get_build_field(build_config, |b| &b.rootfs_stages)),
                 Vec::new(),
             ),
             rootfs_base: String::merge(
                 cli.rootfs_base,
-                Self::get_build_field(build_config, |b| &b.rootfs_base),
+                Self::get_profile_field(profile, |p| &p.rootfs_base)
+                    .or_else(|| Self::get_build_field(build_config, |b| &b.rootfs_base)),
                 "scratch".to_string(),
             ),
+            pull_policy: Self::get_build_field(build_config, |b| &b.pull_policy),
             extra_contexts: Vec::merge(
                 cli.extra_contexts,
-                Self::get_build_field(build_config, |b| &b.extra_contexts),
+                Self::get_profile_field(profile, |p| &p.extra_contexts)
+                    .or_else(|| Self::get_build_field(build_config, |b| &b.extra_contexts)),
                 Vec::new(),
             ),
             extra_mounts: Vec::merge(
                 cli.extra_mounts,
-                Self::get_build_field(build_config, |b| &b.extra_mounts),
+                Self::get_profile_field(profile, |p| &p.extra_mounts)
+                    .or_else(|| Self::get_build_field(build_config, |b| &b.extra_mounts)),
                 Vec::new(),
             ),
+            secrets: Self::resolve_secrets(build_config, &cli.secrets)?,
             builder_tag: String::merge(
                 cli.builder_tag,
                 Self::get_build_field(build_config, |b| &b.builder_tag),
@@ -140,31 +409,86 @@ impl TrellisConfig {
             ),
             rootfs_tag: String::merge(
                 cli.rootfs_tag,
-                Self::get_build_field(build_config, |b| &b.rootfs_tag),
+                Self::get_profile_field(profile, |p| &p.rootfs_tag)
+                    .or_else(|| Self::get_build_field(build_config, |b| &b.rootfs_tag)),
                 containers::DEFAULT_ROOTFS_TAG.to_string(),
             ),
+            keep_versions: Self::get_build_field(build_config, |b| &b.keep_versions)
+                .unwrap_or(containers::DEFAULT_KEEP_VERSIONS),
             podman_build_cache: BoolMerger::merge(
                 cli.podman_build_cache,
                 build_config.and_then(|b| b.podman_build_cache),
                 false,
             ),
             auto_clean: cli.auto_clean || build_config.and_then(|b| b.auto_clean).unwrap_or(false),
+            incremental: BoolMerger::merge(
+                cli.incremental,
+                build_config.and_then(|b| b.incremental),
+                false,
+            ),
+            preprocess: Self::get_build_field(build_config, |b| &b.preprocess).unwrap_or(false),
+            layering: cli
+                .layering
+                .or_else(|| Self::get_build_field(build_config, |b| &b.layering))
+                .unwrap_or_default(),
+            rechunk: BoolMerger::merge(cli.rechunk, build_config.and_then(|b| b.rechunk), false),
+            rechunk_image: Self::get_build_field(build_config, |b| &b.rechunk_image)
+                .unwrap_or_else(|| containers::DEFAULT_RECHUNK_IMAGE.to_string()),
             pacman_cache: Option::merge(
                 cli.pacman_cache,
                 Some(Self::get_env_field(env_config, |e| &e.pacman_cache)),
                 None,
-            ),
+            )
+            .or_else(|| user_dirs.as_ref().map(UserDirs::pacman_cache)),
             aur_cache: Option::merge(
                 cli.aur_cache,
                 Some(Self::get_env_field(env_config, |e| &e.aur_cache)),
                 None,
-            ),
+            )
+            .or_else(|| user_dirs.as_ref().map(UserDirs::aur_cache)),
             stages_dir: cli
                 .stages_dir
                 .or_else(|| env_config.and_then(|e| e.stages_dir.clone()))
                 .unwrap_or_else(|| PathBuf::from(paths::DEFAULT_STAGES_DIR)),
+            sources: StageSource::resolve_all(file_config.sources.as_ref())?,
+            sources_dir: Self::get_env_field(env_config, |e| &e.sources_dir)
+                .unwrap_or_else(|| default_dir(UserDirs::sources_dir, paths::DEFAULT_SOURCES_DIR)),
             hooks_dir: Self::resolve_hooks_dir(env_config),
+            state_dir: Self::get_env_field(env_config, |e| &e.state_dir)
+                .unwrap_or_else(|| default_dir(UserDirs::state_dir, paths::DEFAULT_STATE_DIR)),
+            log_dir: Self::get_env_field(env_config, |e| &e.log_dir)
+                .unwrap_or_else(|| default_dir(UserDirs::log_dir, paths::DEFAULT_LOG_DIR)),
+            log_retention: Self::get_env_field(env_config, |e| &e.log_retention)
+                .unwrap_or(paths::DEFAULT_LOG_RETENTION),
+            lock_dir: Self::get_env_field(env_config, |e| &e.lock_dir)
+                .unwrap_or_else(|| default_dir(UserDirs::lock_dir, paths::DEFAULT_LOCK_DIR)),
+            rootless,
+            storage_dir: user_dirs.as_ref().map_or_else(
+                || PathBuf::from(containers::STORAGE_DIR),
+                UserDirs::storage_dir,
+            ),
+            privilege_command: Self::get_env_field(env_config, |e| &e.privilege_command),
+            engine: file_config
+                .engine
+                .as_ref()
+                .and_then(|e| e.kind)
+                .unwrap_or_default(),
+            podman_socket: file_config.engine.as_ref().and_then(|e| {
+                e.api_socket(&user_dirs.as_ref().map_or_else(
+                    || PathBuf::from(containers::PODMAN_SOCKET),
+                    UserDirs::podman_socket,
+                ))
+            }),
+            stage_configs: file_config.stages.clone().unwrap_or_default(),
+            vars: file_config.vars.clone().unwrap_or_default(),
+            security: SecurityProfile::resolve(file_config.security.as_ref())?,
+            registry: Registry::resolve(file_config.registry.as_ref())?,
+            profile: cli.profile,
+            config_path: config_file,
             quiet: cli.quiet,
+            dry_run: cli.dry_run,
+            wait: cli.wait,
+            force: cli.force,
         };
 
         // Validate the complete configuration
@@ -173,6 +497,92 @@ impl TrellisConfig {
         Ok(config)
     }
 
+    /// Looks up the profile selected on the command line.
+    fn resolve_profile<'c>(
+        file_config: &'c Config,
+        name: Option<&str>,
+    ) -> Result<Option<&'c ProfileConfig>> {
+        let Some(name) = name else {
+            return Ok(None);
+        };
+
+        let profiles = file_config.profiles.as_ref();
+        match profiles.and_then(|p| p.get(name)) {
+            Some(profile) => Ok(Some(profile)),
+            None => {
+                let available: Vec<&str> = profiles
+                    .map(|p| p.keys().map(String::as_str).collect())
+                    .unwrap_or_default();
+                if available.is_empty() {
+                    Err(anyhow!(
+                        "Profile '{name}' not found: no profiles are defined"
+                    ))
+                } else {
+                    Err(anyhow!(
+                        "Profile '{name}' not found. Available profiles: {}",
+                        available.join(", ")
+                    ))
+                }
+            }
+        }
+    }
+
+    /// Merges `[build.secrets]` with `--secret` options, which replace configured
+    /// secrets of the same ID.
+    fn resolve_secrets(
+        build_config: Option<&BuildConfig>,
+        cli_secrets: &[String],
+    ) -> Result<BTreeMap<String, PathBuf>> {
+        let mut secrets = Self::get_build_field(build_config, |b| &b.secrets).unwrap_or_default();
+        for spec in cli_secrets {
+            let (id, src) = Self::parse_secret(spec)?;
+            secrets.insert(id, src);
+        }
+        Ok(secrets)
+    }
+
+    /// Parses a `--secret id=ID,src=PATH` option.
+    fn parse_secret(spec: &str) -> Result<(String, PathBuf)> {
+        let mut id = None;
+        let mut src = None;
+        for part in spec.split(',') {
+            match part.split_once('=') {
+                Some(("id", value)) if !value.is_empty() => id = Some(value.to_string()),
+                Some(("src", value)) if !value.is_empty() => src = Some(PathBuf::from(value)),
+                _ => return Err(anyhow!("Invalid secret '{spec}': expected id=ID,src=PATH")),
+            }
+        }
+
+        match (id, src) {
+            (Some(id), Some(src)) => Ok((id, src)),
+            _ => Err(anyhow!("Invalid secret '{spec}': expected id=ID,src=PATH")),
+        }
+    }
+
+    /// Returns the `[stages.<name>]` settings for a stage, if any.
+    ///
+    /// Settings for the full `group:stage` name take precedence over settings for
+    /// the bare stage name.
+    pub fn stage_config(&self, build_stage: &str) -> Option<&StageConfig> {
+        self.stage_configs.get(build_stage).or_else(|| {
+            build_stage
+                .split_once(':')
+                .and_then(|(_, stage)| self.stage_configs.get(stage))
+        })
+    }
+
+    /// Returns the directories searched for Containerfiles, in order of precedence:
+    /// the stages directory followed by the checkouts of `[[sources]]` repositories.
+    pub fn stage_dirs(&self) -> Vec<PathBuf> {
+        std::iter::once(self.stages_dir.clone())
+            .chain(
+                self.sources
+                    .iter()
+                    .map(|source| source.stages_dir(&self.sources_dir)),
+            )
+            .collect()
+    }
+
     /// Resolves the hooks directory with proper existence checking.
     fn resolve_hooks_dir(env_config: Option<&EnvironmentConfig>) -> Option<PathBuf> {
         let hooks_dir = env_config
@@ -189,6 +599,14 @@ impl TrellisConfig {
         build_config.and_then(|b| field_getter(b).clone())
     }
 
+    /// Helper function to consolidate profile field access patterns.
+    fn get_profile_field<T: Clone>(
+        profile: Option<&ProfileConfig>,
+        field_getter: fn(&ProfileConfig) -> &Option<T>,
+    ) -> Option<T> {
+        profile.and_then(|p| field_getter(p).clone())
+    }
+
     /// Helper function to consolidate environment config field access patterns.
     fn get_env_field<T: Clone>(
         env_config: Option<&EnvironmentConfig>,
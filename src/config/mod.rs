@@ -1,6 +1,15 @@
+mod engine;
 mod lib;
 pub mod merger;
+mod registry;
+mod security;
+mod sources;
 pub mod validator;
+pub mod writer;
 
+pub use engine::*;
 pub use lib::*;
+pub use registry::*;
+pub use security::*;
+pub use sources::*;
 pub use validator::ConfigValidator;
@@ -0,0 +1,254 @@
+//! Destination of `trls push`.
+//!
+//! The `[registry]` section names a registry host, or an `oci:` directory for
+//! pushing to a local OCI layout, and the repository the rootfs image is pushed
+//! to. Images can optionally be signed through podman with a GPG key or a
+//! sigstore private key.
+
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+
+/// The `[registry]` section of the configuration file.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct RegistryConfig {
+    /// Registry host, e.g. `registry.example.com:5000`, or `oci:<directory>`
+    pub url: String,
+    /// Repository within the registry, defaults to the rootfs tag
+    pub repository: Option<String>,
+    /// Credentials file as written by `podman login --authfile`
+    pub auth_file: Option<PathBuf>,
+    pub tls_verify: Option<bool>,
+    /// GPG key to sign with, passed to `podman push --sign-by`
+    pub sign_by: Option<String>,
+    /// Sigstore private key to sign with, passed to `--sign-by-sigstore-private-key`
+    pub sigstore_private_key: Option<PathBuf>,
+    /// File holding the passphrase of the signing key
+    pub sign_passphrase_file: Option<PathBuf>,
+    /// Push `trellis.lock` and `build.json` as a `<version>-metadata` image
+    pub attach_metadata: Option<bool>,
+}
+
+/// Where images are pushed to.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub enum RegistryDestination {
+    /// A registry, by host and optional port
+    Registry(String),
+    /// An OCI layout directory holding one layout per repository
+    OciLayout(PathBuf),
+}
+
+impl RegistryDestination {
+    /// Parses a registry URL, `[docker://]host[:port]` or `oci:<directory>`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the URL is empty or uses an unsupported transport.
+    pub fn parse(url: &str) -> Result<Self> {
+        let url = url.trim();
+        if let Some(path) = url.strip_prefix("oci:") {
+            if path.is_empty() {
+                return Err(anyhow!("Registry URL '{url}' has no OCI layout directory"));
+            }
+            return Ok(Self::OciLayout(PathBuf::from(path)));
+        }
+
+        let host = url
+            .strip_prefix("docker://")
+            .or_else(|| url.strip_prefix("https://"))
+            .unwrap_or(url)
+            .trim_end_matches('/');
+        if host.is_empty() {
+            return Err(anyhow!("Registry URL is empty"));
+        }
+        if host.contains("://") {
+            return Err(anyhow!(
+                "Unsupported registry URL '{url}': use a registry host or oci:<directory>"
+            ));
+        }
+        Ok(Self::Registry(host.to_string()))
+    }
+
+    /// Returns the podman reference of `repository:tag` at the destination.
+    pub fn reference(&self, repository: &str, tag: &str) -> String {
+        match self {
+            Self::Registry(host) => format!("docker://{host}/{repository}:{tag}"),
+            Self::OciLayout(dir) => format!("oci:{}:{tag}", dir.join(repository).display()),
+        }
+    }
+}
+
+/// Validated push settings.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Registry {
+    pub destination: RegistryDestination,
+    /// Repository to push to, the rootfs tag unless set
+    pub repository: Option<String>,
+    pub auth_file: Option<PathBuf>,
+    pub tls_verify: bool,
+    pub sign_by: Option<String>,
+    pub sigstore_private_key: Option<PathBuf>,
+    pub sign_passphrase_file: Option<PathBuf>,
+    pub attach_metadata: bool,
+}
+
+impl Registry {
+    /// Creates settings for a destination with every option at its default.
+    pub fn new(destination: RegistryDestination) -> Self {
+        Self {
+            destination,
+            repository: None,
+            auth_file: None,
+            tls_verify: true,
+            sign_by: None,
+            sigstore_private_key: None,
+            sign_passphrase_file: None,
+            attach_metadata: true,
+        }
+    }
+
+    /// Validates the `[registry]` section.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the URL is invalid, the repository is not a valid image
+    /// name, or both kinds of signing keys are set.
+    pub fn resolve(config: Option<&RegistryConfig>) -> Result<Option<Self>> {
+        let Some(config) = config else {
+            return Ok(None);
+        };
+
+        if let Some(repository) = &config.repository {
+            Self::validate_repository(repository)?;
+        }
+        if config.sign_by.is_some() && config.sigstore_private_key.is_some() {
+            return Err(anyhow!(
+                "Set either sign_by or sigstore_private_key in [registry], not both"
+            ));
+        }
+
+        Ok(Some(Self {
+            repository: config.repository.clone(),
+            auth_file: config.auth_file.clone(),
+            tls_verify: config.tls_verify.unwrap_or(true),
+            sign_by: config.sign_by.clone(),
+            sigstore_private_key: config.sigstore_private_key.clone(),
+            sign_passphrase_file: config.sign_passphrase_file.clone(),
+            attach_metadata: config.attach_metadata.unwrap_or(true),
+            ..Self::new(RegistryDestination::parse(&config.url)?)
+        }))
+    }
+
+    /// Checks that a repository consists of lowercase path components.
+    fn validate_repository(repository: &str) -> Result<()> {
+        let valid = !repository.is_empty()
+            && repository.split('/').all(|component| {
+                !component.is_empty()
+                    && component.starts_with(|c: char| c.is_ascii_alphanumeric())
+                    && component.chars().all(|c| {
+                        c.is_ascii_lowercase() || c.is_ascii_digit() || matches!(c, '.' | '_' | '-')
+                    })
+            });
+        if valid {
+            Ok(())
+        } else {
+            Err(anyhow!(
+                "Invalid registry repository '{repository}': use lowercase letters, digits, '.', '_', '-' and '/'"
+            ))
+        }
+    }
+
+    /// Whether images are signed when pushed.
+    pub fn signs(&self) -> bool {
+        self.sign_by.is_some() || self.sigstore_private_key.is_some()
+    }
+
+    /// Returns the `podman push` options for authentication, TLS and signing.
+    pub fn push_args(&self) -> Vec<String> {
+        let mut args = Vec::new();
+        if let RegistryDestination::Registry(_) = self.destination {
+            args.push(format!("--tls-verify={}", self.tls_verify));
+            if let Some(auth_file) = &self.auth_file {
+                args.extend(["--authfile".to_string(), auth_file.display().to_string()]);
+            }
+        }
+        if let Some(key) = &self.sign_by {
+            args.extend(["--sign-by".to_string(), key.clone()]);
+        }
+        if let Some(key) = &self.sigstore_private_key {
+            args.extend([
+                "--sign-by-sigstore-private-key".to_string(),
+                key.display().to_string(),
+            ]);
+        }
+        if let Some(passphrase) = &self.sign_passphrase_file {
+            args.extend([
+                "--sign-passphrase-file".to_string(),
+                passphrase.display().to_string(),
+            ]);
+        }
+        args
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_destination_references() {
+        let registry = RegistryDestination::parse("docker://localhost:5000/").unwrap();
+        assert_eq!(
+            registry.reference("os/rootfs", "latest"),
+            "docker://localhost:5000/os/rootfs:latest"
+        );
+
+        let layout = RegistryDestination::parse("oci:/srv/oci").unwrap();
+        assert_eq!(
+            layout.reference("trellis-rootfs", "20240101-120000-0123abcd"),
+            "oci:/srv/oci/trellis-rootfs:20240101-120000-0123abcd"
+        );
+
+        assert!(RegistryDestination::parse("oci:").is_err());
+        assert!(RegistryDestination::parse("sftp://example.com").is_err());
+    }
+
+    #[test]
+    fn test_registry_settings_are_validated() {
+        let config = RegistryConfig {
+            url: "registry.example.com".to_string(),
+            repository: Some("desktop/rootfs".to_string()),
+            auth_file: Some(PathBuf::from("/etc/trellis/auth.json")),
+            tls_verify: Some(false),
+            sign_by: Some("releases@example.com".to_string()),
+            ..Default::default()
+        };
+        let registry = Registry::resolve(Some(&config)).unwrap().unwrap();
+        assert!(registry.signs());
+        assert_eq!(
+            registry.push_args(),
+            vec![
+                "--tls-verify=false",
+                "--authfile",
+                "/etc/trellis/auth.json",
+                "--sign-by",
+                "releases@example.com",
+            ]
+        );
+
+        let uppercase = RegistryConfig {
+            url: "registry.example.com".to_string(),
+            repository: Some("Desktop".to_string()),
+            ..Default::default()
+        };
+        assert!(Registry::resolve(Some(&uppercase)).is_err());
+
+        let both_keys = RegistryConfig {
+            url: "registry.example.com".to_string(),
+            sign_by: Some("releases@example.com".to_string()),
+            sigstore_private_key: Some(PathBuf::from("/etc/trellis/cosign.key")),
+            ..Default::default()
+        };
+        assert!(Registry::resolve(Some(&both_keys)).is_err());
+    }
+}
@@ -0,0 +1,349 @@
+//! Security profiles for the containers trellis starts.
+//!
+//! A profile sets the network mode, added capabilities, seccomp profile, SELinux
+//! label options and privileged mode separately for the three kinds of containers:
+//! `podman build` for stages, `podman run` for `trls run` and `quick-update`, and
+//! the `bootc install` container used by `trls image`.
+//!
+//! The built-in profiles are `default`, which matches the behaviour of earlier
+//! releases, `hardened` and `privileged`. Custom profiles are defined in
+//! `[security.profiles.<name>]` tables and extend a built-in profile.
+
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+use super::NetworkMode;
+use crate::trellis::runner::ContainerCapability;
+
+/// Name of the profile used when none is configured.
+pub const DEFAULT_SECURITY_PROFILE: &str = "default";
+
+/// Names of the built-in profiles.
+pub const BUILTIN_SECURITY_PROFILES: &[&str] = &["default", "hardened", "privileged"];
+
+/// Capabilities left out of run containers by the `hardened` profile; the others
+/// are enough for package managers.
+const HARDENED_RUN_EXCLUDED: &[ContainerCapability] = &[
+    ContainerCapability::SysAdmin,
+    ContainerCapability::SysPtrace,
+];
+
+/// The `[security]` section of the configuration file.
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct SecurityConfig {
+    /// Name of the profile in effect, `default` unless set
+    pub profile: Option<String>,
+    pub profiles: Option<BTreeMap<String, SecurityProfileConfig>>,
+}
+
+/// A custom profile from a `[security.profiles.<name>]` table.
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct SecurityProfileConfig {
+    /// Built-in profile providing every setting not given here, `default` unless set
+    pub extends: Option<String>,
+    pub build: Option<SecuritySettings>,
+    pub run: Option<SecuritySettings>,
+    pub install: Option<SecuritySettings>,
+}
+
+/// Settings of a custom profile for one kind of container.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct SecuritySettings {
+    pub network: Option<NetworkMode>,
+    /// Capabilities to add, e.g. `["sys_admin"]` or `["all"]`
+    pub capabilities: Option<Vec<String>>,
+    /// Seccomp profile path, or `unconfined`
+    pub seccomp: Option<String>,
+    /// SELinux label options, e.g. `["disable"]` or `["type:unconfined_t"]`
+    pub labels: Option<Vec<String>>,
+    pub privileged: Option<bool>,
+}
+
+/// Effective security options for one kind of container.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct SecurityOptions {
+    /// Network mode, or `None` for podman's default
+    pub network: Option<NetworkMode>,
+    pub capabilities: Vec<String>,
+    /// Seccomp profile, or `None` for podman's default
+    pub seccomp: Option<String>,
+    pub labels: Vec<String>,
+    pub privileged: bool,
+}
+
+impl SecurityOptions {
+    fn new(network: Option<NetworkMode>, capabilities: &[&str], privileged: bool) -> Self {
+        Self::with_capabilities(
+            network,
+            capabilities.iter().map(|cap| cap.to_string()).collect(),
+            privileged,
+        )
+    }
+
+    fn with_capabilities(
+        network: Option<NetworkMode>,
+        capabilities: Vec<String>,
+        privileged: bool,
+    ) -> Self {
+        Self {
+            network,
+            capabilities,
+            seccomp: None,
+            labels: Vec::new(),
+            privileged,
+        }
+    }
+
+    fn with_labels(mut self, labels: &[&str]) -> Self {
+        self.labels = labels.iter().map(|label| label.to_string()).collect();
+        self
+    }
+
+    fn with_seccomp(mut self, seccomp: &str) -> Self {
+        self.seccomp = Some(seccomp.to_string());
+        self
+    }
+
+    /// Returns a copy with the values set in `settings` replaced.
+    fn merged(&self, settings: Option<&SecuritySettings>) -> Self {
+        let Some(settings) = settings else {
+            return self.clone();
+        };
+        Self {
+            network: settings.network.or(self.network),
+            capabilities: settings
+                .capabilities
+                .clone()
+                .unwrap_or_else(|| self.capabilities.clone()),
+            seccomp: settings.seccomp.clone().or_else(|| self.seccomp.clone()),
+            labels: settings
+                .labels
+                .clone()
+                .unwrap_or_else(|| self.labels.clone()),
+            privileged: settings.privileged.unwrap_or(self.privileged),
+        }
+    }
+
+    /// Returns the podman arguments for everything but the network mode.
+    pub fn podman_args(&self) -> Vec<String> {
+        let mut args = Vec::new();
+        if self.privileged {
+            args.push("--privileged".to_string());
+        }
+        for cap in &self.capabilities {
+            args.extend(["--cap-add".to_string(), cap.clone()]);
+        }
+        if let Some(seccomp) = &self.seccomp {
+            args.extend(["--security-opt".to_string(), format!("seccomp={seccomp}")]);
+        }
+        for label in &self.labels {
+            args.extend(["--security-opt".to_string(), format!("label={label}")]);
+        }
+        args
+    }
+
+    /// Summarizes the options on one line for messages and logs.
+    pub fn describe(&self) -> String {
+        let or_default = |values: &[String]| {
+            if values.is_empty() {
+                "default".to_string()
+            } else {
+                values.join(",")
+            }
+        };
+        format!(
+            "network={}, cap-add={}, seccomp={}, label={}, privileged={}",
+            self.network.map_or("default", |network| network.as_str()),
+            or_default(&self.capabilities),
+            self.seccomp.as_deref().unwrap_or("default"),
+            or_default(&self.labels),
+            if self.privileged { "yes" } else { "no" }
+        )
+    }
+}
+
+/// The security profile in effect, with options for every kind of container.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct SecurityProfile {
+    pub name: String,
+    /// Options for `podman build`
+    pub build: SecurityOptions,
+    /// Options for `trls run` and `quick-update` containers
+    pub run: SecurityOptions,
+    /// Options for the `bootc install` container of `trls image`
+    pub install: SecurityOptions,
+}
+
+impl Default for SecurityProfile {
+    fn default() -> Self {
+        Self::builtin(DEFAULT_SECURITY_PROFILE).expect("default profile is built in")
+    }
+}
+
+impl SecurityProfile {
+    /// Returns a built-in profile by name.
+    ///
+    /// `bootc install` needs a privileged container, so every built-in profile
+    /// installs with `--privileged`.
+    pub fn builtin(name: &str) -> Option<Self> {
+        let run_capabilities = |excluded: &[ContainerCapability]| {
+            ContainerCapability::ALL
+                .iter()
+                .filter(|cap| !excluded.contains(cap))
+                .map(|cap| cap.as_str().to_string())
+                .collect()
+        };
+        let install = SecurityOptions::new(None, &[], true).with_labels(&["type:unconfined_t"]);
+
+        let (build, run) = match name {
+            "default" => (
+                SecurityOptions::new(Some(NetworkMode::Host), &["sys_admin", "mknod"], false),
+                SecurityOptions::with_capabilities(
+                    Some(NetworkMode::Host),
+                    run_capabilities(&[]),
+                    false,
+                ),
+            ),
+            "hardened" => (
+                SecurityOptions::new(Some(NetworkMode::Host), &[], false),
+                SecurityOptions::with_capabilities(
+                    Some(NetworkMode::Host),
+                    run_capabilities(HARDENED_RUN_EXCLUDED),
+                    false,
+                ),
+            ),
+            "privileged" => (
+                // podman build has no privileged mode; lift the restrictions instead
+                SecurityOptions::new(Some(NetworkMode::Host), &["all"], false)
+                    .with_seccomp("unconfined")
+                    .with_labels(&["disable"]),
+                SecurityOptions::new(Some(NetworkMode::Host), &[], true),
+            ),
+            _ => return None,
+        };
+
+        Some(Self {
+            name: name.to_string(),
+            build,
+            run,
+            install,
+        })
+    }
+
+    /// Resolves the profile selected in the `[security]` section.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the profile is not defined, extends a profile that is
+    /// not built in, or enables privileged mode for builds.
+    pub fn resolve(config: Option<&SecurityConfig>) -> Result<Self> {
+        let name = config
+            .and_then(|c| c.profile.as_deref())
+            .unwrap_or(DEFAULT_SECURITY_PROFILE);
+        let custom = config
+            .and_then(|c| c.profiles.as_ref())
+            .and_then(|profiles| profiles.get(name));
+
+        let profile = match custom {
+            Some(custom) => {
+                let extends = custom
+                    .extends
+                    .as_deref()
+                    .unwrap_or(DEFAULT_SECURITY_PROFILE);
+                let base = Self::builtin(extends).ok_or_else(|| {
+                    anyhow!(
+                        "Security profile '{name}' extends unknown profile '{extends}'. Built-in profiles: {}",
+                        BUILTIN_SECURITY_PROFILES.join(", ")
+                    )
+                })?;
+                Self {
+                    name: name.to_string(),
+                    build: base.build.merged(custom.build.as_ref()),
+                    run: base.run.merged(custom.run.as_ref()),
+                    install: base.install.merged(custom.install.as_ref()),
+                }
+            }
+            None => Self::builtin(name).ok_or_else(|| {
+                let mut available: Vec<&str> = BUILTIN_SECURITY_PROFILES.to_vec();
+                available.extend(
+                    config
+                        .and_then(|c| c.profiles.as_ref())
+                        .into_iter()
+                        .flat_map(|profiles| profiles.keys().map(String::as_str)),
+                );
+                anyhow!(
+                    "Security profile '{name}' not found. Available profiles: {}",
+                    available.join(", ")
+                )
+            })?,
+        };
+
+        if profile.build.privileged {
+            return Err(anyhow!(
+                "Security profile '{name}' cannot enable privileged mode for builds: podman build has no privileged mode"
+            ));
+        }
+
+        Ok(profile)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_default_profile_matches_previous_behaviour() {
+        let profile = SecurityProfile::resolve(None).unwrap();
+        assert_eq!(profile.name, "default");
+        assert_eq!(
+            profile.build.podman_args(),
+            vec!["--cap-add", "sys_admin", "--cap-add", "mknod"]
+        );
+        assert_eq!(profile.run.capabilities.len(), 7);
+        assert_eq!(
+            profile.install.podman_args(),
+            vec!["--privileged", "--security-opt", "label=type:unconfined_t"]
+        );
+    }
+
+    #[test]
+    fn test_custom_profile_extends_builtin() {
+        let config: SecurityConfig = toml::from_str(
+            r#"
+profile = "ci"
+
+[profiles.ci]
+extends = "hardened"
+build = { network = "none", seccomp = "/etc/trellis/seccomp.json" }
+"#,
+        )
+        .unwrap();
+
+        let profile = SecurityProfile::resolve(Some(&config)).unwrap();
+        assert_eq!(profile.name, "ci");
+        assert_eq!(profile.build.network, Some(NetworkMode::None));
+        assert!(profile.build.capabilities.is_empty());
+        assert_eq!(
+            profile.build.describe(),
+            "network=none, cap-add=default, seccomp=/etc/trellis/seccomp.json, label=default, privileged=no"
+        );
+        assert_eq!(
+            profile.run,
+            SecurityProfile::builtin("hardened").unwrap().run
+        );
+    }
+
+    #[test]
+    fn test_invalid_profiles_are_rejected() {
+        let unknown: SecurityConfig = toml::from_str("profile = \"paranoid\"").unwrap();
+        let error = SecurityProfile::resolve(Some(&unknown)).unwrap_err();
+        assert!(error.to_string().contains("default, hardened, privileged"));
+
+        let privileged_build: SecurityConfig =
+            toml::from_str("profile = \"x\"\n[profiles.x]\nbuild = { privileged = true }\n")
+                .unwrap();
+        assert!(SecurityProfile::resolve(Some(&privileged_build)).is_err());
+    }
+}
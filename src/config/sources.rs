@@ -0,0 +1,173 @@
+//! Git repositories providing stage Containerfiles.
+//!
+//! Every `[[sources]]` entry names a git repository, by URL or local path, and a
+//! pinned ref. Trellis keeps a checkout of each in the sources directory and
+//! searches them for Containerfiles after the stages directory.
+
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use std::path::{Component, Path, PathBuf};
+
+/// A `[[sources]]` entry of the configuration file.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct SourceConfig {
+    /// Name of the checkout, defaults to the last component of the URL
+    pub name: Option<String>,
+    /// Repository URL or local path, e.g. `https://example.com/stages.git` or `file:///srv/stages.git`
+    pub git: String,
+    /// Branch, tag or commit to check out
+    #[serde(rename = "ref")]
+    pub reference: String,
+    /// Directory within the repository holding the Containerfiles
+    pub subdir: Option<PathBuf>,
+}
+
+/// A validated stage source.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct StageSource {
+    pub name: String,
+    pub git: String,
+    #[serde(rename = "ref")]
+    pub reference: String,
+    pub subdir: Option<PathBuf>,
+}
+
+impl StageSource {
+    /// Validates the `[[sources]]` entries.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if an entry has no URL or ref, a name that is not a plain
+    /// file name, a name used by another entry, or a subdir leaving the repository.
+    pub fn resolve_all(sources: Option<&Vec<SourceConfig>>) -> Result<Vec<Self>> {
+        let mut resolved: Vec<Self> = Vec::new();
+
+        for source in sources.into_iter().flatten() {
+            let git = source.git.trim();
+            if git.is_empty() {
+                return Err(anyhow!("Source without git URL"));
+            }
+            let name = match &source.name {
+                Some(name) => name.clone(),
+                None => Self::default_name(git),
+            };
+            if name.is_empty()
+                || name.starts_with('.')
+                || !name
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-'))
+            {
+                return Err(anyhow!(
+                    "Invalid source name '{name}' for {git}: use letters, digits, '.', '_' and '-'"
+                ));
+            }
+            if resolved.iter().any(|other| other.name == name) {
+                return Err(anyhow!(
+                    "Duplicate source name '{name}'. Set distinct names with `name = \"...\"`"
+                ));
+            }
+            if source.reference.trim().is_empty() {
+                return Err(anyhow!("Source '{name}' has no ref"));
+            }
+            if let Some(subdir) = &source.subdir {
+                if !subdir
+                    .components()
+                    .all(|component| matches!(component, Component::Normal(_)))
+                {
+                    return Err(anyhow!(
+                        "Subdir of source '{name}' must be a relative path within the repository: {}",
+                        subdir.display()
+                    ));
+                }
+            }
+
+            resolved.push(Self {
+                name,
+                git: git.to_string(),
+                reference: source.reference.trim().to_string(),
+                subdir: source.subdir.clone(),
+            });
+        }
+
+        Ok(resolved)
+    }
+
+    /// Derives a name from the last component of a URL or path, without `.git`.
+    fn default_name(git: &str) -> String {
+        let last = git
+            .trim_end_matches('/')
+            .rsplit(['/', ':'])
+            .next()
+            .unwrap_or(git);
+        last.strip_suffix(".git").unwrap_or(last).to_string()
+    }
+
+    /// Returns the directory of the source's checkout.
+    pub fn checkout_dir(&self, sources_dir: &Path) -> PathBuf {
+        sources_dir.join(&self.name)
+    }
+
+    /// Returns the directory searched for Containerfiles.
+    pub fn stages_dir(&self, sources_dir: &Path) -> PathBuf {
+        let checkout = self.checkout_dir(sources_dir);
+        match &self.subdir {
+            Some(subdir) => checkout.join(subdir),
+            None => checkout,
+        }
+    }
+}
+
+impl From<&StageSource> for SourceConfig {
+    fn from(source: &StageSource) -> Self {
+        Self {
+            name: Some(source.name.clone()),
+            git: source.git.clone(),
+            reference: source.reference.clone(),
+            subdir: source.subdir.clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn source(name: Option<&str>, git: &str, subdir: Option<&str>) -> SourceConfig {
+        SourceConfig {
+            name: name.map(str::to_string),
+            git: git.to_string(),
+            reference: "main".to_string(),
+            subdir: subdir.map(PathBuf::from),
+        }
+    }
+
+    #[test]
+    fn test_source_names_default_to_repository_name() {
+        let sources = vec![
+            source(None, "https://example.com/org/desktop-stages.git", None),
+            source(None, "file:///srv/git/base/", Some("stages")),
+        ];
+        let resolved = StageSource::resolve_all(Some(&sources)).unwrap();
+        assert_eq!(resolved[0].name, "desktop-stages");
+        assert_eq!(resolved[1].name, "base");
+        assert_eq!(
+            resolved[1].stages_dir(Path::new("/var/cache/trellis/sources")),
+            PathBuf::from("/var/cache/trellis/sources/base/stages")
+        );
+    }
+
+    #[test]
+    fn test_invalid_sources_are_rejected() {
+        let duplicate = vec![
+            source(None, "/srv/a/stages.git", None),
+            source(None, "/srv/b/stages.git", None),
+        ];
+        assert!(StageSource::resolve_all(Some(&duplicate)).is_err());
+
+        let escaping = vec![source(Some("x"), "/srv/x.git", Some("../etc"))];
+        assert!(StageSource::resolve_all(Some(&escaping)).is_err());
+
+        let bad_name = vec![source(Some("a/b"), "/srv/x.git", None)];
+        assert!(StageSource::resolve_all(Some(&bad_name)).is_err());
+    }
+}
@@ -1,6 +1,8 @@
+use super::engine::EngineKind;
 use super::lib::TrellisConfig;
-use crate::trellis::constants::errors;
-use anyhow::{anyhow, Result};
+use crate::trellis::{base_image, constants::errors};
+use anyhow::{anyhow, Context, Result};
+use std::{collections::BTreeMap, os::unix::fs::PermissionsExt, path::PathBuf};
 
 /// Centralized configuration validator.
 ///
@@ -53,6 +55,47 @@ impl ConfigValidator {
         Ok(())
     }
 
+    /// Validates build secrets before they are passed to podman.
+    ///
+    /// Secret IDs may not contain `,` or `=`, and every source must be an existing
+    /// regular file that is not readable by other users. Only paths are checked and
+    /// reported; the secret contents are never read.
+    ///
+    /// # Arguments
+    ///
+    /// * `secrets` - Secret sources by ID
+    ///
+    /// # Errors
+    ///
+    /// Returns an error naming the first invalid secret
+    pub fn validate_secrets(secrets: &BTreeMap<String, PathBuf>) -> Result<()> {
+        for (id, src) in secrets {
+            if id.is_empty() || id.contains([',', '=']) {
+                return Err(anyhow!(
+                    "Invalid secret ID '{id}': IDs cannot be empty or contain ',' or '='"
+                ));
+            }
+
+            let metadata = std::fs::metadata(src).with_context(|| {
+                format!("Source of secret '{id}' does not exist: {}", src.display())
+            })?;
+            if !metadata.is_file() {
+                return Err(anyhow!(
+                    "Source of secret '{id}' is not a file: {}",
+                    src.display()
+                ));
+            }
+            if metadata.permissions().mode() & 0o004 != 0 {
+                return Err(anyhow!(
+                    "Source of secret '{id}' is world-readable: {}. Restrict it with chmod o-r",
+                    src.display()
+                ));
+            }
+        }
+
+        Ok(())
+    }
+
     /// Validates path-related configuration.
     ///
     /// # Arguments
@@ -132,6 +175,45 @@ impl ConfigValidator {
             ));
         }
 
+        // Rootless mode drives the user's podman storage
+        if config.rootless && config.engine == EngineKind::Docker {
+            return Err(anyhow!(
+                "Rootless mode uses podman's user storage and cannot be combined with the docker engine"
+            ));
+        }
+
+        // Docker's API is not libpod's
+        if config.podman_socket.is_some() && config.engine == EngineKind::Docker {
+            return Err(anyhow!(
+                "api = true queries podman's REST API and cannot be combined with the docker engine"
+            ));
+        }
+
+        // Validate the digest of a pinned base image
+        if let Some(digest) = base_image::pinned_digest(&config.rootfs_base) {
+            if !base_image::is_digest(digest) {
+                return Err(anyhow!(
+                    "Invalid digest in rootfs_base '{}': expected sha256:<64 hex digits>",
+                    config.rootfs_base
+                ));
+            }
+        }
+
+        // Validate per-stage settings
+        for (name, stage_config) in &config.stage_configs {
+            if let Some(build_args) = &stage_config.build_args {
+                if build_args.contains_key("BASE_IMAGE") {
+                    return Err(anyhow!(
+                        "[stages.{name}] cannot set BASE_IMAGE, it is managed by trellis"
+                    ));
+                }
+            }
+
+            if stage_config.target.as_deref() == Some("") {
+                return Err(anyhow!("[stages.{name}] target cannot be empty"));
+            }
+        }
+
         Ok(())
     }
 }
@@ -148,17 +230,45 @@ mod tests {
             builder_stages: vec!["base".to_string()],
             rootfs_stages: vec!["stage1".to_string()],
             rootfs_base: "scratch".to_string(),
+            pull_policy: None,
             extra_contexts: vec![],
             extra_mounts: vec![],
+            secrets: Default::default(),
             builder_tag: "test-builder".to_string(),
             rootfs_tag: "test-rootfs".to_string(),
+            keep_versions: 5,
             podman_build_cache: true,
             auto_clean: false,
+            incremental: false,
+            preprocess: false,
+            layering: Default::default(),
+            rechunk: false,
+            rechunk_image: "quay.io/fedora/fedora-bootc:latest".to_string(),
             pacman_cache: None,
             aur_cache: None,
             stages_dir: temp_dir.path().to_path_buf(),
+            sources: Vec::new(),
+            sources_dir: temp_dir.path().join("sources"),
             hooks_dir: None,
+            state_dir: temp_dir.path().join("state"),
+            log_dir: temp_dir.path().join("logs"),
+            log_retention: 10,
+            lock_dir: Default::default(),
+            rootless: false,
+            storage_dir: Default::default(),
+            privilege_command: None,
+            engine: Default::default(),
+            podman_socket: None,
             quiet: false,
+            profile: None,
+            config_path: Default::default(),
+            stage_configs: Default::default(),
+            vars: Default::default(),
+            security: Default::default(),
+            registry: None,
+            dry_run: false,
+            wait: false,
+            force: false,
         };
         (config, temp_dir)
     }
@@ -328,6 +438,32 @@ mod tests {
         assert!(result.is_err());
     }
 
+    #[test]
+    fn test_validate_secrets() {
+        use std::fs::{self, Permissions};
+
+        let temp_dir = TempDir::new().unwrap();
+        let src = temp_dir.path().join("token");
+        fs::write(&src, "hunter2").unwrap();
+        fs::set_permissions(&src, Permissions::from_mode(0o600)).unwrap();
+
+        let mut secrets = BTreeMap::from([("repo_token".to_string(), src.clone())]);
+        assert!(ConfigValidator::validate_secrets(&secrets).is_ok());
+
+        fs::set_permissions(&src, Permissions::from_mode(0o644)).unwrap();
+        let error = ConfigValidator::validate_secrets(&secrets).unwrap_err();
+        assert!(error.to_string().contains("world-readable"));
+        assert!(!error.to_string().contains("hunter2"));
+
+        secrets.insert("missing".to_string(), temp_dir.path().join("missing"));
+        fs::set_permissions(&src, Permissions::from_mode(0o600)).unwrap();
+        let error = ConfigValidator::validate_secrets(&secrets).unwrap_err();
+        assert!(error.to_string().contains("does not exist"));
+
+        let secrets = BTreeMap::from([("a=b".to_string(), src)]);
+        assert!(ConfigValidator::validate_secrets(&secrets).is_err());
+    }
+
     #[test]
     fn test_validate_complete_with_cross_dependency_error() {
         let (mut config, _temp_dir) = create_test_config();
@@ -337,4 +473,26 @@ mod tests {
         let result = ConfigValidator::validate_complete(&config);
         assert!(result.is_err());
     }
+
+    #[test]
+    fn test_validate_rootless_docker_engine() {
+        let (mut config, _temp_dir) = create_test_config();
+        config.rootless = true;
+        config.engine = EngineKind::Docker;
+        assert!(ConfigValidator::validate_complete(&config).is_err());
+
+        config.engine = EngineKind::Buildah;
+        assert!(ConfigValidator::validate_complete(&config).is_ok());
+    }
+
+    #[test]
+    fn test_validate_api_docker_engine() {
+        let (mut config, _temp_dir) = create_test_config();
+        config.podman_socket = Some(PathBuf::from("/run/podman/podman.sock"));
+        config.engine = EngineKind::Docker;
+        assert!(ConfigValidator::validate_complete(&config).is_err());
+
+        config.engine = EngineKind::Podman;
+        assert!(ConfigValidator::validate_complete(&config).is_ok());
+    }
 }
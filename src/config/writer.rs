@@ -0,0 +1,103 @@
+//! Updates of values in the configuration file.
+//!
+//! Edits keep the comments and formatting of the rest of the file, including any
+//! comment after the edited value.
+
+use anyhow::{anyhow, Context, Result};
+use std::{fs, path::Path};
+use toml_edit::{DocumentMut, Item, TableLike, Value};
+
+/// Sets `rootfs_base` in the configuration file at `path`.
+///
+/// The value is written to `[profiles.<profile>]` if that profile sets
+/// `rootfs_base`, and to `[build]` otherwise. Returns the name of the table written.
+///
+/// # Errors
+///
+/// Returns an error if the file does not exist, cannot be parsed or cannot be written.
+pub fn set_rootfs_base(path: &Path, profile: Option<&str>, value: &str) -> Result<String> {
+    if !path.exists() {
+        return Err(anyhow!(
+            "Configuration file {} does not exist. Set rootfs_base = \"{value}\" in [build]",
+            path.display()
+        ));
+    }
+    let content = fs::read_to_string(path)
+        .with_context(|| format!("Failed to read config file: {}", path.display()))?;
+    let mut document: DocumentMut = content
+        .parse()
+        .with_context(|| format!("Failed to parse config file: {}", path.display()))?;
+
+    let profile_table = profile.and_then(|name| {
+        document
+            .get_mut("profiles")
+            .and_then(Item::as_table_like_mut)
+            .and_then(|profiles| profiles.get_mut(name))
+            .and_then(Item::as_table_like_mut)
+            .filter(|table| table.contains_key("rootfs_base"))
+            .map(|table| (format!("profiles.{name}"), table))
+    });
+    let (name, table) = match profile_table {
+        Some(found) => found,
+        None => (
+            "build".to_string(),
+            document
+                .entry("build")
+                .or_insert(toml_edit::table())
+                .as_table_like_mut()
+                .ok_or_else(|| anyhow!("build in {} is not a table", path.display()))?,
+        ),
+    };
+    set_string(table, "rootfs_base", value);
+
+    fs::write(path, document.to_string())
+        .with_context(|| format!("Failed to write config file: {}", path.display()))?;
+    Ok(name)
+}
+
+/// Sets a string value, keeping the comments around an existing value.
+fn set_string(table: &mut dyn TableLike, key: &str, value: &str) {
+    let mut new_value = Value::from(value);
+    match table.get_mut(key) {
+        Some(item) => {
+            if let Some(existing) = item.as_value() {
+                *new_value.decor_mut() = existing.decor().clone();
+            }
+            *item = Item::Value(new_value);
+        }
+        None => {
+            table.insert(key, Item::Value(new_value));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_set_rootfs_base_keeps_comments() {
+        let temp = tempfile::TempDir::new().unwrap();
+        let path = temp.path().join("trellis.toml");
+        fs::write(
+            &path,
+            "# Trellis\n[build]\nrootfs_base = \"archlinux:latest\"  # base\nrootfs_tag = \"os\"\n\n\
+             [profiles.server]\nrootfs_base = \"alpine:latest\"\n",
+        )
+        .unwrap();
+
+        assert_eq!(
+            set_rootfs_base(&path, None, "archlinux:latest@sha256:aa").unwrap(),
+            "build"
+        );
+        assert_eq!(
+            set_rootfs_base(&path, Some("server"), "alpine:latest@sha256:bb").unwrap(),
+            "profiles.server"
+        );
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            "# Trellis\n[build]\nrootfs_base = \"archlinux:latest@sha256:aa\"  # base\n\
+             rootfs_tag = \"os\"\n\n[profiles.server]\nrootfs_base = \"alpine:latest@sha256:bb\"\n"
+        );
+    }
+}
@@ -12,7 +12,7 @@ mod trellis;
 use cli::Cli;
 use trellis::{
     common::{TrellisMessager, TrellisMessaging},
-    TrellisApp,
+    interrupt, TrellisApp,
 };
 
 /// Checks if the current process is running as root with multiple fallback methods.
@@ -62,6 +62,7 @@ fn prompt_continue_as_non_root() -> Result<bool> {
     let messager = TrellisMessager::new();
     messager.warning("Running trls as non-root user");
     messager.warning("Container operations may fail or require additional permissions");
+    messager.warning("Set rootless = \"auto\" in [environment] to use rootless podman");
     messager.prompt("Do you want to continue? [y/N]: ");
 
     let stdin = io::stdin();
@@ -78,9 +79,12 @@ fn prompt_continue_as_non_root() -> Result<bool> {
 fn main() -> Result<()> {
     let cli = Cli::parse();
     let messager = TrellisMessager::new();
+    let skip_root_check = cli.skip_root_check;
+    let writes_stdout = cli.command.writes_stdout();
+    let app = TrellisApp::new(cli)?;
 
-    // Check if running as root and prompt user if not
-    match is_running_as_root(cli.skip_root_check) {
+    // Check if running as root and prompt user if not, unless podman runs rootless
+    match is_running_as_root(skip_root_check || app.config().rootless) {
         Ok(true) => {} // Running as root, continue normally
         Ok(false) => {
             if !prompt_continue_as_non_root()? {
@@ -94,17 +98,24 @@ fn main() -> Result<()> {
         }
     }
 
-    let app = TrellisApp::new(cli)?;
+    // Ctrl-C stops the running command and unwinds through the cleanup guards
+    interrupt::install();
 
     let result = app.run();
 
     match result {
         Ok(_) => {
-            messager.msg("Successful");
+            if !writes_stdout {
+                messager.msg("Successful");
+            }
             Ok(())
         }
         Err(e) => {
             messager.error(&format!("{e}"));
+            if interrupt::interrupted() {
+                messager.warning("Interrupted, partial state was cleaned up");
+                process::exit(interrupt::exit_code());
+            }
             process::exit(1);
         }
     }
@@ -0,0 +1,148 @@
+//! Digest pinning of the rootfs base image.
+//!
+//! `rootfs_base` may be pinned as `image@sha256:<digest>`, optionally keeping the
+//! tag it follows (`image:tag@sha256:<digest>`). `trls base check` pulls the
+//! floating reference and compares its digest with the pinned one, and
+//! `trls base pin` writes the newest digest to the configuration file.
+
+use anyhow::{anyhow, Context, Result};
+use std::sync::Arc;
+
+use super::{
+    constants::containers::LOCALHOST_PREFIX, executor::CommandExecutor, libpod::ImageInspect,
+};
+use crate::config::TrellisConfig;
+
+/// Splits a reference into the floating reference and the pinned digest, if any.
+pub fn split_pinned(reference: &str) -> (&str, Option<&str>) {
+    match reference.split_once('@') {
+        Some((floating, digest)) => (floating, Some(digest)),
+        None => (reference, None),
+    }
+}
+
+/// Returns the pinned digest of a reference, if it is pinned.
+pub fn pinned_digest(reference: &str) -> Option<&str> {
+    split_pinned(reference).1
+}
+
+/// Checks whether a value is a `sha256:` digest.
+pub fn is_digest(value: &str) -> bool {
+    value.strip_prefix("sha256:").is_some_and(|hex| {
+        hex.len() == 64
+            && hex
+                .chars()
+                .all(|c| c.is_ascii_digit() || ('a'..='f').contains(&c))
+    })
+}
+
+/// State of the rootfs base image compared with the newest available image.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BaseStatus {
+    /// The pinned digest is the newest one
+    UpToDate { digest: String },
+    /// A newer image than the pinned one is available
+    Outdated { pinned: String, latest: String },
+    /// The base image follows its tag without a pinned digest
+    Unpinned { latest: String },
+}
+
+/// Resolves the newest digest of the rootfs base image.
+pub struct BaseImageChecker<'a> {
+    config: &'a TrellisConfig,
+    executor: Arc<dyn CommandExecutor>,
+}
+
+impl<'a> BaseImageChecker<'a> {
+    pub fn new(config: &'a TrellisConfig, executor: Arc<dyn CommandExecutor>) -> Self {
+        Self { config, executor }
+    }
+
+    /// Returns the reference of the base image without its pinned digest.
+    pub fn floating_reference(&self) -> &str {
+        split_pinned(&self.config.rootfs_base).0
+    }
+
+    /// Returns the base image reference pinned to `digest`.
+    pub fn pinned_reference(&self, digest: &str) -> String {
+        format!("{}@{digest}", self.floating_reference())
+    }
+
+    /// Pulls the floating reference of the base image and returns its digest.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the base image is `scratch` or a local image, cannot be
+    /// pulled, or has no digest.
+    pub fn latest_digest(&self) -> Result<String> {
+        let reference = self.floating_reference();
+        if reference == "scratch" || reference.starts_with(LOCALHOST_PREFIX) {
+            return Err(anyhow!(
+                "rootfs_base '{reference}' is not a registry image and cannot be pinned"
+            ));
+        }
+
+        let args = vec![
+            "pull".to_string(),
+            "--quiet".to_string(),
+            reference.to_string(),
+        ];
+        let output = self
+            .executor
+            .podman_command(&args)
+            .with_context(|| format!("Failed to pull {reference}"))?;
+        if !output.status.success() {
+            return Err(anyhow!(
+                "Failed to pull {reference}: {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            ));
+        }
+
+        let inspect = self
+            .executor
+            .inspect_image(reference)
+            .with_context(|| format!("Failed to inspect {reference}"))?;
+        inspect
+            .as_ref()
+            .and_then(ImageInspect::registry_digest)
+            .map(str::to_string)
+            .ok_or_else(|| anyhow!("Could not determine the digest of {reference}"))
+    }
+
+    /// Compares the pinned digest of the base image with the newest available one.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the newest digest cannot be determined.
+    pub fn check(&self) -> Result<BaseStatus> {
+        let latest = self.latest_digest()?;
+        Ok(match pinned_digest(&self.config.rootfs_base) {
+            Some(pinned) if pinned == latest => BaseStatus::UpToDate { digest: latest },
+            Some(pinned) => BaseStatus::Outdated {
+                pinned: pinned.to_string(),
+                latest,
+            },
+            None => BaseStatus::Unpinned { latest },
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pinned_references() {
+        let digest = format!("sha256:{}", "0a".repeat(32));
+        let pinned = format!("quay.io/archlinux/archlinux:latest@{digest}");
+
+        assert_eq!(
+            split_pinned(&pinned),
+            ("quay.io/archlinux/archlinux:latest", Some(digest.as_str()))
+        );
+        assert_eq!(pinned_digest("quay.io/archlinux/archlinux:latest"), None);
+        assert!(is_digest(&digest));
+        assert!(!is_digest("sha256:abc"));
+        assert!(!is_digest(&digest.to_uppercase()));
+    }
+}
@@ -0,0 +1,243 @@
+//! Per-stage build logs.
+//!
+//! Every build gets its own directory under the log directory, named by a build
+//! ID that sorts chronologically, holding one `<stage>.log` file per built stage.
+//! Only the most recent builds are kept, according to the configured retention.
+
+use anyhow::{anyhow, Context, Result};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use super::common::UtcTime;
+
+/// Log directory of a single build.
+#[derive(Debug, Clone)]
+pub struct BuildLog {
+    id: String,
+    dir: PathBuf,
+}
+
+impl BuildLog {
+    /// Creates the log directory for a new build.
+    ///
+    /// Older build directories are pruned first so that, including the new one,
+    /// at most `retention` builds are kept. A retention of 0 keeps every build.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the log directory cannot be created.
+    pub fn start(log_dir: &Path, retention: usize) -> Result<Self> {
+        if retention > 0 {
+            Self::prune(log_dir, retention - 1)?;
+        }
+
+        let id = new_build_id();
+        let dir = log_dir.join(&id);
+        fs::create_dir_all(&dir)
+            .with_context(|| format!("Failed to create log directory: {}", dir.display()))?;
+        Ok(Self { id, dir })
+    }
+
+    /// Returns the build ID.
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    /// Returns the log directory of this build.
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Returns the log file path for a stage.
+    pub fn stage_path(&self, build_stage: &str) -> PathBuf {
+        self.dir.join(format!("{build_stage}.log"))
+    }
+
+    /// Lists the IDs of all recorded builds, oldest first.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the log directory exists but cannot be read.
+    pub fn list(log_dir: &Path) -> Result<Vec<String>> {
+        if !log_dir.exists() {
+            return Ok(Vec::new());
+        }
+
+        let mut ids = Vec::new();
+        for entry in fs::read_dir(log_dir)
+            .with_context(|| format!("Failed to read log directory: {}", log_dir.display()))?
+        {
+            let entry = entry?;
+            let name = entry.file_name().to_string_lossy().to_string();
+            if entry.path().is_dir() && Self::is_build_id(&name) {
+                ids.push(name);
+            }
+        }
+        ids.sort();
+        Ok(ids)
+    }
+
+    /// Opens an existing build by ID or unique ID prefix.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if no build or more than one build matches.
+    pub fn open(log_dir: &Path, id: &str) -> Result<Self> {
+        let matches: Vec<String> = Self::list(log_dir)?
+            .into_iter()
+            .filter(|candidate| candidate.starts_with(id))
+            .collect();
+
+        match matches.as_slice() {
+            [id] => Ok(Self {
+                id: id.clone(),
+                dir: log_dir.join(id),
+            }),
+            [] => Err(anyhow!("No build logs found for build '{id}'")),
+            _ => Err(anyhow!(
+                "Build ID '{id}' is ambiguous: {}",
+                matches.join(", ")
+            )),
+        }
+    }
+
+    /// Opens the most recent build.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if no builds have been logged.
+    pub fn latest(log_dir: &Path) -> Result<Self> {
+        let id = Self::list(log_dir)?
+            .pop()
+            .ok_or_else(|| anyhow!("No build logs found in {}", log_dir.display()))?;
+        Ok(Self {
+            dir: log_dir.join(&id),
+            id,
+        })
+    }
+
+    /// Lists the stages logged for this build, in the order they were built.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the build directory cannot be read.
+    pub fn stages(&self) -> Result<Vec<String>> {
+        let mut stages = Vec::new();
+        for entry in fs::read_dir(&self.dir)
+            .with_context(|| format!("Failed to read log directory: {}", self.dir.display()))?
+        {
+            let path = entry?.path();
+            if let Some(stage) = path
+                .extension()
+                .filter(|ext| *ext == "log")
+                .and(path.file_stem())
+            {
+                let modified = fs::metadata(&path).and_then(|m| m.modified()).ok();
+                stages.push((modified, stage.to_string_lossy().to_string()));
+            }
+        }
+        stages.sort();
+        Ok(stages.into_iter().map(|(_, stage)| stage).collect())
+    }
+
+    /// Finds the log file of a stage by full name or by the stage part of `group:stage`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the build has no log for the stage.
+    pub fn find_stage(&self, name: &str) -> Result<PathBuf> {
+        let stages = self.stages()?;
+        stages
+            .iter()
+            .find(|stage| *stage == name)
+            .or_else(|| {
+                stages
+                    .iter()
+                    .find(|stage| stage.split_once(':').is_some_and(|(_, s)| s == name))
+            })
+            .map(|stage| self.stage_path(stage))
+            .ok_or_else(|| anyhow!("Build '{}' has no log for stage '{name}'", self.id))
+    }
+
+    /// Removes the oldest builds so that at most `keep` remain.
+    fn prune(log_dir: &Path, keep: usize) -> Result<()> {
+        let ids = Self::list(log_dir)?;
+        let excess = ids.len().saturating_sub(keep);
+        for id in &ids[..excess] {
+            let dir = log_dir.join(id);
+            fs::remove_dir_all(&dir)
+                .with_context(|| format!("Failed to remove old build logs: {}", dir.display()))?;
+        }
+        Ok(())
+    }
+
+    /// Returns true if `name` has the shape of a generated build ID.
+    fn is_build_id(name: &str) -> bool {
+        let parts: Vec<&str> = name.split('-').collect();
+        matches!(
+            parts.as_slice(),
+            [date, time, suffix]
+                if date.len() == 8
+                    && time.len() == 6
+                    && suffix.len() == 8
+                    && date.chars().chain(time.chars()).all(|c| c.is_ascii_digit())
+                    && suffix.chars().all(|c| c.is_ascii_hexdigit())
+        )
+    }
+}
+
+/// Generates a build ID of the form `YYYYMMDD-HHMMSS-xxxxxxxx`.
+///
+/// IDs sort chronologically; the random suffix keeps concurrent builds apart.
+pub fn new_build_id() -> String {
+    let now = UtcTime::now();
+    let suffix = uuid::Uuid::new_v4().simple().to_string();
+    format!(
+        "{:04}{:02}{:02}-{:02}{:02}{:02}-{}",
+        now.year,
+        now.month,
+        now.day,
+        now.hour,
+        now.minute,
+        now.second,
+        &suffix[..8]
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_build_log_retention_prunes_oldest() {
+        let temp_dir = TempDir::new().unwrap();
+        for id in ["20240101-000000-aaaaaaaa", "20240102-000000-bbbbbbbb"] {
+            fs::create_dir_all(temp_dir.path().join(id)).unwrap();
+        }
+        fs::create_dir_all(temp_dir.path().join("unrelated")).unwrap();
+
+        let log = BuildLog::start(temp_dir.path(), 2).unwrap();
+
+        let ids = BuildLog::list(temp_dir.path()).unwrap();
+        assert_eq!(ids, vec!["20240102-000000-bbbbbbbb", log.id()]);
+        assert!(temp_dir.path().join("unrelated").exists());
+    }
+
+    #[test]
+    fn test_build_log_finds_stage_by_prefix_and_name() {
+        let temp_dir = TempDir::new().unwrap();
+        let log = BuildLog::start(temp_dir.path(), 0).unwrap();
+        fs::write(log.stage_path("features:gpu"), "output").unwrap();
+
+        let opened = BuildLog::open(temp_dir.path(), &log.id()[..8]).unwrap();
+        assert_eq!(opened.stages().unwrap(), vec!["features:gpu"]);
+        assert_eq!(
+            opened.find_stage("gpu").unwrap(),
+            log.stage_path("features:gpu")
+        );
+        assert!(opened.find_stage("base").is_err());
+    }
+}
@@ -0,0 +1,103 @@
+//! Persistent progress tracking for multi-stage builds.
+//!
+//! After every completed stage the builder records how far it got, so a failed
+//! or interrupted build can later be resumed from the first incomplete stage.
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+/// Progress of the most recent multi-stage build of a given kind.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BuildState {
+    /// Stage list the build was started with
+    pub stages: Vec<String>,
+    /// Number of stages that completed successfully
+    pub completed: usize,
+}
+
+impl BuildState {
+    /// Returns the state file path for builds using the given temporary image name.
+    pub fn path(state_dir: &Path, tmp_name: &str) -> PathBuf {
+        state_dir.join(format!("{tmp_name}-build.json"))
+    }
+
+    /// Loads the recorded state, returning `None` if no build progress was recorded.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the state file exists but cannot be read or parsed.
+    pub fn load(state_dir: &Path, tmp_name: &str) -> Result<Option<Self>> {
+        let path = Self::path(state_dir, tmp_name);
+        if !path.exists() {
+            return Ok(None);
+        }
+
+        let content = fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read build state: {}", path.display()))?;
+        let state = serde_json::from_str(&content)
+            .with_context(|| format!("Failed to parse build state: {}", path.display()))?;
+        Ok(Some(state))
+    }
+
+    /// Writes the state to disk, creating the state directory if needed.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the state directory or file cannot be written.
+    pub fn save(&self, state_dir: &Path, tmp_name: &str) -> Result<()> {
+        fs::create_dir_all(state_dir).with_context(|| {
+            format!("Failed to create state directory: {}", state_dir.display())
+        })?;
+
+        let path = Self::path(state_dir, tmp_name);
+        let content = serde_json::to_string_pretty(self).context("Failed to serialize state")?;
+        fs::write(&path, content)
+            .with_context(|| format!("Failed to write build state: {}", path.display()))
+    }
+
+    /// Removes any recorded state.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if an existing state file cannot be removed.
+    pub fn clear(state_dir: &Path, tmp_name: &str) -> Result<()> {
+        let path = Self::path(state_dir, tmp_name);
+        if path.exists() {
+            fs::remove_file(&path)
+                .with_context(|| format!("Failed to remove build state: {}", path.display()))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_build_state_round_trip() {
+        let temp_dir = TempDir::new().unwrap();
+        let state = BuildState {
+            stages: vec!["base".to_string(), "final".to_string()],
+            completed: 1,
+        };
+
+        state.save(temp_dir.path(), "stage").unwrap();
+        let loaded = BuildState::load(temp_dir.path(), "stage").unwrap();
+        assert_eq!(loaded, Some(state));
+
+        BuildState::clear(temp_dir.path(), "stage").unwrap();
+        assert_eq!(BuildState::load(temp_dir.path(), "stage").unwrap(), None);
+    }
+
+    #[test]
+    fn test_build_state_missing_is_none() {
+        let temp_dir = TempDir::new().unwrap();
+        assert_eq!(BuildState::load(temp_dir.path(), "builder").unwrap(), None);
+    }
+}
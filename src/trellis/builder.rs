@@ -1,10 +1,34 @@
 use anyhow::{anyhow, Context, Result};
-use std::{fs, sync::Arc};
+use std::{
+    collections::BTreeMap,
+    fs,
+    io::Write,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, PoisonError},
+    time::{Duration, Instant},
+};
 
 use super::{
-    common::TrellisMessaging, discovery::ContainerfileDiscovery, executor::CommandExecutor,
+    base_image,
+    build_log::BuildLog,
+    build_state::BuildState,
+    common::TrellisMessaging,
+    constants::containers,
+    discovery::ContainerfileDiscovery,
+    disk_space::DiskSpaceChecker,
+    engine,
+    executor::CommandExecutor,
+    interrupt::{self, Cleanup},
+    libpod::ImageInspect,
+    lockfile::{BuildLock, LockedStage},
+    metadata::{BuildMetadata, MetadataContext},
+    preprocess::{Preprocessor, RenderedContainerfile},
+    rootless,
+    stage_key::{self, StageKeyInputs},
+};
+use crate::config::{
+    ConfigValidator, Layering, NetworkMode, PullPolicy, SecurityOptions, TrellisConfig,
 };
-use crate::config::TrellisConfig;
 
 /// Type of container build operation.
 #[derive(Debug, Clone, Copy)]
@@ -13,9 +37,48 @@ pub enum BuildType {
     Rootfs,
 }
 
+/// Result of building a single stage.
+#[derive(Debug, Clone)]
+pub struct StageResult {
+    /// Stage name as listed in the configuration
+    pub name: String,
+    /// Whether an existing image was reused instead of building
+    pub reused: bool,
+    /// Time spent on the stage
+    pub duration: Duration,
+}
+
+/// Result of a multi-stage build.
+#[derive(Debug, Clone, Default)]
+pub struct BuildSummary {
+    pub stages: Vec<StageResult>,
+    /// ID of the build's stage logs, if logs were written
+    pub log_id: Option<String>,
+}
+
+/// Freshness of a stage relative to existing content-keyed images.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StageState {
+    /// An image with the stage's content key exists and would be reused
+    UpToDate,
+    /// The stage's own inputs changed and it would be rebuilt
+    Changed,
+    /// An earlier stage will be rebuilt, so this stage must be rebuilt too
+    ParentChanged,
+}
+
+/// Freshness report for a single stage.
+#[derive(Debug, Clone)]
+pub struct StageStatus {
+    pub name: String,
+    pub cache_tag: Option<String>,
+    pub state: StageState,
+}
+
 /// Builder for constructing podman commands with type safety.
 pub struct PodmanCommandBuilder {
     args: Vec<String>,
+    context_dir: Option<String>,
 }
 
 impl Default for PodmanCommandBuilder {
@@ -26,25 +89,34 @@ impl Default for PodmanCommandBuilder {
 
 impl PodmanCommandBuilder {
     pub fn new() -> Self {
-        Self { args: Vec::new() }
+        Self {
+            args: Vec::new(),
+            context_dir: None,
+        }
     }
 
-    /// Creates a new build command with standard capabilities
-    pub fn new_build_command() -> Self {
+    /// Creates a new build command with the options of the security profile in effect.
+    ///
+    /// Stages use the host network namespace (`--net host`) unless configured otherwise.
+    /// This is necessary for:
+    /// - Package manager repository access (pacman, yay, apt, etc.)
+    /// - DNS resolution to reach package mirrors and registries
+    /// - Downloading base images and build dependencies
+    /// - Running build scripts that may require internet access (git clones, cargo fetch, etc.)
+    ///
+    /// Stages that need none of this can be isolated with `network = "none"`.
+    ///
+    /// Layers are squashed according to `layering`.
+    pub fn new_build_command(
+        network: NetworkMode,
+        security: &SecurityOptions,
+        layering: Layering,
+    ) -> Self {
         Self::new()
             .build_subcommand()
-            // Use host network namespace (--net host) to enable network access during builds
-            // This is necessary for:
-            // - Package manager repository access (pacman, yay, apt, etc.)
-            // - DNS resolution to reach package mirrors and registries
-            // - Downloading base images and build dependencies
-            // - Running build scripts that may require internet access (git clones, cargo fetch, etc.)
-            // Without host network, Containerfile RUN commands that need to download packages
-            // or access network resources would fail
-            .network_host()
-            .add_capability("sys_admin")
-            .add_capability("mknod")
-            .squash()
+            .network(network)
+            .security(security)
+            .layering(layering)
     }
 
     pub fn build_subcommand(self) -> Self {
@@ -59,18 +131,22 @@ impl PodmanCommandBuilder {
         self
     }
 
-    pub fn network_host(mut self) -> Self {
-        self.args.extend(["--net".to_string(), "host".to_string()]);
+    pub fn network(mut self, mode: NetworkMode) -> Self {
+        self.args
+            .extend(["--net".to_string(), mode.as_str().to_string()]);
         self
     }
 
-    pub fn add_capability(mut self, cap: &str) -> Self {
-        self.args.extend(["--cap-add".to_string(), cap.to_string()]);
+    /// Adds the capabilities, seccomp profile and SELinux label options of a security profile.
+    pub fn security(mut self, security: &SecurityOptions) -> Self {
+        self.args.extend(security.podman_args());
         self
     }
 
-    pub fn squash(mut self) -> Self {
-        self.args.push("--squash".to_string());
+    pub fn layering(mut self, layering: Layering) -> Self {
+        if let Some(flag) = layering.podman_flag() {
+            self.args.push(flag.to_string());
+        }
         self
     }
 
@@ -89,6 +165,14 @@ impl PodmanCommandBuilder {
         self
     }
 
+    /// Sets the build context directory, passed after all options.
+    ///
+    /// Without one, podman uses the directory of the Containerfile.
+    pub fn context_dir<P: AsRef<std::path::Path>>(mut self, path: P) -> Self {
+        self.context_dir = Some(path.as_ref().to_string_lossy().to_string());
+        self
+    }
+
     pub fn build_arg(mut self, key: &str, value: &str) -> Self {
         self.args
             .extend(["--build-arg".to_string(), format!("{key}={value}")]);
@@ -111,6 +195,32 @@ impl PodmanCommandBuilder {
         self
     }
 
+    /// Passes a secret file to the build as `--secret id=<id>,src=<path>`.
+    ///
+    /// Only the path is part of the command; podman reads the contents, which are
+    /// available to `RUN --mount=type=secret` without being stored in a layer.
+    pub fn secret<P: AsRef<std::path::Path>>(mut self, id: &str, src: P) -> Self {
+        self.args.extend([
+            "--secret".to_string(),
+            format!("id={id},src={}", src.as_ref().display()),
+        ]);
+        self
+    }
+
+    pub fn label(mut self, key: &str, value: &str) -> Self {
+        self.args
+            .extend(["--label".to_string(), format!("{key}={value}")]);
+        self
+    }
+
+    /// Sets when podman pulls the base image, leaving podman's default if `None`.
+    pub fn pull(mut self, policy: Option<PullPolicy>) -> Self {
+        if let Some(policy) = policy {
+            self.args.push(format!("--pull={}", policy.as_str()));
+        }
+        self
+    }
+
     pub fn build_context(mut self, context: &str) -> Self {
         self.args
             .extend(["--build-context".to_string(), context.to_string()]);
@@ -118,7 +228,8 @@ impl PodmanCommandBuilder {
     }
 
     /// Returns the collected arguments for execution via CommandExecutor.
-    pub fn build_args(self) -> Vec<String> {
+    pub fn build_args(mut self) -> Vec<String> {
+        self.args.extend(self.context_dir);
         self.args
     }
 }
@@ -128,6 +239,7 @@ pub struct ContainerBuilder<'a> {
     config: &'a TrellisConfig,
     discovery: ContainerfileDiscovery<'a>,
     executor: Arc<dyn CommandExecutor>,
+    last_summary: Mutex<Option<BuildSummary>>,
 }
 
 impl<'a> TrellisMessaging for ContainerBuilder<'a> {}
@@ -138,9 +250,16 @@ impl<'a> ContainerBuilder<'a> {
             config,
             discovery: ContainerfileDiscovery::new(config),
             executor,
+            last_summary: Mutex::new(None),
         }
     }
 
+    /// Returns the Containerfile discovery used for builds, whose caches are shared
+    /// with the other commands.
+    pub fn discovery(&self) -> &ContainerfileDiscovery<'a> {
+        &self.discovery
+    }
+
     /// Determines the base image for a given stage in the build process.
     /// This method is primarily for testing the base image selection logic.
     pub fn determine_base_image(
@@ -160,37 +279,118 @@ impl<'a> ContainerBuilder<'a> {
     }
 
     /// Builds a multi-stage container with improved error handling and resource management.
+    ///
+    /// When incremental builds are enabled, each stage image is additionally tagged
+    /// with a content key and stages whose key matches an existing image are reused.
     pub fn build_multistage_container(
         &self,
         tmp_name: &str,
         final_tag: &str,
         build_stages: &[String],
         build_type: BuildType,
+    ) -> Result<BuildSummary> {
+        self.build_multistage_container_from(tmp_name, final_tag, build_stages, build_type, 0)
+    }
+
+    /// Builds a multi-stage container starting at the stage with index `start_index`.
+    ///
+    /// Earlier stages are not rebuilt; the intermediate image left behind by the
+    /// stage before `start_index` is used as `BASE_IMAGE` and must still exist.
+    /// Progress is recorded after every stage so a failed build can be resumed.
+    pub fn build_multistage_container_from(
+        &self,
+        tmp_name: &str,
+        final_tag: &str,
+        build_stages: &[String],
+        build_type: BuildType,
+        start_index: usize,
+    ) -> Result<BuildSummary> {
+        let mut summary = BuildSummary::default();
+        let result = self.build_stages(
+            tmp_name,
+            final_tag,
+            build_stages,
+            build_type,
+            start_index,
+            &mut summary,
+        );
+        *self
+            .last_summary
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner) = Some(summary.clone());
+        result.map(|()| summary)
+    }
+
+    /// Takes the summary of the most recent build, including builds that failed
+    /// part way through. Returns `None` if nothing was built since the last call.
+    pub fn take_last_summary(&self) -> Option<BuildSummary> {
+        self.last_summary
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .take()
+    }
+
+    /// Builds the stages from `start_index` on, recording each finished stage in `summary`.
+    fn build_stages(
+        &self,
+        tmp_name: &str,
+        final_tag: &str,
+        build_stages: &[String],
+        build_type: BuildType,
+        start_index: usize,
+        summary: &mut BuildSummary,
     ) -> Result<()> {
         // Validate all containerfiles exist upfront
         self.discovery.validate_stages(build_stages)?;
+        ConfigValidator::validate_secrets(&self.config.secrets)?;
+
+        if start_index > 0 && start_index >= build_stages.len() {
+            return Err(anyhow!(
+                "Cannot start build at stage {}: only {} stages configured",
+                start_index + 1,
+                build_stages.len()
+            ));
+        }
+        DiskSpaceChecker::new(self.config, self.executor.clone())
+            .check_build(final_tag, build_type)?;
+        self.check_engine_support(&build_stages[start_index..], build_type)?;
 
         let mut last_stage = String::new();
+        let build_log = self.start_build_log();
+        summary.log_id = build_log.as_ref().map(|log| log.id().to_string());
+        let security = engine::build_security(self.config);
+        self.msg(&format!(
+            "Security profile '{}' for builds: {}",
+            self.config.security.name,
+            security.describe()
+        ));
 
-        for (i, build_stage) in build_stages.iter().enumerate() {
+        if start_index > 0 {
+            let (group, stage) =
+                ContainerfileDiscovery::parse_stage_name(&build_stages[start_index - 1]);
+            let previous = Self::stage_image_name(tmp_name, &group, &stage);
+
+            if !self.image_exists(&format!("{}{previous}", containers::LOCALHOST_PREFIX))? {
+                return Err(anyhow!(
+                    "Intermediate image '{previous}' from stage '{}' not found. Run a full build first.",
+                    build_stages[start_index - 1]
+                ));
+            }
+            last_stage = previous;
+        }
+
+        for (i, build_stage) in build_stages.iter().enumerate().skip(start_index) {
+            interrupt::check()?;
+            let stage_started = Instant::now();
             let (group, stage) = ContainerfileDiscovery::parse_stage_name(build_stage);
 
             let tag = if i == build_stages.len() - 1 {
                 final_tag.to_string()
-            } else if stage != group {
-                format!("trellis-{tmp_name}-{group}-{stage}")
             } else {
-                format!("trellis-{tmp_name}-{stage}")
+                Self::stage_image_name(tmp_name, &group, &stage)
             };
 
-            // For "group:stage" syntax, look for Containerfile.group containing stage target
-            let containerfile_path = if stage != group {
-                // group:stage syntax - look for file named after group
-                self.discovery.find_containerfile(&group)?
-            } else {
-                // simple stage name - look for file named after stage
-                self.discovery.find_containerfile(&stage)?
-            };
+            let containerfile_path = self.find_stage_containerfile(&group, &stage)?;
 
             self.msg(&format!(
                 "Building stage {}/{}: {} -> {}",
@@ -203,21 +403,110 @@ impl<'a> ContainerBuilder<'a> {
             // For the first stage, use rootfs_base as BASE_IMAGE; for subsequent stages, use the previous stage
             let base_image = self.determine_base_image(i, build_type, &last_stage);
 
-            let mut builder = PodmanCommandBuilder::new_build_command()
-                .containerfile(&containerfile_path)
-                .build_arg("BASE_IMAGE", &base_image)
-                .target(&stage)
-                .tag(&tag)
-                .no_cache(!self.config.podman_build_cache)
-                .layers(self.config.podman_build_cache);
+            let cache_tag = if self.config.incremental {
+                let key = self.compute_stage_key(
+                    &containerfile_path,
+                    build_stage,
+                    &self.resolve_image_id(&base_image),
+                    build_type,
+                )?;
+                let cache_tag = Self::stage_cache_tag(tmp_name, &group, &stage, &key);
+
+                // Dry runs plan every stage, since a cached image cannot be confirmed
+                if !self.config.dry_run && self.image_exists(&cache_tag)? {
+                    self.msg(&format!("Stage unchanged, reusing {cache_tag}"));
+                    self.tag_image(&cache_tag, &tag)?;
+                    summary.stages.push(StageResult {
+                        name: build_stage.clone(),
+                        reused: true,
+                        duration: stage_started.elapsed(),
+                    });
+                    last_stage = tag;
+                    self.record_progress(tmp_name, build_stages, i + 1);
+                    continue;
+                }
+                Some(cache_tag)
+            } else {
+                None
+            };
+
+            let stage_config = self.config.stage_config(build_stage);
+            let network = stage_config
+                .and_then(|c| c.network)
+                .or(security.network)
+                .unwrap_or_default();
+            let no_cache = stage_config
+                .and_then(|c| c.no_cache)
+                .unwrap_or(!self.config.podman_build_cache);
+
+            let rendered = self.render_containerfile(&containerfile_path, build_stage)?;
+            let build_file = rendered
+                .as_ref()
+                .map_or(containerfile_path.as_path(), RenderedContainerfile::path);
+            // A rendered Containerfile lives in a temporary directory, so the stage
+            // directory is passed explicitly to keep it as the build context
+            let context_dir = Self::stage_context_dir(&containerfile_path);
+
+            let mut builder = PodmanCommandBuilder::new_build_command(
+                network,
+                &security,
+                Self::stage_layering(self.config, build_type),
+            )
+            .containerfile(build_file)
+            .context_dir(context_dir)
+            .build_arg("BASE_IMAGE", &base_image)
+            .target(&self.stage_target(build_stage))
+            .tag(&tag)
+            .no_cache(no_cache)
+            .layers(!no_cache);
+
+            // Stage images only exist locally, so the pull policy applies to the base image
+            if !base_image.starts_with(containers::LOCALHOST_PREFIX) {
+                builder = builder.pull(self.config.pull_policy);
+            }
+
+            if let Some(cache_tag) = &cache_tag {
+                builder = builder.tag(cache_tag);
+            }
 
             // Add rootfs-specific configuration
             if matches!(build_type, BuildType::Rootfs) {
                 builder = self.add_rootfs_config(builder)?;
             }
 
+            // Add settings from the stage's [stages.<name>] table
+            builder = self.add_stage_config(builder, build_stage);
+
+            for (id, src) in &self.config.secrets {
+                builder = builder.secret(id, src);
+            }
+
+            // An interrupted stage leaves no tags behind. The final tag keeps the
+            // previous image, which podman only replaces once the stage completes.
+            let partial_tags: Vec<&str> = (tag != final_tag)
+                .then_some(tag.as_str())
+                .into_iter()
+                .chain(cache_tag.as_deref())
+                .collect();
+            let stage_cleanup = Cleanup::on_interrupt(|| self.remove_partial_tags(&partial_tags));
+
             // Execute build using injected executor
             let build_args = builder.build_args();
+            let log_path = build_log.as_ref().map(|log| log.stage_path(build_stage));
+            if let Some(log_path) = &log_path {
+                let options = SecurityOptions {
+                    network: Some(network),
+                    ..security.clone()
+                };
+                let header = format!(
+                    "# trellis: security profile '{}': {}\n",
+                    self.config.security.name,
+                    options.describe()
+                );
+                if let Err(e) = fs::write(log_path, header) {
+                    self.warning(&format!("Failed to write stage log: {e}"));
+                }
+            }
             let success = if self.config.quiet {
                 // Use regular execution to capture output when quiet
                 let output = self
@@ -225,6 +514,18 @@ impl<'a> ContainerBuilder<'a> {
                     .podman_build(&build_args)
                     .with_context(|| format!("Failed to build stage: {build_stage}"))?;
 
+                if let Some(log_path) = &log_path {
+                    let content = [output.stdout.as_slice(), output.stderr.as_slice()].concat();
+                    let written = fs::OpenOptions::new()
+                        .append(true)
+                        .create(true)
+                        .open(log_path)
+                        .and_then(|mut file| file.write_all(&content));
+                    if let Err(e) = written {
+                        self.warning(&format!("Failed to write stage log: {e}"));
+                    }
+                }
+
                 if !output.status.success() {
                     let stderr = String::from_utf8_lossy(&output.stderr);
                     return Err(anyhow!("Podman build failed with exit code: {:?}. Error: {}. Check podman logs for details. Ensure sufficient disk space and proper permissions.", output.status.code(), stderr));
@@ -232,10 +533,11 @@ impl<'a> ContainerBuilder<'a> {
                 output.status.success()
             } else {
                 // Use streaming execution to show live output
-                let status = self
-                    .executor
-                    .podman_build_streaming(&build_args)
-                    .with_context(|| format!("Failed to build stage: {build_stage}"))?;
+                let status = match &log_path {
+                    Some(log_path) => self.executor.podman_build_logged(&build_args, log_path),
+                    None => self.executor.podman_build_streaming(&build_args),
+                }
+                .with_context(|| format!("Failed to build stage: {build_stage}"))?;
 
                 if !status.success() {
                     return Err(anyhow!("Podman build failed with exit code: {:?}. Check podman logs for details. Ensure sufficient disk space and proper permissions.", status.code()));
@@ -247,7 +549,689 @@ impl<'a> ContainerBuilder<'a> {
                 return Err(anyhow!("Build process failed unexpectedly"));
             }
 
+            stage_cleanup.disarm();
+            summary.stages.push(StageResult {
+                name: build_stage.clone(),
+                reused: false,
+                duration: stage_started.elapsed(),
+            });
             last_stage = tag;
+            self.record_progress(tmp_name, build_stages, i + 1);
+        }
+
+        if !self.config.dry_run {
+            if let Err(e) = BuildState::clear(&self.config.state_dir, tmp_name) {
+                self.warning(&format!("Failed to clear build state: {e}"));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Resolves the stage index a build should start at.
+    ///
+    /// `from` selects a stage by name, either the full `group:stage` form or just
+    /// the stage part. `resume` starts after the last stage that completed in the
+    /// previous build, which must have used the same stage list.
+    pub fn resolve_start_stage(
+        &self,
+        tmp_name: &str,
+        build_stages: &[String],
+        from: Option<&str>,
+        resume: bool,
+    ) -> Result<usize> {
+        if let Some(from) = from {
+            return build_stages
+                .iter()
+                .position(|s| s == from || ContainerfileDiscovery::parse_stage_name(s).1 == from)
+                .ok_or_else(|| {
+                    anyhow!(
+                        "Stage '{from}' is not part of the build. Configured stages: {}",
+                        build_stages.join(", ")
+                    )
+                });
+        }
+
+        if !resume {
+            return Ok(0);
+        }
+
+        let state = BuildState::load(&self.config.state_dir, tmp_name)?
+            .ok_or_else(|| anyhow!("No interrupted build to resume"))?;
+
+        if state.stages != build_stages {
+            return Err(anyhow!(
+                "Stage list changed since the interrupted build ({}). Use --from to choose a stage.",
+                state.stages.join(", ")
+            ));
+        }
+        if state.completed >= build_stages.len() {
+            return Err(anyhow!("No interrupted build to resume"));
+        }
+
+        Ok(state.completed)
+    }
+
+    /// Reports which stages would be rebuilt by an incremental build, without building.
+    ///
+    /// A stage is stale when no image tagged with its content key exists. Once a stage
+    /// is stale, every following stage is stale too, since its parent image will change.
+    pub fn check_stages(
+        &self,
+        tmp_name: &str,
+        build_stages: &[String],
+        build_type: BuildType,
+    ) -> Result<Vec<StageStatus>> {
+        self.discovery.validate_stages(build_stages)?;
+
+        let mut statuses = Vec::with_capacity(build_stages.len());
+        let mut parent_cache_tag: Option<String> = None;
+
+        for (i, build_stage) in build_stages.iter().enumerate() {
+            if i > 0 && parent_cache_tag.is_none() {
+                statuses.push(StageStatus {
+                    name: build_stage.clone(),
+                    cache_tag: None,
+                    state: StageState::ParentChanged,
+                });
+                continue;
+            }
+
+            let (group, stage) = ContainerfileDiscovery::parse_stage_name(build_stage);
+            let containerfile_path = self.find_stage_containerfile(&group, &stage)?;
+
+            let parent_digest = match &parent_cache_tag {
+                Some(parent) => self.resolve_image_id(parent),
+                None => self.resolve_image_id(&self.determine_base_image(0, build_type, "")),
+            };
+
+            let key = self.compute_stage_key(
+                &containerfile_path,
+                build_stage,
+                &parent_digest,
+                build_type,
+            )?;
+            let cache_tag = Self::stage_cache_tag(tmp_name, &group, &stage, &key);
+
+            if self.image_exists(&cache_tag)? {
+                parent_cache_tag = Some(cache_tag.clone());
+                statuses.push(StageStatus {
+                    name: build_stage.clone(),
+                    cache_tag: Some(cache_tag),
+                    state: StageState::UpToDate,
+                });
+            } else {
+                parent_cache_tag = None;
+                statuses.push(StageStatus {
+                    name: build_stage.clone(),
+                    cache_tag: Some(cache_tag),
+                    state: StageState::Changed,
+                });
+            }
+        }
+
+        Ok(statuses)
+    }
+
+    /// Returns the intermediate image name for a stage, e.g. `trellis-stage-base`.
+    pub fn stage_image_name(tmp_name: &str, group: &str, stage: &str) -> String {
+        if stage != group {
+            format!("trellis-{tmp_name}-{group}-{stage}")
+        } else {
+            format!("trellis-{tmp_name}-{stage}")
+        }
+    }
+
+    /// Creates the log directory for this build, warning and disabling logging on failure.
+    fn start_build_log(&self) -> Option<BuildLog> {
+        if self.config.dry_run {
+            return None;
+        }
+
+        match BuildLog::start(&self.config.log_dir, self.config.log_retention) {
+            Ok(log) => {
+                self.msg(&format!(
+                    "Writing logs for build {} to {}",
+                    log.id(),
+                    log.dir().display()
+                ));
+                Some(log)
+            }
+            Err(e) => {
+                self.warning(&format!("Stage logs disabled: {e:#}"));
+                None
+            }
+        }
+    }
+
+    /// Records that the first `completed` stages finished, warning on failure.
+    fn record_progress(&self, tmp_name: &str, build_stages: &[String], completed: usize) {
+        if self.config.dry_run {
+            return;
+        }
+        let state = BuildState {
+            stages: build_stages.to_vec(),
+            completed,
+        };
+        if let Err(e) = state.save(&self.config.state_dir, tmp_name) {
+            self.warning(&format!("Failed to record build progress: {e}"));
+        }
+    }
+
+    /// Returns the content-keyed image reference for a stage.
+    fn stage_cache_tag(tmp_name: &str, group: &str, stage: &str, key: &str) -> String {
+        format!(
+            "{}{}:{}",
+            containers::LOCALHOST_PREFIX,
+            Self::stage_image_name(tmp_name, group, stage),
+            stage_key::short_key(key)
+        )
+    }
+
+    /// Finds the containerfile for a parsed stage name.
+    fn find_stage_containerfile(&self, group: &str, stage: &str) -> Result<PathBuf> {
+        // For "group:stage" syntax, look for Containerfile.group containing stage target
+        if stage != group {
+            // group:stage syntax - look for file named after group
+            self.discovery.find_containerfile(group)
+        } else {
+            // simple stage name - look for file named after stage
+            self.discovery.find_containerfile(stage)
+        }
+    }
+
+    /// Computes the content key of a stage from its parent image and build inputs.
+    fn compute_stage_key(
+        &self,
+        containerfile_path: &Path,
+        build_stage: &str,
+        parent_digest: &str,
+        build_type: BuildType,
+    ) -> Result<String> {
+        let containerfile_sha256 = self.containerfile_sha256(containerfile_path, build_stage)?;
+        let build_args = self.stage_build_args(build_stage, build_type);
+        let contexts = self.stage_contexts(build_stage, build_type);
+        let mounts = self.stage_mounts(build_stage, build_type);
+
+        Ok(stage_key::compute_stage_key(&StageKeyInputs {
+            parent_digest,
+            containerfile_sha256: &containerfile_sha256,
+            target: &self.stage_target(build_stage),
+            context_dir: Self::stage_context_dir(containerfile_path),
+            build_args: &build_args,
+            contexts: &contexts,
+            mounts: &mounts,
+            layering: Self::stage_layering(self.config, build_type).as_str(),
+        }))
+    }
+
+    /// Returns the build context of a stage: the directory holding its Containerfile.
+    fn stage_context_dir(containerfile_path: &Path) -> &Path {
+        containerfile_path.parent().unwrap_or(Path::new("."))
+    }
+
+    /// Hashes the Containerfile of a stage as it is passed to podman.
+    ///
+    /// With preprocessing enabled this is the rendered file, so edits to included
+    /// fragments or variables are detected like edits to the Containerfile itself.
+    fn containerfile_sha256(&self, containerfile_path: &Path, build_stage: &str) -> Result<String> {
+        if self.config.preprocess {
+            let rendered =
+                Preprocessor::new(self.config, build_stage).render_file(containerfile_path)?;
+            Ok(stage_key::sha256_str(&rendered))
+        } else {
+            stage_key::sha256_file(containerfile_path)
+        }
+    }
+
+    /// Renders a stage's Containerfile to a temporary file if preprocessing is enabled.
+    ///
+    /// Dry runs render the Containerfile to report errors, but do not write it.
+    fn render_containerfile(
+        &self,
+        containerfile_path: &Path,
+        build_stage: &str,
+    ) -> Result<Option<RenderedContainerfile>> {
+        if !self.config.preprocess {
+            return Ok(None);
+        }
+
+        let rendered = Preprocessor::new(self.config, build_stage)
+            .render_file(containerfile_path)
+            .with_context(|| {
+                format!("Failed to preprocess Containerfile for stage {build_stage}")
+            })?;
+        if self.config.dry_run {
+            return Ok(None);
+        }
+        RenderedContainerfile::write(build_stage, &rendered).map(Some)
+    }
+
+    /// Returns the build target of a stage, which defaults to the stage name.
+    fn stage_target(&self, build_stage: &str) -> String {
+        ContainerfileDiscovery::stage_target(self.config, build_stage)
+    }
+
+    /// Returns the build arguments passed to a stage, excluding `BASE_IMAGE`.
+    fn stage_build_args(
+        &self,
+        build_stage: &str,
+        build_type: BuildType,
+    ) -> BTreeMap<String, String> {
+        let mut build_args = BTreeMap::new();
+        if matches!(build_type, BuildType::Rootfs) {
+            if let Some(hooks_dir) = &self.config.hooks_dir {
+                build_args.insert("HOOKS_DIR".to_string(), hooks_dir.display().to_string());
+            }
+        }
+        if let Some(stage_args) = self
+            .config
+            .stage_config(build_stage)
+            .and_then(|c| c.build_args.as_ref())
+        {
+            build_args.extend(stage_args.clone());
+        }
+        build_args
+    }
+
+    /// Returns the layering mode of a stage. Builder images are always squashed.
+    ///
+    /// Stages are built layered if the container engine cannot produce the mode.
+    fn stage_layering(config: &TrellisConfig, build_type: BuildType) -> Layering {
+        let layering = match build_type {
+            BuildType::Rootfs => config.layering,
+            BuildType::Builder => Layering::Squash,
+        };
+        if engine::capabilities(config).supports_layering(layering) {
+            layering
+        } else {
+            Layering::Layered
+        }
+    }
+
+    /// Checks a build against the features of the container engine.
+    ///
+    /// Features the stages depend on, such as secrets, build contexts and mounts,
+    /// fail the build, as do `pull_policy = "never"` and rechunking with an engine
+    /// outside containers-storage. Package caches, added capabilities and SELinux
+    /// labels are left out and layering falls back to one layer per instruction,
+    /// with a warning.
+    fn check_engine_support(&self, build_stages: &[String], build_type: BuildType) -> Result<()> {
+        let capabilities = engine::capabilities(self.config);
+        let engine = self.config.engine;
+        let unsupported = |feature: String| {
+            anyhow!(
+                "The {engine} engine does not support {feature}. \
+                 Remove them or select another kind in [engine]"
+            )
+        };
+        let rootfs = matches!(build_type, BuildType::Rootfs);
+
+        if !capabilities.secrets && !self.config.secrets.is_empty() {
+            return Err(unsupported("build secrets".to_string()));
+        }
+
+        if !capabilities.build_contexts {
+            if let Some(stage) = build_stages
+                .iter()
+                .find(|stage| !self.stage_contexts(stage, build_type).is_empty())
+            {
+                return Err(unsupported(format!(
+                    "build contexts, used by stage '{stage}'"
+                )));
+            }
+        }
+
+        if !capabilities.build_volumes {
+            if rootfs && (self.config.hooks_dir.is_some() || !self.config.extra_mounts.is_empty()) {
+                return Err(unsupported(
+                    "volumes in builds, used for hooks_dir and extra_mounts".to_string(),
+                ));
+            }
+            if let Some(stage) = build_stages.iter().find(|stage| {
+                self.config
+                    .stage_config(stage)
+                    .and_then(|c| c.mounts.as_ref())
+                    .is_some_and(|mounts| !mounts.is_empty())
+            }) {
+                return Err(unsupported(format!(
+                    "volumes in builds, used by the mounts of stage '{stage}'"
+                )));
+            }
+            if rootfs && (self.config.pacman_cache.is_some() || self.config.aur_cache.is_some()) {
+                self.warning(&format!(
+                    "The {engine} engine cannot mount package caches into builds, building without them"
+                ));
+            }
+        }
+
+        let security = &self.config.security.build;
+        if !capabilities.security_options {
+            if let Some(seccomp) = security.seccomp.as_deref().filter(|s| *s != "unconfined") {
+                return Err(unsupported(format!("seccomp profiles, set to {seccomp}")));
+            }
+            if security.privileged
+                || security.seccomp.is_some()
+                || !security.capabilities.is_empty()
+                || !security.labels.is_empty()
+            {
+                self.warning(&format!(
+                    "The {engine} engine cannot apply the build options of security profile '{}', \
+                     building with its defaults",
+                    self.config.security.name
+                ));
+            }
+        }
+
+        if !capabilities.pull_never && self.config.pull_policy == Some(PullPolicy::Never) {
+            return Err(unsupported("pull_policy = \"never\"".to_string()));
+        }
+
+        if rootfs && self.config.rechunk {
+            engine::require_containers_storage(self.config, "rechunk = true")?;
+        }
+
+        if rootfs && !capabilities.supports_layering(self.config.layering) {
+            self.warning(&format!(
+                "The {engine} engine cannot build with layering = \"{}\", keeping one layer per instruction",
+                self.config.layering.as_str()
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Returns the extra build contexts passed to a stage.
+    fn stage_contexts(&self, build_stage: &str, build_type: BuildType) -> Vec<String> {
+        let mut contexts = match build_type {
+            BuildType::Rootfs => self.config.extra_contexts.clone(),
+            BuildType::Builder => Vec::new(),
+        };
+        if let Some(stage_contexts) = self
+            .config
+            .stage_config(build_stage)
+            .and_then(|c| c.contexts.as_ref())
+        {
+            contexts.extend(stage_contexts.iter().cloned());
+        }
+        contexts
+    }
+
+    /// Returns the host paths mounted into a stage's build, without package caches.
+    fn stage_mounts(&self, build_stage: &str, build_type: BuildType) -> Vec<String> {
+        let mut mounts = Vec::new();
+        if matches!(build_type, BuildType::Rootfs) {
+            let paths = self
+                .config
+                .hooks_dir
+                .iter()
+                .chain(&self.config.extra_mounts);
+            mounts.extend(paths.map(|path| path.display().to_string()));
+        }
+        if let Some(stage_mounts) = self
+            .config
+            .stage_config(build_stage)
+            .and_then(|c| c.mounts.as_ref())
+        {
+            mounts.extend(stage_mounts.iter().map(|mount| {
+                mount
+                    .split_once(':')
+                    .map_or(mount.as_str(), |(source, _)| source)
+                    .to_string()
+            }));
+        }
+        mounts
+    }
+
+    /// Resolves the current inputs of a build into a lock, without image IDs.
+    ///
+    /// The base digest is left empty while the base image is not in local storage.
+    pub fn resolve_lock(
+        &self,
+        build_stages: &[String],
+        build_type: BuildType,
+    ) -> Result<BuildLock> {
+        self.discovery.validate_stages(build_stages)?;
+
+        let rootfs_base = self.determine_base_image(0, build_type, "");
+        let mut stages = Vec::with_capacity(build_stages.len());
+
+        for build_stage in build_stages {
+            let (group, stage) = ContainerfileDiscovery::parse_stage_name(build_stage);
+            let containerfile_path = self.find_stage_containerfile(&group, &stage)?;
+
+            stages.push(LockedStage {
+                name: build_stage.clone(),
+                containerfile: containerfile_path
+                    .strip_prefix(&self.config.stages_dir)
+                    .unwrap_or(&containerfile_path)
+                    .to_path_buf(),
+                sha256: self.containerfile_sha256(&containerfile_path, build_stage)?,
+                target: self.stage_target(build_stage),
+                image_id: String::new(),
+                build_args: self.stage_build_args(build_stage, build_type),
+            });
+        }
+
+        Ok(BuildLock {
+            base_digest: self.resolve_base_digest(&rootfs_base).unwrap_or_default(),
+            rootfs_base,
+            stages,
+        })
+    }
+
+    /// Splits a built image into content-based layers, replacing it in place.
+    ///
+    /// Runs `rpm-ostree compose build-chunked-oci` from the configured rechunk image
+    /// against the host's container storage, using the install options of the
+    /// security profile since it needs the same access as `bootc install`.
+    pub fn rechunk_image(&self, image_tag: &str) -> Result<()> {
+        let image = format!("{}{image_tag}", containers::LOCALHOST_PREFIX);
+        self.msg(&format!(
+            "Rechunking {image} with {}",
+            self.config.rechunk_image
+        ));
+
+        let security = &self.config.security.install;
+        let mut run_args = vec!["--rm".to_string()];
+        if let Some(network) = security.network {
+            run_args.extend(["--net".to_string(), network.as_str().to_string()]);
+        }
+        run_args.extend(security.podman_args());
+        run_args.extend([
+            "-v".to_string(),
+            rootless::storage_volume(self.config),
+            self.config.rechunk_image.clone(),
+            "rpm-ostree".to_string(),
+            "compose".to_string(),
+            "build-chunked-oci".to_string(),
+            "--bootc".to_string(),
+            "--format-version=1".to_string(),
+            format!("--from={image}"),
+            format!("--output=containers-storage:{image}"),
+        ]);
+
+        let status = if self.config.quiet {
+            let output = self
+                .executor
+                .podman_run(&run_args)
+                .context("Failed to run rechunk container")?;
+            if !output.status.success() {
+                let stderr = String::from_utf8_lossy(&output.stderr);
+                return Err(anyhow!("Rechunking {image} failed: {stderr}"));
+            }
+            output.status
+        } else {
+            self.executor
+                .podman_run_streaming(&run_args)
+                .context("Failed to run rechunk container")?
+        };
+
+        if !status.success() {
+            return Err(anyhow!(
+                "Rechunking {image} failed with exit code: {:?}",
+                status.code()
+            ));
+        }
+        Ok(())
+    }
+
+    /// Adds labels, `build.json`, the lockfile and os-release entries describing the
+    /// build to an image.
+    ///
+    /// Runs a build on top of the image that needs no network and replaces its tag,
+    /// keeping the layering mode of the rootfs stages. Dry runs only print the labels.
+    pub fn embed_metadata(
+        &self,
+        image_tag: &str,
+        metadata: &BuildMetadata,
+        lock: &BuildLock,
+    ) -> Result<()> {
+        let image = format!("{}{image_tag}", containers::LOCALHOST_PREFIX);
+        self.msg(&format!(
+            "Embedding build metadata {} in {image}",
+            metadata.build_id
+        ));
+
+        // The build context is written to disk, so a dry run only reports the labels
+        if self.config.dry_run {
+            for (key, value) in metadata.labels() {
+                self.msg(&format!("Would label {image} with {key}={value}"));
+            }
+            return Ok(());
+        }
+
+        let context = MetadataContext::write(metadata, lock, &image)?;
+        let mut builder = PodmanCommandBuilder::new_build_command(
+            NetworkMode::None,
+            &engine::build_security(self.config),
+            Self::stage_layering(self.config, BuildType::Rootfs),
+        )
+        .containerfile(context.containerfile())
+        .tag(image_tag);
+        for (key, value) in metadata.labels() {
+            builder = builder.label(&key, &value);
+        }
+        let build_args = builder.build_args();
+
+        let status = if self.config.quiet {
+            let output = self
+                .executor
+                .podman_build(&build_args)
+                .context("Failed to embed build metadata")?;
+            if !output.status.success() {
+                let stderr = String::from_utf8_lossy(&output.stderr);
+                return Err(anyhow!(
+                    "Embedding build metadata in {image} failed: {stderr}"
+                ));
+            }
+            output.status
+        } else {
+            self.executor
+                .podman_build_streaming(&build_args)
+                .context("Failed to embed build metadata")?
+        };
+
+        if !status.success() {
+            return Err(anyhow!(
+                "Embedding build metadata in {image} failed with exit code: {:?}",
+                status.code()
+            ));
+        }
+        Ok(())
+    }
+
+    /// Fills in the IDs of the images produced by a completed build.
+    pub fn record_image_ids(&self, lock: &mut BuildLock, tmp_name: &str, final_tag: &str) {
+        let last = lock.stages.len().saturating_sub(1);
+        for (i, locked) in lock.stages.iter_mut().enumerate() {
+            let tag = if i == last {
+                final_tag.to_string()
+            } else {
+                let (group, stage) = ContainerfileDiscovery::parse_stage_name(&locked.name);
+                Self::stage_image_name(tmp_name, &group, &stage)
+            };
+            locked.image_id = self.resolve_image_id(&tag);
+        }
+    }
+
+    /// Resolves the digest recorded for the base image of rootfs builds.
+    ///
+    /// A pinned base resolves to its pinned digest and `scratch` to itself. Other
+    /// bases resolve to their repository digest, or to the image ID for local
+    /// images without one.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the base image is not in local storage.
+    pub fn resolve_base_digest(&self, rootfs_base: &str) -> Result<String> {
+        if let Some(digest) = base_image::pinned_digest(rootfs_base) {
+            return Ok(digest.to_string());
+        }
+        if rootfs_base == "scratch" {
+            return Ok(rootfs_base.to_string());
+        }
+
+        self.inspect_image(rootfs_base)
+            .and_then(|inspect| match inspect.registry_digest() {
+                Some(digest) => Some(digest.to_string()),
+                None => (!inspect.id.is_empty()).then_some(inspect.id),
+            })
+            .ok_or_else(|| {
+                anyhow!("Could not resolve the digest of {rootfs_base}: it is not in local storage")
+            })
+    }
+
+    /// Inspects a local image, or returns `None` if the image is not available.
+    fn inspect_image(&self, image: &str) -> Option<ImageInspect> {
+        self.executor.inspect_image(image).ok().flatten()
+    }
+
+    /// Resolves an image reference to its image ID.
+    ///
+    /// Falls back to the reference itself when the image is not available locally
+    /// (for example `scratch` or a base image that has not been pulled yet).
+    pub fn resolve_image_id(&self, image: &str) -> String {
+        self.inspect_image(image)
+            .map(|inspect| inspect.id)
+            .filter(|id| !id.is_empty())
+            .unwrap_or_else(|| image.to_string())
+    }
+
+    /// Checks whether an image exists in local container storage.
+    fn image_exists(&self, image: &str) -> Result<bool> {
+        self.executor
+            .image_exists(image)
+            .with_context(|| format!("Failed to check if image exists: {image}"))
+    }
+
+    /// Removes the tags of an interrupted stage build.
+    fn remove_partial_tags(&self, tags: &[&str]) {
+        if self.config.dry_run || tags.is_empty() {
+            return;
+        }
+        self.msg(&format!(
+            "Removing tags of the interrupted stage: {}",
+            tags.join(" ")
+        ));
+        let args: Vec<String> = std::iter::once("--ignore")
+            .chain(tags.iter().copied())
+            .map(str::to_string)
+            .collect();
+        let _ = self.executor.podman_rmi(&args);
+    }
+
+    /// Adds an additional tag to an existing image.
+    fn tag_image(&self, source: &str, tag: &str) -> Result<()> {
+        let args = vec!["tag".to_string(), source.to_string(), tag.to_string()];
+        let output = self
+            .executor
+            .podman_command(&args)
+            .with_context(|| format!("Failed to tag {source} as {tag}"))?;
+
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            return Err(anyhow!("Failed to tag {source} as {tag}: {stderr}"));
         }
 
         Ok(())
@@ -260,19 +1244,21 @@ impl<'a> ContainerBuilder<'a> {
             builder = builder.build_context(context);
         }
 
-        // Add cache directories
-        builder = self.add_cache_mount(
-            builder,
-            &self.config.pacman_cache,
-            "pacman",
-            "/var/cache/pacman/pkg",
-        )?;
-        builder = self.add_cache_mount(
-            builder,
-            &self.config.aur_cache,
-            "AUR",
-            "/var/cache/trellis/aur",
-        )?;
+        // Add cache directories, if the engine can mount them
+        if engine::capabilities(self.config).build_volumes {
+            builder = self.add_cache_mount(
+                builder,
+                &self.config.pacman_cache,
+                "pacman",
+                "/var/cache/pacman/pkg",
+            )?;
+            builder = self.add_cache_mount(
+                builder,
+                &self.config.aur_cache,
+                "AUR",
+                "/var/cache/trellis/aur",
+            )?;
+        }
 
         // Add hooks directory
         if let Some(hooks_dir) = &self.config.hooks_dir {
@@ -291,6 +1277,37 @@ impl<'a> ContainerBuilder<'a> {
         Ok(builder)
     }
 
+    /// Adds the build args, contexts and mounts from a stage's `[stages.<name>]` table.
+    fn add_stage_config(
+        &self,
+        mut builder: PodmanCommandBuilder,
+        build_stage: &str,
+    ) -> PodmanCommandBuilder {
+        let Some(stage_config) = self.config.stage_config(build_stage) else {
+            return builder;
+        };
+
+        for (key, value) in stage_config.build_args.iter().flatten() {
+            builder = builder.build_arg(key, value);
+        }
+
+        for context in stage_config.contexts.iter().flatten() {
+            builder = builder.build_context(context);
+        }
+
+        // Mounts are either a single path mounted at the same location, or podman's
+        // `source:destination[:options]` form
+        for mount in stage_config.mounts.iter().flatten() {
+            if mount.contains(':') {
+                builder = builder.volume(mount);
+            } else {
+                builder = builder.volume(&format!("{mount}:{mount}"));
+            }
+        }
+
+        builder
+    }
+
     /// Adds cache mount configuration with proper validation and error categorization.
     fn add_cache_mount(
         &self,
@@ -300,7 +1317,12 @@ impl<'a> ContainerBuilder<'a> {
         container_path: &str,
     ) -> Result<PodmanCommandBuilder> {
         if let Some(cache_dir) = cache_path {
-            // Try to create the cache directory
+            if self.config.dry_run {
+                return Ok(builder.volume(&format!("{}:{container_path}", cache_dir.display())));
+            }
+
+            // Try to create the cache directory. In rootless mode the invoking user
+            // owns it, which is root in podman's user namespace, so builds can write to it
             if let Err(e) = fs::create_dir_all(cache_dir) {
                 let error_msg =
                     format!("Failed to create {cache_name} cache directory: {cache_dir:?} - {e}");
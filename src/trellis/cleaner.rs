@@ -1,15 +1,19 @@
-use anyhow::{anyhow, Context, Result};
+use anyhow::{Context, Result};
 use std::sync::Arc;
 
-use super::{common::TrellisMessaging, constants::containers, executor::CommandExecutor};
+use super::{
+    common::TrellisMessaging, constants::containers, executor::CommandExecutor,
+    libpod::ImageSummary, versions::RootfsVersions,
+};
 use crate::config::TrellisConfig;
 
 /// Mode for cleaning container images.
 #[derive(Debug, Clone, Copy)]
 pub enum CleanMode {
-    /// Remove all trls-generated images
+    /// Remove all trls-generated images, including versioned rootfs tags
     Full,
     /// Remove only intermediate images, keep final builder/rootfs tags
+    /// (and content-keyed stage images when incremental builds are enabled)
     Auto,
 }
 
@@ -60,6 +64,36 @@ impl<'a> ImageCleaner<'a> {
         Ok(())
     }
 
+    /// Removes the oldest versioned rootfs tags beyond the configured number.
+    ///
+    /// The version `latest` points at is kept regardless of its age.
+    pub fn prune_versions(&self) -> Result<()> {
+        if self.config.keep_versions == 0 {
+            return Ok(());
+        }
+
+        let versions = RootfsVersions::new(self.config, Arc::clone(&self.executor));
+        let stale: Vec<String> = versions
+            .list()?
+            .into_iter()
+            .skip(self.config.keep_versions)
+            .filter(|version| !version.latest)
+            .map(|version| versions.reference(&version.version))
+            .collect();
+        if stale.is_empty() {
+            return Ok(());
+        }
+
+        self.msg(&format!(
+            "Removing {} rootfs versions beyond the last {}",
+            stale.len(),
+            self.config.keep_versions
+        ));
+        let stale: Vec<&str> = stale.iter().map(String::as_str).collect();
+        self.remove_images_batch(&stale)?;
+        Ok(())
+    }
+
     /// Core image cleaning logic with optimized filtering and batch operations.
     fn clean_images(&self, mode: CleanMode) -> Result<u32> {
         let mode_desc = match mode {
@@ -67,21 +101,7 @@ impl<'a> ImageCleaner<'a> {
             CleanMode::Auto => "intermediate trls-generated",
         };
 
-        let args = vec![
-            "--format".to_string(),
-            "{{.Repository}}:{{.Tag}}".to_string(),
-        ];
-        let output = self
-            .executor
-            .podman_images(&args)
-            .context("Failed to list podman images")?;
-
-        if !output.status.success() {
-            return Err(anyhow!(
-                "Failed to list images: {}",
-                String::from_utf8_lossy(&output.stderr)
-            ));
-        }
+        let images = self.executor.list_images(None)?;
 
         // Pre-compute expected image names once for efficiency
         let expected_builder = format!(
@@ -95,20 +115,12 @@ impl<'a> ImageCleaner<'a> {
             self.config.rootfs_tag
         );
 
-        let image_list = String::from_utf8_lossy(&output.stdout);
-
         // Optimized filtering: process images and collect those to remove in one pass
-        let images_to_remove: Vec<&str> = image_list
-            .lines()
-            .filter_map(|line| {
-                let line = line.trim();
-                if !line.is_empty()
-                    && self.should_remove_image(line, mode, &expected_builder, &expected_rootfs)
-                {
-                    Some(line)
-                } else {
-                    None
-                }
+        let images_to_remove: Vec<&str> = images
+            .iter()
+            .flat_map(ImageSummary::references)
+            .filter(|image| {
+                self.should_remove_image(image, mode, &expected_builder, &expected_rootfs)
             })
             .collect();
 
@@ -146,10 +158,17 @@ impl<'a> ImageCleaner<'a> {
             containers::STAGE_PREFIX
         );
 
+        let rootfs_repository = expected_rootfs.trim_end_matches(":latest");
+        let is_version = image
+            .strip_prefix(rootfs_repository)
+            .and_then(|tag| tag.strip_prefix(':'))
+            .is_some_and(RootfsVersions::is_version);
+
         let is_trellis = image.starts_with(&builder_prefix)
             || image.starts_with(&stage_prefix)
             || image == expected_builder
-            || image == expected_rootfs;
+            || image == expected_rootfs
+            || is_version;
 
         if !is_trellis {
             return false;
@@ -158,6 +177,16 @@ impl<'a> ImageCleaner<'a> {
         match mode {
             CleanMode::Full => true, // Remove all trellis images
             CleanMode::Auto => {
+                // Versions are pruned according to `keep_versions` instead
+                if is_version {
+                    return false;
+                }
+
+                // Preserve content-keyed stage images so incremental builds can reuse them
+                if self.config.incremental && !image.ends_with(":latest") {
+                    return false;
+                }
+
                 // Only remove intermediate images, preserve final tags
                 image != expected_builder && image != expected_rootfs
             }
@@ -22,6 +22,12 @@ pub trait TrellisMessaging {
         println!("{}{message}", messages::INFO_PREFIX);
     }
 
+    /// Displays an informational message on stderr, for commands whose output
+    /// goes to stdout.
+    fn msg_stderr(&self, message: &str) {
+        eprintln!("{}{message}", messages::INFO_PREFIX);
+    }
+
     /// Displays a warning message with the standard trellis warning prefix.
     fn warning(&self, message: &str) {
         eprintln!("{}{message}", messages::WARNING_PREFIX);
@@ -57,3 +63,87 @@ impl TrellisMessager {
         Self
     }
 }
+
+/// Calendar date and time in UTC, broken down from seconds since the Unix epoch.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UtcTime {
+    pub year: i64,
+    pub month: u32,
+    pub day: u32,
+    pub hour: u32,
+    pub minute: u32,
+    pub second: u32,
+}
+
+impl UtcTime {
+    /// Converts seconds since the Unix epoch to a UTC calendar time.
+    pub fn from_unix(secs: u64) -> Self {
+        let days = (secs / 86_400) as i64;
+        let rem = (secs % 86_400) as u32;
+
+        // Civil-from-days conversion over 400-year eras starting on March 1st
+        let z = days + 719_468;
+        let era = z.div_euclid(146_097);
+        let doe = z.rem_euclid(146_097);
+        let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+        let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+        let mp = (5 * doy + 2) / 153;
+        let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+        let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+        let year = yoe + era * 400 + i64::from(month <= 2);
+
+        Self {
+            year,
+            month,
+            day,
+            hour: rem / 3_600,
+            minute: rem % 3_600 / 60,
+            second: rem % 60,
+        }
+    }
+
+    /// Converts the calendar time back to seconds since the Unix epoch.
+    pub fn to_unix(self) -> i64 {
+        // Days-from-civil conversion, the inverse of `from_unix`
+        let year = self.year - i64::from(self.month <= 2);
+        let era = year.div_euclid(400);
+        let yoe = year.rem_euclid(400);
+        let month = i64::from(self.month);
+        let mp = if month > 2 { month - 3 } else { month + 9 };
+        let doy = (153 * mp + 2) / 5 + i64::from(self.day) - 1;
+        let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+        let days = era * 146_097 + doe - 719_468;
+        days * 86_400 + i64::from(self.hour * 3_600 + self.minute * 60 + self.second)
+    }
+
+    /// Returns the current time.
+    pub fn now() -> Self {
+        Self::from_unix(unix_now())
+    }
+
+    /// Formats the time as RFC 3339, e.g. `2024-05-01T12:30:00Z`.
+    pub fn to_rfc3339(self) -> String {
+        format!(
+            "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+            self.year, self.month, self.day, self.hour, self.minute, self.second
+        )
+    }
+}
+
+impl std::fmt::Display for UtcTime {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{:04}-{:02}-{:02} {:02}:{:02}:{:02} UTC",
+            self.year, self.month, self.day, self.hour, self.minute, self.second
+        )
+    }
+}
+
+/// Returns the current time in seconds since the Unix epoch.
+pub fn unix_now() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
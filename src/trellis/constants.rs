@@ -11,11 +11,29 @@ pub mod paths {
     /// Default stages directory for containerfiles
     pub const DEFAULT_STAGES_DIR: &str = "/var/lib/trellis/stages";
 
+    /// Default directory for checkouts of `[[sources]]` repositories
+    pub const DEFAULT_SOURCES_DIR: &str = "/var/cache/trellis/sources";
+
     /// Default pacman cache directory
     pub const DEFAULT_PACMAN_CACHE: &str = "/var/cache/pacman/pkg";
 
     /// Default AUR cache directory
     pub const DEFAULT_AUR_CACHE: &str = "/var/cache/trellis/aur";
+
+    /// Default directory for persistent trellis state (build progress, etc.)
+    pub const DEFAULT_STATE_DIR: &str = "/var/lib/trellis/state";
+
+    /// Default directory for per-build stage logs
+    pub const DEFAULT_LOG_DIR: &str = "/var/log/trellis";
+
+    /// Default directory for the lock serializing trls runs
+    pub const DEFAULT_LOCK_DIR: &str = "/run/trellis";
+
+    /// Directory of the archive handing an image over to root's container storage
+    pub const HANDOFF_DIR: &str = "/var/tmp";
+
+    /// Default number of builds whose logs are kept
+    pub const DEFAULT_LOG_RETENTION: usize = 10;
 }
 
 /// Container and image related constants
@@ -34,6 +52,24 @@ pub mod containers {
 
     /// Stage image tag prefix for intermediate images
     pub const STAGE_PREFIX: &str = "trellis-stage";
+
+    /// Default image providing `rpm-ostree compose build-chunked-oci` for rechunking
+    pub const DEFAULT_RECHUNK_IMAGE: &str = "quay.io/fedora/fedora-bootc:latest";
+
+    /// Tag of the current rootfs image
+    pub const LATEST_TAG: &str = "latest";
+
+    /// Default number of versioned rootfs tags kept
+    pub const DEFAULT_KEEP_VERSIONS: usize = 5;
+
+    /// Suffix of the tag holding a pushed version's lockfile and build.json
+    pub const METADATA_TAG_SUFFIX: &str = "-metadata";
+
+    /// Podman's container storage, holding images and build layers
+    pub const STORAGE_DIR: &str = "/var/lib/containers/storage";
+
+    /// Socket of root's libpod REST API, provided by `podman.socket`
+    pub const PODMAN_SOCKET: &str = "/run/podman/podman.sock";
 }
 
 /// File and path patterns
@@ -41,6 +77,24 @@ pub mod patterns {
     /// Containerfile filename pattern
     pub const CONTAINERFILE_PREFIX: &str = "Containerfile.";
 
+    /// Prefix of Containerfile header comments carrying stage metadata
+    pub const METADATA_PREFIX: &str = "trellis:";
+
+    /// Build lockfile name, written to the stages directory
+    pub const LOCK_FILE: &str = "trellis.lock";
+
+    /// Directory of the build metadata files inside rootfs images
+    pub const BUILD_METADATA_DIR: &str = "/usr/share/trellis";
+
+    /// Location of the build metadata file inside rootfs images
+    pub const BUILD_METADATA_FILE: &str = "/usr/share/trellis/build.json";
+
+    /// Location of the build's lockfile inside rootfs images
+    pub const BUILD_LOCK_FILE: &str = "/usr/share/trellis/trellis.lock";
+
+    /// Location of os-release inside rootfs images
+    pub const OS_RELEASE_FILE: &str = "/usr/lib/os-release";
+
     /// Maximum directory traversal depth for safety
     pub const MAX_SEARCH_DEPTH: usize = 20;
 }
@@ -56,6 +110,9 @@ pub mod errors {
     /// Missing required containerfiles error
     pub const MISSING_CONTAINERFILES: &str = "Missing required containerfiles";
 
+    /// Containerfile lint errors
+    pub const CONTAINERFILE_LINT_FAILED: &str = "Containerfile lint failed";
+
     /// Containerfile not found error
     pub const CONTAINERFILE_NOT_FOUND: &str = "Containerfile not found";
 }
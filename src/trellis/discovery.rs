@@ -1,15 +1,17 @@
-use anyhow::{anyhow, Result};
+use anyhow::{anyhow, Context, Result};
 use lru::LruCache;
 use std::cell::RefCell;
 use std::collections::{HashMap, HashSet};
 use std::fs;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::time::SystemTime;
 use walkdir::WalkDir;
 
 use super::{
     common::TrellisMessaging,
     constants::{errors, patterns},
+    lint::{ContainerfileLinter, LintFinding, Severity},
+    preprocess,
 };
 use crate::config::TrellisConfig;
 
@@ -66,10 +68,72 @@ impl ContainerfileCache {
     }
 }
 
+/// Dependency metadata declared in the header comments of a Containerfile.
+///
+/// Metadata lines have the form `# trellis: requires=base,gpu conflicts=cosmic` and
+/// must appear in the leading comment block, before the first instruction. Metadata
+/// in a grouped Containerfile applies to every stage built from it.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct StageMetadata {
+    /// Stages that must be built before this one
+    pub requires: Vec<String>,
+    /// Stages that cannot be part of the same build
+    pub conflicts: Vec<String>,
+}
+
+impl StageMetadata {
+    /// Parses the metadata from Containerfile contents.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a metadata line contains an unknown or malformed entry.
+    pub fn parse(content: &str) -> Result<Self> {
+        let mut metadata = Self::default();
+
+        for line in content.lines() {
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            let Some(comment) = line.strip_prefix('#') else {
+                break;
+            };
+            let Some(entries) = comment.trim_start().strip_prefix(patterns::METADATA_PREFIX) else {
+                continue;
+            };
+            // Preprocessor directives share the prefix but are not metadata
+            if preprocess::is_directive(entries) {
+                continue;
+            }
+
+            for entry in entries.split_whitespace() {
+                let (key, values) = entry.split_once('=').ok_or_else(|| {
+                    anyhow!("Invalid trellis metadata '{entry}': expected key=value")
+                })?;
+                let values = values
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|value| !value.is_empty())
+                    .map(str::to_string);
+
+                match key {
+                    "requires" => metadata.requires.extend(values),
+                    "conflicts" => metadata.conflicts.extend(values),
+                    _ => return Err(anyhow!("Unknown trellis metadata key '{key}'")),
+                }
+            }
+        }
+
+        Ok(metadata)
+    }
+}
+
 /// Handles discovery of Containerfiles in the source directory.
 pub struct ContainerfileDiscovery<'a> {
     config: &'a TrellisConfig,
     cache: RefCell<ContainerfileCache>,
+    /// Stages whose lint findings were reported already
+    linted: RefCell<HashSet<String>>,
 }
 
 impl<'a> TrellisMessaging for ContainerfileDiscovery<'a> {}
@@ -79,6 +143,7 @@ impl<'a> ContainerfileDiscovery<'a> {
         Self {
             config,
             cache: RefCell::new(ContainerfileCache::new()),
+            linted: RefCell::new(HashSet::new()),
         }
     }
 
@@ -127,14 +192,66 @@ impl<'a> ContainerfileDiscovery<'a> {
     }
 
     /// Performs uncached containerfile discovery using walkdir.
+    ///
+    /// The search directories are tried in order and the first one containing the
+    /// Containerfile wins; within a directory the most deeply nested match is used.
     fn find_containerfile_uncached(&self, group: &str) -> Result<PathBuf> {
         let filename = format!("{}{group}", patterns::CONTAINERFILE_PREFIX);
+        let search_dirs = self.search_dirs();
+
+        for dir in &search_dirs {
+            // Search for the containerfile, collecting paths with depth for efficient sorting
+            let mut found_paths_with_depth = Vec::new();
+
+            for entry in self.walk(dir) {
+                if entry.file_type().is_file() && entry.file_name() == filename.as_str() {
+                    let depth = entry.path().components().count();
+                    found_paths_with_depth.push((entry.path().to_path_buf(), depth));
+                }
+            }
+
+            // Sort by depth (descending) for most specific match
+            found_paths_with_depth.sort_unstable_by_key(|(_, depth)| std::cmp::Reverse(*depth));
+
+            if let Some((path, _)) = found_paths_with_depth.into_iter().next() {
+                return Ok(path);
+            }
+        }
 
+        let searched: Vec<String> = search_dirs
+            .iter()
+            .map(|dir| dir.display().to_string())
+            .collect();
+        Err(anyhow!(
+            "{}: {filename} (searched recursively in {} and all subdirectories). \
+             Ensure the file exists and has correct permissions. \
+             Use 'find {} -name \"{}\"' to verify file location.",
+            errors::CONTAINERFILE_NOT_FOUND,
+            searched.join(", "),
+            searched.join(" "),
+            filename
+        ))
+    }
+
+    /// Returns the directories searched for Containerfiles: the stages directory,
+    /// followed by the checkouts of `[[sources]]` repositories that exist.
+    fn search_dirs(&self) -> Vec<PathBuf> {
+        self.config
+            .stage_dirs()
+            .into_iter()
+            .enumerate()
+            .filter(|(i, dir)| *i == 0 || dir.is_dir())
+            .map(|(_, dir)| dir)
+            .collect()
+    }
+
+    /// Walks a directory tree, warning about entries that cannot be accessed.
+    fn walk<'d>(&'d self, dir: &'d Path) -> impl Iterator<Item = walkdir::DirEntry> + 'd {
         // Use walkdir for efficient directory traversal with built-in features:
         // - Automatic cycle detection
         // - Depth limiting
         // - Error handling for inaccessible directories
-        let walker = WalkDir::new(&self.config.stages_dir)
+        WalkDir::new(dir)
             .max_depth(patterns::MAX_SEARCH_DEPTH) // Reasonable depth limit to prevent runaway searches
             .follow_links(false) // Don't follow symlinks to avoid cycles
             .into_iter()
@@ -147,35 +264,7 @@ impl<'a> ContainerfileDiscovery<'a> {
                         None
                     }
                 }
-            });
-
-        // Search for the containerfile, collecting paths with depth for efficient sorting
-        let mut found_paths_with_depth = Vec::new();
-
-        for entry in walker {
-            if entry.file_type().is_file() && entry.file_name() == filename.as_str() {
-                let depth = entry.path().components().count();
-                found_paths_with_depth.push((entry.path().to_path_buf(), depth));
-            }
-        }
-
-        if found_paths_with_depth.is_empty() {
-            return Err(anyhow!(
-                "{}: {filename} (searched recursively in {} and all subdirectories). \
-                 Ensure the file exists and has correct permissions. \
-                 Use 'find {} -name \"{}\"' to verify file location.",
-                errors::CONTAINERFILE_NOT_FOUND,
-                self.config.stages_dir.display(),
-                self.config.stages_dir.display(),
-                filename
-            ));
-        }
-
-        // Sort by depth (descending) for most specific match
-        found_paths_with_depth.sort_unstable_by_key(|(_, depth)| std::cmp::Reverse(*depth));
-
-        // Return the path as PathBuf
-        Ok(found_paths_with_depth[0].0.clone())
+            })
     }
 
     /// Efficiently discovers multiple containerfiles with early termination.
@@ -199,53 +288,46 @@ impl<'a> ContainerfileDiscovery<'a> {
             return Ok(HashMap::new());
         }
 
-        let mut found = HashMap::new();
+        let mut result = HashMap::new();
         let mut remaining: HashSet<String> = groups.iter().cloned().collect();
 
-        // Use walkdir for efficient directory traversal
-        let walker = WalkDir::new(&self.config.stages_dir)
-            .max_depth(patterns::MAX_SEARCH_DEPTH)
-            .follow_links(false)
-            .into_iter()
-            .filter_map(|entry| match entry {
-                Ok(entry) => Some(entry),
-                Err(err) => {
-                    self.warning(&format!("Error accessing directory: {err}"));
-                    None
-                }
-            });
-
-        for entry in walker {
-            if entry.file_type().is_file() {
-                if let Some(filename) = entry.file_name().to_str() {
-                    if let Some(group) = self.extract_group_from_filename(filename) {
-                        if remaining.contains(&group) {
-                            let depth = entry.path().components().count();
-
-                            // If we already found this group, keep the deeper (more specific) one
-                            match found.get(&group) {
-                                Some((_, existing_depth)) if depth <= *existing_depth => continue,
-                                _ => {}
-                            }
-
-                            found.insert(group.clone(), (entry.path().to_path_buf(), depth));
-                            remaining.remove(&group);
-
-                            // Early termination when all files are found
-                            if remaining.is_empty() {
-                                break;
+        for dir in self.search_dirs() {
+            let mut found: HashMap<String, (PathBuf, usize)> = HashMap::new();
+
+            for entry in self.walk(&dir) {
+                if entry.file_type().is_file() {
+                    if let Some(filename) = entry.file_name().to_str() {
+                        if let Some(group) = self.extract_group_from_filename(filename) {
+                            if remaining.contains(&group) {
+                                let depth = entry.path().components().count();
+
+                                // If we already found this group, keep the deeper (more specific) one
+                                match found.get(&group) {
+                                    Some((_, existing_depth)) if depth <= *existing_depth => {
+                                        continue
+                                    }
+                                    _ => {}
+                                }
+
+                                found.insert(group.clone(), (entry.path().to_path_buf(), depth));
+                                remaining.remove(&group);
+
+                                // Early termination when all files are found
+                                if remaining.is_empty() {
+                                    break;
+                                }
                             }
                         }
                     }
                 }
             }
-        }
 
-        // Convert to final result format (remove depth information)
-        let result: HashMap<String, PathBuf> = found
-            .into_iter()
-            .map(|(group, (path, _))| (group, path))
-            .collect();
+            // Convert to final result format (remove depth information)
+            result.extend(found.into_iter().map(|(group, (path, _))| (group, path)));
+            if remaining.is_empty() {
+                break;
+            }
+        }
 
         Ok(result)
     }
@@ -284,6 +366,146 @@ impl<'a> ContainerfileDiscovery<'a> {
             .unwrap_or_else(|| (build_stage.to_string(), build_stage.to_string()))
     }
 
+    /// Returns the build target of a stage: the `target` of its `[stages.x]` table,
+    /// or the stage part of its name.
+    pub fn stage_target(config: &TrellisConfig, build_stage: &str) -> String {
+        config
+            .stage_config(build_stage)
+            .and_then(|c| c.target.clone())
+            .unwrap_or_else(|| Self::parse_stage_name(build_stage).1)
+    }
+
+    /// Reads the dependency metadata of a stage from its Containerfile header.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the Containerfile cannot be found or read, or if its
+    /// metadata is malformed.
+    pub fn stage_metadata(&self, build_stage: &str) -> Result<StageMetadata> {
+        let (group, _) = Self::parse_stage_name(build_stage);
+        let path = self.find_containerfile(&group)?;
+        let content = fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read containerfile: {}", path.display()))?;
+        StageMetadata::parse(&content)
+            .with_context(|| format!("Invalid metadata in {}", path.display()))
+    }
+
+    /// Orders stages so that every stage is built after the stages it requires.
+    ///
+    /// The configured order is kept wherever the declared dependencies allow it.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a Containerfile is missing, a required stage is not part of
+    /// the stage list, two listed stages conflict, or the dependencies form a cycle.
+    pub fn order_stages(&self, stages: &[String]) -> Result<Vec<String>> {
+        self.validate_stages(stages)?;
+
+        let metadata = stages
+            .iter()
+            .map(|stage| self.stage_metadata(stage))
+            .collect::<Result<Vec<_>>>()?;
+
+        let mut dependencies = Vec::with_capacity(stages.len());
+        for (i, (stage, meta)) in stages.iter().zip(&metadata).enumerate() {
+            for conflict in &meta.conflicts {
+                if let Some(j) = Self::find_stage(stages, conflict, i) {
+                    return Err(anyhow!("Stage '{stage}' conflicts with '{}'", stages[j]));
+                }
+            }
+
+            let mut requires = Vec::with_capacity(meta.requires.len());
+            for required in &meta.requires {
+                if Self::stage_matches(stage, required) {
+                    continue;
+                }
+                let j = Self::find_stage(stages, required, i).ok_or_else(|| {
+                    anyhow!("Stage '{stage}' requires '{required}', which is not in the stage list")
+                })?;
+                requires.push(j);
+            }
+            dependencies.push(requires);
+        }
+
+        let mut placed = vec![false; stages.len()];
+        let mut ordered = Vec::with_capacity(stages.len());
+        while ordered.len() < stages.len() {
+            let next = (0..stages.len())
+                .find(|&i| !placed[i] && dependencies[i].iter().all(|&j| placed[j]));
+
+            let Some(i) = next else {
+                let remaining: Vec<&str> = (0..stages.len())
+                    .filter(|&i| !placed[i])
+                    .map(|i| stages[i].as_str())
+                    .collect();
+                return Err(anyhow!(
+                    "Dependency cycle between stages: {}",
+                    remaining.join(", ")
+                ));
+            };
+
+            placed[i] = true;
+            ordered.push(stages[i].clone());
+        }
+
+        Ok(ordered)
+    }
+
+    /// Renders the declared dependencies between stages as a Graphviz DOT graph.
+    ///
+    /// Edges point from a required stage to the stage that requires it. Conflicts are
+    /// drawn as dashed red edges.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a Containerfile is missing or its metadata is malformed.
+    pub fn stage_graph_dot(&self, stages: &[String]) -> Result<String> {
+        self.validate_stages(stages)?;
+
+        let mut dot = String::from("digraph stages {\n    rankdir=LR;\n");
+        for stage in stages {
+            dot.push_str(&format!("    \"{stage}\";\n"));
+        }
+
+        for (i, stage) in stages.iter().enumerate() {
+            let meta = self.stage_metadata(stage)?;
+            for required in &meta.requires {
+                if Self::stage_matches(stage, required) {
+                    continue;
+                }
+                let from = Self::find_stage(stages, required, i)
+                    .map_or(required.as_str(), |j| stages[j].as_str());
+                dot.push_str(&format!("    \"{from}\" -> \"{stage}\";\n"));
+            }
+            for conflict in &meta.conflicts {
+                let other = Self::find_stage(stages, conflict, i)
+                    .map_or(conflict.as_str(), |j| stages[j].as_str());
+                dot.push_str(&format!(
+                    "    \"{stage}\" -> \"{other}\" [style=dashed, color=red, dir=none, label=\"conflicts\"];\n"
+                ));
+            }
+        }
+
+        dot.push_str("}\n");
+        Ok(dot)
+    }
+
+    /// Finds the stage list entry a metadata reference points to, ignoring `skip`.
+    fn find_stage(stages: &[String], reference: &str, skip: usize) -> Option<usize> {
+        stages
+            .iter()
+            .enumerate()
+            .find(|&(i, stage)| i != skip && Self::stage_matches(stage, reference))
+            .map(|(i, _)| i)
+    }
+
+    /// Checks whether a stage list entry is named by a metadata reference.
+    ///
+    /// References may use the full `group:stage` form or just the stage name.
+    fn stage_matches(stage: &str, reference: &str) -> bool {
+        stage == reference || Self::parse_stage_name(stage).1 == reference
+    }
+
     /// Validates that all required containerfiles exist for the given stages.
     ///
     /// This performs upfront validation to fail fast if any required files are missing.
@@ -345,6 +567,47 @@ impl<'a> ContainerfileDiscovery<'a> {
             ));
         }
 
+        // Report lint findings once per stage, even if validated repeatedly
+        let findings = self.lint_stages(stages)?;
+        let mut linted = self.linted.borrow_mut();
+        let mut errors = Vec::new();
+        for finding in findings {
+            if linted.contains(&finding.stage) {
+                continue;
+            }
+            match finding.severity {
+                Severity::Error => errors.push(finding.to_string()),
+                Severity::Warning => self.warning(&finding.to_string()),
+            }
+        }
+        if !errors.is_empty() {
+            return Err(anyhow!(
+                "{}:\n{}",
+                errors::CONTAINERFILE_LINT_FAILED,
+                errors.join("\n")
+            ));
+        }
+        linted.extend(stages.iter().cloned());
+
         Ok(())
     }
+
+    /// Runs the static Containerfile checks for the given stages.
+    ///
+    /// The first stage may start from an image of its own; every later stage is
+    /// expected to build on `${BASE_IMAGE}`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a Containerfile is missing or cannot be read or rendered.
+    pub fn lint_stages(&self, stages: &[String]) -> Result<Vec<LintFinding>> {
+        let linter = ContainerfileLinter::new(self.config);
+        let mut findings = Vec::new();
+        for (i, stage) in stages.iter().enumerate() {
+            let (group, _) = Self::parse_stage_name(stage);
+            let path = self.find_containerfile(&group)?;
+            findings.extend(linter.lint_stage(stage, &path, i == 0)?);
+        }
+        Ok(findings)
+    }
 }
@@ -0,0 +1,253 @@
+//! Free space checks before builds and disk image generation.
+//!
+//! Each step's needs are estimated up front and added up per filesystem, so
+//! container storage and a cache on the same filesystem are compared with its free
+//! space together. A shortfall stops the step before it starts, unless `--force`
+//! is given.
+
+use anyhow::{anyhow, Context, Result};
+use std::{
+    collections::BTreeMap,
+    ffi::CString,
+    os::unix::{ffi::OsStrExt, fs::MetadataExt},
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use super::{
+    builder::BuildType, common::TrellisMessaging, constants::containers::LOCALHOST_PREFIX,
+    executor::CommandExecutor,
+};
+use crate::config::TrellisConfig;
+
+/// Bytes per GiB
+pub const GIB: u64 = 1 << 30;
+
+/// Factor applied to the previous image size: the new stage images plus the copies
+/// podman makes while committing their layers.
+const BUILD_SIZE_FACTOR: u64 = 2;
+
+/// Free space kept for packages downloaded into each cache during a rootfs build.
+const CACHE_RESERVE: u64 = GIB;
+
+/// Space a step needs on the filesystem holding `path`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SpaceRequirement {
+    pub path: PathBuf,
+    pub bytes: u64,
+}
+
+impl SpaceRequirement {
+    pub fn new(path: impl Into<PathBuf>, bytes: u64) -> Self {
+        Self {
+            path: path.into(),
+            bytes,
+        }
+    }
+}
+
+/// Free space on the filesystem holding a path.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FreeSpace {
+    /// Device of the filesystem, identifying it across paths
+    pub device: u64,
+    /// Bytes available to unprivileged users
+    pub available: u64,
+}
+
+/// Returns the free space on the filesystem holding `path`, or the nearest existing
+/// ancestor of `path` if it does not exist yet.
+///
+/// # Errors
+///
+/// Returns an error if no ancestor of `path` exists or the filesystem cannot be queried.
+pub fn free_space(path: &Path) -> Result<FreeSpace> {
+    let existing = path
+        .ancestors()
+        .find(|p| !p.as_os_str().is_empty() && p.exists())
+        .unwrap_or(Path::new("."));
+    let device = existing
+        .metadata()
+        .with_context(|| format!("Failed to read {}", existing.display()))?
+        .dev();
+
+    let c_path = CString::new(existing.as_os_str().as_bytes())
+        .with_context(|| format!("Invalid path: {}", existing.display()))?;
+    // SAFETY: statvfs is a plain C struct for which all zeroes is a valid value
+    let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+    // SAFETY: c_path is a valid C string and stat a valid statvfs
+    if unsafe { libc::statvfs(c_path.as_ptr(), &mut stat) } != 0 {
+        return Err(std::io::Error::last_os_error())
+            .with_context(|| format!("Failed to query free space of {}", existing.display()));
+    }
+
+    Ok(FreeSpace {
+        device,
+        available: stat.f_bavail as u64 * stat.f_frsize as u64,
+    })
+}
+
+/// Formats a byte count in GiB with one decimal.
+pub fn format_gib(bytes: u64) -> String {
+    format!("{:.1} GiB", bytes as f64 / GIB as f64)
+}
+
+/// Checks free space before builds and disk image generation.
+pub struct DiskSpaceChecker<'a> {
+    config: &'a TrellisConfig,
+    executor: Arc<dyn CommandExecutor>,
+}
+
+impl<'a> TrellisMessaging for DiskSpaceChecker<'a> {}
+
+impl<'a> DiskSpaceChecker<'a> {
+    pub fn new(config: &'a TrellisConfig, executor: Arc<dyn CommandExecutor>) -> Self {
+        Self { config, executor }
+    }
+
+    /// Checks that a build of `final_tag` fits into container storage and, for
+    /// rootfs builds, the package caches.
+    ///
+    /// The build is estimated from the size of the previous `final_tag` image, or
+    /// of the base image for a first build. Without either, only the caches are checked.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error naming the shortfall if a filesystem has too little free
+    /// space and `--force` was not given.
+    pub fn check_build(&self, final_tag: &str, build_type: BuildType) -> Result<()> {
+        let previous = format!("{LOCALHOST_PREFIX}{final_tag}");
+        let estimate = self.image_size(&previous).or_else(|| match build_type {
+            BuildType::Rootfs if self.config.rootfs_base != "scratch" => {
+                self.image_size(&self.config.rootfs_base)
+            }
+            _ => None,
+        });
+
+        let mut requirements = Vec::new();
+        match estimate {
+            Some(size) => requirements.push(SpaceRequirement::new(
+                &self.config.storage_dir,
+                size.saturating_mul(BUILD_SIZE_FACTOR),
+            )),
+            None => self.msg(&format!(
+                "No previous {final_tag} image to estimate the build size from, \
+                 checking only the caches"
+            )),
+        }
+        if matches!(build_type, BuildType::Rootfs) {
+            for cache in [&self.config.pacman_cache, &self.config.aur_cache]
+                .into_iter()
+                .flatten()
+            {
+                requirements.push(SpaceRequirement::new(cache, CACHE_RESERVE));
+            }
+        }
+
+        self.check("build", &requirements)
+    }
+
+    /// Checks that a disk image of `size_gb` GiB fits next to `output_path`.
+    /// Existing image files are reused and need no space.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error naming the shortfall if the output filesystem has too
+    /// little free space and `--force` was not given.
+    pub fn check_image(&self, output_path: &Path, size_gb: u64) -> Result<()> {
+        if output_path.exists() {
+            return Ok(());
+        }
+        let output_dir = output_path
+            .parent()
+            .filter(|dir| !dir.as_os_str().is_empty())
+            .unwrap_or(Path::new("."));
+        self.check(
+            "disk image",
+            &[SpaceRequirement::new(
+                output_dir,
+                size_gb.saturating_mul(GIB),
+            )],
+        )
+    }
+
+    /// Compares the requirements, added up per filesystem, with the free space.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error listing every filesystem that falls short, unless `--force`
+    /// was given, in which case the shortfall is only reported as a warning.
+    pub fn check(&self, operation: &str, requirements: &[SpaceRequirement]) -> Result<()> {
+        if self.config.dry_run || requirements.is_empty() {
+            return Ok(());
+        }
+
+        let mut filesystems: BTreeMap<u64, (FreeSpace, u64, Vec<&Path>)> = BTreeMap::new();
+        for requirement in requirements {
+            let free = free_space(&requirement.path)?;
+            let entry = filesystems
+                .entry(free.device)
+                .or_insert_with(|| (free, 0, Vec::new()));
+            entry.1 = entry.1.saturating_add(requirement.bytes);
+            entry.2.push(&requirement.path);
+        }
+
+        let shortfalls: Vec<String> = filesystems
+            .values()
+            .filter(|(free, needed, _)| *needed > free.available)
+            .map(|(free, needed, paths)| {
+                let paths: Vec<String> = paths.iter().map(|p| p.display().to_string()).collect();
+                format!(
+                    "{}: {} needed, {} free, {} short",
+                    paths.join(", "),
+                    format_gib(*needed),
+                    format_gib(free.available),
+                    format_gib(needed - free.available)
+                )
+            })
+            .collect();
+        if shortfalls.is_empty() {
+            return Ok(());
+        }
+
+        let message = format!(
+            "Not enough free space for the {operation}:\n  {}",
+            shortfalls.join("\n  ")
+        );
+        if self.config.force {
+            self.warning(&message);
+            self.warning("Continuing because of --force");
+            return Ok(());
+        }
+        Err(anyhow!(
+            "{message}\nFree up space or use --force to start anyway"
+        ))
+    }
+
+    /// Returns the size of a local image in bytes, if it exists.
+    fn image_size(&self, image: &str) -> Option<u64> {
+        let inspect = self.executor.inspect_image(image).ok().flatten()?;
+        Some(inspect.size)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_free_space_of_missing_path_uses_ancestor() {
+        let temp = tempfile::TempDir::new().unwrap();
+        let missing = temp.path().join("does/not/exist");
+
+        assert_eq!(
+            free_space(&missing).unwrap().device,
+            free_space(temp.path()).unwrap().device
+        );
+    }
+
+    #[test]
+    fn test_format_gib() {
+        assert_eq!(format_gib(3 * GIB + GIB / 2), "3.5 GiB");
+    }
+}
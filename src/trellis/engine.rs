@@ -0,0 +1,300 @@
+//! Container engines behind the command executor.
+//!
+//! trls describes builds and containers with podman's command line. A
+//! `ContainerEngine` runs them with its own tool, translating the options where
+//! the tools differ, and declares the build features it supports so the builder
+//! can adapt a build or refuse it before starting.
+
+use anyhow::{anyhow, Result};
+
+use crate::config::{EngineKind, Layering, SecurityOptions, TrellisConfig};
+
+/// Operation run through a container engine.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operation {
+    Build,
+    Run,
+    Images,
+    Inspect,
+    Rmi,
+    Commit,
+    /// Any other command, with the podman subcommand as the first argument
+    Other,
+}
+
+impl Operation {
+    /// Returns the subcommand of the operation, or `None` for `Other`.
+    pub fn subcommand(&self) -> Option<&'static str> {
+        match self {
+            Operation::Build => Some("build"),
+            Operation::Run => Some("run"),
+            Operation::Images => Some("images"),
+            Operation::Inspect => Some("inspect"),
+            Operation::Rmi => Some("rmi"),
+            Operation::Commit => Some("commit"),
+            Operation::Other => None,
+        }
+    }
+}
+
+/// Build features an engine supports.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EngineCapabilities {
+    /// Build secrets passed with `--secret`
+    pub secrets: bool,
+    /// Named build contexts passed with `--build-context`
+    pub build_contexts: bool,
+    /// Layering modes builds can produce
+    pub layering: &'static [Layering],
+    /// Volumes mounted into build containers, used for caches, hooks and mounts
+    pub build_volumes: bool,
+    /// Added capabilities, seccomp profiles and SELinux labels for build containers
+    pub security_options: bool,
+    /// Whether images end up in containers-storage, where podman and bootc find them
+    pub containers_storage: bool,
+    /// Builds that never pull the base image, `pull_policy = "never"`
+    pub pull_never: bool,
+}
+
+impl EngineCapabilities {
+    pub fn supports_layering(&self, layering: Layering) -> bool {
+        self.layering.contains(&layering)
+    }
+}
+
+/// A tool that builds images and runs containers.
+pub trait ContainerEngine: Send + Sync {
+    fn capabilities(&self) -> EngineCapabilities;
+
+    /// Returns the program running `operation`.
+    fn program(&self, operation: Operation) -> &'static str;
+
+    /// Translates podman arguments of `operation` to those of the program.
+    fn translate(&self, _operation: Operation, args: &[String]) -> Vec<String> {
+        args.to_vec()
+    }
+
+    /// Returns the program and arguments running `operation` with podman arguments.
+    fn command_line(&self, operation: Operation, args: &[String]) -> (&'static str, Vec<String>) {
+        let mut line: Vec<String> = operation
+            .subcommand()
+            .map(str::to_string)
+            .into_iter()
+            .collect();
+        line.extend(self.translate(operation, args));
+        (self.program(operation), line)
+    }
+}
+
+/// Returns the engine of `kind`.
+pub fn for_kind(kind: EngineKind) -> Box<dyn ContainerEngine> {
+    match kind {
+        EngineKind::Podman => Box::new(Podman),
+        EngineKind::Buildah => Box::new(Buildah),
+        EngineKind::Docker => Box::new(Docker),
+    }
+}
+
+/// Returns the capabilities of the configured engine.
+pub fn capabilities(config: &TrellisConfig) -> EngineCapabilities {
+    for_kind(config.engine).capabilities()
+}
+
+/// Returns the security options of build containers, without the ones the
+/// configured engine cannot apply.
+pub fn build_security(config: &TrellisConfig) -> SecurityOptions {
+    let security = &config.security.build;
+    if capabilities(config).security_options {
+        return security.clone();
+    }
+    SecurityOptions {
+        network: security.network,
+        ..Default::default()
+    }
+}
+
+/// Checks that the configured engine stores images in containers-storage.
+///
+/// # Errors
+///
+/// Returns an error naming `operation` if the engine keeps its own image store.
+pub fn require_containers_storage(config: &TrellisConfig, operation: &str) -> Result<()> {
+    if capabilities(config).containers_storage {
+        return Ok(());
+    }
+    Err(anyhow!(
+        "`{operation}` needs the image in containers-storage, which {} does not use. \
+         Set kind = \"podman\" or \"buildah\" in [engine]",
+        config.engine
+    ))
+}
+
+/// Podman, the reference for the command lines of trls.
+pub struct Podman;
+
+impl ContainerEngine for Podman {
+    fn capabilities(&self) -> EngineCapabilities {
+        EngineCapabilities {
+            secrets: true,
+            build_contexts: true,
+            layering: &[Layering::Squash, Layering::SquashAll, Layering::Layered],
+            build_volumes: true,
+            security_options: true,
+            containers_storage: true,
+            pull_never: true,
+        }
+    }
+
+    fn program(&self, _operation: Operation) -> &'static str {
+        "podman"
+    }
+}
+
+/// Buildah builds, podman runs and manages the images in the shared storage.
+///
+/// `buildah build --squash` squashes the base image layers too, so it produces
+/// `squash-all` and buildah has no equivalent of podman's `--squash`.
+pub struct Buildah;
+
+impl ContainerEngine for Buildah {
+    fn capabilities(&self) -> EngineCapabilities {
+        EngineCapabilities {
+            layering: &[Layering::SquashAll, Layering::Layered],
+            ..Podman.capabilities()
+        }
+    }
+
+    fn program(&self, operation: Operation) -> &'static str {
+        match operation {
+            Operation::Build => "buildah",
+            _ => "podman",
+        }
+    }
+
+    fn translate(&self, operation: Operation, args: &[String]) -> Vec<String> {
+        match operation {
+            Operation::Build => args
+                .iter()
+                .map(|arg| match arg.as_str() {
+                    "--squash-all" => "--squash".to_string(),
+                    _ => arg.clone(),
+                })
+                .collect(),
+            _ => args.to_vec(),
+        }
+    }
+}
+
+/// Docker with BuildKit, keeping images in its own store.
+pub struct Docker;
+
+impl ContainerEngine for Docker {
+    fn capabilities(&self) -> EngineCapabilities {
+        EngineCapabilities {
+            secrets: true,
+            build_contexts: true,
+            layering: &[Layering::Layered],
+            build_volumes: false,
+            security_options: false,
+            containers_storage: false,
+            pull_never: false,
+        }
+    }
+
+    fn program(&self, _operation: Operation) -> &'static str {
+        "docker"
+    }
+
+    fn translate(&self, operation: Operation, args: &[String]) -> Vec<String> {
+        match operation {
+            Operation::Build => {
+                let mut translated = Vec::with_capacity(args.len());
+                for arg in args {
+                    match arg.as_str() {
+                        "--net" => translated.push("--network".to_string()),
+                        // --no-cache already disables the layer cache
+                        "--layers=false" => {}
+                        "--pull=always" => translated.push("--pull".to_string()),
+                        // Docker only pulls missing base images by default
+                        "--pull=missing" => {}
+                        _ => translated.push(arg.clone()),
+                    }
+                }
+                translated
+            }
+            // docker rmi --force skips images that do not exist
+            Operation::Rmi => args
+                .iter()
+                .map(|arg| match arg.as_str() {
+                    "--ignore" => "--force".to_string(),
+                    _ => arg.clone(),
+                })
+                .collect(),
+            Operation::Other if args.starts_with(&["image".to_string(), "exists".to_string()]) => {
+                ["image", "inspect", "--format", "{{.Id}}"]
+                    .into_iter()
+                    .map(str::to_string)
+                    .chain(args[2..].iter().cloned())
+                    .collect()
+            }
+            _ => args.to_vec(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strings(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn test_podman_passes_arguments_through() {
+        let (program, args) =
+            Podman.command_line(Operation::Build, &strings(&["--squash", "-t", "x"]));
+        assert_eq!(program, "podman");
+        assert_eq!(args, ["build", "--squash", "-t", "x"]);
+    }
+
+    #[test]
+    fn test_buildah_builds_and_podman_runs() {
+        let (program, args) = Buildah.command_line(Operation::Build, &strings(&["--squash-all"]));
+        assert_eq!(program, "buildah");
+        assert_eq!(args, ["build", "--squash"]);
+
+        let (program, args) = Buildah.command_line(Operation::Other, &strings(&["tag", "a", "b"]));
+        assert_eq!(program, "podman");
+        assert_eq!(args, ["tag", "a", "b"]);
+    }
+
+    #[test]
+    fn test_docker_translates_podman_options() {
+        let (program, args) = Docker.command_line(
+            Operation::Build,
+            &strings(&[
+                "--net",
+                "host",
+                "--layers=false",
+                "--pull=missing",
+                "-t",
+                "x",
+            ]),
+        );
+        assert_eq!(program, "docker");
+        assert_eq!(args, ["build", "--network", "host", "-t", "x"]);
+
+        let (_, args) = Docker.command_line(Operation::Rmi, &strings(&["--ignore", "x"]));
+        assert_eq!(args, ["rmi", "--force", "x"]);
+
+        let (_, args) = Docker.command_line(
+            Operation::Other,
+            &strings(&["image", "exists", "localhost/x"]),
+        );
+        assert_eq!(
+            args,
+            ["image", "inspect", "--format", "{{.Id}}", "localhost/x"]
+        );
+    }
+}
@@ -3,8 +3,19 @@
 //! This module provides traits and implementations for abstracting external
 //! command execution, enabling comprehensive testing through mocking.
 
-use anyhow::Result;
-use std::process::{ExitStatus, Output};
+use anyhow::{anyhow, Context, Result};
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Write};
+use std::os::unix::process::ExitStatusExt;
+use std::path::Path;
+use std::process::{Command, ExitStatus, Output, Stdio};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+
+use super::engine::{self, ContainerEngine, Operation};
+use super::interrupt::{self, ChildGuard};
+use super::libpod::{ImageInspect, ImageSummary};
+use crate::config::EngineKind;
 
 /// Trait for executing external commands.
 ///
@@ -17,6 +28,12 @@ pub trait CommandExecutor: Send + Sync {
     /// Execute a podman build command with streaming output.
     fn podman_build_streaming(&self, args: &[String]) -> Result<ExitStatus>;
 
+    /// Execute a podman build command with streaming output, also appending the
+    /// output to the log file. The default implementation only streams.
+    fn podman_build_logged(&self, args: &[String], _log_path: &Path) -> Result<ExitStatus> {
+        self.podman_build_streaming(args)
+    }
+
     /// Execute a podman run command.
     fn podman_run(&self, args: &[String]) -> Result<Output>;
 
@@ -35,6 +52,55 @@ pub trait CommandExecutor: Send + Sync {
     /// Execute a podman commit command.
     fn podman_commit(&self, args: &[String]) -> Result<Output>;
 
+    /// Execute a podman command managing images or containers, such as `tag` or
+    /// `image exists`, with the subcommand as the first argument.
+    fn podman_command(&self, args: &[String]) -> Result<Output> {
+        self.execute("podman", args)
+    }
+
+    /// Lists the local images, only those matching `reference` if given.
+    ///
+    /// The default implementation parses `podman images --format json`.
+    fn list_images(&self, reference: Option<&str>) -> Result<Vec<ImageSummary>> {
+        let mut args = vec!["--format".to_string(), "json".to_string()];
+        if let Some(reference) = reference {
+            args.push("--filter".to_string());
+            args.push(format!("reference={reference}"));
+        }
+        let output = self
+            .podman_images(&args)
+            .context("Failed to list podman images")?;
+        if !output.status.success() {
+            return Err(anyhow!(
+                "Failed to list images: {}",
+                String::from_utf8_lossy(&output.stderr)
+            ));
+        }
+        ImageSummary::parse_list(&output.stdout)
+    }
+
+    /// Checks whether an image exists in local storage.
+    ///
+    /// The default implementation runs `podman image exists`.
+    fn image_exists(&self, name: &str) -> Result<bool> {
+        let args = vec!["image".to_string(), "exists".to_string(), name.to_string()];
+        Ok(self.podman_command(&args)?.status.success())
+    }
+
+    /// Returns the inspect data of a local image, or `None` if it is not available.
+    ///
+    /// The default implementation parses `podman inspect --type image`.
+    fn inspect_image(&self, name: &str) -> Result<Option<ImageInspect>> {
+        let args = vec!["--type".to_string(), "image".to_string(), name.to_string()];
+        let output = self.podman_inspect(&args)?;
+        if !output.status.success() {
+            return Ok(None);
+        }
+        let images: Vec<ImageInspect> = serde_json::from_slice(&output.stdout)
+            .context("Failed to parse podman inspect output")?;
+        Ok(images.into_iter().next())
+    }
+
     /// Check if a command is available in a container.
     fn check_command_in_container(&self, container_tag: &str, command: &str) -> Result<bool>;
 
@@ -46,14 +112,38 @@ pub trait CommandExecutor: Send + Sync {
 
     /// Execute any generic command.
     fn execute(&self, command: &str, args: &[String]) -> Result<Output>;
+
+    /// Execute any generic command with streaming output.
+    fn execute_streaming(&self, command: &str, args: &[String]) -> Result<ExitStatus> {
+        self.execute(command, args).map(|output| output.status)
+    }
 }
 
 /// Real command executor for production use.
-pub struct RealCommandExecutor;
+///
+/// Container operations run with the configured container engine, podman unless
+/// created with `with_engine`.
+pub struct RealCommandExecutor {
+    engine: Box<dyn ContainerEngine>,
+}
 
 impl RealCommandExecutor {
     pub fn new() -> Self {
-        Self
+        Self::with_engine(EngineKind::Podman)
+    }
+
+    pub fn with_engine(kind: EngineKind) -> Self {
+        Self {
+            engine: engine::for_kind(kind),
+        }
+    }
+
+    /// Returns the command running `operation` with podman arguments `args`.
+    fn engine_command(&self, operation: Operation, args: &[String]) -> Command {
+        let (program, args) = self.engine.command_line(operation, args);
+        let mut command = Command::new(program);
+        command.args(args);
+        command
     }
 }
 
@@ -65,100 +155,372 @@ impl Default for RealCommandExecutor {
 
 impl CommandExecutor for RealCommandExecutor {
     fn podman_build(&self, args: &[String]) -> Result<Output> {
-        let output = std::process::Command::new("podman")
-            .arg("build")
-            .args(args)
-            .output()?;
-        Ok(output)
+        run_output(&mut self.engine_command(Operation::Build, args))
     }
 
     fn podman_build_streaming(&self, args: &[String]) -> Result<ExitStatus> {
-        let status = std::process::Command::new("podman")
-            .arg("build")
-            .args(args)
-            .status()?;
-        Ok(status)
+        run_status(&mut self.engine_command(Operation::Build, args))
+    }
+
+    fn podman_build_logged(&self, args: &[String], log_path: &Path) -> Result<ExitStatus> {
+        run_logged(self.engine_command(Operation::Build, args), log_path)
     }
 
     fn podman_run(&self, args: &[String]) -> Result<Output> {
-        let output = std::process::Command::new("podman")
-            .arg("run")
-            .args(args)
-            .output()?;
-        Ok(output)
+        run_output(&mut self.engine_command(Operation::Run, args))
     }
 
     fn podman_run_streaming(&self, args: &[String]) -> Result<ExitStatus> {
-        let status = std::process::Command::new("podman")
-            .arg("run")
-            .args(args)
-            .status()?;
-        Ok(status)
+        run_status(&mut self.engine_command(Operation::Run, args))
     }
 
     fn podman_images(&self, args: &[String]) -> Result<Output> {
-        let output = std::process::Command::new("podman")
-            .arg("images")
-            .args(args)
-            .output()?;
-        Ok(output)
+        run_output(&mut self.engine_command(Operation::Images, args))
     }
 
     fn podman_inspect(&self, args: &[String]) -> Result<Output> {
-        let output = std::process::Command::new("podman")
-            .arg("inspect")
-            .args(args)
-            .output()?;
-        Ok(output)
+        run_output(&mut self.engine_command(Operation::Inspect, args))
     }
 
     fn podman_rmi(&self, args: &[String]) -> Result<Output> {
-        let output = std::process::Command::new("podman")
-            .arg("rmi")
-            .args(args)
-            .output()?;
-        Ok(output)
+        run_output(&mut self.engine_command(Operation::Rmi, args))
     }
 
     fn podman_commit(&self, args: &[String]) -> Result<Output> {
-        let output = std::process::Command::new("podman")
-            .arg("commit")
-            .args(args)
-            .output()?;
-        Ok(output)
+        run_output(&mut self.engine_command(Operation::Commit, args))
+    }
+
+    fn podman_command(&self, args: &[String]) -> Result<Output> {
+        run_output(&mut self.engine_command(Operation::Other, args))
     }
 
     fn check_command_in_container(&self, container_tag: &str, command: &str) -> Result<bool> {
         // Run a test command in the container image to check if command exists
-        let output = std::process::Command::new("podman")
-            .arg("run")
-            .arg("--rm")
-            .arg(format!("localhost/{}", container_tag))
-            .arg("sh")
-            .arg("-c")
-            .arg(format!("which {}", command))
-            .output()?;
+        let args = [
+            "--rm".to_string(),
+            format!("localhost/{}", container_tag),
+            "sh".to_string(),
+            "-c".to_string(),
+            format!("which {}", command),
+        ];
+        let output = run_output(&mut self.engine_command(Operation::Run, &args))?;
         Ok(output.status.success())
     }
 
     fn bootc(&self, args: &[String]) -> Result<Output> {
-        let output = std::process::Command::new("bootc")
-            .args(args)
-            .env("LC_ALL", "C.UTF-8")
-            .output()?;
-        Ok(output)
+        let mut command = Command::new("bootc");
+        command.args(args).env("LC_ALL", "C.UTF-8");
+        run_output(&mut command)
+    }
+
+    fn bootc_streaming(&self, args: &[String]) -> Result<ExitStatus> {
+        let mut command = Command::new("bootc");
+        command.args(args).env("LC_ALL", "C.UTF-8");
+        run_status(&mut command)
+    }
+
+    fn execute(&self, command: &str, args: &[String]) -> Result<Output> {
+        let mut command = Command::new(command);
+        command.args(args);
+        run_output(&mut command)
+    }
+
+    fn execute_streaming(&self, command: &str, args: &[String]) -> Result<ExitStatus> {
+        let mut command = Command::new(command);
+        command.args(args);
+        run_status(&mut command)
+    }
+}
+
+/// Runs `command` to completion with its output captured, like `Command::output`.
+///
+/// Signals trls receives are forwarded to the command while it runs. Returns an
+/// `Interrupted` error if trls was interrupted by the time the command exited.
+fn run_output(command: &mut Command) -> Result<Output> {
+    let child = command
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    let _guard = ChildGuard::new(&child);
+    let output = child.wait_with_output()?;
+    interrupt::check()?;
+    Ok(output)
+}
+
+/// Runs `command` to completion with inherited stdio, like `Command::status`.
+///
+/// Signals are forwarded and interruption is reported as for `run_output`.
+fn run_status(command: &mut Command) -> Result<ExitStatus> {
+    let mut child = command.spawn()?;
+    let _guard = ChildGuard::new(&child);
+    let status = child.wait()?;
+    interrupt::check()?;
+    Ok(status)
+}
+
+/// Runs `command`, appending its stdout and stderr to `log_path` while also copying
+/// them to the terminal.
+fn run_logged(mut command: Command, log_path: &Path) -> Result<ExitStatus> {
+    let log = OpenOptions::new()
+        .append(true)
+        .create(true)
+        .open(log_path)
+        .with_context(|| format!("Failed to open log file: {}", log_path.display()))?;
+    let log = Arc::new(Mutex::new(log));
+
+    let mut child = command
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    let guard = ChildGuard::new(&child);
+    let readers = [
+        child
+            .stdout
+            .take()
+            .map(|out| tee(out, std::io::stdout(), Arc::clone(&log))),
+        child
+            .stderr
+            .take()
+            .map(|err| tee(err, std::io::stderr(), Arc::clone(&log))),
+    ];
+
+    let status = child.wait()?;
+    drop(guard);
+    for reader in readers.into_iter().flatten() {
+        let _ = reader.join();
+    }
+    interrupt::check()?;
+    Ok(status)
+}
+
+/// Copies `reader` to `terminal` and `log` on a background thread until EOF.
+fn tee<R, W>(mut reader: R, mut terminal: W, log: Arc<Mutex<File>>) -> JoinHandle<()>
+where
+    R: Read + Send + 'static,
+    W: Write + Send + 'static,
+{
+    thread::spawn(move || {
+        let mut buf = [0u8; 8192];
+        loop {
+            match reader.read(&mut buf) {
+                Ok(0) | Err(_) => break,
+                Ok(n) => {
+                    let _ = terminal.write_all(&buf[..n]);
+                    let _ = terminal.flush();
+                    if let Ok(mut log) = log.lock() {
+                        let _ = log.write_all(&buf[..n]);
+                    }
+                }
+            }
+        }
+    })
+}
+
+/// Recording executor used by `--dry-run`.
+///
+/// Every invocation is printed and recorded instead of being executed. Queries
+/// receive synthesized successful answers so the surrounding logic follows the
+/// same path it would on a host where every referenced image exists.
+pub struct DryRunCommandExecutor {
+    commands: Mutex<Vec<String>>,
+    engine: Box<dyn ContainerEngine>,
+}
+
+impl Default for DryRunCommandExecutor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl DryRunCommandExecutor {
+    pub fn new() -> Self {
+        Self::with_engine(EngineKind::Podman)
+    }
+
+    /// Creates an executor recording container operations as `kind` would run them.
+    pub fn with_engine(kind: EngineKind) -> Self {
+        Self {
+            commands: Mutex::new(Vec::new()),
+            engine: engine::for_kind(kind),
+        }
+    }
+
+    /// Returns the command lines recorded so far, in invocation order.
+    #[allow(dead_code)]
+    pub fn commands(&self) -> Vec<String> {
+        self.commands
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone()
+    }
+
+    fn record(&self, program: &str, subcommand: Option<&str>, args: &[String]) {
+        let line = std::iter::once(program)
+            .chain(subcommand)
+            .map(str::to_string)
+            .chain(args.iter().map(|arg| shell_quote(arg)))
+            .collect::<Vec<_>>()
+            .join(" ");
+        println!("[dry-run] {line}");
+        self.commands
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .push(line);
+    }
+
+    fn record_engine(&self, operation: Operation, args: &[String]) {
+        let (program, args) = self.engine.command_line(operation, args);
+        self.record(program, None, &args);
+    }
+
+    fn output(stdout: &str) -> Output {
+        Output {
+            status: ExitStatus::from_raw(0),
+            stdout: stdout.as_bytes().to_vec(),
+            stderr: Vec::new(),
+        }
+    }
+}
+
+/// Quotes an argument for display when it contains shell metacharacters.
+fn shell_quote(arg: &str) -> String {
+    let plain = !arg.is_empty()
+        && arg
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-_./:=,@+%".contains(c));
+    if plain {
+        arg.to_string()
+    } else {
+        format!("'{}'", arg.replace('\'', r"'\''"))
+    }
+}
+
+impl CommandExecutor for DryRunCommandExecutor {
+    fn podman_build(&self, args: &[String]) -> Result<Output> {
+        self.record_engine(Operation::Build, args);
+        Ok(Self::output(""))
+    }
+
+    fn podman_build_streaming(&self, args: &[String]) -> Result<ExitStatus> {
+        self.record_engine(Operation::Build, args);
+        Ok(ExitStatus::from_raw(0))
+    }
+
+    fn podman_run(&self, args: &[String]) -> Result<Output> {
+        self.record_engine(Operation::Run, args);
+        Ok(Self::output(""))
+    }
+
+    fn podman_run_streaming(&self, args: &[String]) -> Result<ExitStatus> {
+        self.record_engine(Operation::Run, args);
+        Ok(ExitStatus::from_raw(0))
+    }
+
+    fn podman_images(&self, args: &[String]) -> Result<Output> {
+        self.record_engine(Operation::Images, args);
+        // Answer reference filters with the requested image so existence checks pass
+        let reference = args
+            .iter()
+            .find_map(|arg| arg.strip_prefix("reference="))
+            .map(|r| match r.rsplit_once(':') {
+                Some((_, tag)) if !tag.contains('/') => r.to_string(),
+                _ => format!("{r}:latest"),
+            });
+        if args.iter().any(|arg| arg == "json") {
+            let images: Vec<serde_json::Value> = reference
+                .iter()
+                .map(|r| serde_json::json!({ "Id": "", "Names": [r] }))
+                .collect();
+            Ok(Self::output(&serde_json::Value::from(images).to_string()))
+        } else {
+            Ok(Self::output(
+                &reference.map(|r| r + "\n").unwrap_or_default(),
+            ))
+        }
+    }
+
+    fn podman_inspect(&self, args: &[String]) -> Result<Output> {
+        self.record_engine(Operation::Inspect, args);
+        // Formatted queries get no answer so callers fall back to the reference itself
+        if args.iter().any(|arg| arg.starts_with("--format")) {
+            Ok(Self::output(""))
+        } else {
+            Ok(Self::output(r#"[{"Size": 0}]"#))
+        }
+    }
+
+    fn podman_rmi(&self, args: &[String]) -> Result<Output> {
+        self.record_engine(Operation::Rmi, args);
+        Ok(Self::output(""))
+    }
+
+    fn podman_commit(&self, args: &[String]) -> Result<Output> {
+        self.record_engine(Operation::Commit, args);
+        Ok(Self::output(""))
+    }
+
+    fn podman_command(&self, args: &[String]) -> Result<Output> {
+        self.record_engine(Operation::Other, args);
+        Ok(Self::output(""))
+    }
+
+    fn check_command_in_container(&self, container_tag: &str, command: &str) -> Result<bool> {
+        self.record_engine(
+            Operation::Run,
+            &[
+                "--rm".to_string(),
+                format!("localhost/{container_tag}"),
+                "sh".to_string(),
+                "-c".to_string(),
+                format!("which {command}"),
+            ],
+        );
+        Ok(true)
+    }
+
+    fn bootc(&self, args: &[String]) -> Result<Output> {
+        self.record("bootc", None, args);
+        Ok(Self::output(""))
     }
 
     fn bootc_streaming(&self, args: &[String]) -> Result<ExitStatus> {
-        let status = std::process::Command::new("bootc")
-            .args(args)
-            .env("LC_ALL", "C.UTF-8")
-            .status()?;
-        Ok(status)
+        self.record("bootc", None, args);
+        Ok(ExitStatus::from_raw(0))
     }
 
     fn execute(&self, command: &str, args: &[String]) -> Result<Output> {
-        let output = std::process::Command::new(command).args(args).output()?;
-        Ok(output)
+        self.record(command, None, args);
+        let stdout = match command {
+            "losetup" if args.iter().any(|arg| arg == "--show") => "/dev/loop0\n",
+            _ => "",
+        };
+        Ok(Self::output(stdout))
+    }
+
+    fn execute_streaming(&self, command: &str, args: &[String]) -> Result<ExitStatus> {
+        self.record(command, None, args);
+        Ok(ExitStatus::from_raw(0))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_run_logged_captures_stdout_and_stderr() {
+        let temp_dir = TempDir::new().unwrap();
+        let log_path = temp_dir.path().join("stage.log");
+        let mut command = Command::new("sh");
+        command.args(["-c", "echo out; echo err >&2; exit 3"]);
+
+        let status = run_logged(command, &log_path).unwrap();
+
+        assert_eq!(status.code(), Some(3));
+        let log = std::fs::read_to_string(&log_path).unwrap();
+        assert!(log.contains("out\n"));
+        assert!(log.contains("err\n"));
     }
 }
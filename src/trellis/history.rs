@@ -0,0 +1,344 @@
+//! Local history of builds, updates and image generations.
+//!
+//! Every operation is stored as one JSON file under `<state_dir>/history`, named
+//! by an ID that sorts chronologically. Stage timings of successful builds are
+//! compared against the previous successful build to flag slowdowns.
+
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+/// Factor by which a stage must slow down to be flagged as a regression
+const REGRESSION_FACTOR: f64 = 1.5;
+
+/// Minimum slowdown in seconds for a regression, so short stages don't cause noise
+const REGRESSION_MIN_SECONDS: f64 = 10.0;
+
+/// Kind of operation recorded in the history.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Operation {
+    Build,
+    #[serde(rename = "build-builder")]
+    BuildBuilder,
+    Update,
+    QuickUpdate,
+    Image,
+}
+
+impl Operation {
+    /// Returns the command name of the operation.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Operation::Build => "build",
+            Operation::BuildBuilder => "build-builder",
+            Operation::Update => "update",
+            Operation::QuickUpdate => "quick-update",
+            Operation::Image => "image",
+        }
+    }
+}
+
+/// Time spent on a single stage.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StageTiming {
+    pub name: String,
+    pub seconds: f64,
+    /// Whether an existing image was reused instead of building
+    #[serde(default)]
+    pub reused: bool,
+}
+
+/// One recorded operation.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct HistoryEntry {
+    pub id: String,
+    pub operation: Operation,
+    /// Start time in seconds since the Unix epoch
+    pub started: u64,
+    /// End time in seconds since the Unix epoch
+    pub finished: u64,
+    /// Configured stage list
+    #[serde(default)]
+    pub stages: Vec<String>,
+    /// Timings of the stages that were reached
+    #[serde(default)]
+    pub stage_timings: Vec<StageTiming>,
+    pub success: bool,
+    /// Error message of a failed operation
+    pub error: Option<String>,
+    /// ID of the resulting image
+    pub image_id: Option<String>,
+    /// ID of the stage logs written by the build, see `trls logs`
+    pub log_id: Option<String>,
+    /// Effective configuration at the time of the operation
+    pub config: serde_json::Value,
+}
+
+/// A stage that took notably longer than in the previous successful build.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Regression {
+    pub stage: String,
+    pub previous_seconds: f64,
+    pub seconds: f64,
+    /// ID of the build compared against
+    pub previous_id: String,
+}
+
+impl std::fmt::Display for Regression {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Stage '{}' took {}, {:.0}% longer than in build {} ({})",
+            self.stage,
+            format_duration(self.seconds),
+            (self.seconds / self.previous_seconds - 1.0) * 100.0,
+            self.previous_id,
+            format_duration(self.previous_seconds)
+        )
+    }
+}
+
+impl HistoryEntry {
+    /// Returns the total duration of the operation in seconds.
+    pub fn duration(&self) -> u64 {
+        self.finished.saturating_sub(self.started)
+    }
+
+    /// Whether the entry carries timings of stages that were actually built.
+    fn has_built_stages(&self) -> bool {
+        self.stage_timings.iter().any(|timing| !timing.reused)
+    }
+
+    /// Compares built stages against the same stages in `previous`.
+    pub fn regressions(&self, previous: &HistoryEntry) -> Vec<Regression> {
+        self.stage_timings
+            .iter()
+            .filter(|timing| !timing.reused)
+            .filter_map(|timing| {
+                let before = previous
+                    .stage_timings
+                    .iter()
+                    .find(|t| t.name == timing.name && !t.reused)?;
+                let slower = timing.seconds > before.seconds * REGRESSION_FACTOR
+                    && timing.seconds - before.seconds >= REGRESSION_MIN_SECONDS;
+                slower.then(|| Regression {
+                    stage: timing.name.clone(),
+                    previous_seconds: before.seconds,
+                    seconds: timing.seconds,
+                    previous_id: previous.id.clone(),
+                })
+            })
+            .collect()
+    }
+}
+
+/// History entries stored under the state directory.
+pub struct History {
+    dir: PathBuf,
+}
+
+impl History {
+    pub fn new(state_dir: &Path) -> Self {
+        Self {
+            dir: state_dir.join("history"),
+        }
+    }
+
+    /// Writes an entry, creating the history directory if needed.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the entry cannot be written.
+    pub fn save(&self, entry: &HistoryEntry) -> Result<()> {
+        fs::create_dir_all(&self.dir).with_context(|| {
+            format!("Failed to create history directory: {}", self.dir.display())
+        })?;
+
+        let path = self.dir.join(format!("{}.json", entry.id));
+        let content =
+            serde_json::to_string_pretty(entry).context("Failed to serialize history entry")?;
+        fs::write(&path, content)
+            .with_context(|| format!("Failed to write history entry: {}", path.display()))
+    }
+
+    /// Loads all entries, oldest first.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the history directory or an entry cannot be read.
+    pub fn entries(&self) -> Result<Vec<HistoryEntry>> {
+        if !self.dir.exists() {
+            return Ok(Vec::new());
+        }
+
+        let mut paths = Vec::new();
+        for entry in fs::read_dir(&self.dir)
+            .with_context(|| format!("Failed to read history directory: {}", self.dir.display()))?
+        {
+            let path = entry?.path();
+            if path.extension().is_some_and(|ext| ext == "json") {
+                paths.push(path);
+            }
+        }
+        paths.sort();
+
+        paths
+            .iter()
+            .map(|path| {
+                let content = fs::read_to_string(path)
+                    .with_context(|| format!("Failed to read history entry: {}", path.display()))?;
+                serde_json::from_str(&content)
+                    .with_context(|| format!("Failed to parse history entry: {}", path.display()))
+            })
+            .collect()
+    }
+
+    /// Finds an entry by ID or unique ID prefix.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if no entry or more than one entry matches.
+    pub fn find(&self, id: &str) -> Result<HistoryEntry> {
+        let mut matches: Vec<HistoryEntry> = self
+            .entries()?
+            .into_iter()
+            .filter(|entry| entry.id.starts_with(id))
+            .collect();
+
+        match matches.len() {
+            1 => Ok(matches.remove(0)),
+            0 => Err(anyhow!("No history entry found for '{id}'")),
+            _ => Err(anyhow!(
+                "History ID '{id}' is ambiguous: {}",
+                matches
+                    .iter()
+                    .map(|entry| entry.id.as_str())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            )),
+        }
+    }
+
+    /// Returns the most recent successful entry with built stages that precedes `entry`.
+    ///
+    /// Builder builds are only compared with builder builds, since their stages are
+    /// not the rootfs stages.
+    pub fn previous_successful(
+        entries: &[HistoryEntry],
+        entry: &HistoryEntry,
+    ) -> Option<HistoryEntry> {
+        let builder = entry.operation == Operation::BuildBuilder;
+        entries
+            .iter()
+            .filter(|candidate| candidate.id < entry.id)
+            .filter(|candidate| (candidate.operation == Operation::BuildBuilder) == builder)
+            .rfind(|candidate| candidate.success && candidate.has_built_stages())
+            .cloned()
+    }
+}
+
+/// Formats a duration like `1h02m03s`, `5m12s` or `8.4s`.
+pub fn format_duration(seconds: f64) -> String {
+    let whole = seconds.round() as u64;
+    if whole >= 3600 {
+        format!(
+            "{}h{:02}m{:02}s",
+            whole / 3600,
+            whole % 3600 / 60,
+            whole % 60
+        )
+    } else if whole >= 60 {
+        format!("{}m{:02}s", whole / 60, whole % 60)
+    } else {
+        format!("{seconds:.1}s")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn entry(id: &str, success: bool, timings: &[(&str, f64)]) -> HistoryEntry {
+        HistoryEntry {
+            id: id.to_string(),
+            operation: Operation::Build,
+            started: 0,
+            finished: 100,
+            stages: timings.iter().map(|(name, _)| name.to_string()).collect(),
+            stage_timings: timings
+                .iter()
+                .map(|(name, seconds)| StageTiming {
+                    name: name.to_string(),
+                    seconds: *seconds,
+                    reused: false,
+                })
+                .collect(),
+            success,
+            error: None,
+            image_id: None,
+            log_id: None,
+            config: serde_json::Value::Null,
+        }
+    }
+
+    #[test]
+    fn test_history_round_trip_and_prefix_lookup() {
+        let temp_dir = TempDir::new().unwrap();
+        let history = History::new(temp_dir.path());
+        let first = entry("20240101-000000-aaaaaaaa", true, &[("base", 30.0)]);
+        let second = entry("20240102-000000-bbbbbbbb", false, &[]);
+        history.save(&second).unwrap();
+        history.save(&first).unwrap();
+
+        assert_eq!(history.entries().unwrap(), vec![first.clone(), second]);
+        assert_eq!(history.find("20240101").unwrap(), first);
+        assert!(history.find("2024").is_err());
+        assert!(history.find("2025").is_err());
+    }
+
+    #[test]
+    fn test_regressions_against_previous_successful_build() {
+        let entries = vec![
+            entry("1", true, &[("base", 60.0), ("final", 5.0)]),
+            entry("2", false, &[("base", 10.0)]),
+        ];
+        let current = entry("3", true, &[("base", 100.0), ("final", 9.0)]);
+
+        let previous = History::previous_successful(&entries, &current).unwrap();
+        assert_eq!(previous.id, "1");
+
+        // final is slower by more than the factor but by less than the minimum
+        let regressions = current.regressions(&previous);
+        assert_eq!(regressions.len(), 1);
+        assert_eq!(regressions[0].stage, "base");
+        assert_eq!(
+            regressions[0].to_string(),
+            "Stage 'base' took 1m40s, 67% longer than in build 1 (1m00s)"
+        );
+    }
+
+    #[test]
+    fn test_builder_builds_compared_with_builder_builds() {
+        let mut builder = entry("1", true, &[("base", 60.0)]);
+        builder.operation = Operation::BuildBuilder;
+        let entries = vec![builder, entry("2", true, &[("base", 10.0)])];
+
+        let rootfs = entry("3", true, &[("base", 100.0)]);
+        assert_eq!(
+            History::previous_successful(&entries, &rootfs).unwrap().id,
+            "2"
+        );
+
+        let mut current = entry("4", true, &[("base", 100.0)]);
+        current.operation = Operation::BuildBuilder;
+        assert_eq!(
+            History::previous_successful(&entries, &current).unwrap().id,
+            "1"
+        );
+    }
+}
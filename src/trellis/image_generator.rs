@@ -16,7 +16,10 @@ use std::{
     sync::Arc,
 };
 
-use super::{common::TrellisMessaging, executor::CommandExecutor};
+use super::{
+    common::TrellisMessaging, disk_space::DiskSpaceChecker, engine, executor::CommandExecutor,
+    interrupt::Cleanup, rootless,
+};
 use crate::config::{Config, TrellisConfig};
 
 /// Deserialization structure for podman inspect output.
@@ -86,6 +89,20 @@ impl<'a> ImageGenerator<'a> {
         Self { config, executor }
     }
 
+    /// Refuses image generation in rootless mode.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error explaining how to generate the image as root instead.
+    pub fn require_root(&self) -> Result<()> {
+        rootless::require_root(
+            self.config,
+            "trls image",
+            "bootc install attaches the disk image to a loop device and mounts its \
+             partitions, which rootless podman cannot do",
+        )
+    }
+
     /// Generate a bootable disk image from a container image.
     ///
     /// # Arguments
@@ -98,6 +115,8 @@ impl<'a> ImageGenerator<'a> {
     /// # Errors
     ///
     /// Returns an error if:
+    /// - trls runs in rootless mode
+    /// - The container engine does not use containers-storage
     /// - Container image doesn't exist
     /// - Size calculation fails (when size_gb is None)
     /// - Any operation in the generation process fails
@@ -109,6 +128,8 @@ impl<'a> ImageGenerator<'a> {
         size_gb: Option<u64>,
         root_password: Option<&str>,
     ) -> Result<()> {
+        self.require_root()?;
+        engine::require_containers_storage(self.config, "trls image")?;
         self.msg(&format!("Generating bootable image from {}", image_tag));
         // Validate image exists
         self.validate_image_exists(image_tag)?;
@@ -128,12 +149,24 @@ impl<'a> ImageGenerator<'a> {
                 calculated_size
             }
         };
-        // Create image file
+        // Create image file, removed again if the generation is interrupted
+        let created = !output_path.exists();
+        DiskSpaceChecker::new(self.config, self.executor.clone())
+            .check_image(output_path, final_size)?;
         self.create_image_file(output_path, final_size)?;
+        let image_file = Cleanup::on_interrupt(|| {
+            if created && std::fs::remove_file(output_path).is_ok() {
+                self.msg(&format!(
+                    "Removed incomplete image file: {}",
+                    output_path.display()
+                ));
+            }
+        });
         // Install bootable system using the ORIGINAL container image
         self.install_bootable_system(image_tag, output_path, filesystem)?;
         // Inject trellis configuration into the INSTALLED disk image
         self.inject_configuration_to_disk(output_path, root_password)?;
+        image_file.disarm();
         self.msg("Bootable image generated successfully");
         Ok(())
     }
@@ -268,6 +301,31 @@ impl<'a> ImageGenerator<'a> {
         Ok(())
     }
 
+    /// Returns the configuration written into disk images, with the stages and
+    /// `[[sources]]` of the build.
+    fn image_config(&self) -> Config {
+        let mut image_config = Config::default();
+
+        // Override with actual build values
+        if let Some(build) = &mut image_config.build {
+            build.builder_stages = Some(self.config.builder_stages.clone());
+            build.rootfs_stages = Some(self.config.rootfs_stages.clone());
+            build.builder_tag = Some(self.config.builder_tag.clone());
+            build.rootfs_tag = Some(self.config.rootfs_tag.clone());
+        }
+
+        if let Some(environment) = &mut image_config.environment {
+            environment.stages_dir = Some(PathBuf::from("/var/lib/trellis/stages"));
+        }
+
+        // Stages may come from sources, which the installed system checks out again
+        if !self.config.sources.is_empty() {
+            image_config.sources = Some(self.config.sources.iter().map(Into::into).collect());
+        }
+
+        image_config
+    }
+
     /// Inject trellis configuration into an installed disk image.
     ///
     /// This mounts the disk image's root partition and writes the trellis
@@ -288,23 +346,8 @@ impl<'a> ImageGenerator<'a> {
     ) -> Result<()> {
         self.msg("Injecting trellis configuration into disk image");
 
-        // Generate the configuration TOML
-        let mut image_config = Config::default();
-
-        // Override with actual build values
-        if let Some(build) = &mut image_config.build {
-            build.builder_stages = Some(self.config.builder_stages.clone());
-            build.rootfs_stages = Some(self.config.rootfs_stages.clone());
-            build.builder_tag = Some(self.config.builder_tag.clone());
-            build.rootfs_tag = Some(self.config.rootfs_tag.clone());
-        }
-
-        if let Some(environment) = &mut image_config.environment {
-            environment.stages_dir = Some(PathBuf::from("/var/lib/trellis/stages"));
-        }
-
-        let toml_content =
-            toml::to_string_pretty(&image_config).context("Failed to serialize configuration")?;
+        let toml_content = toml::to_string_pretty(&self.image_config())
+            .context("Failed to serialize configuration")?;
 
         // Set up the loopback device for the disk image
         let output = self.executor.execute(
@@ -324,6 +367,9 @@ impl<'a> ImageGenerator<'a> {
 
         let loop_device = String::from_utf8_lossy(&output.stdout).trim().to_string();
         self.msg(&format!("Created loopback device: {}", loop_device));
+        // Partial state is undone in reverse order: unmount, remove the mount point,
+        // detach the loopback device
+        let attached = Cleanup::new(|| self.detach_loop_device(&loop_device));
 
         // The root partition is typically partition 3 (after EFI and boot)
         // GPT disk layout from bootc: p1=EFI, p2=boot, p3=root
@@ -332,17 +378,12 @@ impl<'a> ImageGenerator<'a> {
         // Create a temporary mount point
         let mount_point =
             std::env::temp_dir().join(format!("trellis-mount-{}", std::process::id()));
-        std::fs::create_dir_all(&mount_point).context("Failed to create mount point")?;
-
-        // Function to ensure cleanup happens
-        let cleanup = |executor: &dyn CommandExecutor, mount_point: &Path, loop_device: &str| {
-            // Unmount
-            let _ = executor.execute("umount", &[mount_point.to_string_lossy().to_string()]);
-            // Remove mount point
-            let _ = std::fs::remove_dir(mount_point);
-            // Detach loopback device
-            let _ = executor.execute("losetup", &["-d".to_string(), loop_device.to_string()]);
-        };
+        if !self.config.dry_run {
+            std::fs::create_dir_all(&mount_point).context("Failed to create mount point")?;
+        }
+        let mount_dir = Cleanup::new(|| {
+            let _ = std::fs::remove_dir(&mount_point);
+        });
 
         // Mount the root partition
         let output = self.executor.execute(
@@ -354,7 +395,6 @@ impl<'a> ImageGenerator<'a> {
         )?;
 
         if !output.status.success() {
-            cleanup(self.executor.as_ref(), &mount_point, &loop_device);
             let stderr = String::from_utf8_lossy(&output.stderr);
             return Err(anyhow!(
                 "Failed to mount root partition {}: {}",
@@ -362,6 +402,7 @@ impl<'a> ImageGenerator<'a> {
                 stderr
             ));
         }
+        let mounted = Cleanup::new(|| self.unmount(&mount_point));
 
         self.msg(&format!(
             "Mounted {} at {}",
@@ -372,24 +413,25 @@ impl<'a> ImageGenerator<'a> {
         // Create trellis directories
         let trellis_config_dir = mount_point.join("etc/trellis");
         let trellis_stages_dir = mount_point.join("var/lib/trellis/stages");
-
-        if let Err(e) = std::fs::create_dir_all(&trellis_config_dir) {
-            cleanup(self.executor.as_ref(), &mount_point, &loop_device);
-            return Err(anyhow!("Failed to create config directory: {}", e));
-        }
-
-        if let Err(e) = std::fs::create_dir_all(&trellis_stages_dir) {
-            cleanup(self.executor.as_ref(), &mount_point, &loop_device);
-            return Err(anyhow!("Failed to create stages directory: {}", e));
-        }
-
-        // Write trellis.toml
         let config_path = trellis_config_dir.join("trellis.toml");
-        if let Err(e) = std::fs::write(&config_path, &toml_content) {
-            cleanup(self.executor.as_ref(), &mount_point, &loop_device);
-            return Err(anyhow!("Failed to write trellis.toml: {}", e));
+
+        // Nothing is mounted during a dry run, so skip writing into the mount point
+        if self.config.dry_run {
+            self.msg(&format!(
+                "Would write configuration to {}",
+                config_path.display()
+            ));
+        } else {
+            std::fs::create_dir_all(&trellis_config_dir)
+                .map_err(|e| anyhow!("Failed to create config directory: {}", e))?;
+            std::fs::create_dir_all(&trellis_stages_dir)
+                .map_err(|e| anyhow!("Failed to create stages directory: {}", e))?;
+
+            // Write trellis.toml
+            std::fs::write(&config_path, &toml_content)
+                .map_err(|e| anyhow!("Failed to write trellis.toml: {}", e))?;
+            self.msg(&format!("Wrote configuration to {}", config_path.display()));
         }
-        self.msg(&format!("Wrote configuration to {}", config_path.display()));
 
         // Copy stages directory if it exists
         if self.config.stages_dir.exists() {
@@ -409,47 +451,49 @@ impl<'a> ImageGenerator<'a> {
             )?;
 
             if !output.status.success() {
-                cleanup(self.executor.as_ref(), &mount_point, &loop_device);
                 let stderr = String::from_utf8_lossy(&output.stderr);
                 return Err(anyhow!("Failed to copy stages: {}", stderr));
             }
         }
 
         // Set root password if provided
-        if let Some(password) = root_password {
+        if let Some(password) = root_password.filter(|_| !self.config.dry_run) {
             self.msg("Setting root password in disk image");
-            if let Err(e) = self.set_root_password_in_shadow(&mount_point, password) {
-                cleanup(self.executor.as_ref(), &mount_point, &loop_device);
-                return Err(e);
-            }
+            self.set_root_password_in_shadow(&mount_point, password)?;
         }
 
         // Sync to ensure all writes are flushed
         let _ = self.executor.execute("sync", &[]);
 
         // Clean up: unmount and detach loopback
+        drop(mounted);
+        drop(mount_dir);
+        drop(attached);
+
+        self.msg("Configuration injected successfully");
+        Ok(())
+    }
+
+    /// Unmounts the root partition of a disk image, warning on failure.
+    fn unmount(&self, mount_point: &Path) {
         let output = self
             .executor
-            .execute("umount", &[mount_point.to_string_lossy().to_string()])?;
-
-        if !output.status.success() {
+            .execute("umount", &[mount_point.to_string_lossy().to_string()]);
+        if let Some(output) = output.ok().filter(|o| !o.status.success()) {
             let stderr = String::from_utf8_lossy(&output.stderr);
             self.warning(&format!("Failed to unmount cleanly: {}", stderr));
         }
+    }
 
-        let _ = std::fs::remove_dir(&mount_point);
-
+    /// Detaches the loopback device of a disk image, warning on failure.
+    fn detach_loop_device(&self, loop_device: &str) {
         let output = self
             .executor
-            .execute("losetup", &["-d".to_string(), loop_device.clone()])?;
-
-        if !output.status.success() {
+            .execute("losetup", &["-d".to_string(), loop_device.to_string()]);
+        if let Some(output) = output.ok().filter(|o| !o.status.success()) {
             let stderr = String::from_utf8_lossy(&output.stderr);
             self.warning(&format!("Failed to detach loopback device: {}", stderr));
         }
-
-        self.msg("Configuration injected successfully");
-        Ok(())
     }
 
     /// Install the bootable system using bootc.
@@ -477,7 +521,9 @@ impl<'a> ImageGenerator<'a> {
             ],
         )?;
         let stdout = String::from_utf8_lossy(&check_output.stdout);
-        if !check_output.status.success() || stdout.trim().is_empty() {
+        // A dry run does not run the probe, so there is nothing to check
+        let found = check_output.status.success() && !stdout.trim().is_empty();
+        if !found && !self.config.dry_run {
             return Err(anyhow!(
                 "Required filesystem tool mkfs.fat (or mkfs.vfat) not found in image '{}'. Please install dosfstools or provide an image with mkfs.fat available.",
                 image_tag
@@ -485,11 +531,18 @@ impl<'a> ImageGenerator<'a> {
         }
 
         // Build the full podman run command including the bootc command at the end
-        let mut run_args = vec![
-            "--rm".to_string(),
-            "--privileged".to_string(),
-            "--pid=host".to_string(),
-        ];
+        let security = &self.config.security.install;
+        self.msg(&format!(
+            "Security profile '{}' for installs: {}",
+            self.config.security.name,
+            security.describe()
+        ));
+        let mut run_args = vec!["--rm".to_string()];
+        if let Some(network) = security.network {
+            run_args.extend(["--net".to_string(), network.as_str().to_string()]);
+        }
+        run_args.extend(security.podman_args());
+        run_args.push("--pid=host".to_string());
 
         // Add SELinux bind mount only if the host path exists
         if std::path::Path::new("/sys/fs/selinux").exists() {
@@ -503,13 +556,11 @@ impl<'a> ImageGenerator<'a> {
             "-v".to_string(),
             "/etc/containers:/etc/containers:Z".to_string(),
             "-v".to_string(),
-            "/var/lib/containers:/var/lib/containers:Z".to_string(),
+            format!("{}:Z", rootless::storage_volume(self.config)),
             "-v".to_string(),
             "/dev:/dev".to_string(),
             "-v".to_string(),
             format!("{}:/data", output_dir.display()),
-            "--security-opt".to_string(),
-            "label=type:unconfined_t".to_string(),
             image_tag.to_string(),
             // Bootc command and args
             "bootc".to_string(),
@@ -694,19 +745,69 @@ mod tests {
             builder_tag: "test-builder".to_string(),
             podman_build_cache: false,
             auto_clean: false,
+            incremental: false,
+            preprocess: false,
+            layering: Default::default(),
+            rechunk: false,
+            rechunk_image: "quay.io/fedora/fedora-bootc:latest".to_string(),
             pacman_cache: None,
             aur_cache: None,
             stages_dir: PathBuf::from("/tmp"),
+            sources: Vec::new(),
+            sources_dir: PathBuf::from("/var/cache/trellis/sources"),
             rootfs_stages: vec!["base".to_string()],
             rootfs_base: "scratch".to_string(),
+            pull_policy: None,
             extra_contexts: vec![],
             extra_mounts: vec![],
+            secrets: Default::default(),
             rootfs_tag: "trellis-rootfs".to_string(),
+            keep_versions: 5,
             hooks_dir: None,
+            state_dir: std::env::temp_dir().join("trellis-test-state"),
+            log_dir: std::env::temp_dir().join("trellis-test-logs"),
+            log_retention: 10,
+            lock_dir: Default::default(),
+            rootless: false,
+            storage_dir: Default::default(),
+            privilege_command: None,
+            engine: Default::default(),
+            podman_socket: None,
             quiet: false,
+            profile: None,
+            config_path: Default::default(),
+            stage_configs: Default::default(),
+            vars: Default::default(),
+            security: Default::default(),
+            registry: None,
+            dry_run: false,
+            wait: false,
+            force: false,
         }
     }
 
+    #[test]
+    fn image_config_keeps_sources() {
+        use crate::config::StageSource;
+
+        let mut config = create_test_config();
+        config.sources = vec![StageSource {
+            name: "shared".to_string(),
+            git: "https://example.com/shared.git".to_string(),
+            reference: "v1".to_string(),
+            subdir: Some(PathBuf::from("stages")),
+        }];
+        let generator = ImageGenerator::new(
+            &config,
+            Arc::new(super::super::executor::RealCommandExecutor::new()),
+        );
+
+        let toml = toml::to_string_pretty(&generator.image_config()).unwrap();
+        let parsed: Config = toml::from_str(&toml).unwrap();
+        let resolved = StageSource::resolve_all(parsed.sources.as_ref()).unwrap();
+        assert_eq!(resolved, config.sources);
+    }
+
     #[test]
     fn resolve_image_tag_full_path_with_version() {
         let config = create_test_config();
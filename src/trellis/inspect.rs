@@ -0,0 +1,212 @@
+//! Layer inspection of built images.
+//!
+//! Combines `podman image inspect`, for the image ID, total size and layer count,
+//! with `podman history`, for the size of every layer and the instruction that
+//! created it.
+
+use anyhow::{anyhow, Context, Result};
+use serde::Deserialize;
+use std::sync::Arc;
+
+use super::executor::CommandExecutor;
+
+/// Maximum length of the creating instruction shown per layer.
+const CREATED_BY_WIDTH: usize = 72;
+
+#[derive(Debug, Deserialize)]
+struct ImageInspect {
+    #[serde(rename = "Id", default)]
+    id: String,
+    #[serde(rename = "Size", default)]
+    size: u64,
+    #[serde(rename = "RootFS")]
+    root_fs: Option<RootFs>,
+}
+
+#[derive(Debug, Deserialize)]
+struct RootFs {
+    #[serde(rename = "Layers", default)]
+    layers: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct HistoryRecord {
+    #[serde(default)]
+    size: u64,
+    #[serde(rename = "CreatedBy", default)]
+    created_by: String,
+}
+
+/// A layer of an image with the instruction that created it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Layer {
+    pub size: u64,
+    pub created_by: String,
+}
+
+/// Layer information of an image.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ImageLayers {
+    pub image: String,
+    pub id: String,
+    pub size: u64,
+    /// Number of layers in the image's root filesystem
+    pub layer_count: usize,
+    /// Layers that add content, oldest first
+    pub layers: Vec<Layer>,
+}
+
+/// Reads layer information through podman.
+pub struct ImageInspector {
+    executor: Arc<dyn CommandExecutor>,
+}
+
+impl ImageInspector {
+    pub fn new(executor: Arc<dyn CommandExecutor>) -> Self {
+        Self { executor }
+    }
+
+    /// Inspects the layers of an image.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the image does not exist or podman's output cannot be parsed.
+    pub fn layers(&self, image: &str) -> Result<ImageLayers> {
+        let output = self
+            .executor
+            .podman_inspect(&["--type".to_string(), "image".to_string(), image.to_string()])
+            .context("Failed to inspect image")?;
+        if !output.status.success() {
+            return Err(anyhow!(
+                "Image not found: {image}. {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            ));
+        }
+        let inspect: Vec<ImageInspect> = serde_json::from_slice(&output.stdout)
+            .context("Failed to parse podman inspect JSON output")?;
+        let inspect = inspect
+            .into_iter()
+            .next()
+            .ok_or_else(|| anyhow!("No image information returned for {image}"))?;
+
+        let output = self
+            .executor
+            .podman_command(&[
+                "history".to_string(),
+                "--no-trunc".to_string(),
+                "--format".to_string(),
+                "json".to_string(),
+                image.to_string(),
+            ])
+            .context("Failed to read image history")?;
+        if !output.status.success() {
+            return Err(anyhow!(
+                "podman history failed for {image}: {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            ));
+        }
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        let history: Vec<HistoryRecord> = if stdout.trim().is_empty() {
+            Vec::new()
+        } else {
+            serde_json::from_str(&stdout).context("Failed to parse podman history JSON output")?
+        };
+
+        // History is newest first, and instructions like ENV or LABEL add no layer
+        let layers = history
+            .into_iter()
+            .rev()
+            .filter(|record| record.size > 0)
+            .map(|record| Layer {
+                size: record.size,
+                created_by: record.created_by,
+            })
+            .collect();
+
+        Ok(ImageLayers {
+            image: image.to_string(),
+            id: inspect.id,
+            size: inspect.size,
+            layer_count: inspect.root_fs.map_or(0, |root_fs| root_fs.layers.len()),
+            layers,
+        })
+    }
+}
+
+impl std::fmt::Display for ImageLayers {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "Image:  {}", self.image)?;
+        writeln!(f, "ID:     {}", &self.id[..self.id.len().min(12)])?;
+        writeln!(f, "Size:   {}", format_size(self.size))?;
+        writeln!(f, "Layers: {}", self.layer_count)?;
+
+        if !self.layers.is_empty() {
+            writeln!(f)?;
+            writeln!(f, "{:<6} {:>10}  CREATED BY", "LAYER", "SIZE")?;
+            for (index, layer) in self.layers.iter().enumerate() {
+                writeln!(
+                    f,
+                    "{:<6} {:>10}  {}",
+                    index + 1,
+                    format_size(layer.size),
+                    shorten_instruction(&layer.created_by)
+                )?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Formats a size in bytes with decimal units, like podman does.
+pub fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "kB", "MB", "GB", "TB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1000.0 && unit < UNITS.len() - 1 {
+        size /= 1000.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{bytes} B")
+    } else {
+        format!("{size:.1} {}", UNITS[unit])
+    }
+}
+
+/// Strips the shell prefix from a history instruction and cuts it to a single line.
+fn shorten_instruction(created_by: &str) -> String {
+    let instruction = created_by
+        .trim_start_matches("/bin/sh -c ")
+        .trim_start_matches("#(nop) ")
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ");
+    if instruction.chars().count() > CREATED_BY_WIDTH {
+        let cut: String = instruction.chars().take(CREATED_BY_WIDTH - 3).collect();
+        format!("{cut}...")
+    } else {
+        instruction
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_size() {
+        assert_eq!(format_size(512), "512 B");
+        assert_eq!(format_size(1_500), "1.5 kB");
+        assert_eq!(format_size(812_300_000), "812.3 MB");
+    }
+
+    #[test]
+    fn test_shorten_instruction() {
+        assert_eq!(
+            shorten_instruction("/bin/sh -c #(nop) LABEL a=b"),
+            "LABEL a=b"
+        );
+        let long = format!("RUN {}", "x".repeat(100));
+        assert_eq!(shorten_instruction(&long).chars().count(), CREATED_BY_WIDTH);
+    }
+}
@@ -0,0 +1,219 @@
+//! Interruption by SIGINT or SIGTERM and cleanup of partial state.
+//!
+//! `install` replaces the default handlers, so an interrupted trls keeps running
+//! until its current child process exits. The signal is forwarded to the registered
+//! children, the command that was running returns an `Interrupted` error, and the
+//! `Cleanup` guards of the unwinding operations undo their partial state in reverse
+//! order. A second signal exits immediately.
+
+use anyhow::Result;
+use std::{
+    fmt,
+    process::Child,
+    sync::atomic::{AtomicBool, AtomicI32, Ordering},
+};
+
+/// Number of child processes signals are forwarded to.
+const MAX_CHILDREN: usize = 16;
+
+/// Signal that interrupted trls, or 0.
+static SIGNAL: AtomicI32 = AtomicI32::new(0);
+
+/// Process IDs of the running child processes, 0 for free slots.
+static CHILDREN: [AtomicI32; MAX_CHILDREN] = [const { AtomicI32::new(0) }; MAX_CHILDREN];
+
+/// Whether signals exit right away, as there is no partial state to undo.
+static EXIT_IMMEDIATELY: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn handle_signal(signal: libc::c_int) {
+    // Exit on a second signal, giving up on cleanup
+    if SIGNAL.swap(signal, Ordering::SeqCst) != 0 || EXIT_IMMEDIATELY.load(Ordering::SeqCst) {
+        unsafe { libc::_exit(128 + signal) };
+    }
+    for child in &CHILDREN {
+        let pid = child.load(Ordering::SeqCst);
+        if pid > 0 {
+            unsafe { libc::kill(pid, signal) };
+        }
+    }
+}
+
+/// Installs the SIGINT and SIGTERM handlers.
+pub fn install() {
+    for signal in [libc::SIGINT, libc::SIGTERM] {
+        unsafe {
+            let mut action: libc::sigaction = std::mem::zeroed();
+            action.sa_sigaction = handle_signal as *const () as libc::sighandler_t;
+            action.sa_flags = libc::SA_RESTART;
+            libc::sigemptyset(&mut action.sa_mask);
+            libc::sigaction(signal, &action, std::ptr::null_mut());
+        }
+    }
+}
+
+/// Runs `f` with signals exiting trls right away, for waits without partial state
+/// such as reading user input, which would otherwise resume after the signal.
+pub fn exit_immediately<T>(f: impl FnOnce() -> T) -> T {
+    let previous = EXIT_IMMEDIATELY.swap(true, Ordering::SeqCst);
+    let result = f();
+    EXIT_IMMEDIATELY.store(previous, Ordering::SeqCst);
+    result
+}
+
+/// Returns the signal that interrupted trls, if any.
+pub fn signal() -> Option<i32> {
+    match SIGNAL.load(Ordering::SeqCst) {
+        0 => None,
+        signal => Some(signal),
+    }
+}
+
+/// Checks whether trls was interrupted.
+pub fn interrupted() -> bool {
+    signal().is_some()
+}
+
+/// Marks trls as interrupted by `signal`, as the signal handler does.
+///
+/// Test hook for simulating an interruption; trls itself only sets the signal
+/// from its handler.
+#[allow(dead_code)]
+pub fn interrupt(signal: i32) {
+    SIGNAL.store(signal, Ordering::SeqCst);
+}
+
+/// Clears a previous interruption.
+///
+/// Test hook for isolating tests that share the process-wide interruption state.
+#[allow(dead_code)]
+pub fn reset() {
+    SIGNAL.store(0, Ordering::SeqCst);
+}
+
+/// Exit status of an interrupted trls: 128 plus the signal number.
+pub fn exit_code() -> i32 {
+    128 + signal().unwrap_or(libc::SIGINT)
+}
+
+/// Returns an `Interrupted` error if trls was interrupted.
+///
+/// # Errors
+///
+/// Returns an error after SIGINT or SIGTERM.
+pub fn check() -> Result<()> {
+    match signal() {
+        Some(signal) => Err(Interrupted { signal }.into()),
+        None => Ok(()),
+    }
+}
+
+/// Error returned by operations stopped by a signal.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Interrupted {
+    pub signal: i32,
+}
+
+impl fmt::Display for Interrupted {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self.signal {
+            libc::SIGINT => "SIGINT",
+            libc::SIGTERM => "SIGTERM",
+            _ => "a signal",
+        };
+        write!(f, "Interrupted by {name}")
+    }
+}
+
+impl std::error::Error for Interrupted {}
+
+/// Registers a child process to forward signals to while it runs.
+pub struct ChildGuard {
+    slot: Option<usize>,
+}
+
+impl ChildGuard {
+    /// Registers `child` until the guard is dropped. Children beyond the first
+    /// `MAX_CHILDREN` still receive terminal signals, but not forwarded ones.
+    pub fn new(child: &Child) -> Self {
+        let pid = i32::try_from(child.id()).unwrap_or(0);
+        let slot = CHILDREN.iter().position(|slot| {
+            slot.compare_exchange(0, pid, Ordering::SeqCst, Ordering::SeqCst)
+                .is_ok()
+        });
+        Self { slot }
+    }
+}
+
+impl Drop for ChildGuard {
+    fn drop(&mut self) {
+        if let Some(slot) = self.slot {
+            CHILDREN[slot].store(0, Ordering::SeqCst);
+        }
+    }
+}
+
+/// Undoes a step of partial state when dropped, unless it was disarmed.
+///
+/// Guards created one after another run in reverse order, like the locals they are.
+pub struct Cleanup<F: FnOnce()> {
+    action: Option<F>,
+    on_interrupt_only: bool,
+}
+
+impl<F: FnOnce()> Cleanup<F> {
+    /// Runs `action` when the guard is dropped.
+    pub fn new(action: F) -> Self {
+        Self {
+            action: Some(action),
+            on_interrupt_only: false,
+        }
+    }
+
+    /// Runs `action` when the guard is dropped after trls was interrupted.
+    pub fn on_interrupt(action: F) -> Self {
+        Self {
+            action: Some(action),
+            on_interrupt_only: true,
+        }
+    }
+
+    /// Keeps the state, for steps that completed.
+    pub fn disarm(mut self) {
+        self.action = None;
+    }
+}
+
+impl<F: FnOnce()> Drop for Cleanup<F> {
+    fn drop(&mut self) {
+        if let Some(action) = self.action.take() {
+            if !self.on_interrupt_only || interrupted() {
+                action();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cleanup_runs_in_reverse_order_unless_disarmed() {
+        let steps = std::cell::RefCell::new(Vec::new());
+        {
+            let _first = Cleanup::new(|| steps.borrow_mut().push("first"));
+            let second = Cleanup::new(|| steps.borrow_mut().push("second"));
+            let _third = Cleanup::new(|| steps.borrow_mut().push("third"));
+            second.disarm();
+        }
+        assert_eq!(*steps.borrow(), ["third", "first"]);
+    }
+
+    #[test]
+    fn test_interrupted_display() {
+        let error = Interrupted {
+            signal: libc::SIGTERM,
+        };
+        assert_eq!(error.to_string(), "Interrupted by SIGTERM");
+    }
+}
@@ -0,0 +1,747 @@
+//! Podman's libpod REST API.
+//!
+//! With `api = true` in `[engine]`, image and container queries go to the libpod
+//! REST API on podman's socket instead of spawning a `podman` process for each.
+//! `LibpodClient` returns typed models, which `LibpodCommandExecutor` hands to the
+//! typed queries of the executor, such as `list_images`. Builds, containers and
+//! every other command run on the command line.
+//!
+//! The command-line executors parse the same models from `--format json`.
+
+use anyhow::{anyhow, Context, Result};
+use serde::Deserialize;
+use std::{
+    io::{ErrorKind, Read, Write},
+    os::unix::{net::UnixStream, process::ExitStatusExt},
+    path::{Path, PathBuf},
+    process::{ExitStatus, Output},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use super::{base_image::is_digest, common::UtcTime, executor::CommandExecutor, interrupt};
+
+/// API version in request paths, served by podman 4 and later.
+pub const API_VERSION: &str = "v4.0.0";
+
+/// Time a request may wait for the API without receiving any data.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// Interval at which a waiting request checks whether trls was interrupted.
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// An image from `GET /libpod/images/json` or `podman images --format json`.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct ImageSummary {
+    pub id: String,
+    /// Tagged references, `repository:tag`
+    #[serde(default)]
+    pub repo_tags: Option<Vec<String>>,
+    /// Names in local storage, which `podman images` lists instead of `RepoTags`
+    #[serde(default)]
+    pub names: Option<Vec<String>>,
+    /// Creation time in seconds since the Unix epoch
+    #[serde(default)]
+    pub created: i64,
+}
+
+impl ImageSummary {
+    /// Returns the tagged references of the image, none for dangling images.
+    pub fn references(&self) -> impl Iterator<Item = &str> {
+        self.repo_tags
+            .as_deref()
+            .filter(|tags| !tags.is_empty())
+            .or(self.names.as_deref())
+            .unwrap_or_default()
+            .iter()
+            .map(String::as_str)
+            .filter(|reference| !reference.contains('@'))
+    }
+
+    /// Parses the output of `images --format json`: a JSON array from podman, or
+    /// one object per line from docker.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the output is not a list of images.
+    pub fn parse_list(output: &[u8]) -> Result<Vec<Self>> {
+        let output = String::from_utf8_lossy(output);
+        let output = output.trim();
+        if output.starts_with('[') {
+            return serde_json::from_str(output).context("Failed to parse image list");
+        }
+        output
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| {
+                serde_json::from_str::<DockerImageSummary>(line)
+                    .map(Self::from)
+                    .context("Failed to parse image list")
+            })
+            .collect()
+    }
+}
+
+/// An image as listed by `docker images --format json`.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct DockerImageSummary {
+    #[serde(rename = "ID")]
+    id: String,
+    repository: String,
+    tag: String,
+    #[serde(default)]
+    created_at: String,
+}
+
+impl From<DockerImageSummary> for ImageSummary {
+    fn from(image: DockerImageSummary) -> Self {
+        let tagged = image.repository != "<none>" && image.tag != "<none>";
+        Self {
+            id: image.id,
+            repo_tags: tagged.then(|| vec![format!("{}:{}", image.repository, image.tag)]),
+            names: None,
+            created: parse_docker_time(&image.created_at).unwrap_or_default(),
+        }
+    }
+}
+
+/// Parses a time as docker prints it, e.g. `2024-01-01 12:00:00 +0100 CET`, to
+/// seconds since the Unix epoch.
+fn parse_docker_time(text: &str) -> Option<i64> {
+    let mut fields = text.split_whitespace();
+    let numbers = |field: &str, separator: char| -> Option<Vec<u32>> {
+        field.split(separator).map(|n| n.parse().ok()).collect()
+    };
+    let date = numbers(fields.next()?, '-')?;
+    let time = numbers(fields.next()?, ':')?;
+    let ([year, month, day], [hour, minute, second]) = (date.as_slice(), time.as_slice()) else {
+        return None;
+    };
+
+    let offset = fields.next()?;
+    let (sign, hhmm) = match offset.split_at_checked(1)? {
+        ("+", hhmm) => (1, hhmm),
+        ("-", hhmm) => (-1, hhmm),
+        _ => return None,
+    };
+    let hhmm: i64 = hhmm.parse().ok()?;
+
+    let time = UtcTime {
+        year: i64::from(*year),
+        month: *month,
+        day: *day,
+        hour: *hour,
+        minute: *minute,
+        second: *second,
+    };
+    Some(time.to_unix() - sign * (hhmm / 100 * 3_600 + hhmm % 100 * 60))
+}
+
+/// Inspect data of an image, from `GET /libpod/images/{name}/json` or
+/// `podman inspect`.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct ImageInspect {
+    #[serde(default)]
+    pub id: String,
+    /// Manifest digest, only reported by podman
+    #[serde(default)]
+    pub digest: Option<String>,
+    /// `repository@digest` references the image was pulled or pushed as
+    #[serde(default)]
+    pub repo_digests: Option<Vec<String>>,
+    /// Size in bytes
+    #[serde(default)]
+    pub size: u64,
+}
+
+impl ImageInspect {
+    /// Returns the registry digest of the image: the manifest digest, or the
+    /// digest of its first repository reference.
+    pub fn registry_digest(&self) -> Option<&str> {
+        let repo_digests = self
+            .repo_digests
+            .iter()
+            .flatten()
+            .filter_map(|reference| reference.split_once('@'))
+            .map(|(_, digest)| digest);
+        self.digest
+            .as_deref()
+            .into_iter()
+            .chain(repo_digests)
+            .find(|digest| is_digest(digest))
+    }
+}
+
+/// Result of `DELETE /libpod/images/remove`.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct ImageRemoveReport {
+    #[serde(default)]
+    pub deleted: Option<Vec<String>>,
+    #[serde(default)]
+    pub untagged: Option<Vec<String>>,
+    #[serde(default)]
+    pub errors: Option<Vec<String>>,
+    #[serde(default)]
+    pub exit_code: i32,
+}
+
+/// Error body of failed requests.
+#[derive(Debug, Deserialize)]
+struct ApiError {
+    #[serde(default)]
+    message: String,
+}
+
+/// A response from the API.
+#[derive(Debug, Clone)]
+pub struct ApiResponse {
+    pub status: u16,
+    pub body: Vec<u8>,
+}
+
+impl ApiResponse {
+    /// Parses an HTTP/1.1 response, with a `Content-Length`, chunked or read to EOF.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the status line, headers or chunks are malformed.
+    pub fn parse(raw: &[u8]) -> Result<Self> {
+        let head_end = raw
+            .windows(4)
+            .position(|window| window == b"\r\n\r\n")
+            .ok_or_else(|| anyhow!("Incomplete response from the podman API"))?;
+        let head = String::from_utf8_lossy(&raw[..head_end]);
+        let mut lines = head.split("\r\n");
+        let status = lines
+            .next()
+            .and_then(|line| line.split_whitespace().nth(1))
+            .and_then(|code| code.parse().ok())
+            .ok_or_else(|| anyhow!("Invalid status line from the podman API"))?;
+
+        let mut chunked = false;
+        let mut content_length = None;
+        for line in lines {
+            let Some((name, value)) = line.split_once(':') else {
+                continue;
+            };
+            let value = value.trim();
+            if name.eq_ignore_ascii_case("transfer-encoding") {
+                chunked = value.eq_ignore_ascii_case("chunked");
+            } else if name.eq_ignore_ascii_case("content-length") {
+                content_length = value.parse::<usize>().ok();
+            }
+        }
+
+        let rest = &raw[head_end + 4..];
+        let body = if chunked {
+            Self::decode_chunked(rest)?
+        } else {
+            match content_length {
+                Some(length) => rest[..length.min(rest.len())].to_vec(),
+                None => rest.to_vec(),
+            }
+        };
+        Ok(Self { status, body })
+    }
+
+    fn decode_chunked(mut data: &[u8]) -> Result<Vec<u8>> {
+        let mut body = Vec::new();
+        loop {
+            let line_end = data
+                .windows(2)
+                .position(|window| window == b"\r\n")
+                .ok_or_else(|| anyhow!("Malformed chunk from the podman API"))?;
+            let size_line = String::from_utf8_lossy(&data[..line_end]);
+            let size_field = size_line.split(';').next().unwrap_or_default().trim();
+            let size = usize::from_str_radix(size_field, 16)
+                .with_context(|| format!("Invalid chunk size '{size_field}'"))?;
+            data = &data[line_end + 2..];
+            if size == 0 {
+                return Ok(body);
+            }
+            if data.len() < size {
+                return Err(anyhow!("Truncated chunk from the podman API"));
+            }
+            body.extend_from_slice(&data[..size]);
+            data = data.get(size + 2..).unwrap_or_default();
+        }
+    }
+
+    pub fn is_success(&self) -> bool {
+        (200..300).contains(&self.status)
+    }
+
+    /// Deserializes the JSON body.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the body is not the expected JSON.
+    pub fn json<T: serde::de::DeserializeOwned>(&self) -> Result<T> {
+        serde_json::from_slice(&self.body).context("Failed to parse podman API response")
+    }
+
+    /// Returns the error message of a failed request.
+    pub fn error_message(&self) -> String {
+        serde_json::from_slice::<ApiError>(&self.body)
+            .map(|error| error.message)
+            .unwrap_or_else(|_| String::from_utf8_lossy(&self.body).trim().to_string())
+    }
+}
+
+/// Client of the libpod REST API on a Unix socket.
+#[derive(Debug, Clone)]
+pub struct LibpodClient {
+    socket: PathBuf,
+}
+
+impl LibpodClient {
+    pub fn new(socket: impl Into<PathBuf>) -> Self {
+        Self {
+            socket: socket.into(),
+        }
+    }
+
+    /// Sends a request without body to `path` under `/libpod`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the socket cannot be reached or the response is malformed.
+    pub fn request(&self, method: &str, path: &str) -> Result<ApiResponse> {
+        let mut stream = UnixStream::connect(&self.socket).with_context(|| {
+            format!(
+                "Failed to connect to the podman API at {}. Enable it with \
+                 `systemctl enable --now podman.socket` or set api = false in [engine]",
+                self.socket.display()
+            )
+        })?;
+        stream
+            .set_write_timeout(Some(REQUEST_TIMEOUT))
+            .and_then(|()| stream.set_read_timeout(Some(POLL_INTERVAL)))
+            .context("Failed to set timeouts on the podman API socket")?;
+        write!(
+            stream,
+            "{method} /{API_VERSION}/libpod{path} HTTP/1.1\r\n\
+             Host: d\r\nConnection: close\r\nContent-Length: 0\r\n\r\n"
+        )
+        .context("Failed to send request to the podman API")?;
+
+        // Reads in short intervals so an interrupt or a stuck service ends the wait
+        let mut raw = Vec::new();
+        let mut buf = [0u8; 8192];
+        let mut last_data = Instant::now();
+        loop {
+            match stream.read(&mut buf) {
+                Ok(0) => break,
+                Ok(n) => {
+                    raw.extend_from_slice(&buf[..n]);
+                    last_data = Instant::now();
+                }
+                Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {
+                    interrupt::check()?;
+                    if last_data.elapsed() >= REQUEST_TIMEOUT {
+                        return Err(anyhow!(
+                            "The podman API at {} did not respond within {} seconds",
+                            self.socket.display(),
+                            REQUEST_TIMEOUT.as_secs()
+                        ));
+                    }
+                }
+                Err(e) if e.kind() == ErrorKind::Interrupted => interrupt::check()?,
+                Err(e) => {
+                    return Err(e).context("Failed to read response from the podman API");
+                }
+            }
+        }
+        ApiResponse::parse(&raw)
+    }
+
+    /// Lists the images, only those matching `reference` if given.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the request fails.
+    pub fn images(&self, reference: Option<&str>) -> Result<Vec<ImageSummary>> {
+        let mut path = "/images/json".to_string();
+        if let Some(reference) = reference {
+            let filters = serde_json::json!({ "reference": [reference] });
+            path.push_str(&format!("?filters={}", encode(&filters.to_string())));
+        }
+        let response = self.request("GET", &path)?;
+        if !response.is_success() {
+            return Err(anyhow!(
+                "Failed to list images: {}",
+                response.error_message()
+            ));
+        }
+        response.json()
+    }
+
+    /// Returns the inspect data of an image, or `None` if it does not exist.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the request fails for another reason.
+    pub fn inspect_image(&self, name: &str) -> Result<Option<ImageInspect>> {
+        let response = self.request("GET", &format!("/images/{}/json", encode_path(name)))?;
+        match response.status {
+            404 => Ok(None),
+            _ if response.is_success() => response.json().map(Some),
+            _ => Err(anyhow!(
+                "Failed to inspect {name}: {}",
+                response.error_message()
+            )),
+        }
+    }
+
+    /// Checks whether an image exists locally.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the request fails.
+    pub fn image_exists(&self, name: &str) -> Result<bool> {
+        self.exists(&format!("/images/{}/exists", encode_path(name)))
+    }
+
+    /// Checks whether a container exists.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the request fails.
+    pub fn container_exists(&self, name: &str) -> Result<bool> {
+        self.exists(&format!("/containers/{}/exists", encode_path(name)))
+    }
+
+    fn exists(&self, path: &str) -> Result<bool> {
+        let response = self.request("GET", path)?;
+        match response.status {
+            204 => Ok(true),
+            404 => Ok(false),
+            _ => Err(anyhow!("Podman API error: {}", response.error_message())),
+        }
+    }
+
+    /// Removes images, like `podman rmi`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the request fails without a removal report.
+    pub fn remove_images(
+        &self,
+        images: &[String],
+        force: bool,
+        ignore: bool,
+    ) -> Result<ImageRemoveReport> {
+        let mut query: Vec<String> = images
+            .iter()
+            .map(|image| format!("images={}", encode(image)))
+            .collect();
+        query.push(format!("force={force}"));
+        query.push(format!("ignore={ignore}"));
+        let response = self.request("DELETE", &format!("/images/remove?{}", query.join("&")))?;
+        match response.json::<ImageRemoveReport>() {
+            Ok(report) => Ok(report),
+            Err(_) if !response.is_success() => Ok(ImageRemoveReport {
+                errors: Some(vec![response.error_message()]),
+                exit_code: 1,
+                ..Default::default()
+            }),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Removes a container, like `podman rm`. Returns false if it does not exist.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the container cannot be removed.
+    pub fn remove_container(&self, name: &str, force: bool) -> Result<bool> {
+        let response = self.request(
+            "DELETE",
+            &format!("/containers/{}?force={force}", encode_path(name)),
+        )?;
+        match response.status {
+            404 => Ok(false),
+            _ if response.is_success() => Ok(true),
+            _ => Err(anyhow!(
+                "Failed to remove container {name}: {}",
+                response.error_message()
+            )),
+        }
+    }
+}
+
+/// Percent-encodes a query value.
+fn encode(value: &str) -> String {
+    encode_except(value, "-._~")
+}
+
+/// Percent-encodes a name in a path, keeping the `/` and `:` of image references.
+fn encode_path(value: &str) -> String {
+    encode_except(value, "-._~/:@")
+}
+
+fn encode_except(value: &str, keep: &str) -> String {
+    value
+        .bytes()
+        .map(|byte| {
+            if byte.is_ascii_alphanumeric() || keep.as_bytes().contains(&byte) {
+                (byte as char).to_string()
+            } else {
+                format!("%{byte:02X}")
+            }
+        })
+        .collect()
+}
+
+/// Options of a podman command line relevant to the API.
+#[derive(Default)]
+struct ParsedArgs<'a> {
+    flags: Vec<&'a str>,
+    names: Vec<&'a str>,
+}
+
+impl<'a> ParsedArgs<'a> {
+    fn parse(args: &'a [String]) -> Self {
+        let (flags, names) = args
+            .iter()
+            .map(String::as_str)
+            .partition(|arg| arg.starts_with('-'));
+        Self { flags, names }
+    }
+
+    /// Checks that every flag is one of `allowed`.
+    fn only_flags(&self, allowed: &[&str]) -> bool {
+        self.flags.iter().all(|flag| allowed.contains(flag))
+    }
+}
+
+/// Executor answering image and container queries through the libpod REST API.
+///
+/// Typed image queries, `rmi` and the existence and removal of containers go to
+/// the API. Calls with options the API does not take and everything else go to
+/// the fallback executor.
+pub struct LibpodCommandExecutor {
+    client: LibpodClient,
+    fallback: Arc<dyn CommandExecutor>,
+}
+
+impl LibpodCommandExecutor {
+    pub fn new(client: LibpodClient, fallback: Arc<dyn CommandExecutor>) -> Self {
+        Self { client, fallback }
+    }
+
+    fn output(code: i32, stdout: String, stderr: String) -> Output {
+        Output {
+            status: ExitStatus::from_raw(code << 8),
+            stdout: stdout.into_bytes(),
+            stderr: stderr.into_bytes(),
+        }
+    }
+
+    /// Answers `podman rmi`, or returns `None` to leave it to podman.
+    fn rmi(&self, args: &[String]) -> Option<Result<Output>> {
+        let parsed = ParsedArgs::parse(args);
+        if !parsed.only_flags(&["-f", "--force", "-i", "--ignore"]) || parsed.names.is_empty() {
+            return None;
+        }
+        let force = parsed.flags.iter().any(|f| matches!(*f, "-f" | "--force"));
+        let ignore = parsed.flags.iter().any(|f| matches!(*f, "-i" | "--ignore"));
+        let images: Vec<String> = parsed.names.iter().map(|name| name.to_string()).collect();
+
+        Some(
+            self.client
+                .remove_images(&images, force, ignore)
+                .map(|report| {
+                    let mut stdout = String::new();
+                    for line in report.untagged.iter().chain(&report.deleted).flatten() {
+                        stdout.push_str(&format!("{line}\n"));
+                    }
+                    let stderr: String = report
+                        .errors
+                        .iter()
+                        .flatten()
+                        .map(|error| format!("Error: {error}\n"))
+                        .collect();
+                    Self::output(report.exit_code, stdout, stderr)
+                }),
+        )
+    }
+
+    /// Answers `container exists` and `rm`, or returns `None`.
+    fn command(&self, args: &[String]) -> Option<Result<Output>> {
+        let args: Vec<&str> = args.iter().map(String::as_str).collect();
+        let (exists, not_found) = match args.as_slice() {
+            ["container", "exists", name] => (self.client.container_exists(name), String::new()),
+            ["rm", "--force" | "-f", name] => (
+                self.client.remove_container(name, true),
+                format!("Error: no container with name or ID \"{name}\" found\n"),
+            ),
+            ["rm", name] if !name.starts_with('-') => (
+                self.client.remove_container(name, false),
+                format!("Error: no container with name or ID \"{name}\" found\n"),
+            ),
+            _ => return None,
+        };
+        Some(exists.map(|exists| {
+            if exists {
+                Self::output(0, String::new(), String::new())
+            } else {
+                Self::output(1, String::new(), not_found)
+            }
+        }))
+    }
+}
+
+impl CommandExecutor for LibpodCommandExecutor {
+    fn podman_build(&self, args: &[String]) -> Result<Output> {
+        self.fallback.podman_build(args)
+    }
+
+    fn podman_build_streaming(&self, args: &[String]) -> Result<ExitStatus> {
+        self.fallback.podman_build_streaming(args)
+    }
+
+    fn podman_build_logged(&self, args: &[String], log_path: &Path) -> Result<ExitStatus> {
+        self.fallback.podman_build_logged(args, log_path)
+    }
+
+    fn podman_run(&self, args: &[String]) -> Result<Output> {
+        self.fallback.podman_run(args)
+    }
+
+    fn podman_run_streaming(&self, args: &[String]) -> Result<ExitStatus> {
+        self.fallback.podman_run_streaming(args)
+    }
+
+    fn podman_images(&self, args: &[String]) -> Result<Output> {
+        self.fallback.podman_images(args)
+    }
+
+    fn podman_inspect(&self, args: &[String]) -> Result<Output> {
+        self.fallback.podman_inspect(args)
+    }
+
+    fn podman_rmi(&self, args: &[String]) -> Result<Output> {
+        self.rmi(args)
+            .unwrap_or_else(|| self.fallback.podman_rmi(args))
+    }
+
+    fn podman_commit(&self, args: &[String]) -> Result<Output> {
+        self.fallback.podman_commit(args)
+    }
+
+    fn podman_command(&self, args: &[String]) -> Result<Output> {
+        self.command(args)
+            .unwrap_or_else(|| self.fallback.podman_command(args))
+    }
+
+    fn list_images(&self, reference: Option<&str>) -> Result<Vec<ImageSummary>> {
+        self.client.images(reference)
+    }
+
+    fn image_exists(&self, name: &str) -> Result<bool> {
+        self.client.image_exists(name)
+    }
+
+    fn inspect_image(&self, name: &str) -> Result<Option<ImageInspect>> {
+        self.client.inspect_image(name)
+    }
+
+    fn check_command_in_container(&self, container_tag: &str, command: &str) -> Result<bool> {
+        self.fallback
+            .check_command_in_container(container_tag, command)
+    }
+
+    fn bootc(&self, args: &[String]) -> Result<Output> {
+        self.fallback.bootc(args)
+    }
+
+    fn bootc_streaming(&self, args: &[String]) -> Result<ExitStatus> {
+        self.fallback.bootc_streaming(args)
+    }
+
+    fn execute(&self, command: &str, args: &[String]) -> Result<Output> {
+        self.fallback.execute(command, args)
+    }
+
+    fn execute_streaming(&self, command: &str, args: &[String]) -> Result<ExitStatus> {
+        self.fallback.execute_streaming(command, args)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_content_length_response() {
+        let raw =
+            b"HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: 2\r\n\r\n[]";
+        let response = ApiResponse::parse(raw).unwrap();
+        assert_eq!(response.status, 200);
+        assert_eq!(response.body, b"[]");
+    }
+
+    #[test]
+    fn test_parse_chunked_response() {
+        let raw = b"HTTP/1.1 404 Not Found\r\nTransfer-Encoding: chunked\r\n\r\n\
+                    4\r\n{\"me\r\nb\r\nssage\":\"x\"}\r\n0\r\n\r\n";
+        let response = ApiResponse::parse(raw).unwrap();
+        assert_eq!(response.status, 404);
+        assert_eq!(response.error_message(), "x");
+    }
+
+    #[test]
+    fn test_parse_podman_and_docker_image_lists() {
+        let podman = br#"[{"Id": "aa", "RepoTags": null, "Names": ["localhost/x:latest", "quay.io/x@sha256:bb"], "Created": 1704067200}]"#;
+        let images = ImageSummary::parse_list(podman).unwrap();
+        assert_eq!(
+            images[0].references().collect::<Vec<_>>(),
+            ["localhost/x:latest"]
+        );
+        assert_eq!(images[0].created, 1_704_067_200);
+
+        let docker = br#"{"ID": "aa", "Repository": "localhost/x", "Tag": "latest", "CreatedAt": "2024-01-01 01:00:00 +0100 CET", "Digest": "<none>"}
+{"ID": "bb", "Repository": "<none>", "Tag": "<none>", "CreatedAt": "2024-01-01 00:00:00 +0000 UTC"}
+"#;
+        let images = ImageSummary::parse_list(docker).unwrap();
+        assert_eq!(
+            images[0].references().collect::<Vec<_>>(),
+            ["localhost/x:latest"]
+        );
+        assert_eq!(images[0].created, 1_704_067_200);
+        assert_eq!(images[1].references().count(), 0);
+
+        assert!(ImageSummary::parse_list(b"[]").unwrap().is_empty());
+        assert!(ImageSummary::parse_list(b"malformed").is_err());
+    }
+
+    #[test]
+    fn test_registry_digest_from_podman_and_docker_inspect() {
+        let digest = format!("sha256:{}", "a".repeat(64));
+        let podman: ImageInspect =
+            serde_json::from_str(&format!(r#"{{"Id": "abc", "Digest": "{digest}"}}"#)).unwrap();
+        assert_eq!(podman.registry_digest(), Some(digest.as_str()));
+
+        let docker: ImageInspect = serde_json::from_str(&format!(
+            r#"{{"Id": "sha256:abc", "RepoDigests": ["quay.io/x@{digest}"]}}"#
+        ))
+        .unwrap();
+        assert_eq!(docker.registry_digest(), Some(digest.as_str()));
+
+        let local: ImageInspect =
+            serde_json::from_str(r#"{"Id": "abc", "Digest": "sha256:short", "RepoDigests": null}"#)
+                .unwrap();
+        assert_eq!(local.registry_digest(), None);
+    }
+
+    #[test]
+    fn test_encoding() {
+        assert_eq!(encode("{\"a\":[\"b\"]}"), "%7B%22a%22%3A%5B%22b%22%5D%7D");
+        assert_eq!(encode_path("localhost/x:latest"), "localhost/x:latest");
+    }
+}
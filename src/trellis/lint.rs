@@ -0,0 +1,632 @@
+//! Static checks of stage Containerfiles.
+//!
+//! Catches mistakes that otherwise only show up during, or after, a podman build:
+//! - the stage's target does not exist in the Containerfile
+//! - `FROM` uses `${BASE_IMAGE}` without a global `ARG BASE_IMAGE`, or ignores it
+//! - build args that are passed but never declared, and `ARG`s that are never used
+//! - `COPY --from` references to unknown stages or build contexts
+//! - writes to `/var`, which bootc only populates on the first installation
+//!
+//! Problems podman would fail on are errors; the others are warnings.
+
+use anyhow::{Context, Result};
+use std::{
+    collections::HashSet,
+    fmt, fs,
+    path::{Path, PathBuf},
+};
+
+use super::{discovery::ContainerfileDiscovery, preprocess::Preprocessor};
+use crate::config::TrellisConfig;
+
+/// Build argument holding the image a stage builds on.
+const BASE_IMAGE_ARG: &str = "BASE_IMAGE";
+
+/// Build arguments trellis passes to stages on its own.
+const IMPLICIT_BUILD_ARGS: &[&str] = &[BASE_IMAGE_ARG, "HOOKS_DIR"];
+
+/// Directories below `/var` whose contents are not expected to persist.
+const VAR_EXCEPTIONS: &[&str] = &["/var/cache", "/var/tmp"];
+
+/// Commands that write to every path argument.
+const WRITE_COMMANDS: &[&str] = &["mkdir", "touch", "tee"];
+
+/// Commands that write to their last argument.
+const COPY_COMMANDS: &[&str] = &["cp", "mv", "install", "ln", "rsync"];
+
+/// Severity of a lint finding.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    /// The build will fail or cannot produce the expected image
+    Error,
+    /// The build succeeds, but probably not as intended
+    Warning,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Severity::Error => write!(f, "error"),
+            Severity::Warning => write!(f, "warning"),
+        }
+    }
+}
+
+/// A problem found in a Containerfile.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LintFinding {
+    pub severity: Severity,
+    pub path: PathBuf,
+    /// Line of the instruction, or `None` for problems of the file as a whole
+    pub line: Option<usize>,
+    /// Stage name as listed in the configuration
+    pub stage: String,
+    pub message: String,
+}
+
+impl fmt::Display for LintFinding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.path.display())?;
+        if let Some(line) = self.line {
+            write!(f, ":{line}")?;
+        }
+        write!(f, ": {} [{}]: {}", self.severity, self.stage, self.message)
+    }
+}
+
+/// An instruction of a Containerfile, with continuation lines joined.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Instruction {
+    /// Line the instruction starts on
+    pub line: usize,
+    /// Uppercase instruction keyword, e.g. `RUN`
+    pub keyword: String,
+    pub args: String,
+}
+
+/// A build stage: a `FROM` instruction and the instructions following it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BuildStage {
+    pub line: usize,
+    /// Image or earlier stage the stage builds on
+    pub base: String,
+    /// Name given with `AS`, lowercased like podman does
+    pub name: Option<String>,
+    pub instructions: Vec<Instruction>,
+}
+
+/// A parsed Containerfile.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Containerfile {
+    /// `ARG` instructions before the first `FROM`
+    pub global_args: Vec<Instruction>,
+    pub stages: Vec<BuildStage>,
+}
+
+impl Containerfile {
+    /// Parses Containerfile contents.
+    ///
+    /// Handles comments, line continuations and heredocs; instruction arguments
+    /// are kept as written.
+    pub fn parse(content: &str) -> Self {
+        let mut containerfile = Self::default();
+        let mut lines = content.lines().enumerate().peekable();
+
+        while let Some((index, line)) = lines.next() {
+            let trimmed = line.trim();
+            if trimmed.is_empty() || trimmed.starts_with('#') {
+                continue;
+            }
+
+            let mut text = trimmed.to_string();
+            while text.ends_with('\\') {
+                text.pop();
+                // Comment lines inside a continued instruction are ignored
+                match lines.find(|(_, next)| !next.trim_start().starts_with('#')) {
+                    Some((_, next)) => {
+                        text.push(' ');
+                        text.push_str(next.trim());
+                    }
+                    None => break,
+                }
+            }
+
+            if let Some(delimiter) = Self::heredoc_delimiter(&text) {
+                for (_, next) in lines.by_ref() {
+                    if next.trim() == delimiter {
+                        break;
+                    }
+                    text.push('\n');
+                    text.push_str(next);
+                }
+            }
+
+            let (keyword, args) = match text.split_once(char::is_whitespace) {
+                Some((keyword, args)) => (keyword, args.trim()),
+                None => (text.as_str(), ""),
+            };
+            let instruction = Instruction {
+                line: index + 1,
+                keyword: keyword.to_ascii_uppercase(),
+                args: args.to_string(),
+            };
+
+            if instruction.keyword == "FROM" {
+                let mut words = args
+                    .split_whitespace()
+                    .filter(|word| !word.starts_with("--"));
+                let base = words.next().unwrap_or_default().to_string();
+                let name = match (words.next(), words.next()) {
+                    (Some(word), Some(name)) if word.eq_ignore_ascii_case("as") => {
+                        Some(name.to_ascii_lowercase())
+                    }
+                    _ => None,
+                };
+                containerfile.stages.push(BuildStage {
+                    line: instruction.line,
+                    base,
+                    name,
+                    instructions: Vec::new(),
+                });
+            } else if let Some(stage) = containerfile.stages.last_mut() {
+                stage.instructions.push(instruction);
+            } else if instruction.keyword == "ARG" {
+                containerfile.global_args.push(instruction);
+            }
+        }
+
+        containerfile
+    }
+
+    /// Returns the delimiter of a heredoc started by an instruction, if any.
+    fn heredoc_delimiter(text: &str) -> Option<String> {
+        let start = text.find("<<")?;
+        let rest = text[start + 2..].trim_start_matches('-');
+        let delimiter: String = rest
+            .trim_start_matches(['"', '\''])
+            .chars()
+            .take_while(|c| c.is_ascii_alphanumeric() || *c == '_')
+            .collect();
+        (!delimiter.is_empty()).then_some(delimiter)
+    }
+
+    /// Returns the index of the stage with the given name.
+    pub fn find_stage(&self, name: &str) -> Option<usize> {
+        let name = name.to_ascii_lowercase();
+        self.stages
+            .iter()
+            .position(|stage| stage.name.as_deref() == Some(name.as_str()))
+    }
+
+    /// Returns the indices of a stage and the stages it builds on, target first.
+    pub fn stage_chain(&self, index: usize) -> Vec<usize> {
+        let mut chain = vec![index];
+        let mut current = index;
+        while let Some(parent) = self.find_stage(&self.stages[current].base) {
+            // Only earlier stages can be referenced; this also guards against cycles
+            if parent >= current {
+                break;
+            }
+            chain.push(parent);
+            current = parent;
+        }
+        chain
+    }
+}
+
+/// Returns the names declared by an `ARG` instruction.
+fn declared_args(instruction: &Instruction) -> Vec<String> {
+    instruction
+        .args
+        .split_whitespace()
+        .map(|arg| arg.split('=').next().unwrap_or(arg).to_string())
+        .collect()
+}
+
+/// Returns true if `text` references the variable `name`.
+fn references(text: &str, name: &str) -> bool {
+    let braced = format!("${{{name}");
+    let plain = format!("${name}");
+    text.contains(&braced)
+        || text.match_indices(&plain).any(|(i, _)| {
+            !text[i + plain.len()..]
+                .chars()
+                .next()
+                .is_some_and(|c| c.is_ascii_alphanumeric() || c == '_')
+        })
+}
+
+/// Returns true if a path is below `/var` and expected to persist.
+fn is_persistent_var(path: &str) -> bool {
+    let path = path.trim_matches(['"', '\'']);
+    (path == "/var" || path.starts_with("/var/"))
+        && !VAR_EXCEPTIONS
+            .iter()
+            .any(|exception| path == *exception || path.starts_with(&format!("{exception}/")))
+}
+
+/// Returns the first `/var` path a shell command writes to.
+fn var_write_in_run(command: &str) -> Option<String> {
+    let mut current: Option<&str> = None;
+    let mut arguments: Vec<&str> = Vec::new();
+    let mut redirect = false;
+
+    let check = |current: Option<&str>, arguments: &[&str]| -> Option<String> {
+        let command = current?.rsplit('/').next()?;
+        let paths: Vec<&str> = arguments
+            .iter()
+            .filter(|arg| !arg.starts_with('-'))
+            .copied()
+            .collect();
+        if WRITE_COMMANDS.contains(&command) {
+            paths.into_iter().find(|path| is_persistent_var(path))
+        } else if COPY_COMMANDS.contains(&command) {
+            paths.last().filter(|path| is_persistent_var(path)).copied()
+        } else {
+            None
+        }
+        .map(str::to_string)
+    };
+
+    for word in command.split_whitespace() {
+        if matches!(word, "&&" | "||" | ";" | "|") {
+            if let Some(path) = check(current, &arguments) {
+                return Some(path);
+            }
+            current = None;
+            arguments.clear();
+            continue;
+        }
+
+        let target = word.trim_start_matches(['1', '2', '&']);
+        if let Some(path) = target
+            .strip_prefix(">>")
+            .or_else(|| target.strip_prefix('>'))
+        {
+            if path.is_empty() {
+                redirect = true;
+            } else if is_persistent_var(path) {
+                return Some(path.to_string());
+            }
+            continue;
+        }
+        if redirect {
+            redirect = false;
+            if is_persistent_var(word) {
+                return Some(word.to_string());
+            }
+            continue;
+        }
+
+        let word = word.trim_end_matches(';');
+        match current {
+            None => current = Some(word),
+            Some(_) => arguments.push(word),
+        }
+    }
+
+    check(current, &arguments)
+}
+
+/// Returns the `--from` value and destination of a `COPY` or `ADD` instruction.
+fn copy_from_and_destination(args: &str) -> (Option<String>, Option<String>) {
+    let mut from = None;
+    let mut words = args.split_whitespace().peekable();
+    let mut rest = Vec::new();
+    while let Some(word) = words.next() {
+        if let Some(value) = word.strip_prefix("--from=") {
+            from = Some(value.to_string());
+        } else if word == "--from" {
+            from = words.next().map(str::to_string);
+        } else if !word.starts_with("--") {
+            rest.push(word);
+        }
+    }
+
+    let rest = rest.join(" ");
+    let destination = if rest.starts_with('[') {
+        serde_json::from_str::<Vec<String>>(&rest)
+            .ok()
+            .and_then(|paths| paths.last().cloned())
+    } else if rest.starts_with("<<") {
+        None
+    } else {
+        rest.split_whitespace().last().map(str::to_string)
+    };
+    (from, destination)
+}
+
+/// Lints stage Containerfiles against the configuration.
+pub struct ContainerfileLinter<'a> {
+    config: &'a TrellisConfig,
+}
+
+impl<'a> ContainerfileLinter<'a> {
+    pub fn new(config: &'a TrellisConfig) -> Self {
+        Self { config }
+    }
+
+    /// Lints the Containerfile of a stage.
+    ///
+    /// With preprocessing enabled the rendered Containerfile is checked, and line
+    /// numbers refer to the rendered output.
+    ///
+    /// `first` is set for the first stage of a build, which may start from an
+    /// image of its own instead of `${BASE_IMAGE}`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the Containerfile cannot be read or rendered.
+    pub fn lint_stage(
+        &self,
+        build_stage: &str,
+        path: &Path,
+        first: bool,
+    ) -> Result<Vec<LintFinding>> {
+        let content = if self.config.preprocess {
+            Preprocessor::new(self.config, build_stage)
+                .render_file(path)
+                .with_context(|| {
+                    format!("Failed to preprocess Containerfile for stage {build_stage}")
+                })?
+        } else {
+            fs::read_to_string(path)
+                .with_context(|| format!("Failed to read containerfile: {}", path.display()))?
+        };
+
+        let mut findings = Vec::new();
+        let mut report = |severity, line, message: String| {
+            findings.push(LintFinding {
+                severity,
+                path: path.to_path_buf(),
+                line,
+                stage: build_stage.to_string(),
+                message,
+            });
+        };
+
+        let containerfile = Containerfile::parse(&content);
+        let target = ContainerfileDiscovery::stage_target(self.config, build_stage);
+        let Some(target_index) = containerfile.find_stage(&target) else {
+            let names: Vec<&str> = containerfile
+                .stages
+                .iter()
+                .filter_map(|stage| stage.name.as_deref())
+                .collect();
+            report(
+                Severity::Error,
+                None,
+                if names.is_empty() {
+                    format!("Target '{target}' not found: no stage is named with FROM ... AS")
+                } else {
+                    format!(
+                        "Target '{target}' not found. Stages in this file: {}",
+                        names.join(", ")
+                    )
+                },
+            );
+            return Ok(findings);
+        };
+
+        let chain = containerfile.stage_chain(target_index);
+        let global_args: HashSet<String> = containerfile
+            .global_args
+            .iter()
+            .flat_map(declared_args)
+            .collect();
+
+        // The stage at the root of the chain pulls in the previous stage's image
+        let root = &containerfile.stages[*chain.last().unwrap_or(&target_index)];
+        if references(&root.base, BASE_IMAGE_ARG) {
+            if !global_args.contains(BASE_IMAGE_ARG) {
+                report(
+                    Severity::Error,
+                    Some(root.line),
+                    format!(
+                        "FROM uses ${{{BASE_IMAGE_ARG}}}, but ARG {BASE_IMAGE_ARG} is not declared before the first FROM"
+                    ),
+                );
+            }
+        } else if !first {
+            report(
+                Severity::Warning,
+                Some(root.line),
+                format!(
+                    "FROM {} ignores ${{{BASE_IMAGE_ARG}}}, so the stage does not build on the previous stage",
+                    root.base
+                ),
+            );
+        }
+
+        // Build args must be declared to reach the build
+        let mut declared = global_args.clone();
+        for &index in &chain {
+            for instruction in &containerfile.stages[index].instructions {
+                if instruction.keyword == "ARG" {
+                    declared.extend(declared_args(instruction));
+                }
+            }
+        }
+        if let Some(build_args) = self
+            .config
+            .stage_config(build_stage)
+            .and_then(|c| c.build_args.as_ref())
+        {
+            for name in build_args.keys() {
+                if !declared.contains(name) {
+                    report(
+                        Severity::Warning,
+                        None,
+                        format!(
+                            "Build arg '{name}' is passed to the stage but never declared with ARG"
+                        ),
+                    );
+                }
+            }
+        }
+
+        let contexts = self.context_names(build_stage);
+        for &index in chain.iter().rev() {
+            let stage = &containerfile.stages[index];
+            for (i, instruction) in stage.instructions.iter().enumerate() {
+                match instruction.keyword.as_str() {
+                    "ARG" => {
+                        let later = &stage.instructions[i + 1..];
+                        for name in declared_args(instruction) {
+                            if IMPLICIT_BUILD_ARGS.contains(&name.as_str()) {
+                                continue;
+                            }
+                            if !later.iter().any(|next| references(&next.args, &name)) {
+                                report(
+                                    Severity::Warning,
+                                    Some(instruction.line),
+                                    format!("ARG {name} is declared but never used"),
+                                );
+                            }
+                        }
+                    }
+                    "COPY" | "ADD" => {
+                        let (from, destination) = copy_from_and_destination(&instruction.args);
+                        if let Some(from) = from {
+                            let known = containerfile.stages[..index]
+                                .iter()
+                                .any(|earlier| {
+                                    earlier.name.as_deref() == Some(&from.to_ascii_lowercase())
+                                })
+                                || contexts.contains(&from)
+                                // Image references such as `docker.io/library/alpine:3`
+                                || from.contains(['/', ':', '.', '$']);
+                            match from.parse::<usize>() {
+                                Ok(n) if n >= index => report(
+                                    Severity::Error,
+                                    Some(instruction.line),
+                                    format!(
+                                        "{} --from={from} refers to a stage that is not defined before it",
+                                        instruction.keyword
+                                    ),
+                                ),
+                                Ok(_) => {}
+                                // Bare names such as `busybox` are pulled as images
+                                Err(_) if !known => report(
+                                    Severity::Warning,
+                                    Some(instruction.line),
+                                    format!(
+                                        "{} --from={from} is neither an earlier stage nor a build context and will be pulled as an image",
+                                        instruction.keyword
+                                    ),
+                                ),
+                                Err(_) => {}
+                            }
+                        }
+                        if let Some(destination) = destination.filter(|d| is_persistent_var(d)) {
+                            report(
+                                Severity::Warning,
+                                Some(instruction.line),
+                                format!(
+                                    "{} writes to {destination}, which bootc only populates on the first installation; use /usr or systemd-tmpfiles",
+                                    instruction.keyword
+                                ),
+                            );
+                        }
+                    }
+                    "RUN" => {
+                        if let Some(path) = var_write_in_run(&instruction.args) {
+                            report(
+                                Severity::Warning,
+                                Some(instruction.line),
+                                format!(
+                                    "RUN writes to {path}, which bootc only populates on the first installation; use /usr or systemd-tmpfiles"
+                                ),
+                            );
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        // Unused global args, other than those trellis passes itself
+        for instruction in &containerfile.global_args {
+            for name in declared_args(instruction) {
+                if IMPLICIT_BUILD_ARGS.contains(&name.as_str()) {
+                    continue;
+                }
+                let used = containerfile.stages.iter().any(|stage| {
+                    references(&stage.base, &name)
+                        || stage.instructions.iter().any(|next| {
+                            next.keyword == "ARG" && declared_args(next).contains(&name)
+                        })
+                });
+                if !used {
+                    report(
+                        Severity::Warning,
+                        Some(instruction.line),
+                        format!("ARG {name} is declared but never used"),
+                    );
+                }
+            }
+        }
+
+        findings.sort_by_key(|finding| (finding.line, finding.severity));
+        Ok(findings)
+    }
+
+    /// Returns the names of the build contexts passed to a stage.
+    fn context_names(&self, build_stage: &str) -> HashSet<String> {
+        self.config
+            .extra_contexts
+            .iter()
+            .chain(
+                self.config
+                    .stage_config(build_stage)
+                    .and_then(|c| c.contexts.as_ref())
+                    .into_iter()
+                    .flatten(),
+            )
+            .filter_map(|context| context.split_once('=').map(|(name, _)| name.to_string()))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_joins_continuations_and_heredocs() {
+        let containerfile = Containerfile::parse(
+            "ARG BASE_IMAGE\n\
+             FROM ${BASE_IMAGE} AS Base\n\
+             RUN echo a \\\n\
+             # comment\n    && echo b\n\
+             RUN <<EOF\nmkdir /var/lib/x\nEOF\n\
+             COPY --from=base /a /b\n",
+        );
+        assert_eq!(containerfile.global_args.len(), 1);
+        assert_eq!(containerfile.stages.len(), 1);
+        let stage = &containerfile.stages[0];
+        assert_eq!(stage.name.as_deref(), Some("base"));
+        assert_eq!(stage.instructions[0].args, "echo a  && echo b");
+        assert!(stage.instructions[1].args.contains("mkdir /var/lib/x"));
+        assert_eq!(stage.instructions[2].line, 9);
+    }
+
+    #[test]
+    fn test_var_writes() {
+        assert_eq!(
+            var_write_in_run("echo x > /var/lib/foo/conf").as_deref(),
+            Some("/var/lib/foo/conf")
+        );
+        assert_eq!(
+            var_write_in_run("pacman -Syu && mkdir -p /var/lib/app").as_deref(),
+            Some("/var/lib/app")
+        );
+        assert_eq!(
+            var_write_in_run("cp /var/lib/a /usr/lib/a").as_deref(),
+            None
+        );
+        assert_eq!(var_write_in_run("mkdir -p /var/cache/app"), None);
+        assert!(references("echo ${FOO:-x}", "FOO"));
+        assert!(!references("echo $FOOBAR", "FOO"));
+    }
+}
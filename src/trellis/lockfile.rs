@@ -0,0 +1,229 @@
+//! Build lockfile recording exactly what went into a rootfs image.
+//!
+//! After every rootfs build a `trellis.lock` is written to the stages directory.
+//! `trls build --locked` compares the current build inputs against it and
+//! refuses to build when anything changed.
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use super::constants::patterns;
+
+/// Contents of a `trellis.lock` file.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BuildLock {
+    /// Base image reference as configured
+    pub rootfs_base: String,
+    /// Resolved digest (or image ID) of the base image, `scratch` for builds from scratch
+    pub base_digest: String,
+    /// Inputs and results of every stage, in build order
+    #[serde(default, rename = "stage")]
+    pub stages: Vec<LockedStage>,
+}
+
+/// Lockfile entry for a single stage.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LockedStage {
+    /// Stage name as listed in the configuration
+    pub name: String,
+    /// Containerfile path, relative to the stages directory when possible
+    pub containerfile: PathBuf,
+    /// SHA-256 of the Containerfile
+    pub sha256: String,
+    /// Build target within the Containerfile
+    pub target: String,
+    /// ID of the image produced by the stage, empty until the stage is built
+    #[serde(default)]
+    pub image_id: String,
+    /// Build arguments, excluding `BASE_IMAGE` which is covered by the base digest
+    #[serde(default)]
+    pub build_args: BTreeMap<String, String>,
+}
+
+impl BuildLock {
+    /// Returns the lockfile path within a stages directory.
+    ///
+    /// Builds with a profile get their own `trellis.<profile>.lock`, so profiles
+    /// sharing a stages directory do not overwrite each other's lock.
+    pub fn path(stages_dir: &Path, profile: Option<&str>) -> PathBuf {
+        match profile {
+            Some(profile) => stages_dir.join(format!("trellis.{profile}.lock")),
+            None => stages_dir.join(patterns::LOCK_FILE),
+        }
+    }
+
+    /// Loads a lockfile, returning `None` if it does not exist.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the lockfile exists but cannot be read or parsed.
+    pub fn load(path: &Path) -> Result<Option<Self>> {
+        if !path.exists() {
+            return Ok(None);
+        }
+
+        let content = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read lockfile: {}", path.display()))?;
+        let lock = toml::from_str(&content)
+            .with_context(|| format!("Failed to parse lockfile: {}", path.display()))?;
+        Ok(Some(lock))
+    }
+
+    /// Writes the lockfile.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the lockfile cannot be serialized or written.
+    pub fn save(&self, path: &Path) -> Result<()> {
+        fs::write(path, self.to_toml()?)
+            .with_context(|| format!("Failed to write lockfile: {}", path.display()))
+    }
+
+    /// Returns the contents of the lockfile.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the lock cannot be serialized.
+    pub fn to_toml(&self) -> Result<String> {
+        let content = toml::to_string_pretty(self).context("Failed to serialize lockfile")?;
+        Ok(format!("# Generated by trellis. Do not edit.\n{content}"))
+    }
+
+    /// Lists every build input that differs between this lock and `current`.
+    ///
+    /// Resulting image IDs are outputs rather than inputs and are not compared.
+    pub fn differences(&self, current: &BuildLock) -> Vec<String> {
+        let mut differences = Vec::new();
+
+        if self.rootfs_base == current.rootfs_base && current.base_digest.is_empty() {
+            differences.push(format!(
+                "rootfs_base: {} is not in local storage, pull it to compare against the locked {}",
+                current.rootfs_base, self.base_digest
+            ));
+        } else if self.rootfs_base != current.rootfs_base || self.base_digest != current.base_digest
+        {
+            differences.push(format!(
+                "rootfs_base: locked {} ({}), now {} ({})",
+                self.rootfs_base, self.base_digest, current.rootfs_base, current.base_digest
+            ));
+        }
+
+        let locked_names: Vec<&str> = self.stages.iter().map(|s| s.name.as_str()).collect();
+        let current_names: Vec<&str> = current.stages.iter().map(|s| s.name.as_str()).collect();
+        if locked_names != current_names {
+            differences.push(format!(
+                "stages: locked [{}], now [{}]",
+                locked_names.join(", "),
+                current_names.join(", ")
+            ));
+            return differences;
+        }
+
+        for (locked, now) in self.stages.iter().zip(&current.stages) {
+            if locked.containerfile != now.containerfile {
+                differences.push(format!(
+                    "{}: containerfile changed from {} to {}",
+                    locked.name,
+                    locked.containerfile.display(),
+                    now.containerfile.display()
+                ));
+            }
+            if locked.sha256 != now.sha256 {
+                differences.push(format!(
+                    "{}: {} content changed",
+                    locked.name,
+                    now.containerfile.display()
+                ));
+            }
+            if locked.target != now.target {
+                differences.push(format!(
+                    "{}: target changed from {} to {}",
+                    locked.name, locked.target, now.target
+                ));
+            }
+            if locked.build_args != now.build_args {
+                differences.push(format!("{}: build args changed", locked.name));
+            }
+        }
+
+        differences
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn sample_lock() -> BuildLock {
+        BuildLock {
+            rootfs_base: "quay.io/archlinux/archlinux:latest".to_string(),
+            base_digest: "sha256:abc".to_string(),
+            stages: vec![LockedStage {
+                name: "base".to_string(),
+                containerfile: PathBuf::from("Containerfile.base"),
+                sha256: "0123".to_string(),
+                target: "base".to_string(),
+                image_id: "deadbeef".to_string(),
+                build_args: BTreeMap::from([(
+                    "HOOKS_DIR".to_string(),
+                    "/etc/trellis/hooks.d".to_string(),
+                )]),
+            }],
+        }
+    }
+
+    #[test]
+    fn test_lockfile_round_trip() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = BuildLock::path(temp_dir.path(), None);
+        let lock = sample_lock();
+
+        lock.save(&path).unwrap();
+        assert_eq!(BuildLock::load(&path).unwrap(), Some(lock));
+    }
+
+    #[test]
+    fn test_lockfile_path_per_profile() {
+        let stages_dir = Path::new("/stages");
+        assert_eq!(
+            BuildLock::path(stages_dir, None),
+            PathBuf::from("/stages/trellis.lock")
+        );
+        assert_eq!(
+            BuildLock::path(stages_dir, Some("laptop")),
+            PathBuf::from("/stages/trellis.laptop.lock")
+        );
+    }
+
+    #[test]
+    fn test_lockfile_differences_ignore_image_id() {
+        let lock = sample_lock();
+        let mut current = lock.clone();
+        current.stages[0].image_id = String::new();
+        assert!(lock.differences(&current).is_empty());
+
+        current.stages[0].sha256 = "4567".to_string();
+        current.base_digest = "sha256:def".to_string();
+        let differences = lock.differences(&current);
+        assert_eq!(differences.len(), 2);
+        assert!(differences[0].starts_with("rootfs_base"));
+        assert!(differences[1].contains("content changed"));
+    }
+
+    #[test]
+    fn test_lockfile_differences_base_not_in_storage() {
+        let lock = sample_lock();
+        let mut current = lock.clone();
+        current.base_digest = String::new();
+
+        let differences = lock.differences(&current);
+        assert_eq!(differences.len(), 1);
+        assert!(differences[0].contains("is not in local storage"));
+    }
+}
@@ -0,0 +1,334 @@
+//! Build metadata embedded in rootfs images.
+//!
+//! After the last stage of a rootfs build, a small build on top of the image adds:
+//! - `org.opencontainers.image.*` and `dev.trellis.*` labels
+//! - `/usr/share/trellis/build.json` with the same information
+//! - `/usr/share/trellis/trellis.lock`, the lockfile of the build
+//! - `IMAGE_ID` and `IMAGE_VERSION` in `/usr/lib/os-release`
+//!
+//! A booted system can then tell exactly which build it is running.
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use super::{
+    common::UtcTime,
+    constants::{containers, patterns},
+    lockfile::BuildLock,
+};
+use crate::config::TrellisConfig;
+
+/// Prefix of the trellis-specific image labels.
+pub const LABEL_PREFIX: &str = "dev.trellis.";
+
+/// Prefix of the standard OCI image annotations.
+const OCI_LABEL_PREFIX: &str = "org.opencontainers.image.";
+
+/// Information about a rootfs build, as written to `build.json`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BuildMetadata {
+    /// ID of the build, matching its logs and history entry when stage logs are written
+    pub build_id: String,
+    /// `IMAGE_ID` written to os-release, derived from the rootfs tag
+    pub image_id: String,
+    /// `IMAGE_VERSION` written to os-release, the build ID
+    pub image_version: String,
+    /// Build timestamp in RFC 3339 format
+    pub created: String,
+    pub trellis_version: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub profile: Option<String>,
+    pub rootfs_base: String,
+    /// Digest (or image ID) of the base image
+    pub base_digest: String,
+    /// Stages in build order
+    pub stages: Vec<String>,
+    /// SHA-256 of every stage's Containerfile, as passed to podman
+    pub containerfiles: BTreeMap<String, String>,
+    /// Commit checked out in the stages directory, if it is a git repository
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stages_commit: Option<String>,
+    /// Commits of the `[[sources]]` repositories
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub sources: Vec<SourceRevision>,
+}
+
+/// Revision of a `[[sources]]` repository used by a build.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SourceRevision {
+    pub name: String,
+    pub git: String,
+    #[serde(rename = "ref")]
+    pub reference: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub commit: Option<String>,
+}
+
+impl BuildMetadata {
+    /// Collects the metadata of a build from its configuration and resolved lock.
+    pub fn new(config: &TrellisConfig, lock: &BuildLock, build_id: &str) -> Self {
+        Self {
+            build_id: build_id.to_string(),
+            image_id: os_release_value(image_name(&config.rootfs_tag)),
+            image_version: os_release_value(build_id),
+            created: UtcTime::now().to_rfc3339(),
+            trellis_version: env!("CARGO_PKG_VERSION").to_string(),
+            profile: config.profile.clone(),
+            rootfs_base: lock.rootfs_base.clone(),
+            base_digest: lock.base_digest.clone(),
+            stages: lock.stages.iter().map(|stage| stage.name.clone()).collect(),
+            containerfiles: lock
+                .stages
+                .iter()
+                .map(|stage| (stage.name.clone(), stage.sha256.clone()))
+                .collect(),
+            stages_commit: git_commit(&config.stages_dir),
+            sources: config
+                .sources
+                .iter()
+                .map(|source| SourceRevision {
+                    name: source.name.clone(),
+                    git: source.git.clone(),
+                    reference: source.reference.clone(),
+                    commit: git_commit(&source.checkout_dir(&config.sources_dir)),
+                })
+                .collect(),
+        }
+    }
+
+    /// Returns the image labels, OCI annotations first.
+    pub fn labels(&self) -> Vec<(String, String)> {
+        let oci = |key: &str| format!("{OCI_LABEL_PREFIX}{key}");
+        let trellis = |key: &str| format!("{LABEL_PREFIX}{key}");
+
+        let mut labels = vec![
+            (oci("title"), self.image_id.clone()),
+            (oci("created"), self.created.clone()),
+            (oci("version"), self.image_version.clone()),
+        ];
+        if let Some(commit) = &self.stages_commit {
+            labels.push((oci("revision"), commit.clone()));
+        }
+        labels.push((oci("base.name"), self.rootfs_base.clone()));
+        if self.base_digest.starts_with("sha256:") {
+            labels.push((oci("base.digest"), self.base_digest.clone()));
+        }
+
+        labels.extend([
+            (trellis("version"), self.trellis_version.clone()),
+            (trellis("build-id"), self.build_id.clone()),
+            (trellis("build-timestamp"), self.created.clone()),
+            (trellis("stages"), self.stages.join(",")),
+            (
+                trellis("containerfiles"),
+                self.stages
+                    .iter()
+                    .filter_map(|stage| {
+                        let sha256 = self.containerfiles.get(stage)?;
+                        Some(format!("{stage}=sha256:{sha256}"))
+                    })
+                    .collect::<Vec<_>>()
+                    .join(","),
+            ),
+        ]);
+        if let Some(commit) = &self.stages_commit {
+            labels.push((trellis("stages-commit"), commit.clone()));
+        }
+        if !self.sources.is_empty() {
+            let sources: Vec<String> = self
+                .sources
+                .iter()
+                .map(|source| {
+                    format!(
+                        "{}={}",
+                        source.name,
+                        source.commit.as_deref().unwrap_or("unknown")
+                    )
+                })
+                .collect();
+            labels.push((trellis("sources"), sources.join(",")));
+        }
+        if let Some(profile) = &self.profile {
+            labels.push((trellis("profile"), profile.clone()));
+        }
+        labels
+    }
+
+    /// Returns the Containerfile that embeds the metadata and the lockfile into `image`.
+    ///
+    /// Existing `IMAGE_ID` and `IMAGE_VERSION` entries are replaced. Both values
+    /// only contain characters allowed by os-release, so they need no quoting.
+    pub fn containerfile(&self, image: &str) -> String {
+        let metadata_file = patterns::BUILD_METADATA_FILE;
+        let lock = patterns::LOCK_FILE;
+        let lock_file = patterns::BUILD_LOCK_FILE;
+        let os_release = patterns::OS_RELEASE_FILE;
+        format!(
+            "FROM {image}\n\
+             COPY build.json {metadata_file}\n\
+             COPY {lock} {lock_file}\n\
+             RUN sed -i -e '/^IMAGE_ID=/d' -e '/^IMAGE_VERSION=/d' {os_release} \\\n    \
+             && printf 'IMAGE_ID=%s\\nIMAGE_VERSION=%s\\n' {} {} >> {os_release}\n",
+            self.image_id, self.image_version
+        )
+    }
+}
+
+/// Returns the name part of an image tag, without registry, path or tag.
+fn image_name(tag: &str) -> &str {
+    let name = tag.rsplit('/').next().unwrap_or(tag);
+    let name = name.split(['@', ':']).next().unwrap_or(name);
+    if name.is_empty() {
+        containers::DEFAULT_ROOTFS_TAG
+    } else {
+        name
+    }
+}
+
+/// Converts a value to the character set os-release allows for `IMAGE_ID` and
+/// `IMAGE_VERSION`: lowercase letters, digits, `.`, `_` and `-`.
+pub fn os_release_value(value: &str) -> String {
+    value
+        .chars()
+        .map(|c| match c.to_ascii_lowercase() {
+            c @ ('a'..='z' | '0'..='9' | '.' | '_' | '-') => c,
+            _ => '-',
+        })
+        .collect()
+}
+
+/// Returns the commit checked out in the git repository containing `dir`.
+///
+/// Reads the repository files directly, so git does not need to be installed.
+/// Returns `None` if the directory is not in a repository or HEAD is unborn.
+pub fn git_commit(dir: &Path) -> Option<String> {
+    let dir = fs::canonicalize(dir).ok()?;
+    let dot_git = dir
+        .ancestors()
+        .map(|dir| dir.join(".git"))
+        .find(|path| path.exists())?;
+
+    // Worktrees and submodules have a `.git` file pointing to the git directory
+    let git_dir = if dot_git.is_file() {
+        let content = fs::read_to_string(&dot_git).ok()?;
+        let target = content.trim().strip_prefix("gitdir:")?.trim();
+        dot_git.parent()?.join(target)
+    } else {
+        dot_git
+    };
+    let common_dir = fs::read_to_string(git_dir.join("commondir"))
+        .map(|dir| git_dir.join(dir.trim()))
+        .unwrap_or_else(|_| git_dir.clone());
+
+    let head = fs::read_to_string(git_dir.join("HEAD")).ok()?;
+    let commit = match head.trim().strip_prefix("ref:") {
+        Some(reference) => {
+            let reference = reference.trim();
+            [&git_dir, &common_dir]
+                .iter()
+                .find_map(|dir| fs::read_to_string(dir.join(reference)).ok())
+                .map(|commit| commit.trim().to_string())
+                .or_else(|| packed_ref(&common_dir, reference))?
+        }
+        None => head.trim().to_string(),
+    };
+
+    is_commit(&commit).then_some(commit)
+}
+
+/// Looks up a reference in `packed-refs`.
+fn packed_ref(common_dir: &Path, reference: &str) -> Option<String> {
+    let packed = fs::read_to_string(common_dir.join("packed-refs")).ok()?;
+    packed.lines().find_map(|line| {
+        let (commit, name) = line.split_once(' ')?;
+        (name == reference).then(|| commit.to_string())
+    })
+}
+
+fn is_commit(value: &str) -> bool {
+    matches!(value.len(), 40 | 64) && value.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+/// Build context holding the metadata Containerfile, `build.json` and the
+/// lockfile, removed when dropped.
+pub struct MetadataContext {
+    dir: PathBuf,
+}
+
+impl MetadataContext {
+    /// Writes the build context for embedding metadata into `image`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the metadata cannot be serialized or the files cannot be written.
+    pub fn write(metadata: &BuildMetadata, lock: &BuildLock, image: &str) -> Result<Self> {
+        let dir = std::env::temp_dir().join(format!("trellis-metadata-{}", std::process::id()));
+        fs::create_dir_all(&dir)
+            .with_context(|| format!("Failed to create metadata directory: {}", dir.display()))?;
+        let context = Self { dir };
+
+        let json =
+            serde_json::to_string_pretty(metadata).context("Failed to serialize build metadata")?;
+        fs::write(context.dir.join("build.json"), format!("{json}\n"))
+            .context("Failed to write build.json")?;
+        fs::write(context.dir.join(patterns::LOCK_FILE), lock.to_toml()?)
+            .context("Failed to write lockfile")?;
+        fs::write(context.containerfile(), metadata.containerfile(image))
+            .context("Failed to write metadata Containerfile")?;
+        Ok(context)
+    }
+
+    pub fn containerfile(&self) -> PathBuf {
+        self.dir.join("Containerfile")
+    }
+}
+
+impl Drop for MetadataContext {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_os_release_values() {
+        assert_eq!(
+            image_name("localhost/trellis-rootfs:latest"),
+            "trellis-rootfs"
+        );
+        assert_eq!(image_name("My_Rootfs"), "My_Rootfs");
+        assert_eq!(os_release_value("My_Rootfs+v2"), "my_rootfs-v2");
+    }
+
+    #[test]
+    fn test_git_commit_reads_refs() {
+        let temp = tempfile::TempDir::new().unwrap();
+        let commit = "0123456789abcdef0123456789abcdef01234567";
+        let git_dir = temp.path().join(".git");
+        let stages_dir = temp.path().join("stages");
+        fs::create_dir_all(git_dir.join("refs/heads")).unwrap();
+        fs::create_dir_all(&stages_dir).unwrap();
+        fs::write(git_dir.join("HEAD"), "ref: refs/heads/main\n").unwrap();
+
+        // Unborn branch
+        assert_eq!(git_commit(&stages_dir), None);
+
+        fs::write(
+            git_dir.join("packed-refs"),
+            format!("# pack-refs with: peeled\n{commit} refs/heads/main\n"),
+        )
+        .unwrap();
+        assert_eq!(git_commit(&stages_dir).as_deref(), Some(commit));
+
+        fs::write(git_dir.join("HEAD"), format!("{commit}\n")).unwrap();
+        assert_eq!(git_commit(&stages_dir).as_deref(), Some(commit));
+    }
+}
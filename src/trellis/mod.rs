@@ -1,24 +1,56 @@
 //! Trellis core functionality modules.
 //!
 //! This module contains the main application logic split into focused components:
+//! - `base_image`: Digest pinning of the rootfs base image
 //! - `builder`: Container building operations
+//! - `build_log`: Per-stage build logs and their retention
+//! - `build_state`: Progress tracking for resumable builds
 //! - `cleaner`: Image cleanup and management
+//! - `rootless`: Rootless podman paths and handing root operations off
 //! - `runner`: Container execution
+//! - `run_lock`: Advisory lock serializing trls runs
+//! - `sources`: Checkouts of git repositories providing stages
 //! - `discovery`: Containerfile discovery logic
+//! - `disk_space`: Free space checks before builds and image generation
+//! - `engine`: Container engines (podman, buildah, docker) and their capabilities
+//! - `history`: Record of past builds, updates and image generations
+//! - `interrupt`: Signal handling and cleanup of partial state
+//! - `libpod`: Image and container queries through podman's REST API
+//! - `lint`: Static checks of stage Containerfiles
+//! - `lockfile`: Build lockfile recording stage inputs and results
+//! - `metadata`: Labels and files describing a build, embedded in rootfs images
+//! - `preprocess`: Containerfile includes, variables and conditionals
+//! - `registry`: Pushing rootfs images to a registry
+//! - `stage_key`: Content keys for incremental stage builds
+//! - `versions`: Versioned tags of the rootfs image
 
 use anyhow::{anyhow, Context, Result};
 use std::sync::Arc;
 
 use crate::{
-    cli::{Cli, Commands},
-    config::{ConfigValidator, TrellisConfig},
+    cli::{BaseCommands, BuildArgs, Cli, Commands, GraphFormat, SourcesCommands, StagesCommands},
+    config::{writer, ConfigValidator, TrellisConfig},
 };
 
-use common::TrellisMessaging;
-use executor::{CommandExecutor, RealCommandExecutor};
+use base_image::{BaseImageChecker, BaseStatus};
+use build_log::{new_build_id, BuildLog};
+use common::{unix_now, TrellisMessaging, UtcTime};
+use constants::errors;
+use discovery::ContainerfileDiscovery;
+use executor::{CommandExecutor, DryRunCommandExecutor, RealCommandExecutor};
+use history::{format_duration, History, HistoryEntry, Operation, StageTiming};
 use image_generator::ImageGenerator;
+use inspect::ImageInspector;
+use libpod::{LibpodClient, LibpodCommandExecutor};
+use lint::Severity;
+use metadata::BuildMetadata;
+use preprocess::Preprocessor;
+use registry::RegistryPusher;
+use run_lock::{RunLock, RunLocker};
+use sources::SourceManager;
 use std::io::{self, BufRead};
 use std::path::PathBuf;
+use versions::RootfsVersions;
 
 /// Trait for handling user interactions like prompts and confirmations.
 /// This allows for dependency injection and mocking in tests.
@@ -44,11 +76,8 @@ impl UserInteraction for RealUserInteraction {
     fn prompt_yes_no(&self, message: &str) -> Result<bool> {
         eprint!("{message}");
 
-        let stdin = io::stdin();
-        let mut handle = stdin.lock();
         let mut input = String::new();
-        handle
-            .read_line(&mut input)
+        interrupt::exit_immediately(|| io::stdin().lock().read_line(&mut input))
             .context("Failed to read user input")?;
 
         let response = input.trim().to_lowercase();
@@ -56,14 +85,33 @@ impl UserInteraction for RealUserInteraction {
     }
 }
 
+pub mod base_image;
+pub mod build_log;
+pub mod build_state;
 pub mod builder;
 pub mod cleaner;
 pub mod common;
 pub mod constants;
 pub mod discovery;
+pub mod disk_space;
+pub mod engine;
 pub mod executor;
+pub mod history;
 pub mod image_generator;
+pub mod inspect;
+pub mod interrupt;
+pub mod libpod;
+pub mod lint;
+pub mod lockfile;
+pub mod metadata;
+pub mod preprocess;
+pub mod registry;
+pub mod rootless;
+pub mod run_lock;
 pub mod runner;
+pub mod sources;
+pub mod stage_key;
+pub mod versions;
 
 pub use builder::ContainerBuilder;
 pub use cleaner::ImageCleaner;
@@ -91,7 +139,16 @@ impl TrellisApp {
     pub fn new(cli: Cli) -> Result<Self> {
         let command = cli.command.clone();
         let config = TrellisConfig::new(cli)?;
-        let executor = Arc::new(RealCommandExecutor::new());
+        let executor: Arc<dyn CommandExecutor> = if config.dry_run {
+            Arc::new(DryRunCommandExecutor::with_engine(config.engine))
+        } else if let Some(socket) = &config.podman_socket {
+            Arc::new(LibpodCommandExecutor::new(
+                LibpodClient::new(socket),
+                Arc::new(RealCommandExecutor::with_engine(config.engine)),
+            ))
+        } else {
+            Arc::new(RealCommandExecutor::with_engine(config.engine))
+        };
 
         Ok(TrellisApp {
             config,
@@ -113,22 +170,45 @@ impl TrellisApp {
         })
     }
 
+    /// Configuration the command runs with.
+    pub fn config(&self) -> &TrellisConfig {
+        &self.config
+    }
+
     pub fn run(&self) -> Result<()> {
+        let _lock = self.lock()?;
         let user_interaction = Arc::new(RealUserInteraction);
         self.run_with_user_interaction(user_interaction)
     }
 
+    /// Takes the run lock the command needs. Dry runs change nothing and take none.
+    fn lock(&self) -> Result<Option<RunLock>> {
+        match self.command.lock_mode() {
+            Some(mode) if !self.config.dry_run => {
+                RunLocker::new(&self.config).acquire(mode).map(Some)
+            }
+            _ => Ok(None),
+        }
+    }
+
     /// Run with custom user interaction for testing.
     #[allow(dead_code)]
     pub fn run_with_user_interaction(
         &self,
         user_interaction: Arc<dyn UserInteraction>,
     ) -> Result<()> {
-        let trellis = Trellis::new(&self.config, Arc::clone(&self.executor), user_interaction);
+        let trellis = Trellis::new(&self.config, Arc::clone(&self.executor), user_interaction)
+            .with_output_on_stdout(self.command.writes_stdout());
 
         match &self.command {
             Commands::BuildBuilder => trellis.build_builder_container(),
-            Commands::Build => trellis.build_rootfs_container(),
+            Commands::Build(args) => {
+                if args.check {
+                    trellis.check_rootfs_container()
+                } else {
+                    trellis.build_rootfs_container_with(args)
+                }
+            }
             Commands::Run { args } => trellis.run_rootfs_container(args),
             Commands::Clean => trellis.clean(),
             Commands::Update => trellis.update(),
@@ -148,6 +228,29 @@ impl TrellisApp {
                 *size,
                 root_password.as_deref(),
             ),
+            Commands::Inspect { image } => trellis.inspect_image(image.as_deref()),
+            Commands::Images => trellis.list_versions(),
+            Commands::Retag { version } => trellis.retag_version(version),
+            Commands::Push { to } => trellis.push(to.as_deref()),
+            Commands::Base {
+                command: BaseCommands::Check,
+            } => trellis.check_base(),
+            Commands::Base {
+                command: BaseCommands::Pin,
+            } => trellis.pin_base(),
+            Commands::Stages {
+                command: StagesCommands::Graph { format },
+            } => trellis.stages_graph(*format),
+            Commands::Lint { stages } => trellis.lint_stages(stages),
+            Commands::Render { stage } => trellis.render_stage(stage),
+            Commands::Sources {
+                command: SourcesCommands::Update { name },
+            } => trellis.update_sources(name.as_deref()),
+            Commands::History => trellis.show_history(),
+            Commands::Show { id } => trellis.show_history_entry(id),
+            Commands::Logs { build, stage } => {
+                trellis.show_logs(build.as_deref(), stage.as_deref())
+            }
         }
     }
 }
@@ -158,6 +261,8 @@ pub struct Trellis<'a> {
     builder: ContainerBuilder<'a>,
     cleaner: ImageCleaner<'a>,
     runner: ContainerRunner<'a>,
+    sources: SourceManager<'a>,
+    versions: RootfsVersions<'a>,
     #[allow(dead_code)]
     executor: Arc<dyn CommandExecutor>,
     user_interaction: Arc<dyn UserInteraction>,
@@ -176,18 +281,41 @@ impl<'a> Trellis<'a> {
             builder: ContainerBuilder::new(config, Arc::clone(&executor)),
             cleaner: ImageCleaner::new(config, Arc::clone(&executor)),
             runner: ContainerRunner::new(config, Arc::clone(&executor)),
+            sources: SourceManager::new(config, Arc::clone(&executor)),
+            versions: RootfsVersions::new(config, Arc::clone(&executor)),
             executor,
             user_interaction,
         }
     }
 
+    /// Sends progress messages to stderr if the command writes its result to stdout.
+    pub fn with_output_on_stdout(mut self, writes_stdout: bool) -> Self {
+        self.sources = self.sources.with_progress_on_stderr(writes_stdout);
+        self
+    }
+
     pub fn build_builder_container(&self) -> Result<()> {
+        let started = unix_now();
+        let result = self.build_builder();
+        self.record_history(
+            Operation::BuildBuilder,
+            started,
+            &self.config.builder_stages,
+            &self.config.builder_tag,
+            &result,
+        );
+        result
+    }
+
+    fn build_builder(&self) -> Result<()> {
         ConfigValidator::validate_stages(&self.config.builder_stages, "builder")?;
+        self.sources.ensure()?;
+        let stages = self.ordered_stages(&self.config.builder_stages)?;
 
         self.builder.build_multistage_container(
             "builder",
             &self.config.builder_tag,
-            &self.config.builder_stages,
+            &stages,
             builder::BuildType::Builder,
         )?;
 
@@ -199,8 +327,66 @@ impl<'a> Trellis<'a> {
         Ok(())
     }
 
+    #[allow(dead_code)]
     pub fn build_rootfs_container(&self) -> Result<()> {
+        self.build_rootfs_container_with(&BuildArgs::default())
+    }
+
+    /// Builds the rootfs container with the options of the `build` command.
+    ///
+    /// Optionally starts at a named stage or resumes the last failed build, and
+    /// with `--locked` refuses to build if any input differs from `trellis.lock`.
+    pub fn build_rootfs_container_with(&self, args: &BuildArgs) -> Result<()> {
+        let started = unix_now();
+        let result = self.build_rootfs(args);
+        self.record_history(
+            Operation::Build,
+            started,
+            &self.config.rootfs_stages,
+            &self.config.rootfs_tag,
+            &result,
+        );
+        result
+    }
+
+    fn build_rootfs(&self, args: &BuildArgs) -> Result<()> {
         ConfigValidator::validate_stages(&self.config.rootfs_stages, "rootfs")?;
+        self.sources.ensure()?;
+        let stages = self.ordered_stages(&self.config.rootfs_stages)?;
+
+        if let Some(profile) = &self.config.profile {
+            self.msg(&format!("Using profile: {profile}"));
+        }
+
+        let lock_path =
+            lockfile::BuildLock::path(&self.config.stages_dir, self.config.profile.as_deref());
+        let mut lock = self
+            .builder
+            .resolve_lock(&stages, builder::BuildType::Rootfs)?;
+
+        if args.locked {
+            let locked = lockfile::BuildLock::load(&lock_path)?.ok_or_else(|| {
+                anyhow!(
+                    "--locked requires an existing lockfile: {}",
+                    lock_path.display()
+                )
+            })?;
+            let differences = locked.differences(&lock);
+            if !differences.is_empty() {
+                return Err(anyhow!(
+                    "Build inputs differ from {}:\n  {}",
+                    lock_path.display(),
+                    differences.join("\n  ")
+                ));
+            }
+        }
+
+        let start_index = self.builder.resolve_start_stage(
+            "stage",
+            &stages,
+            args.from.as_deref(),
+            args.resume,
+        )?;
 
         // Check if builder container exists before building rootfs
         if !self.check_builder_container_exists()? {
@@ -219,21 +405,343 @@ impl<'a> Trellis<'a> {
             }
         }
 
-        self.builder.build_multistage_container(
+        if start_index > 0 {
+            self.msg(&format!(
+                "Starting at stage {}/{}: {}",
+                start_index + 1,
+                stages.len(),
+                stages[start_index]
+            ));
+        }
+
+        let summary = self.builder.build_multistage_container_from(
             "stage",
             &self.config.rootfs_tag,
-            &self.config.rootfs_stages,
+            &stages,
             builder::BuildType::Rootfs,
+            start_index,
         )?;
 
+        let reused: Vec<&str> = summary
+            .stages
+            .iter()
+            .filter(|stage| stage.reused)
+            .map(|stage| stage.name.as_str())
+            .collect();
+        if !reused.is_empty() {
+            self.msg(&format!(
+                "Reused {}/{} unchanged stages: {}",
+                reused.len(),
+                summary.stages.len(),
+                reused.join(", ")
+            ));
+        }
+
+        // The build may only just have pulled the base image
+        if !self.config.dry_run {
+            lock.base_digest = self.builder.resolve_base_digest(&lock.rootfs_base)?;
+        }
+
+        // Recorded before embedding, so the lock in the image is the one written
+        self.builder
+            .record_image_ids(&mut lock, "stage", &self.config.rootfs_tag);
+
+        let build_id = summary.log_id.clone().unwrap_or_else(new_build_id);
+        let metadata = BuildMetadata::new(self.config, &lock, &build_id);
+        self.builder
+            .embed_metadata(&self.config.rootfs_tag, &metadata, &lock)?;
+
+        if self.config.rechunk {
+            self.builder.rechunk_image(&self.config.rootfs_tag)?;
+        }
+        self.versions.tag(&build_id)?;
+
+        if self.config.dry_run {
+            self.msg(&format!("Would write lockfile {}", lock_path.display()));
+        } else if let Err(e) = lock.save(&lock_path) {
+            self.warning(&format!("Failed to write lockfile: {e}"));
+        }
+
         self.msg("Rootfs container built successfully");
 
         // Auto-clean intermediate images if enabled
         self.cleaner.auto_clean()?;
+        if let Err(e) = self.cleaner.prune_versions() {
+            self.warning(&format!("Failed to remove old rootfs versions: {e}"));
+        }
+
+        Ok(())
+    }
+
+    /// Reports which rootfs stages are stale without building anything.
+    pub fn check_rootfs_container(&self) -> Result<()> {
+        ConfigValidator::validate_stages(&self.config.rootfs_stages, "rootfs")?;
+        self.sources.ensure()?;
+        let stages = self.ordered_stages(&self.config.rootfs_stages)?;
+
+        let statuses = self
+            .builder
+            .check_stages("stage", &stages, builder::BuildType::Rootfs)?;
+
+        let mut stale = 0;
+        for status in &statuses {
+            let description = match status.state {
+                builder::StageState::UpToDate => "up to date",
+                builder::StageState::Changed => "stale (inputs changed)",
+                builder::StageState::ParentChanged => "stale (parent stage changed)",
+            };
+            if status.state != builder::StageState::UpToDate {
+                stale += 1;
+            }
+            match &status.cache_tag {
+                Some(cache_tag) => {
+                    self.msg(&format!("{}: {description} [{cache_tag}]", status.name))
+                }
+                None => self.msg(&format!("{}: {description}", status.name)),
+            }
+        }
+
+        if stale == 0 {
+            self.msg("All stages are up to date");
+        } else {
+            self.msg(&format!(
+                "{stale}/{} stages would be rebuilt",
+                statuses.len()
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Prints the layer count and layer sizes of an image, the rootfs image by default.
+    pub fn inspect_image(&self, image: Option<&str>) -> Result<()> {
+        let image = image.map_or_else(
+            || {
+                format!(
+                    "{}{}",
+                    constants::containers::LOCALHOST_PREFIX,
+                    self.config.rootfs_tag
+                )
+            },
+            str::to_string,
+        );
+        let layers = ImageInspector::new(Arc::clone(&self.executor)).layers(&image)?;
+        print!("{layers}");
+        Ok(())
+    }
+
+    /// Lists the versions of the rootfs image, marking the one `latest` points at.
+    pub fn list_versions(&self) -> Result<()> {
+        let versions = self.versions.list()?;
+        if versions.is_empty() {
+            self.msg(&format!(
+                "No versions of {} found",
+                self.versions.repository()
+            ));
+        }
+        for version in versions {
+            let marker = if version.latest { "*" } else { " " };
+            let created = UtcTime::from_unix(u64::try_from(version.created).unwrap_or_default());
+            println!(
+                "{marker} {}\t{}\t{created}",
+                version.version, version.image_id
+            );
+        }
+        Ok(())
+    }
+
+    /// Points the rootfs image's `latest` tag at an earlier version.
+    pub fn retag_version(&self, version: &str) -> Result<()> {
+        let target = self.versions.retag(version)?;
+        self.msg(&format!(
+            "{} now points at {} ({})",
+            self.versions.reference(constants::containers::LATEST_TAG),
+            target.version,
+            target.image_id
+        ));
+        self.msg("Run `bootc upgrade` to deploy it");
+        Ok(())
+    }
+
+    /// Pushes the rootfs image and its versions to the configured registry, or to
+    /// `to` if given.
+    pub fn push(&self, to: Option<&str>) -> Result<()> {
+        let pusher = RegistryPusher::new(self.config, Arc::clone(&self.executor));
+        let registry = pusher.registry(to)?;
+        let pushed = pusher.push(&registry)?;
+        self.msg(&format!("Pushed {} images", pushed.len()));
+        Ok(())
+    }
+
+    /// Compares the pinned digest of the rootfs base image with the newest one.
+    pub fn check_base(&self) -> Result<()> {
+        let checker = BaseImageChecker::new(self.config, Arc::clone(&self.executor));
+        match checker.check()? {
+            BaseStatus::UpToDate { digest } => {
+                self.msg(&format!(
+                    "{} is up to date ({digest})",
+                    checker.floating_reference()
+                ));
+            }
+            BaseStatus::Outdated { pinned, latest } => {
+                self.msg(&format!(
+                    "A newer {} is available: {latest} (pinned {pinned})",
+                    checker.floating_reference()
+                ));
+                self.msg("Run `trls base pin` to update rootfs_base");
+            }
+            BaseStatus::Unpinned { latest } => {
+                self.msg(&format!(
+                    "rootfs_base {} is not pinned, newest digest is {latest}",
+                    checker.floating_reference()
+                ));
+                self.msg("Run `trls base pin` to pin it");
+            }
+        }
+        Ok(())
+    }
+
+    /// Pins `rootfs_base` to the newest digest in the configuration file.
+    pub fn pin_base(&self) -> Result<()> {
+        let checker = BaseImageChecker::new(self.config, Arc::clone(&self.executor));
+        let pinned = checker.pinned_reference(&checker.latest_digest()?);
+        if pinned == self.config.rootfs_base {
+            self.msg(&format!("rootfs_base is already pinned to {pinned}"));
+            return Ok(());
+        }
+
+        let config_path = &self.config.config_path;
+        if self.config.dry_run {
+            self.msg(&format!(
+                "Would pin rootfs_base to {pinned} in {}",
+                config_path.display()
+            ));
+            return Ok(());
+        }
+        let table = writer::set_rootfs_base(config_path, self.config.profile.as_deref(), &pinned)?;
+        self.msg(&format!(
+            "Pinned rootfs_base to {pinned} in [{table}] of {}",
+            config_path.display()
+        ));
+        Ok(())
+    }
+
+    /// Prints the dependency graph of the rootfs stages.
+    pub fn stages_graph(&self, format: GraphFormat) -> Result<()> {
+        ConfigValidator::validate_stages(&self.config.rootfs_stages, "rootfs")?;
+        self.sources.ensure()?;
+
+        match format {
+            GraphFormat::Dot => {
+                print!(
+                    "{}",
+                    self.builder
+                        .discovery()
+                        .stage_graph_dot(&self.config.rootfs_stages)?
+                );
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Lists logged builds, the stages logged for one build, or prints a stage log.
+    ///
+    /// Without `build`, a stage log is taken from the most recent build.
+    pub fn show_logs(&self, build: Option<&str>, stage: Option<&str>) -> Result<()> {
+        let log_dir = &self.config.log_dir;
+
+        match (build, stage) {
+            (None, None) => {
+                let ids = BuildLog::list(log_dir)?;
+                if ids.is_empty() {
+                    self.msg(&format!("No build logs found in {}", log_dir.display()));
+                }
+                for id in ids {
+                    let stages = BuildLog::open(log_dir, &id)?.stages()?;
+                    println!("{id}\t{}", stages.join(", "));
+                }
+            }
+            (Some(id), None) => {
+                for stage in BuildLog::open(log_dir, id)?.stages()? {
+                    println!("{stage}");
+                }
+            }
+            (build, Some(stage)) => {
+                let log = match build {
+                    Some(id) => BuildLog::open(log_dir, id)?,
+                    None => BuildLog::latest(log_dir)?,
+                };
+                let path = log.find_stage(stage)?;
+                let mut file = std::fs::File::open(&path)
+                    .with_context(|| format!("Failed to open log: {}", path.display()))?;
+                io::copy(&mut file, &mut io::stdout().lock())
+                    .with_context(|| format!("Failed to read log: {}", path.display()))?;
+            }
+        }
 
         Ok(())
     }
 
+    /// Prints the lint findings of the given stages, or of the builder and rootfs
+    /// stages, and fails if any of them is an error.
+    pub fn lint_stages(&self, stages: &[String]) -> Result<()> {
+        self.sources.ensure()?;
+        let lists = if stages.is_empty() {
+            vec![
+                self.config.builder_stages.as_slice(),
+                &self.config.rootfs_stages,
+            ]
+        } else {
+            vec![stages]
+        };
+
+        let (mut errors, mut warnings) = (0, 0);
+        for list in lists {
+            for finding in self.builder.discovery().lint_stages(list)? {
+                match finding.severity {
+                    Severity::Error => errors += 1,
+                    Severity::Warning => warnings += 1,
+                }
+                println!("{finding}");
+            }
+        }
+
+        if errors > 0 {
+            return Err(anyhow!(
+                "{}: {errors} error(s), {warnings} warning(s)",
+                errors::CONTAINERFILE_LINT_FAILED
+            ));
+        }
+        self.msg(&format!("Lint found {warnings} warning(s)"));
+        Ok(())
+    }
+
+    /// Prints a stage's Containerfile after preprocessing, whether or not
+    /// preprocessing is enabled for builds.
+    pub fn render_stage(&self, build_stage: &str) -> Result<()> {
+        self.sources.ensure()?;
+        let (group, _) = ContainerfileDiscovery::parse_stage_name(build_stage);
+        let path = self.builder.discovery().find_containerfile(&group)?;
+        print!(
+            "{}",
+            Preprocessor::new(self.config, build_stage).render_file(&path)?
+        );
+        Ok(())
+    }
+
+    /// Orders stages by their declared dependencies, reporting any reordering.
+    fn ordered_stages(&self, stages: &[String]) -> Result<Vec<String>> {
+        let ordered = self.builder.discovery().order_stages(stages)?;
+        if ordered != stages {
+            self.msg(&format!(
+                "Reordered stages to satisfy dependencies: {}",
+                ordered.join(", ")
+            ));
+        }
+        Ok(ordered)
+    }
+
     pub fn run_rootfs_container(&self, args: &[String]) -> Result<()> {
         self.runner.run_container(&self.config.rootfs_tag, args)
     }
@@ -243,13 +751,34 @@ impl<'a> Trellis<'a> {
     }
 
     pub fn update(&self) -> Result<()> {
-        self.build_rootfs_container()?;
-        self.runner.run_bootc_upgrade()
+        // bootc upgrades from containers-storage
+        engine::require_containers_storage(self.config, "trls update")?;
+        let started = unix_now();
+        let result = self
+            .build_rootfs(&BuildArgs::default())
+            .and_then(|()| self.runner.run_bootc_upgrade());
+        self.record_history(
+            Operation::Update,
+            started,
+            &self.config.rootfs_stages,
+            &self.config.rootfs_tag,
+            &result,
+        );
+        result
     }
 
     /// Performs a quick update of the rootfs container using topgrade.
     pub fn quick_update_rootfs(&self) -> Result<()> {
-        self.runner.quick_update_rootfs()
+        let started = unix_now();
+        let result = self.runner.quick_update_rootfs();
+        self.record_history(
+            Operation::QuickUpdate,
+            started,
+            &[],
+            &self.config.rootfs_tag,
+            &result,
+        );
+        result
     }
 
     /// Generate a bootable disk image from a container image.
@@ -279,22 +808,198 @@ impl<'a> Trellis<'a> {
             );
         }
 
-        // Optionally build first
-        if build_first {
-            self.build_rootfs_container()?;
-        }
+        // Refuse before building rather than after
+        let generator = ImageGenerator::new(self.config, Arc::clone(&self.executor));
+        generator.require_root()?;
+        engine::require_containers_storage(self.config, "trls image")?;
 
+        let started = unix_now();
         let resolved_image_tag = resolve_image_tag(self.config, image_tag);
         let output = output_path.unwrap_or_else(|| PathBuf::from("bootable.img"));
 
-        let generator = ImageGenerator::new(self.config, Arc::clone(&self.executor));
-        generator.generate_bootable_image(
+        // Optionally build first
+        let built = if build_first {
+            self.build_rootfs(&BuildArgs::default())
+        } else {
+            Ok(())
+        };
+        let result = built.and_then(|()| {
+            generator.generate_bootable_image(
+                &resolved_image_tag,
+                &output,
+                filesystem,
+                size_gb,
+                root_password,
+            )
+        });
+
+        let stages = if build_first {
+            self.config.rootfs_stages.as_slice()
+        } else {
+            &[]
+        };
+        self.record_history(
+            Operation::Image,
+            started,
+            stages,
             &resolved_image_tag,
-            &output,
-            filesystem,
-            size_gb,
-            root_password,
-        )
+            &result,
+        );
+        result
+    }
+
+    /// Records a finished operation in the build history and reports stages that
+    /// became notably slower than in the previous successful build.
+    ///
+    /// Failures to write history only produce a warning.
+    fn record_history(
+        &self,
+        operation: Operation,
+        started: u64,
+        stages: &[String],
+        image: &str,
+        result: &Result<()>,
+    ) {
+        if self.config.dry_run {
+            return;
+        }
+
+        let summary = self.builder.take_last_summary().unwrap_or_default();
+        let entry = HistoryEntry {
+            id: summary.log_id.clone().unwrap_or_else(new_build_id),
+            operation,
+            started,
+            finished: unix_now(),
+            stages: stages.to_vec(),
+            stage_timings: summary
+                .stages
+                .iter()
+                .map(|stage| StageTiming {
+                    name: stage.name.clone(),
+                    seconds: stage.duration.as_secs_f64(),
+                    reused: stage.reused,
+                })
+                .collect(),
+            success: result.is_ok(),
+            error: result.as_ref().err().map(|e| format!("{e:#}")),
+            image_id: result.is_ok().then(|| self.builder.resolve_image_id(image)),
+            log_id: summary.log_id,
+            config: serde_json::to_value(self.config).unwrap_or_default(),
+        };
+
+        let history = History::new(&self.config.state_dir);
+        if let Err(e) = history.save(&entry) {
+            self.warning(&format!("Failed to record build history: {e}"));
+            return;
+        }
+
+        if entry.success {
+            let entries = history.entries().unwrap_or_default();
+            if let Some(previous) = History::previous_successful(&entries, &entry) {
+                for regression in entry.regressions(&previous) {
+                    self.warning(&regression.to_string());
+                }
+            }
+        }
+    }
+
+    /// Fetches the `[[sources]]` repositories and checks out their refs again.
+    pub fn update_sources(&self, name: Option<&str>) -> Result<()> {
+        self.sources.update(name)
+    }
+
+    /// Lists recorded operations, oldest first, flagging slower stages.
+    pub fn show_history(&self) -> Result<()> {
+        let entries = History::new(&self.config.state_dir).entries()?;
+        if entries.is_empty() {
+            self.msg("No build history recorded");
+            return Ok(());
+        }
+
+        println!(
+            "{:<24}  {:<12}  {:<23}  {:>9}  RESULT",
+            "ID", "OPERATION", "STARTED", "DURATION"
+        );
+        for entry in &entries {
+            let mut result = if entry.success { "success" } else { "failed" }.to_string();
+            let slower: Vec<String> = History::previous_successful(&entries, entry)
+                .filter(|_| entry.success)
+                .map(|previous| entry.regressions(&previous))
+                .unwrap_or_default()
+                .into_iter()
+                .map(|regression| regression.stage)
+                .collect();
+            if !slower.is_empty() {
+                result.push_str(&format!(" (slower: {})", slower.join(", ")));
+            }
+
+            println!(
+                "{:<24}  {:<12}  {:<23}  {:>9}  {result}",
+                entry.id,
+                entry.operation.as_str(),
+                UtcTime::from_unix(entry.started).to_string(),
+                format_duration(entry.duration() as f64),
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Prints the details of one recorded operation.
+    pub fn show_history_entry(&self, id: &str) -> Result<()> {
+        let history = History::new(&self.config.state_dir);
+        let entry = history.find(id)?;
+
+        println!("ID:         {}", entry.id);
+        println!("Operation:  {}", entry.operation.as_str());
+        println!("Started:    {}", UtcTime::from_unix(entry.started));
+        println!("Finished:   {}", UtcTime::from_unix(entry.finished));
+        println!("Duration:   {}", format_duration(entry.duration() as f64));
+        match &entry.error {
+            None => println!("Result:     success"),
+            Some(error) => println!("Result:     failed: {error}"),
+        }
+        if let Some(image_id) = &entry.image_id {
+            println!("Image:      {image_id}");
+        }
+        if let Some(log_id) = &entry.log_id {
+            println!("Logs:       trls logs --build {log_id}");
+        }
+
+        if !entry.stages.is_empty() {
+            println!("Stages:");
+            let width = entry.stages.iter().map(String::len).max().unwrap_or(0);
+            for stage in &entry.stages {
+                let timing = entry.stage_timings.iter().find(|t| &t.name == stage);
+                let status = match timing {
+                    Some(t) if t.reused => "reused".to_string(),
+                    Some(t) => format_duration(t.seconds),
+                    None => "not built".to_string(),
+                };
+                println!("  {stage:<width$}  {status}");
+            }
+        }
+
+        if entry.success {
+            let entries = history.entries()?;
+            if let Some(previous) = History::previous_successful(&entries, &entry) {
+                let regressions = entry.regressions(&previous);
+                if !regressions.is_empty() {
+                    println!("Regressions:");
+                    for regression in regressions {
+                        println!("  {regression}");
+                    }
+                }
+            }
+        }
+
+        println!("Config:");
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&entry.config).context("Failed to format config")?
+        );
+
+        Ok(())
     }
     ///
     /// # Returns
@@ -303,30 +1008,16 @@ impl<'a> Trellis<'a> {
     /// * `Ok(false)` if the builder container does not exist
     /// * `Err` if the podman command fails
     pub fn check_builder_container_exists(&self) -> Result<bool> {
-        let args = vec![
-            "--filter".to_string(),
-            format!("reference=localhost/{}", self.config.builder_tag),
-            "--format".to_string(),
-            "{{.Repository}}:{{.Tag}}".to_string(),
-        ];
-
-        let output = self
+        let images = self
             .executor
-            .podman_images(&args)
+            .list_images(Some(&format!("localhost/{}", self.config.builder_tag)))
             .context("Failed to check if builder container exists")?;
 
-        if !output.status.success() {
-            return Err(anyhow!(
-                "Podman images command failed with exit code: {:?}",
-                output.status.code()
-            ));
-        }
-
-        let stdout = String::from_utf8_lossy(&output.stdout);
         let expected_prefix = format!("localhost/{}:", self.config.builder_tag);
-
-        Ok(stdout
-            .lines()
-            .any(|line| line.trim().starts_with(&expected_prefix)))
+        let exists = images
+            .iter()
+            .flat_map(|image| image.references())
+            .any(|reference| reference.starts_with(&expected_prefix));
+        Ok(exists)
     }
 }
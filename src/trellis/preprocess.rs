@@ -0,0 +1,302 @@
+//! Containerfile preprocessing.
+//!
+//! When enabled, Containerfiles are rendered before they are passed to podman:
+//! - `# trellis:include <path>` is replaced by the contents of the file at `<path>`,
+//!   relative to the stages directory, or to the `[[sources]]` checkout holding the
+//!   including file
+//! - `{{ name }}` is replaced by the value of the variable `name`
+//! - `# trellis:if <condition>` / `# trellis:else` / `# trellis:endif` keep or drop
+//!   the lines between them
+//!
+//! Conditions are `name == "value"`, `name != "value"` or a bare `name`, which holds
+//! if the variable is set and not empty. Placeholders that are not plain identifiers,
+//! such as Go templates like `{{.Id}}`, are left untouched.
+
+use anyhow::{anyhow, Context, Result};
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use super::constants::patterns;
+use crate::config::TrellisConfig;
+
+/// Directive keywords following the `trellis:` prefix.
+const DIRECTIVES: &[&str] = &["include", "if", "else", "endif"];
+
+/// Maximum nesting depth of includes, guarding against runaway recursion.
+const MAX_INCLUDE_DEPTH: usize = 16;
+
+/// Returns true if the text following `# trellis:` is a preprocessor directive
+/// rather than stage metadata.
+pub fn is_directive(entries: &str) -> bool {
+    entries
+        .split_whitespace()
+        .next()
+        .is_some_and(|word| DIRECTIVES.contains(&word))
+}
+
+/// Renders Containerfiles for a single stage.
+pub struct Preprocessor {
+    /// Directories holding Containerfiles, see `TrellisConfig::stage_dirs`
+    stage_dirs: Vec<PathBuf>,
+    vars: BTreeMap<String, String>,
+}
+
+/// State of an open `trellis:if` block.
+struct Conditional {
+    /// Whether the enclosing block is active
+    parent_active: bool,
+    /// Whether the condition of the block holds
+    condition: bool,
+    in_else: bool,
+}
+
+impl Conditional {
+    fn active(&self) -> bool {
+        self.parent_active && (self.condition != self.in_else)
+    }
+}
+
+impl Preprocessor {
+    /// Creates a preprocessor with the `[vars]` table and the built-in variables
+    /// `stage`, `profile`, `rootfs_tag`, `builder_tag` and `rootfs_base`.
+    ///
+    /// Built-in variables take precedence over `[vars]` entries of the same name.
+    pub fn new(config: &TrellisConfig, build_stage: &str) -> Self {
+        let mut vars = config.vars.clone();
+        vars.insert("stage".to_string(), build_stage.to_string());
+        vars.insert(
+            "profile".to_string(),
+            config.profile.clone().unwrap_or_default(),
+        );
+        vars.insert("rootfs_tag".to_string(), config.rootfs_tag.clone());
+        vars.insert("builder_tag".to_string(), config.builder_tag.clone());
+        vars.insert("rootfs_base".to_string(), config.rootfs_base.clone());
+
+        Self {
+            stage_dirs: config.stage_dirs(),
+            vars,
+        }
+    }
+
+    /// Renders a Containerfile.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error naming the file and line of any malformed directive,
+    /// unknown variable, unbalanced conditional or unreadable include.
+    pub fn render_file(&self, path: &Path) -> Result<String> {
+        let mut output = String::new();
+        self.render_into(path, &mut Vec::new(), &mut output)?;
+        Ok(output)
+    }
+
+    fn render_into(
+        &self,
+        path: &Path,
+        stack: &mut Vec<PathBuf>,
+        output: &mut String,
+    ) -> Result<()> {
+        if stack.len() >= MAX_INCLUDE_DEPTH {
+            return Err(anyhow!(
+                "Includes nested deeper than {MAX_INCLUDE_DEPTH} levels at {}",
+                path.display()
+            ));
+        }
+        if stack.iter().any(|p| p == path) {
+            return Err(anyhow!("Include cycle at {}", path.display()));
+        }
+
+        let content = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        stack.push(path.to_path_buf());
+
+        let mut conditionals: Vec<Conditional> = Vec::new();
+        for (number, line) in content.lines().enumerate() {
+            let location = || format!("{}:{}", path.display(), number + 1);
+            let active = conditionals.last().is_none_or(Conditional::active);
+
+            let Some((keyword, argument)) = Self::directive(line) else {
+                if active {
+                    let line = self
+                        .substitute(line)
+                        .with_context(|| format!("In {}", location()))?;
+                    output.push_str(&line);
+                    output.push('\n');
+                }
+                continue;
+            };
+
+            match keyword {
+                "include" => {
+                    if active {
+                        if argument.is_empty() {
+                            return Err(anyhow!("{}: include requires a path", location()));
+                        }
+                        let include = self.include_root(path).join(argument);
+                        self.render_into(&include, stack, output)
+                            .with_context(|| format!("Included from {}", location()))?;
+                    }
+                }
+                "if" => {
+                    let condition = self
+                        .evaluate(argument)
+                        .with_context(|| format!("In {}", location()))?;
+                    conditionals.push(Conditional {
+                        parent_active: active,
+                        condition,
+                        in_else: false,
+                    });
+                }
+                "else" => match conditionals.last_mut() {
+                    Some(conditional) if !conditional.in_else => conditional.in_else = true,
+                    _ => return Err(anyhow!("{}: else without matching if", location())),
+                },
+                _ => {
+                    if conditionals.pop().is_none() {
+                        return Err(anyhow!("{}: endif without matching if", location()));
+                    }
+                }
+            }
+        }
+
+        if !conditionals.is_empty() {
+            return Err(anyhow!("{}: if without matching endif", path.display()));
+        }
+
+        stack.pop();
+        Ok(())
+    }
+
+    /// Returns the directory includes of a file are relative to: the stages
+    /// directory or source checkout holding the file.
+    fn include_root(&self, path: &Path) -> &Path {
+        self.stage_dirs
+            .iter()
+            .filter(|dir| path.starts_with(dir))
+            .max_by_key(|dir| dir.components().count())
+            .unwrap_or(&self.stage_dirs[0])
+    }
+
+    /// Splits a directive line into its keyword and argument.
+    fn directive(line: &str) -> Option<(&str, &str)> {
+        let entries = line
+            .trim()
+            .strip_prefix('#')?
+            .trim_start()
+            .strip_prefix(patterns::METADATA_PREFIX)?;
+        if !is_directive(entries) {
+            return None;
+        }
+
+        let entries = entries.trim();
+        Some(match entries.split_once(char::is_whitespace) {
+            Some((keyword, argument)) => (keyword, argument.trim()),
+            None => (entries, ""),
+        })
+    }
+
+    /// Evaluates the condition of an `if` directive. Unset variables are empty.
+    fn evaluate(&self, condition: &str) -> Result<bool> {
+        let value_of = |name: &str| -> Result<&str> {
+            if !Self::is_identifier(name) {
+                return Err(anyhow!("Invalid variable name '{name}' in condition"));
+            }
+            Ok(self.vars.get(name).map(String::as_str).unwrap_or_default())
+        };
+        let literal = |value: &str| {
+            let value = value.trim();
+            value
+                .strip_prefix('"')
+                .and_then(|v| v.strip_suffix('"'))
+                .unwrap_or(value)
+                .to_string()
+        };
+
+        if let Some((name, value)) = condition.split_once("!=") {
+            Ok(value_of(name.trim())? != literal(value))
+        } else if let Some((name, value)) = condition.split_once("==") {
+            Ok(value_of(name.trim())? == literal(value))
+        } else if condition.is_empty() {
+            Err(anyhow!("if requires a condition"))
+        } else {
+            Ok(!value_of(condition)?.is_empty())
+        }
+    }
+
+    /// Replaces `{{ name }}` placeholders in a line.
+    fn substitute(&self, line: &str) -> Result<String> {
+        let mut output = String::with_capacity(line.len());
+        let mut rest = line;
+
+        while let Some(start) = rest.find("{{") {
+            let Some(len) = rest[start + 2..].find("}}") else {
+                break;
+            };
+            let name = rest[start + 2..start + 2 + len].trim();
+            output.push_str(&rest[..start]);
+
+            if Self::is_identifier(name) {
+                let value = self
+                    .vars
+                    .get(name)
+                    .ok_or_else(|| anyhow!("Unknown variable '{name}'"))?;
+                output.push_str(value);
+            } else {
+                output.push_str(&rest[start..start + 4 + len]);
+            }
+            rest = &rest[start + 4 + len..];
+        }
+
+        output.push_str(rest);
+        Ok(output)
+    }
+
+    fn is_identifier(name: &str) -> bool {
+        let mut chars = name.chars();
+        chars
+            .next()
+            .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+            && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+    }
+}
+
+/// A rendered Containerfile in a temporary location, removed when dropped.
+pub struct RenderedContainerfile {
+    path: PathBuf,
+}
+
+impl RenderedContainerfile {
+    /// Writes rendered contents for a stage to a temporary file.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the temporary file cannot be written.
+    pub fn write(build_stage: &str, content: &str) -> Result<Self> {
+        let dir = std::env::temp_dir().join(format!("trellis-render-{}", std::process::id()));
+        fs::create_dir_all(&dir)
+            .with_context(|| format!("Failed to create render directory: {}", dir.display()))?;
+
+        let path = dir.join(format!("Containerfile.{}", build_stage.replace(':', "-")));
+        fs::write(&path, content).with_context(|| {
+            format!("Failed to write rendered Containerfile: {}", path.display())
+        })?;
+        Ok(Self { path })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for RenderedContainerfile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+        if let Some(dir) = self.path.parent() {
+            // Only succeeds once the directory is empty
+            let _ = fs::remove_dir(dir);
+        }
+    }
+}
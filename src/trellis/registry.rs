@@ -0,0 +1,200 @@
+//! Pushing the rootfs image to an OCI registry.
+//!
+//! `trls push` copies `latest` and every versioned tag of the rootfs image to the
+//! destination from `[registry]`, optionally signing them. The `build.json` and the
+//! lockfile embedded in the version `latest` points at are pushed alongside as a
+//! small `<version>-metadata` image holding just those two files.
+
+use anyhow::{anyhow, Context, Result};
+use std::{fs, path::PathBuf, sync::Arc};
+
+use super::{
+    builder::PodmanCommandBuilder,
+    common::TrellisMessaging,
+    constants::{
+        containers::{LATEST_TAG, LOCALHOST_PREFIX, METADATA_TAG_SUFFIX},
+        patterns,
+    },
+    engine,
+    executor::CommandExecutor,
+    metadata::LABEL_PREFIX,
+    versions::RootfsVersions,
+};
+use crate::config::{Layering, NetworkMode, Registry, RegistryDestination, TrellisConfig};
+
+/// Pushes rootfs images and their build metadata to a registry.
+pub struct RegistryPusher<'a> {
+    config: &'a TrellisConfig,
+    executor: Arc<dyn CommandExecutor>,
+}
+
+impl<'a> TrellisMessaging for RegistryPusher<'a> {}
+
+impl<'a> RegistryPusher<'a> {
+    pub fn new(config: &'a TrellisConfig, executor: Arc<dyn CommandExecutor>) -> Self {
+        Self { config, executor }
+    }
+
+    /// Returns the push settings, with the destination replaced by `url` if given.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if neither `[registry]` nor `url` name a destination, or
+    /// `url` is invalid.
+    pub fn registry(&self, url: Option<&str>) -> Result<Registry> {
+        match (url, &self.config.registry) {
+            (Some(url), Some(registry)) => Ok(Registry {
+                destination: RegistryDestination::parse(url)?,
+                ..registry.clone()
+            }),
+            (Some(url), None) => Ok(Registry::new(RegistryDestination::parse(url)?)),
+            (None, Some(registry)) => Ok(registry.clone()),
+            (None, None) => Err(anyhow!(
+                "No registry configured. Add a [registry] section or pass --to"
+            )),
+        }
+    }
+
+    /// Pushes `latest` and the versioned tags of the rootfs image, followed by the
+    /// build metadata if enabled. Returns the pushed references.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the container engine does not use containers-storage,
+    /// signing is requested for an OCI layout destination, or an image cannot be
+    /// pushed.
+    pub fn push(&self, registry: &Registry) -> Result<Vec<String>> {
+        engine::require_containers_storage(self.config, "trls push")?;
+        if registry.signs() && matches!(registry.destination, RegistryDestination::OciLayout(_)) {
+            return Err(anyhow!(
+                "Signing requires a registry destination, not an OCI layout directory"
+            ));
+        }
+
+        let repository = registry
+            .repository
+            .clone()
+            .unwrap_or_else(|| self.config.rootfs_tag.clone());
+        let versions = RootfsVersions::new(self.config, Arc::clone(&self.executor));
+        let listed = if self.config.keep_versions > 0 {
+            versions.list()?
+        } else {
+            Vec::new()
+        };
+
+        let mut pushed = Vec::new();
+        let tags = std::iter::once(LATEST_TAG).chain(listed.iter().map(|v| v.version.as_str()));
+        for tag in tags {
+            let target = registry.destination.reference(&repository, tag);
+            self.push_image(registry, &versions.reference(tag), &target)?;
+            pushed.push(target);
+        }
+
+        if registry.attach_metadata {
+            let version = listed
+                .iter()
+                .find(|version| version.latest)
+                .map_or(LATEST_TAG, |version| version.version.as_str());
+            let tag = format!("{version}{METADATA_TAG_SUFFIX}");
+            let target = registry.destination.reference(&repository, &tag);
+            self.push_metadata(registry, &versions.reference(version), &target)?;
+            pushed.push(target);
+        }
+
+        Ok(pushed)
+    }
+
+    /// Builds the metadata image for `image`, pushes it and removes it again.
+    fn push_metadata(&self, registry: &Registry, image: &str, target: &str) -> Result<()> {
+        let context = AttachmentContext::write(image)?;
+        let local = format!(
+            "{LOCALHOST_PREFIX}{}{METADATA_TAG_SUFFIX}:{}",
+            self.config.rootfs_tag,
+            std::process::id()
+        );
+        let build_args = PodmanCommandBuilder::new_build_command(
+            NetworkMode::None,
+            &engine::build_security(self.config),
+            Layering::SquashAll,
+        )
+        .containerfile(context.containerfile())
+        .label(&format!("{LABEL_PREFIX}metadata-for"), image)
+        .tag(&local)
+        .build_args();
+        let output = self
+            .executor
+            .podman_build(&build_args)
+            .context("Failed to build metadata image")?;
+        if !output.status.success() {
+            return Err(anyhow!(
+                "Building metadata image for {image} failed: {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            ));
+        }
+
+        let result = self.push_image(registry, &local, target);
+        let _ = self.executor.podman_rmi(&[local]);
+        result
+    }
+
+    fn push_image(&self, registry: &Registry, source: &str, target: &str) -> Result<()> {
+        self.msg(&format!("Pushing {source} to {target}"));
+
+        let mut args = vec!["push".to_string()];
+        if self.config.quiet {
+            args.push("--quiet".to_string());
+        }
+        args.extend(registry.push_args());
+        args.extend([source.to_string(), target.to_string()]);
+
+        let output = self
+            .executor
+            .execute("podman", &args)
+            .with_context(|| format!("Failed to push {source}"))?;
+        if !output.status.success() {
+            return Err(anyhow!(
+                "Failed to push {source} to {target}: {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Build context of a metadata image, removed when dropped.
+struct AttachmentContext {
+    dir: PathBuf,
+}
+
+impl AttachmentContext {
+    /// Writes a Containerfile copying `build.json` and the lockfile out of `image`.
+    ///
+    /// The whole metadata directory is copied, since images built before the
+    /// lockfile was embedded only hold `build.json`.
+    fn write(image: &str) -> Result<Self> {
+        let dir = std::env::temp_dir().join(format!("trellis-push-{}", std::process::id()));
+        fs::create_dir_all(&dir)
+            .with_context(|| format!("Failed to create metadata directory: {}", dir.display()))?;
+        let context = Self { dir };
+
+        let containerfile = format!(
+            "FROM {image} AS build\n\
+             FROM scratch\n\
+             COPY --from=build {}/ /\n",
+            patterns::BUILD_METADATA_DIR
+        );
+        fs::write(context.containerfile(), containerfile)
+            .context("Failed to write metadata Containerfile")?;
+        Ok(context)
+    }
+
+    fn containerfile(&self) -> PathBuf {
+        self.dir.join("Containerfile")
+    }
+}
+
+impl Drop for AttachmentContext {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
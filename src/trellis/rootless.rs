@@ -0,0 +1,403 @@
+//! Rootless podman support.
+//!
+//! In rootless mode trls drives the invoking user's podman: images live in the
+//! user's container storage, and state, logs, the run lock, source checkouts and
+//! package caches default to the user's XDG directories. Operations that need real root are either
+//! handed off to `sudo` or `run0`, like `bootc upgrade`, or refused with an
+//! explanation, like disk image generation.
+
+use anyhow::{anyhow, Context, Result};
+use std::{
+    ffi::OsString,
+    fs,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use super::{
+    common::TrellisMessaging, constants::paths, executor::CommandExecutor, interrupt::Cleanup,
+};
+use crate::config::{Rootless, TrellisConfig};
+
+/// Commands tried in order to hand operations off to root.
+const PRIVILEGE_COMMANDS: &[&str] = &["sudo", "run0"];
+
+/// Storage configuration read for the `rootless_storage_path` of all users.
+const SYSTEM_STORAGE_CONF: &str = "/etc/containers/storage.conf";
+
+/// Checks whether trls runs with root's effective user ID.
+pub fn is_root() -> bool {
+    // SAFETY: geteuid has no preconditions and cannot fail
+    unsafe { libc::geteuid() == 0 }
+}
+
+/// Resolves the `rootless` setting for the current user.
+///
+/// # Errors
+///
+/// Returns an error if rootless mode is forced on while running as root, where
+/// podman uses root's storage.
+pub fn resolve(mode: Rootless) -> Result<bool> {
+    match mode {
+        Rootless::Off => Ok(false),
+        Rootless::Auto => Ok(!is_root()),
+        Rootless::On if is_root() => Err(anyhow!(
+            "rootless = true, but trls runs as root, where podman uses root's storage. \
+             Use rootless = \"auto\" to build rootful as root"
+        )),
+        Rootless::On => Ok(true),
+    }
+}
+
+/// Returns the volume giving a container access to the container storage trls uses.
+pub fn storage_volume(config: &TrellisConfig) -> String {
+    if config.rootless {
+        format!(
+            "{}:/var/lib/containers/storage",
+            config.storage_dir.display()
+        )
+    } else {
+        "/var/lib/containers:/var/lib/containers".to_string()
+    }
+}
+
+/// Refuses an operation that rootless podman cannot perform.
+///
+/// # Errors
+///
+/// Returns an error naming `operation` and `reason` in rootless mode, telling how
+/// to run `operation` as root instead.
+pub fn require_root(config: &TrellisConfig, operation: &str, reason: &str) -> Result<()> {
+    if config.rootless {
+        return Err(anyhow!(
+            "`{operation}` needs root: {reason}. Copy the image to root's storage with \
+             `podman image scp localhost/{}:latest root@localhost::` and run \
+             `sudo {operation}`",
+            config.rootfs_tag
+        ));
+    }
+    Ok(())
+}
+
+/// XDG base directories of the invoking user, holding trls' rootless defaults.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UserDirs {
+    home: PathBuf,
+    config: PathBuf,
+    data: PathBuf,
+    state: PathBuf,
+    cache: PathBuf,
+    runtime: PathBuf,
+    storage_conf: Option<PathBuf>,
+}
+
+impl UserDirs {
+    /// Reads the directories from the environment.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `HOME` is not set.
+    pub fn from_env() -> Result<Self> {
+        Self::from_vars(|name| std::env::var_os(name))
+    }
+
+    /// Reads the directories from the variables `var` returns, ignoring relative
+    /// XDG directories as the specification requires.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `HOME` is not set.
+    pub fn from_vars(var: impl Fn(&str) -> Option<OsString>) -> Result<Self> {
+        let home = var("HOME")
+            .filter(|home| !home.is_empty())
+            .map(PathBuf::from)
+            .ok_or_else(|| {
+                anyhow!("HOME is not set, rootless mode needs it for its directories")
+            })?;
+        let xdg = |name: &str, default: &str| {
+            var(name)
+                .map(PathBuf::from)
+                .filter(|dir| dir.is_absolute())
+                .unwrap_or_else(|| home.join(default))
+        };
+        // SAFETY: getuid has no preconditions and cannot fail
+        let uid = unsafe { libc::getuid() };
+
+        Ok(Self {
+            config: xdg("XDG_CONFIG_HOME", ".config"),
+            data: xdg("XDG_DATA_HOME", ".local/share"),
+            state: xdg("XDG_STATE_HOME", ".local/state"),
+            cache: xdg("XDG_CACHE_HOME", ".cache"),
+            runtime: var("XDG_RUNTIME_DIR")
+                .map(PathBuf::from)
+                .filter(|dir| dir.is_absolute())
+                .unwrap_or_else(|| PathBuf::from(format!("/tmp/trellis-{uid}"))),
+            storage_conf: var("CONTAINERS_STORAGE_CONF").map(PathBuf::from),
+            home,
+        })
+    }
+
+    /// Default state directory, under `$XDG_STATE_HOME`.
+    pub fn state_dir(&self) -> PathBuf {
+        self.state.join("trellis/state")
+    }
+
+    /// Default build log directory, under `$XDG_STATE_HOME`.
+    pub fn log_dir(&self) -> PathBuf {
+        self.state.join("trellis/logs")
+    }
+
+    /// Default run lock directory, under `$XDG_RUNTIME_DIR`.
+    pub fn lock_dir(&self) -> PathBuf {
+        self.runtime.join("trellis")
+    }
+
+    /// Socket of the user's libpod REST API, under `$XDG_RUNTIME_DIR`.
+    pub fn podman_socket(&self) -> PathBuf {
+        self.runtime.join("podman/podman.sock")
+    }
+
+    /// Default directory of source checkouts, under `$XDG_CACHE_HOME`.
+    pub fn sources_dir(&self) -> PathBuf {
+        self.cache.join("trellis/sources")
+    }
+
+    /// Default Pacman package cache, under `$XDG_CACHE_HOME`.
+    pub fn pacman_cache(&self) -> PathBuf {
+        self.cache.join("trellis/pacman")
+    }
+
+    /// Default AUR package cache, under `$XDG_CACHE_HOME`.
+    pub fn aur_cache(&self) -> PathBuf {
+        self.cache.join("trellis/aur")
+    }
+
+    /// Returns the user's container storage as rootless podman locates it: the
+    /// `graphroot` of the user's storage.conf, the `rootless_storage_path` of the
+    /// system's, or the XDG data directory.
+    pub fn storage_dir(&self) -> PathBuf {
+        self.storage_dir_with(Path::new(SYSTEM_STORAGE_CONF))
+    }
+
+    fn storage_dir_with(&self, system_conf: &Path) -> PathBuf {
+        let user_conf = self
+            .storage_conf
+            .clone()
+            .unwrap_or_else(|| self.config.join("containers/storage.conf"));
+        storage_setting(&user_conf, "graphroot")
+            .or_else(|| storage_setting(system_conf, "rootless_storage_path"))
+            .map(|path| self.expand(&path))
+            .unwrap_or_else(|| self.data.join("containers/storage"))
+    }
+
+    /// Expands the variables storage.conf allows in rootless paths.
+    fn expand(&self, path: &str) -> PathBuf {
+        // SAFETY: getuid has no preconditions and cannot fail
+        let uid = unsafe { libc::getuid() };
+        let user = std::env::var("USER").unwrap_or_default();
+        let expanded = path
+            .replace("$HOME", &self.home.to_string_lossy())
+            .replace("$UID", &uid.to_string())
+            .replace("$USER", &user);
+        PathBuf::from(expanded)
+    }
+}
+
+/// Reads a setting of the `[storage]` table of a storage.conf, if set.
+fn storage_setting(path: &Path, key: &str) -> Option<String> {
+    let content = fs::read_to_string(path).ok()?;
+    let conf: toml::Table = toml::from_str(&content).ok()?;
+    conf.get("storage")?
+        .get(key)?
+        .as_str()
+        .filter(|value| !value.is_empty())
+        .map(str::to_string)
+}
+
+/// Hands operations that need root off to `sudo` or `run0` in rootless mode.
+pub struct RootHandoff<'a> {
+    config: &'a TrellisConfig,
+    executor: Arc<dyn CommandExecutor>,
+}
+
+impl<'a> TrellisMessaging for RootHandoff<'a> {}
+
+impl<'a> RootHandoff<'a> {
+    pub fn new(config: &'a TrellisConfig, executor: Arc<dyn CommandExecutor>) -> Self {
+        Self { config, executor }
+    }
+
+    /// Returns the command running commands as root: `privilege_command` if set,
+    /// else the first of `sudo` and `run0` that is installed.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if neither is set or installed.
+    pub fn privilege_command(&self) -> Result<String> {
+        if let Some(command) = &self.config.privilege_command {
+            return Ok(command.clone());
+        }
+        PRIVILEGE_COMMANDS
+            .iter()
+            .find(|command| which::which(command).is_ok())
+            .map(|command| command.to_string())
+            .ok_or_else(|| {
+                anyhow!(
+                    "This operation needs root, but neither sudo nor run0 is installed. \
+                     Set privilege_command in [environment] or run it as root"
+                )
+            })
+    }
+
+    /// Copies `image` from the user's storage into root's, where bootc looks for it.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if saving the image or loading it as root fails.
+    pub fn copy_image_to_root(&self, image: &str) -> Result<()> {
+        let archive = Path::new(paths::HANDOFF_DIR)
+            .join(format!("trellis-handoff-{}.tar", std::process::id()));
+        let archive_arg = archive.display().to_string();
+        let _archive = Cleanup::new(|| {
+            let _ = fs::remove_file(&archive);
+        });
+
+        self.msg(&format!("Copying {image} to root's container storage"));
+        let save_args = vec![
+            "save".to_string(),
+            "--output".to_string(),
+            archive_arg.clone(),
+            image.to_string(),
+        ];
+        let output = self
+            .executor
+            .execute("podman", &save_args)
+            .with_context(|| format!("Failed to save {image}"))?;
+        if !output.status.success() {
+            return Err(anyhow!(
+                "Failed to save {image}: {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            ));
+        }
+
+        self.run_as_root(&[
+            "podman".to_string(),
+            "load".to_string(),
+            "--input".to_string(),
+            archive_arg,
+        ])
+        .with_context(|| format!("Failed to load {image} into root's container storage"))
+    }
+
+    /// Runs `command` as root, printing its output unless quiet.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if no privilege command is available or `command` fails.
+    pub fn run_as_root(&self, command: &[String]) -> Result<()> {
+        let privilege_command = self.privilege_command()?;
+        self.msg(&format!(
+            "Running `{}` as root with {privilege_command}",
+            command.join(" ")
+        ));
+
+        let status = if self.config.quiet {
+            let output = self.executor.execute(&privilege_command, command)?;
+            if !output.status.success() {
+                return Err(anyhow!(
+                    "{} failed: {}",
+                    command.join(" "),
+                    String::from_utf8_lossy(&output.stderr).trim()
+                ));
+            }
+            output.status
+        } else {
+            self.executor
+                .execute_streaming(&privilege_command, command)?
+        };
+
+        if !status.success() {
+            return Err(anyhow!(
+                "{} failed with exit code: {:?}",
+                command.join(" "),
+                status.code()
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use tempfile::TempDir;
+
+    fn user_dirs(vars: &[(&str, &Path)]) -> UserDirs {
+        let vars: HashMap<String, OsString> = vars
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.as_os_str().to_owned()))
+            .collect();
+        UserDirs::from_vars(|name| vars.get(name).cloned()).unwrap()
+    }
+
+    #[test]
+    fn test_user_dirs_default_to_home_and_ignore_relative_xdg_dirs() {
+        let dirs = user_dirs(&[
+            ("HOME", Path::new("/home/builder")),
+            ("XDG_STATE_HOME", Path::new("relative/state")),
+            ("XDG_RUNTIME_DIR", Path::new("/run/user/1000")),
+        ]);
+
+        assert_eq!(
+            dirs.state_dir(),
+            Path::new("/home/builder/.local/state/trellis/state")
+        );
+        assert_eq!(
+            dirs.sources_dir(),
+            Path::new("/home/builder/.cache/trellis/sources")
+        );
+        assert_eq!(
+            dirs.pacman_cache(),
+            Path::new("/home/builder/.cache/trellis/pacman")
+        );
+        assert_eq!(
+            dirs.aur_cache(),
+            Path::new("/home/builder/.cache/trellis/aur")
+        );
+        assert_eq!(dirs.lock_dir(), Path::new("/run/user/1000/trellis"));
+        assert_eq!(
+            dirs.storage_dir_with(Path::new("/nonexistent")),
+            Path::new("/home/builder/.local/share/containers/storage")
+        );
+    }
+
+    #[test]
+    fn test_storage_dir_prefers_user_graphroot_over_system_setting() {
+        let temp = TempDir::new().unwrap();
+        let system_conf = temp.path().join("system.conf");
+        fs::write(
+            &system_conf,
+            "[storage]\nrootless_storage_path = \"$HOME/containers\"\n",
+        )
+        .unwrap();
+        let dirs = user_dirs(&[("HOME", temp.path()), ("XDG_CONFIG_HOME", temp.path())]);
+
+        assert_eq!(
+            dirs.storage_dir_with(&system_conf),
+            temp.path().join("containers")
+        );
+
+        fs::create_dir(temp.path().join("containers")).unwrap();
+        fs::write(
+            temp.path().join("containers/storage.conf"),
+            "[storage]\ndriver = \"overlay\"\ngraphroot = \"/srv/podman\"\n",
+        )
+        .unwrap();
+
+        assert_eq!(
+            dirs.storage_dir_with(&system_conf),
+            Path::new("/srv/podman")
+        );
+    }
+}
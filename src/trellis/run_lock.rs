@@ -0,0 +1,201 @@
+//! Advisory lock serializing trls runs.
+//!
+//! Commands that change images, containers or the shared caches take the lock
+//! exclusively, commands that only read them take it shared. The lock is a POSIX
+//! record lock on `<lock_dir>/trls.lock`, so it is released when the holding
+//! process exits, and a blocked run can name the process holding it.
+
+use anyhow::{anyhow, Context, Result};
+use std::{
+    fs::{self, File, OpenOptions},
+    io,
+    os::fd::AsRawFd,
+    path::{Path, PathBuf},
+};
+
+use super::{common::TrellisMessaging, interrupt};
+use crate::config::TrellisConfig;
+
+/// Name of the lock file in the lock directory
+const LOCK_FILE: &str = "trls.lock";
+
+/// How a command takes the run lock.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LockMode {
+    /// Alongside other readers, for commands that only read images
+    Shared,
+    /// Alone, for commands that change images, containers or caches
+    Exclusive,
+}
+
+impl LockMode {
+    fn lock_type(self) -> libc::c_short {
+        match self {
+            LockMode::Shared => libc::F_RDLCK as libc::c_short,
+            LockMode::Exclusive => libc::F_WRLCK as libc::c_short,
+        }
+    }
+}
+
+/// A held run lock, released when dropped.
+#[derive(Debug)]
+pub struct RunLock {
+    _file: File,
+}
+
+/// Process holding a lock that conflicts with a requested one.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LockHolder {
+    pub pid: i32,
+    pub command: Option<String>,
+}
+
+impl std::fmt::Display for LockHolder {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.command {
+            Some(command) => write!(f, "PID {} ({command})", self.pid),
+            None => write!(f, "PID {}", self.pid),
+        }
+    }
+}
+
+/// Takes the run lock for a command.
+pub struct RunLocker<'a> {
+    config: &'a TrellisConfig,
+}
+
+impl<'a> TrellisMessaging for RunLocker<'a> {}
+
+impl<'a> RunLocker<'a> {
+    pub fn new(config: &'a TrellisConfig) -> Self {
+        Self { config }
+    }
+
+    /// Path of the lock file.
+    pub fn path(&self) -> PathBuf {
+        self.config.lock_dir.join(LOCK_FILE)
+    }
+
+    /// Takes the run lock in `mode`, waiting for conflicting holders if `wait` is
+    /// set in the configuration.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error naming the holder if the lock is held in a conflicting mode
+    /// and waiting is disabled, or if the lock file cannot be opened.
+    pub fn acquire(&self, mode: LockMode) -> Result<RunLock> {
+        let path = self.path();
+        let file = open_lock_file(&path)?;
+
+        if try_lock(&file, mode, false)? {
+            return Ok(RunLock { _file: file });
+        }
+
+        let holder = match lock_holder(&file, mode)? {
+            Some(holder) => holder.to_string(),
+            // Released between the attempts
+            None => "another trls run".to_string(),
+        };
+        if !self.config.wait {
+            return Err(anyhow!(
+                "trls is already running: {holder} holds {}. Use --wait to wait for it to finish",
+                path.display()
+            ));
+        }
+
+        self.msg(&format!("Waiting for {holder} to finish..."));
+        // Nothing is held yet, so a signal may end trls right away
+        interrupt::exit_immediately(|| try_lock(&file, mode, true))?;
+        Ok(RunLock { _file: file })
+    }
+}
+
+/// Opens the lock file, creating it and its directory if needed.
+fn open_lock_file(path: &Path) -> Result<File> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)
+            .with_context(|| format!("Failed to create lock directory: {}", dir.display()))?;
+    }
+    OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(path)
+        .with_context(|| format!("Failed to open lock file: {}", path.display()))
+}
+
+/// Builds a record lock description covering the whole file.
+fn whole_file_lock(lock_type: libc::c_short) -> libc::flock {
+    // SAFETY: flock is a plain C struct for which all zeroes is a valid value
+    let mut lock: libc::flock = unsafe { std::mem::zeroed() };
+    lock.l_type = lock_type;
+    lock.l_whence = libc::SEEK_SET as libc::c_short;
+    lock
+}
+
+/// Locks `file` in `mode`. Returns `false` if a conflicting lock is held and
+/// `wait` is not set.
+fn try_lock(file: &File, mode: LockMode, wait: bool) -> Result<bool> {
+    let lock = whole_file_lock(mode.lock_type());
+    let command = if wait { libc::F_SETLKW } else { libc::F_SETLK };
+    // SAFETY: the descriptor is open for the lifetime of `file` and `lock` is valid
+    if unsafe { libc::fcntl(file.as_raw_fd(), command, &lock) } == 0 {
+        return Ok(true);
+    }
+
+    let error = io::Error::last_os_error();
+    match error.raw_os_error() {
+        Some(libc::EAGAIN) | Some(libc::EACCES) if !wait => Ok(false),
+        _ => Err(error).context("Failed to take the run lock"),
+    }
+}
+
+/// Returns the process holding a lock that conflicts with `mode`, if any.
+fn lock_holder(file: &File, mode: LockMode) -> Result<Option<LockHolder>> {
+    let mut lock = whole_file_lock(mode.lock_type());
+    // SAFETY: the descriptor is open for the lifetime of `file` and `lock` is valid
+    if unsafe { libc::fcntl(file.as_raw_fd(), libc::F_GETLK, &mut lock) } != 0 {
+        return Err(io::Error::last_os_error()).context("Failed to query the run lock");
+    }
+    if lock.l_type == libc::F_UNLCK as libc::c_short {
+        return Ok(None);
+    }
+    Ok(Some(LockHolder {
+        pid: lock.l_pid,
+        command: process_command(lock.l_pid),
+    }))
+}
+
+/// Reads the command line of a process, with the program's directory left out.
+fn process_command(pid: i32) -> Option<String> {
+    let cmdline = fs::read(format!("/proc/{pid}/cmdline")).ok()?;
+    let mut args = cmdline
+        .split(|&b| b == 0)
+        .filter(|arg| !arg.is_empty())
+        .map(|arg| String::from_utf8_lossy(arg).into_owned());
+    let program = args.next()?;
+    let program = Path::new(&program)
+        .file_name()
+        .map_or(program.clone(), |name| name.to_string_lossy().into_owned());
+    Some(
+        std::iter::once(program)
+            .chain(args)
+            .collect::<Vec<_>>()
+            .join(" "),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_process_command_of_current_process() {
+        let command = process_command(std::process::id() as i32).unwrap();
+        let program = std::env::current_exe().unwrap();
+        let program = program.file_name().unwrap().to_string_lossy();
+
+        assert!(command.starts_with(program.as_ref()), "{command}");
+    }
+}
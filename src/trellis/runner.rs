@@ -1,12 +1,15 @@
 use anyhow::{anyhow, Context, Result};
 use std::sync::Arc;
 
-use super::{common::TrellisMessaging, constants::containers, executor::CommandExecutor};
-use crate::config::TrellisConfig;
+use super::{
+    common::TrellisMessaging, constants::containers, executor::CommandExecutor, interrupt::Cleanup,
+    rootless::RootHandoff,
+};
+use crate::config::{NetworkMode, SecurityOptions, TrellisConfig};
 
 /// Container capabilities enum for type safety.
 /// These capabilities are granted to containers to enable specific operations.
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum ContainerCapability {
     /// SYS_ADMIN: Enables system administration operations like mount, namespace management
     SysAdmin,
@@ -25,7 +28,18 @@ pub enum ContainerCapability {
 }
 
 impl ContainerCapability {
-    fn as_str(self) -> &'static str {
+    /// Every capability, as granted to run containers by the default security profile.
+    pub const ALL: [ContainerCapability; 7] = [
+        ContainerCapability::SysAdmin,
+        ContainerCapability::DacOverride,
+        ContainerCapability::Chown,
+        ContainerCapability::Fowner,
+        ContainerCapability::Setuid,
+        ContainerCapability::Setgid,
+        ContainerCapability::SysPtrace,
+    ];
+
+    pub fn as_str(self) -> &'static str {
         match self {
             ContainerCapability::SysAdmin => "SYS_ADMIN",
             ContainerCapability::DacOverride => "DAC_OVERRIDE",
@@ -54,14 +68,19 @@ impl PodmanRunCommandBuilder {
         Self { args: Vec::new() }
     }
 
-    pub fn network_host(mut self) -> Self {
-        self.args.extend(["--net".to_string(), "host".to_string()]);
+    pub fn network(mut self, mode: NetworkMode) -> Self {
+        self.args
+            .extend(["--net".to_string(), mode.as_str().to_string()]);
         self
     }
 
-    pub fn add_capability(mut self, cap: ContainerCapability) -> Self {
-        self.args
-            .extend(["--cap-add".to_string(), cap.as_str().to_string()]);
+    /// Adds the network mode, capabilities, seccomp profile, SELinux label options
+    /// and privileged mode of a security profile.
+    pub fn security(mut self, security: &SecurityOptions) -> Self {
+        if let Some(network) = security.network {
+            self = self.network(network);
+        }
+        self.args.extend(security.podman_args());
         self
     }
 
@@ -113,26 +132,16 @@ impl<'a> ContainerRunner<'a> {
     pub fn run_container(&self, container_tag: &str, args: &[String]) -> Result<()> {
         self.validate_container_exists(container_tag)?;
 
+        self.msg(&format!(
+            "Security profile '{}' for runs: {}",
+            self.config.security.name,
+            self.config.security.run.describe()
+        ));
+
+        // The default profile shares the host network and grants the capabilities
+        // needed for package management and interactive use
         let run_args = PodmanRunCommandBuilder::new()
-            // Use host network namespace (--net host) to enable network access
-            // This allows the container to resolve DNS, access package mirrors, and perform
-            // any network operations (e.g., git clones, package downloads) required by
-            // the commands executed inside the container.
-            .network_host()
-            // Minimal capabilities required for interactive container use:
-            // - SYS_ADMIN: needed for system-level operations and namespace management
-            // - DAC_OVERRIDE: allows bypassing file permission checks
-            // - CHOWN: required when changing file ownership
-            // - FOWNER: allows changing file permissions without being the owner
-            // - SETUID/SETGID: needed for user switching and privilege-related operations
-            // - SYS_PTRACE: allows tracing and debugging operations
-            .add_capability(ContainerCapability::SysAdmin)
-            .add_capability(ContainerCapability::DacOverride)
-            .add_capability(ContainerCapability::Chown)
-            .add_capability(ContainerCapability::Fowner)
-            .add_capability(ContainerCapability::Setuid)
-            .add_capability(ContainerCapability::Setgid)
-            .add_capability(ContainerCapability::SysPtrace)
+            .security(&self.config.security.run)
             .remove_on_exit()
             .interactive()
             .image(&format!("{}{container_tag}", containers::LOCALHOST_PREFIX))
@@ -179,12 +188,28 @@ impl<'a> ContainerRunner<'a> {
     }
 
     /// Runs bootc upgrade with proper error handling.
+    ///
+    /// In rootless mode the rootfs image is first copied into root's container
+    /// storage, and bootc runs through `sudo` or `run0`.
     pub fn run_bootc_upgrade(&self) -> Result<()> {
         self.msg("Running bootc upgrade...");
 
         // Check if bootc is available
         self.validate_bootc_available()?;
 
+        if self.config.rootless {
+            let handoff = RootHandoff::new(self.config, Arc::clone(&self.executor));
+            handoff.copy_image_to_root(&format!(
+                "{}{}:{}",
+                containers::LOCALHOST_PREFIX,
+                self.config.rootfs_tag,
+                containers::LATEST_TAG
+            ))?;
+            handoff.run_as_root(&["bootc".to_string(), "upgrade".to_string()])?;
+            self.msg("Update completed successfully");
+            return Ok(());
+        }
+
         let args = vec!["upgrade".to_string()];
         let success = if self.config.quiet {
             // Use regular execution to capture output when quiet
@@ -225,13 +250,12 @@ impl<'a> ContainerRunner<'a> {
     /// Validates that the specified container image exists.
     fn validate_container_exists(&self, container_tag: &str) -> Result<()> {
         let full_tag = format!("{}{container_tag}", containers::LOCALHOST_PREFIX);
-        let args = vec!["image".to_string(), "exists".to_string(), full_tag.clone()];
-        let output = self
+        let exists = self
             .executor
-            .execute("podman", &args)
+            .image_exists(&full_tag)
             .context("Failed to check if image exists")?;
 
-        if !output.status.success() {
+        if !exists {
             return Err(anyhow!(
                 "Container image not found: {full_tag}. Run 'trls build' first."
             ));
@@ -273,16 +297,22 @@ impl<'a> ContainerRunner<'a> {
         let container_name = format!("trellis-quick-update-{}", timestamp);
 
         // Step 1: Run topgrade in a new container
-        self.run_topgrade_in_container(rootfs_tag, &container_name)?;
+        let run = self.run_topgrade_in_container(rootfs_tag, &container_name);
 
-        // Step 2: Attempt to commit the updated container, but ensure cleanup ALWAYS runs
-        let commit_result = self.commit_container_updates(&container_name, rootfs_tag);
+        // Once podman has created the temporary container, it is removed on every
+        // exit, including failures and interruption
+        let container = self.container_exists(&container_name).then(|| {
+            Cleanup::new(|| {
+                let _ = self.cleanup_temporary_container(&container_name);
+            })
+        });
+        run?;
 
-        // Step 3: ALWAYS clean up the temporary container, regardless of commit result
-        let _cleanup_result = self.cleanup_temporary_container(&container_name);
+        // Step 2: Commit the updated container
+        self.commit_container_updates(&container_name, rootfs_tag)?;
 
-        // Step 4: Return the commit result (must happen after cleanup)
-        commit_result?;
+        // Step 3: Clean up the temporary container
+        drop(container);
 
         self.msg("Quick update completed successfully");
 
@@ -312,30 +342,16 @@ impl<'a> ContainerRunner<'a> {
     fn run_topgrade_in_container(&self, rootfs_tag: &str, container_name: &str) -> Result<()> {
         self.msg("Running topgrade to update packages...");
 
+        self.msg(&format!(
+            "Security profile '{}' for runs: {}",
+            self.config.security.name,
+            self.config.security.run.describe()
+        ));
+
+        // topgrade downloads updates from package mirrors, so the run profile
+        // must allow network access
         let run_args = PodmanRunCommandBuilder::new()
-            // Use host network namespace (--net host) to enable full network access
-            // This is critical for topgrade functionality:
-            // - Topgrade downloads package updates from package mirrors
-            // - Container must resolve DNS to reach mirror servers
-            // - Network access allows fetching updates for multiple package managers
-            //   (pacman, yay, cargo, pip, etc.)
-            // - Without host network, the container would be isolated and unable to
-            //   access internet resources needed for package updates
-            .network_host()
-            // Minimal capabilities required for package management via topgrade:
-            // - SYS_ADMIN: needed for system-level operations and namespace management
-            // - DAC_OVERRIDE: allows bypassing file permission checks during package installation
-            // - CHOWN: required when packages change file ownership (common in post-install scripts)
-            // - FOWNER: allows changing file permissions without being the owner
-            // - SETUID/SETGID: package scripts may use su/sudo or need to set UIDs
-            // - SYS_PTRACE: some package managers trace processes during installation
-            .add_capability(ContainerCapability::SysAdmin)
-            .add_capability(ContainerCapability::DacOverride)
-            .add_capability(ContainerCapability::Chown)
-            .add_capability(ContainerCapability::Fowner)
-            .add_capability(ContainerCapability::Setuid)
-            .add_capability(ContainerCapability::Setgid)
-            .add_capability(ContainerCapability::SysPtrace)
+            .security(&self.config.security.run)
             .name(container_name)
             .image(&format!("{}{}", containers::LOCALHOST_PREFIX, rootfs_tag))
             .args(&["topgrade".to_string(), "-y".to_string()])
@@ -394,14 +410,31 @@ impl<'a> ContainerRunner<'a> {
         Ok(())
     }
 
+    /// Checks whether a container exists, assuming it does if podman cannot tell.
+    fn container_exists(&self, container_name: &str) -> bool {
+        let args = [
+            "container".to_string(),
+            "exists".to_string(),
+            container_name.to_string(),
+        ];
+        self.executor
+            .podman_command(&args)
+            .map_or(true, |output| output.status.success())
+    }
+
     /// Removes the temporary container used for the update process.
     fn cleanup_temporary_container(&self, container_name: &str) -> Result<()> {
         self.msg("Cleaning up temporary container...");
 
-        let args = vec!["rm".to_string(), container_name.to_string()];
+        // An interrupted topgrade may still be running
+        let args = vec![
+            "rm".to_string(),
+            "--force".to_string(),
+            container_name.to_string(),
+        ];
         let output = self
             .executor
-            .execute("podman", &args)
+            .podman_command(&args)
             .context("Failed to remove temporary container")?;
 
         if !output.status.success() {
@@ -0,0 +1,234 @@
+//! Checkouts of `[[sources]]` git repositories.
+//!
+//! Each source is cloned into `<sources_dir>/<name>` and checked out at its ref as
+//! a detached HEAD. Builds only fetch sources that are missing or whose ref is not
+//! known yet, so branch refs stay at the commit fetched last until
+//! `trls sources update` refreshes them.
+
+use anyhow::{anyhow, Context, Result};
+use std::{path::Path, sync::Arc};
+
+use super::{common::TrellisMessaging, executor::CommandExecutor, metadata};
+use crate::config::{StageSource, TrellisConfig};
+
+/// Fetches and checks out stage sources.
+pub struct SourceManager<'a> {
+    config: &'a TrellisConfig,
+    executor: Arc<dyn CommandExecutor>,
+    progress_on_stderr: bool,
+}
+
+impl<'a> TrellisMessaging for SourceManager<'a> {}
+
+impl<'a> SourceManager<'a> {
+    pub fn new(config: &'a TrellisConfig, executor: Arc<dyn CommandExecutor>) -> Self {
+        Self {
+            config,
+            executor,
+            progress_on_stderr: false,
+        }
+    }
+
+    /// Prints progress messages on stderr instead of stdout.
+    pub fn with_progress_on_stderr(mut self, enabled: bool) -> Self {
+        self.progress_on_stderr = enabled;
+        self
+    }
+
+    fn progress(&self, message: &str) {
+        if self.progress_on_stderr {
+            self.msg_stderr(message);
+        } else {
+            self.msg(message);
+        }
+    }
+
+    /// Makes sure every source is checked out at its ref, fetching only sources
+    /// that are missing or whose ref is unknown to the checkout.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a repository cannot be fetched or its ref does not exist.
+    pub fn ensure(&self) -> Result<()> {
+        for source in &self.config.sources {
+            let checkout = source.checkout_dir(&self.config.sources_dir);
+            if !checkout.join(".git").exists() {
+                self.clone_source(source, &checkout)?;
+            } else if self.resolve_ref(source, &checkout)?.is_none() {
+                self.fetch(source, &checkout)?;
+            }
+            self.check_out(source, &checkout)?;
+        }
+        Ok(())
+    }
+
+    /// Fetches sources and checks out their refs again, moving branch refs to the
+    /// latest commit. Updates only the source called `name` if given.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if no source is called `name`, or a repository cannot be
+    /// fetched or its ref does not exist.
+    pub fn update(&self, name: Option<&str>) -> Result<()> {
+        if let Some(name) = name {
+            if !self.config.sources.iter().any(|source| source.name == name) {
+                return Err(anyhow!("Source '{name}' is not configured"));
+            }
+        }
+        if self.config.sources.is_empty() {
+            self.progress("No sources configured");
+            return Ok(());
+        }
+
+        for source in &self.config.sources {
+            if name.is_some_and(|name| name != source.name) {
+                continue;
+            }
+            let checkout = source.checkout_dir(&self.config.sources_dir);
+            if checkout.join(".git").exists() {
+                self.git(
+                    &checkout,
+                    &["remote", "set-url", "origin", &source.git],
+                    "Failed to set source URL",
+                )?;
+                self.fetch(source, &checkout)?;
+            } else {
+                self.clone_source(source, &checkout)?;
+            }
+            if let Some(commit) = self.check_out(source, &checkout)? {
+                self.progress(&format!(
+                    "Source '{}' is at {commit} ({})",
+                    source.name, source.reference
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    fn clone_source(&self, source: &StageSource, checkout: &Path) -> Result<()> {
+        self.progress(&format!(
+            "Fetching source '{}' from {}",
+            source.name, source.git
+        ));
+        if !self.config.dry_run {
+            std::fs::create_dir_all(&self.config.sources_dir).with_context(|| {
+                format!(
+                    "Failed to create sources directory: {}",
+                    self.config.sources_dir.display()
+                )
+            })?;
+        }
+
+        let checkout = checkout.display().to_string();
+        self.run_git(
+            &["clone", "--quiet", &source.git, &checkout],
+            &format!("Failed to clone source '{}'", source.name),
+        )
+    }
+
+    fn fetch(&self, source: &StageSource, checkout: &Path) -> Result<()> {
+        self.progress(&format!(
+            "Updating source '{}' from {}",
+            source.name, source.git
+        ));
+        self.git(
+            checkout,
+            &[
+                "fetch",
+                "--quiet",
+                "--force",
+                "--tags",
+                "origin",
+                "+refs/heads/*:refs/remotes/origin/*",
+            ],
+            &format!("Failed to fetch source '{}'", source.name),
+        )
+    }
+
+    /// Checks out the commit the source's ref resolves to, unless it is checked out
+    /// already. Returns the commit, or `None` in dry runs where the ref is unknown.
+    fn check_out(&self, source: &StageSource, checkout: &Path) -> Result<Option<String>> {
+        let Some(commit) = self.resolve_ref(source, checkout)? else {
+            if self.config.dry_run {
+                return Ok(None);
+            }
+            return Err(anyhow!(
+                "Ref '{}' not found in source '{}' ({})",
+                source.reference,
+                source.name,
+                source.git
+            ));
+        };
+
+        if metadata::git_commit(checkout).as_deref() != Some(commit.as_str()) {
+            self.progress(&format!(
+                "Checking out source '{}' at {} ({})",
+                source.name,
+                &commit[..commit.len().min(12)],
+                source.reference
+            ));
+            self.git(
+                checkout,
+                &["checkout", "--quiet", "--force", "--detach", &commit],
+                &format!("Failed to check out source '{}'", source.name),
+            )?;
+        }
+        Ok(Some(commit))
+    }
+
+    /// Resolves the source's ref to a commit: a remote branch, then a tag or commit.
+    fn resolve_ref(&self, source: &StageSource, checkout: &Path) -> Result<Option<String>> {
+        for candidate in [
+            format!("refs/remotes/origin/{}", source.reference),
+            source.reference.clone(),
+        ] {
+            let output = self
+                .executor
+                .execute(
+                    "git",
+                    &Self::args(
+                        checkout,
+                        &[
+                            "rev-parse",
+                            "--verify",
+                            "--quiet",
+                            &format!("{candidate}^{{commit}}"),
+                        ],
+                    ),
+                )
+                .context("Failed to run git")?;
+            let commit = String::from_utf8_lossy(&output.stdout).trim().to_string();
+            if output.status.success() && !commit.is_empty() {
+                return Ok(Some(commit));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Runs a git command in a checkout.
+    fn git(&self, checkout: &Path, args: &[&str], error: &str) -> Result<()> {
+        self.run_git(&Self::args(checkout, args), error)
+    }
+
+    fn run_git<S: AsRef<str>>(&self, args: &[S], error: &str) -> Result<()> {
+        let args: Vec<String> = args.iter().map(|arg| arg.as_ref().to_string()).collect();
+        let output = self
+            .executor
+            .execute("git", &args)
+            .with_context(|| error.to_string())?;
+        if !output.status.success() {
+            return Err(anyhow!(
+                "{error}: {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            ));
+        }
+        Ok(())
+    }
+
+    fn args(checkout: &Path, args: &[&str]) -> Vec<String> {
+        ["-C".to_string(), checkout.display().to_string()]
+            .into_iter()
+            .chain(args.iter().map(|arg| arg.to_string()))
+            .collect()
+    }
+}
@@ -0,0 +1,227 @@
+//! Content keys for incremental stage builds.
+//!
+//! Every stage image can be tagged with a key derived from everything that
+//! influences its contents. When a later build computes the same key, the
+//! previously built image is reused instead of running `podman build` again.
+
+use anyhow::{Context, Result};
+use sha2::{Digest, Sha256};
+use std::{collections::BTreeMap, fs, path::Path, time::UNIX_EPOCH};
+use walkdir::WalkDir;
+
+use super::constants::patterns;
+
+/// Number of hex characters of the key used in image tags.
+pub const STAGE_KEY_TAG_LENGTH: usize = 16;
+
+/// Inputs that determine the contents of a single stage image.
+#[derive(Debug, Clone)]
+pub struct StageKeyInputs<'a> {
+    /// Image ID (or reference, when unresolvable) of the parent image
+    pub parent_digest: &'a str,
+    /// SHA-256 of the Containerfile used to build the stage
+    pub containerfile_sha256: &'a str,
+    /// Build target within the Containerfile
+    pub target: &'a str,
+    /// Build context directory the stage can `COPY` from
+    pub context_dir: &'a Path,
+    /// Build arguments, excluding `BASE_IMAGE` which is covered by the parent digest
+    pub build_args: &'a BTreeMap<String, String>,
+    /// Extra build contexts in `name=path` form
+    pub contexts: &'a [String],
+    /// Host paths mounted into the build, such as hooks_dir and extra_mounts
+    pub mounts: &'a [String],
+    /// Layering mode, which changes the layers of the image but not its files
+    pub layering: &'a str,
+}
+
+/// Computes the hex encoded content key for a stage.
+pub fn compute_stage_key(inputs: &StageKeyInputs) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(b"parent\0");
+    hasher.update(inputs.parent_digest.as_bytes());
+    hasher.update(b"\0containerfile\0");
+    hasher.update(inputs.containerfile_sha256.as_bytes());
+    hasher.update(b"\0target\0");
+    hasher.update(inputs.target.as_bytes());
+    hasher.update(b"\0layering\0");
+    hasher.update(inputs.layering.as_bytes());
+    let context_dir = inputs.context_dir.display().to_string();
+    hasher.update(b"\0context_dir\0");
+    hasher.update(path_fingerprint(&context_dir, inputs.context_dir).as_bytes());
+
+    for (key, value) in inputs.build_args {
+        hasher.update(b"\0arg\0");
+        hasher.update(key.as_bytes());
+        hasher.update(b"=");
+        hasher.update(value.as_bytes());
+    }
+
+    for context in inputs.contexts {
+        hasher.update(b"\0context\0");
+        hasher.update(context_fingerprint(context).as_bytes());
+    }
+
+    for mount in inputs.mounts {
+        hasher.update(b"\0mount\0");
+        hasher.update(path_fingerprint(mount, Path::new(mount)).as_bytes());
+    }
+
+    format!("{:x}", hasher.finalize())
+}
+
+/// Returns the shortened form of a stage key used as an image tag.
+pub fn short_key(key: &str) -> &str {
+    &key[..key.len().min(STAGE_KEY_TAG_LENGTH)]
+}
+
+/// Computes the hex encoded SHA-256 of a file's contents.
+///
+/// # Errors
+///
+/// Returns an error if the file cannot be read.
+pub fn sha256_file(path: &Path) -> Result<String> {
+    let content =
+        fs::read(path).with_context(|| format!("Failed to read {} for hashing", path.display()))?;
+    Ok(format!("{:x}", Sha256::digest(&content)))
+}
+
+/// Computes the hex encoded SHA-256 of a string.
+pub fn sha256_str(content: &str) -> String {
+    format!("{:x}", Sha256::digest(content.as_bytes()))
+}
+
+/// Fingerprints a `name=path` build context.
+///
+/// Local directory contexts are fingerprinted by their contents, see
+/// `path_fingerprint`. Other context kinds (URLs, images) are fingerprinted by
+/// their specification only.
+fn context_fingerprint(context: &str) -> String {
+    match context.split_once('=') {
+        Some((_, path)) if Path::new(path).is_dir() => path_fingerprint(context, Path::new(path)),
+        _ => context.to_string(),
+    }
+}
+
+/// Fingerprints `spec` together with the files at `root`.
+///
+/// Files are fingerprinted by their relative path, size and modification time,
+/// so edits to a context or mounted directory invalidate the stages that use it.
+/// A missing path leaves only the specification.
+fn path_fingerprint(spec: &str, root: &Path) -> String {
+    let mut entries: Vec<String> = WalkDir::new(root)
+        .max_depth(patterns::MAX_SEARCH_DEPTH)
+        .follow_links(false)
+        .into_iter()
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_type().is_file())
+        .filter_map(|entry| {
+            let metadata = entry.metadata().ok()?;
+            let mtime = metadata
+                .modified()
+                .ok()
+                .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+                .map(|duration| duration.as_nanos())
+                .unwrap_or_default();
+            let relative = entry.path().strip_prefix(root).ok()?;
+            Some(format!("{}:{}:{mtime}", relative.display(), metadata.len()))
+        })
+        .collect();
+    entries.sort();
+
+    let mut hasher = Sha256::new();
+    hasher.update(spec.as_bytes());
+    for entry in entries {
+        hasher.update(b"\0");
+        hasher.update(entry.as_bytes());
+    }
+    format!("{:x}", hasher.finalize())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn key_with(parent: &str, sha: &str, args: &BTreeMap<String, String>) -> String {
+        compute_stage_key(&StageKeyInputs {
+            parent_digest: parent,
+            containerfile_sha256: sha,
+            target: "base",
+            context_dir: Path::new("/nonexistent"),
+            build_args: args,
+            contexts: &[],
+            mounts: &[],
+            layering: "squash",
+        })
+    }
+
+    #[test]
+    fn test_stage_key_is_deterministic() {
+        let args = BTreeMap::from([("HOOKS_DIR".to_string(), "/etc/hooks".to_string())]);
+        assert_eq!(key_with("abc", "def", &args), key_with("abc", "def", &args));
+    }
+
+    #[test]
+    fn test_stage_key_changes_with_inputs() {
+        let args = BTreeMap::new();
+        let changed_args = BTreeMap::from([("FOO".to_string(), "bar".to_string())]);
+        let key = key_with("abc", "def", &args);
+
+        assert_ne!(key, key_with("abd", "def", &args));
+        assert_ne!(key, key_with("abc", "deg", &args));
+        assert_ne!(key, key_with("abc", "def", &changed_args));
+    }
+
+    #[test]
+    fn test_context_fingerprint_tracks_directory_contents() {
+        let temp_dir = TempDir::new().unwrap();
+        let context = format!("ctx={}", temp_dir.path().display());
+        fs::write(temp_dir.path().join("file"), "one").unwrap();
+        let before = context_fingerprint(&context);
+
+        fs::write(temp_dir.path().join("other"), "two").unwrap();
+        assert_ne!(before, context_fingerprint(&context));
+    }
+
+    #[test]
+    fn test_stage_key_tracks_mounted_files() {
+        let temp_dir = TempDir::new().unwrap();
+        let hook = temp_dir.path().join("hook.sh");
+        fs::write(&hook, "echo one").unwrap();
+        let mounts = [temp_dir.path().display().to_string()];
+        let args = BTreeMap::new();
+        let key = |mounts: &[String]| {
+            compute_stage_key(&StageKeyInputs {
+                parent_digest: "abc",
+                containerfile_sha256: "def",
+                target: "base",
+                context_dir: Path::new("/nonexistent"),
+                build_args: &args,
+                contexts: &[],
+                mounts,
+                layering: "squash",
+            })
+        };
+        let before = key(&mounts);
+        assert_ne!(before, key(&[]));
+
+        fs::write(&hook, "echo two, longer").unwrap();
+        assert_ne!(before, key(&mounts));
+    }
+
+    #[test]
+    fn test_context_fingerprint_non_directory_uses_spec() {
+        assert_eq!(
+            context_fingerprint("img=docker-image://alpine"),
+            "img=docker-image://alpine"
+        );
+    }
+
+    #[test]
+    fn test_short_key() {
+        let key = "0123456789abcdef0123456789abcdef";
+        assert_eq!(short_key(key), "0123456789abcdef");
+        assert_eq!(short_key("abc"), "abc");
+    }
+}
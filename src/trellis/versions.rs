@@ -0,0 +1,167 @@
+//! Versioned tags of the rootfs image.
+//!
+//! Besides `latest`, every rootfs build is tagged with its build ID, e.g.
+//! `localhost/trellis-rootfs:20240101-120000-0123abcd`. Build IDs sort
+//! chronologically, so the versions can be listed, pruned to the configured number
+//! and used to point `latest` back at an earlier build.
+
+use anyhow::{anyhow, Context, Result};
+use std::sync::Arc;
+
+use super::{
+    common::TrellisMessaging,
+    constants::containers::{LATEST_TAG, LOCALHOST_PREFIX},
+    executor::CommandExecutor,
+};
+use crate::config::TrellisConfig;
+
+/// A versioned tag of the rootfs image.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RootfsVersion {
+    /// Tag of the version, the ID of the build that produced it
+    pub version: String,
+    pub image_id: String,
+    /// Creation time in seconds since the Unix epoch
+    pub created: i64,
+    /// Whether `latest` points at this version
+    pub latest: bool,
+}
+
+/// Tags, lists and restores versions of the rootfs image.
+pub struct RootfsVersions<'a> {
+    config: &'a TrellisConfig,
+    executor: Arc<dyn CommandExecutor>,
+}
+
+impl<'a> TrellisMessaging for RootfsVersions<'a> {}
+
+impl<'a> RootfsVersions<'a> {
+    pub fn new(config: &'a TrellisConfig, executor: Arc<dyn CommandExecutor>) -> Self {
+        Self { config, executor }
+    }
+
+    /// Returns the repository of the rootfs image, e.g. `localhost/trellis-rootfs`.
+    pub fn repository(&self) -> String {
+        format!("{LOCALHOST_PREFIX}{}", self.config.rootfs_tag)
+    }
+
+    /// Returns the reference of a version of the rootfs image.
+    pub fn reference(&self, version: &str) -> String {
+        format!("{}:{version}", self.repository())
+    }
+
+    /// Checks whether a tag has the form of a build ID, `YYYYMMDD-HHMMSS-xxxxxxxx`.
+    pub fn is_version(tag: &str) -> bool {
+        let parts: Vec<&str> = tag.split('-').collect();
+        matches!(parts.as_slice(), [date, time, suffix]
+            if date.len() == 8
+                && time.len() == 6
+                && suffix.len() == 8
+                && date.chars().chain(time.chars()).all(|c| c.is_ascii_digit())
+                && suffix.chars().all(|c| c.is_ascii_hexdigit()))
+    }
+
+    /// Tags the current rootfs image as `version`, unless versioned tags are disabled.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `podman tag` fails.
+    pub fn tag(&self, version: &str) -> Result<()> {
+        if self.config.keep_versions == 0 {
+            return Ok(());
+        }
+
+        let reference = self.reference(version);
+        self.podman_tag(&self.reference(LATEST_TAG), &reference)?;
+        self.msg(&format!("Tagged rootfs image as {reference}"));
+        Ok(())
+    }
+
+    /// Lists the versions of the rootfs image, newest first.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the images cannot be listed.
+    pub fn list(&self) -> Result<Vec<RootfsVersion>> {
+        let images = self.executor.list_images(Some(&self.repository()))?;
+
+        let prefix = format!("{}:", self.repository());
+        let mut latest_id = None;
+        let mut versions = Vec::new();
+        for image in &images {
+            for tag in image.references().filter_map(|r| r.strip_prefix(&prefix)) {
+                if tag == LATEST_TAG {
+                    latest_id = Some(image.id.clone());
+                } else if Self::is_version(tag) {
+                    versions.push(RootfsVersion {
+                        version: tag.to_string(),
+                        image_id: image.id.clone(),
+                        created: image.created,
+                        latest: false,
+                    });
+                }
+            }
+        }
+
+        for version in &mut versions {
+            version.latest = latest_id.as_deref() == Some(version.image_id.as_str());
+        }
+        versions.sort_by(|a, b| b.version.cmp(&a.version));
+        Ok(versions)
+    }
+
+    /// Points `latest` at an earlier version of the rootfs image.
+    ///
+    /// `version` may be given as a unique prefix of a version tag.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if no version or more than one version matches, or tagging fails.
+    pub fn retag(&self, version: &str) -> Result<RootfsVersion> {
+        let versions = self.list()?;
+        let matches: Vec<&RootfsVersion> = versions
+            .iter()
+            .filter(|candidate| candidate.version.starts_with(version))
+            .collect();
+        let target = match matches.as_slice() {
+            [target] => (*target).clone(),
+            [] => {
+                return Err(anyhow!(
+                    "No version '{version}' of {}. See `trls images`",
+                    self.repository()
+                ))
+            }
+            _ => {
+                return Err(anyhow!(
+                    "Version '{version}' is ambiguous: {}",
+                    matches
+                        .iter()
+                        .map(|candidate| candidate.version.as_str())
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                ))
+            }
+        };
+
+        self.podman_tag(
+            &self.reference(&target.version),
+            &self.reference(LATEST_TAG),
+        )?;
+        Ok(target)
+    }
+
+    fn podman_tag(&self, source: &str, target: &str) -> Result<()> {
+        let args = vec!["tag".to_string(), source.to_string(), target.to_string()];
+        let output = self
+            .executor
+            .podman_command(&args)
+            .with_context(|| format!("Failed to tag {source} as {target}"))?;
+        if !output.status.success() {
+            return Err(anyhow!(
+                "Failed to tag {source} as {target}: {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            ));
+        }
+        Ok(())
+    }
+}
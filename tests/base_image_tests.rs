@@ -0,0 +1,109 @@
+//! Tests for BaseImageChecker functionality.
+//!
+//! Tests cover update detection of the pinned rootfs base image.
+
+mod common;
+
+use common::mocks::*;
+use std::sync::Arc;
+use tempfile::TempDir;
+use trellis::{
+    config::TrellisConfig,
+    trellis::base_image::{BaseImageChecker, BaseStatus},
+};
+
+fn create_base_config(temp_dir: &TempDir, rootfs_base: &str) -> TrellisConfig {
+    TrellisConfig {
+        rootfs_base: rootfs_base.to_string(),
+        ..common::test_config(temp_dir)
+    }
+}
+
+fn digest(byte: &str) -> String {
+    format!("sha256:{}", byte.repeat(32))
+}
+
+/// Accepts the pull and reports `digest` for the floating reference.
+fn create_registry_mock(digest: String) -> MockCommandExecutor {
+    let mut mock_executor = MockCommandExecutor::new();
+    mock_executor
+        .expect_execute()
+        .times(1)
+        .withf(|command, args| {
+            command == "podman" && args == ["pull", "--quiet", "quay.io/archlinux/archlinux:latest"]
+        })
+        .returning(|_, _| Ok(create_success_output("")));
+    mock_executor
+        .expect_podman_inspect()
+        .times(1)
+        .withf(|args: &[String]| args == ["--type", "image", "quay.io/archlinux/archlinux:latest"])
+        .returning(move |_| {
+            Ok(create_success_output(&format!(
+                r#"[{{"Id": "0123abcd", "Digest": "{digest}"}}]"#
+            )))
+        });
+    mock_executor
+}
+
+#[test]
+fn test_check_reports_outdated_pin() {
+    let temp_dir = TempDir::new().unwrap();
+    let pinned = format!("quay.io/archlinux/archlinux:latest@{}", digest("0a"));
+    let config = create_base_config(&temp_dir, &pinned);
+
+    let checker = BaseImageChecker::new(&config, Arc::new(create_registry_mock(digest("0b"))));
+
+    assert_eq!(
+        checker.check().unwrap(),
+        BaseStatus::Outdated {
+            pinned: digest("0a"),
+            latest: digest("0b"),
+        }
+    );
+    assert_eq!(
+        checker.pinned_reference(&digest("0b")),
+        format!("quay.io/archlinux/archlinux:latest@{}", digest("0b"))
+    );
+}
+
+#[test]
+fn test_check_reports_up_to_date_pin() {
+    let temp_dir = TempDir::new().unwrap();
+    let pinned = format!("quay.io/archlinux/archlinux:latest@{}", digest("0a"));
+    let config = create_base_config(&temp_dir, &pinned);
+
+    let checker = BaseImageChecker::new(&config, Arc::new(create_registry_mock(digest("0a"))));
+
+    assert_eq!(
+        checker.check().unwrap(),
+        BaseStatus::UpToDate {
+            digest: digest("0a")
+        }
+    );
+}
+
+#[test]
+fn test_check_reports_unpinned_base() {
+    let temp_dir = TempDir::new().unwrap();
+    let config = create_base_config(&temp_dir, "quay.io/archlinux/archlinux:latest");
+
+    let checker = BaseImageChecker::new(&config, Arc::new(create_registry_mock(digest("0c"))));
+
+    assert_eq!(
+        checker.check().unwrap(),
+        BaseStatus::Unpinned {
+            latest: digest("0c")
+        }
+    );
+}
+
+#[test]
+fn test_check_rejects_scratch_base() {
+    let temp_dir = TempDir::new().unwrap();
+    let config = create_base_config(&temp_dir, "scratch");
+
+    let checker = BaseImageChecker::new(&config, Arc::new(MockCommandExecutor::new()));
+
+    let error = checker.check().unwrap_err().to_string();
+    assert!(error.contains("not a registry image"), "{error}");
+}
@@ -16,7 +16,7 @@ fn test_real_command_executor_creation() {
 
 #[test]
 fn test_real_command_executor_default() {
-    let _executor = RealCommandExecutor;
+    let _executor = RealCommandExecutor::default();
     // Test passes if no panic occurs during creation
 }
 
@@ -198,15 +198,9 @@ fn test_mock_scenarios_build_failures() {
 fn test_mock_scenarios_no_images() {
     let mock = MockScenarios::no_images();
     let executor: &dyn CommandExecutor = &mock;
-    let result = executor.podman_images(&[]);
+    let result = executor.list_images(None);
     assert!(result.is_ok());
-
-    let output = result.unwrap();
-    assert!(output.status.success());
-    let stdout = String::from_utf8(output.stdout).unwrap();
-    // Should only contain header
-    assert!(stdout.starts_with("REPOSITORY"));
-    assert_eq!(stdout.lines().count(), 1);
+    assert!(result.unwrap().is_empty());
 }
 
 #[test]
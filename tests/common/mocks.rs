@@ -107,7 +107,7 @@ impl MockCommandExecutorBuilder {
 
     /// Configure image listing responses.
     pub fn with_images(mut self, images: Vec<MockImageInfo>) -> Self {
-        let images_output = format_images_output(&images);
+        let images_output = format_images_json(&images);
         self.mock
             .expect_podman_images()
             .returning(move |_| Ok(create_success_output(&images_output)));
@@ -225,16 +225,20 @@ pub fn create_failure_status() -> ExitStatus {
     }
 }
 
-/// Format mock images for podman images output.
-fn format_images_output(images: &[MockImageInfo]) -> String {
-    let mut output = String::from("REPOSITORY\tTAG\tIMAGE ID\tCREATED\tSIZE\n");
-    for image in images {
-        output.push_str(&format!(
-            "{}\t{}\t{}\t{}\t{}\n",
-            image.repository, image.tag, image.id, image.created, image.size
-        ));
-    }
-    output
+/// Formats mock images as `podman images --format json` lists them.
+#[allow(dead_code)]
+pub fn format_images_json(images: &[MockImageInfo]) -> String {
+    let images: Vec<serde_json::Value> = images
+        .iter()
+        .map(|image| {
+            serde_json::json!({
+                "Id": image.id,
+                "Names": [format!("{}:{}", image.repository, image.tag)],
+                "Created": 1_704_067_200,
+            })
+        })
+        .collect();
+    serde_json::Value::from(images).to_string()
 }
 
 /// Test environment setup utilities.
@@ -317,10 +321,15 @@ impl MockScenarios {
                 if args.iter().any(|arg| arg.contains("--filter")) && 
                    args.iter().any(|arg| arg.contains("reference=localhost/test-builder")) {
                     // Return the expected format for builder container check
-                    Ok(create_success_output("localhost/test-builder:latest\n"))
+                    Ok(create_success_output(&format_images_json(&[
+                        MockImageInfo::new("abc123", "localhost/test-builder", "latest"),
+                    ])))
                 } else {
-                    // Return tabular format for general image listing
-                    Ok(create_success_output("REPOSITORY\tTAG\tIMAGE ID\tCREATED\tSIZE\nlocalhost/test-builder\tlatest\tabc123\t2024-01-01T00:00:00Z\t100MB\nlocalhost/test-rootfs\tlatest\tdef456\t2024-01-01T00:00:00Z\t100MB\n"))
+                    // Return the final images for general image listing
+                    Ok(create_success_output(&format_images_json(&[
+                        MockImageInfo::new("abc123", "localhost/test-builder", "latest"),
+                        MockImageInfo::new("def456", "localhost/test-rootfs", "latest"),
+                    ])))
                 }
             });
 
@@ -328,7 +337,7 @@ impl MockScenarios {
         // Return JSON array with image info
         mock.expect_podman_inspect()
             .times(..)
-            .returning(|_| Ok(create_success_output(r#"[{"Size": 1073741824}]"#)));
+            .returning(|_| Ok(create_success_output(r#"[{"Id": "abc123", "Size": 1073741824}]"#)));
 
         // Accept any podman rmi command (multiple times)
         mock.expect_podman_rmi()
@@ -412,18 +421,18 @@ impl MockScenarios {
                     .any(|arg| arg.contains("reference=localhost/test-builder"))
             {
                 // Return the expected format for builder container check
-                Ok(create_success_output("localhost/test-builder:latest\n"))
+                Ok(create_success_output(&format_images_json(&[
+                    MockImageInfo::new("abc123", "localhost/test-builder", "latest"),
+                ])))
             } else {
                 // Return empty list for other calls
-                Ok(create_success_output(
-                    "REPOSITORY\tTAG\tIMAGE ID\tCREATED\tSIZE\n",
-                ))
+                Ok(create_success_output("[]\n"))
             }
         });
 
         mock.expect_podman_inspect()
             .times(..)
-            .returning(|_| Ok(create_success_output(r#"[{"Size": 1073741824}]"#)));
+            .returning(|_| Ok(create_success_output(r#"[{"Id": "abc123", "Size": 1073741824}]"#)));
 
         mock.expect_podman_rmi()
             .times(..)
@@ -489,17 +498,24 @@ impl MockScenarios {
                  if args.iter().any(|arg| arg.contains("--filter")) && 
                     args.iter().any(|arg| arg.contains("reference=localhost/test-builder")) {
                      // Return the expected format for builder container check
-                     Ok(create_success_output("localhost/test-builder:latest\n"))
+                     Ok(create_success_output(&format_images_json(&[
+                         MockImageInfo::new("abc123", "localhost/test-builder", "latest"),
+                     ])))
                  } else {
                      // Return multiple images for cleanup testing
-                     Ok(create_success_output("REPOSITORY\tTAG\tIMAGE ID\tCREATED\tSIZE\nlocalhost/test-builder\tlatest\tabc123\t2024-01-01T00:00:00Z\t100MB\nlocalhost/test-rootfs\tlatest\tdef456\t2024-01-01T00:00:00Z\t100MB\nlocalhost/test-builder\tintermediate\tghi789\t2024-01-01T00:00:00Z\t100MB\nlocalhost/test-rootfs\tintermediate\tjkl012\t2024-01-01T00:00:00Z\t100MB\n"))
+                     Ok(create_success_output(&format_images_json(&[
+                         MockImageInfo::new("abc123", "localhost/test-builder", "latest"),
+                         MockImageInfo::new("def456", "localhost/test-rootfs", "latest"),
+                         MockImageInfo::new("ghi789", "localhost/test-builder", "intermediate"),
+                         MockImageInfo::new("jkl012", "localhost/test-rootfs", "intermediate"),
+                     ])))
                  }
              });
 
          // Accept any podman inspect command (multiple times)
          mock.expect_podman_inspect()
              .times(..)
-             .returning(|_| Ok(create_success_output(r#"[{"Size": 1073741824}]"#)));
+             .returning(|_| Ok(create_success_output(r#"[{"Id": "abc123", "Size": 1073741824}]"#)));
 
          // Accept any podman rmi command (multiple times)
          mock.expect_podman_rmi()
@@ -613,10 +629,10 @@ mod tests {
             MockImageInfo::new("abc123", "localhost/test", "latest"),
             MockImageInfo::new("def456", "localhost/other", "v1.0"),
         ];
-        let output = format_images_output(&images);
-        assert!(output.contains("REPOSITORY"));
-        assert!(output.contains("localhost/test"));
-        assert!(output.contains("localhost/other"));
+        let output = format_images_json(&images);
+        assert!(output.starts_with('['));
+        assert!(output.contains("localhost/test:latest"));
+        assert!(output.contains("localhost/other:v1.0"));
     }
 
     #[test]
@@ -10,7 +10,8 @@ pub mod mocks;
 pub fn setup_test_containerfiles(temp_dir: &TempDir, stages: &[&str]) {
     for stage in stages {
         let containerfile_content = format!(
-            r#"FROM alpine
+            r#"ARG BASE_IMAGE
+FROM ${{BASE_IMAGE}} AS {stage}
 RUN echo "Building stage: {stage}"
 LABEL stage="{stage}"
 "#
@@ -24,16 +25,20 @@ LABEL stage="{stage}"
 // Allow dead_code: Used across multiple test files via wildcard imports but not detected by rustc
 #[allow(dead_code)]
 pub fn setup_nested_containerfiles(temp_dir: &TempDir, groups_and_stages: &[(&str, &str)]) {
-    for (group, stage) in groups_and_stages {
+    for (group, _) in groups_and_stages {
         let group_dir = temp_dir.path().join(group);
         fs::create_dir_all(&group_dir).unwrap();
 
-        let containerfile_content = format!(
-            r#"FROM alpine
+        // Every stage of a group is a target in the group's Containerfile
+        let mut containerfile_content = String::from("ARG BASE_IMAGE\n");
+        for (_, stage) in groups_and_stages.iter().filter(|(g, _)| g == group) {
+            containerfile_content.push_str(&format!(
+                r#"FROM ${{BASE_IMAGE}} AS {stage}
 RUN echo "Building group: {group} stage: {stage}"
 LABEL group="{group}" stage="{stage}"
 "#
-        );
+            ));
+        }
 
         let containerfile_path = group_dir.join(format!("Containerfile.{group}"));
         fs::write(containerfile_path, containerfile_content).unwrap();
@@ -105,6 +110,56 @@ pub fn assert_file_contains(path: &Path, content: &str) {
     );
 }
 
+/// Returns a configuration with every field set, rooted in `dir`. Tests override
+/// the fields they exercise with struct update syntax.
+#[allow(dead_code)]
+pub fn test_config(dir: &TempDir) -> TrellisConfig {
+    TrellisConfig {
+        builder_stages: vec!["base".to_string()],
+        builder_tag: "test-builder".to_string(),
+        podman_build_cache: false,
+        auto_clean: false,
+        incremental: false,
+        preprocess: false,
+        layering: Default::default(),
+        rechunk: false,
+        rechunk_image: "quay.io/fedora/fedora-bootc:latest".to_string(),
+        pacman_cache: None,
+        aur_cache: None,
+        stages_dir: dir.path().to_path_buf(),
+        sources: Vec::new(),
+        sources_dir: dir.path().join("sources"),
+        rootfs_stages: vec!["base".to_string()],
+        rootfs_base: "scratch".to_string(),
+        pull_policy: None,
+        extra_contexts: vec![],
+        extra_mounts: vec![],
+        secrets: Default::default(),
+        rootfs_tag: "test-rootfs".to_string(),
+        keep_versions: 5,
+        hooks_dir: None,
+        state_dir: dir.path().join("state"),
+        log_dir: dir.path().join("logs"),
+        log_retention: 10,
+        lock_dir: dir.path().join("lock"),
+        rootless: false,
+        storage_dir: Default::default(),
+        privilege_command: None,
+        engine: Default::default(),
+        podman_socket: None,
+        quiet: false,
+        profile: None,
+        config_path: Default::default(),
+        stage_configs: Default::default(),
+        vars: Default::default(),
+        security: Default::default(),
+        registry: None,
+        dry_run: false,
+        wait: false,
+        force: false,
+    }
+}
+
 /// Test parameter configuration for flag-based variations
 #[derive(Clone, Debug)]
 pub struct TestVariation {
@@ -8,8 +8,9 @@ use common::{mocks::*, TestVariation};
 use std::sync::Arc;
 use tempfile::TempDir;
 use trellis::{
-    config::TrellisConfig,
+    config::{PullPolicy, TrellisConfig},
     trellis::{
+        build_log::BuildLog,
         builder::{BuildType, ContainerBuilder},
         discovery::ContainerfileDiscovery,
     },
@@ -21,16 +22,44 @@ fn create_builder_config(temp_dir: &TempDir) -> TrellisConfig {
         builder_tag: "test-builder".to_string(),
         podman_build_cache: false,
         auto_clean: false,
+        incremental: false,
+        preprocess: false,
+        layering: Default::default(),
+        rechunk: false,
+        rechunk_image: "quay.io/fedora/fedora-bootc:latest".to_string(),
         pacman_cache: None,
         aur_cache: None,
         stages_dir: temp_dir.path().to_path_buf(),
+        sources: Vec::new(),
+        sources_dir: temp_dir.path().join("sources"),
         rootfs_stages: vec!["base".to_string(), "final".to_string()],
         rootfs_base: "scratch".to_string(),
+        pull_policy: None,
         extra_contexts: vec![],
         extra_mounts: vec![],
+        secrets: Default::default(),
         rootfs_tag: "test-rootfs".to_string(),
+        keep_versions: 5,
         hooks_dir: None,
+        state_dir: temp_dir.path().join("state"),
+        log_dir: temp_dir.path().join("logs"),
+        log_retention: 10,
+        lock_dir: Default::default(),
+        rootless: false,
+        storage_dir: Default::default(),
+        privilege_command: None,
+        engine: Default::default(),
+        podman_socket: None,
         quiet: false,
+        profile: None,
+        config_path: Default::default(),
+        stage_configs: Default::default(),
+        vars: Default::default(),
+        security: Default::default(),
+        registry: None,
+        dry_run: false,
+        wait: false,
+        force: false,
     }
 }
 
@@ -99,6 +128,7 @@ fn test_build_single_stage_container_impl(variation: TestVariation) {
 
     let executor = if variation.quiet {
         let mut mock_executor = MockCommandExecutor::new();
+        expect_no_previous_image(&mut mock_executor);
         mock_executor
             .expect_podman_build()
             .returning(|_| Ok(create_success_output("Build completed")));
@@ -133,6 +163,7 @@ fn test_build_single_stage_container_failure_impl(variation: TestVariation) {
 
     let executor = if variation.quiet {
         let mut mock_executor = MockCommandExecutor::new();
+        expect_no_previous_image(&mut mock_executor);
         mock_executor
             .expect_podman_build()
             .returning(|_| Ok(create_failure_output("Build failed")));
@@ -171,6 +202,7 @@ fn test_build_multistage_container_impl(variation: TestVariation) {
 
     let executor = if variation.quiet {
         let mut mock_executor = MockCommandExecutor::new();
+        expect_no_previous_image(&mut mock_executor);
         mock_executor
             .expect_podman_build()
             .times(2) // Two stages
@@ -459,6 +491,7 @@ fn test_build_error_propagation() {
     let config = create_builder_config(&temp_dir);
 
     let mut mock_executor = MockCommandExecutor::new();
+    expect_no_previous_image(&mut mock_executor);
     mock_executor
         .expect_podman_build_streaming()
         .returning(|_| Ok(common::mocks::create_failure_status()));
@@ -495,3 +528,823 @@ fn test_containerfile_validation_before_build() {
         .to_string()
         .contains("Missing required containerfiles"));
 }
+
+/// Reports no previous image, leaving the disk space check without a build size estimate.
+fn expect_no_previous_image(mock_executor: &mut MockCommandExecutor) {
+    mock_executor
+        .expect_podman_inspect()
+        .withf(|args| args[..2] == ["--type", "image"])
+        .returning(|_| Ok(create_failure_output("image not known")));
+}
+
+/// Creates a mock that reports whether content-keyed images exist via `podman image exists`.
+fn create_incremental_mock(existing_keys: &'static [&'static str]) -> MockCommandExecutor {
+    let mut mock_executor = MockCommandExecutor::new();
+    mock_executor
+        .expect_podman_inspect()
+        .withf(|args| args[..2] == ["--type", "image"])
+        .returning(|args| {
+            Ok(create_success_output(&format!(
+                r#"[{{"Id": "id-of-{}"}}]"#,
+                args[2]
+            )))
+        });
+    mock_executor
+        .expect_execute()
+        .returning(move |command, args| {
+            if command == "podman" && args[0] == "image" && args[1] == "exists" {
+                if existing_keys
+                    .iter()
+                    .any(|prefix| args[2].starts_with(prefix))
+                {
+                    Ok(create_success_output(""))
+                } else {
+                    Ok(create_failure_output(""))
+                }
+            } else {
+                Ok(create_success_output(""))
+            }
+        });
+    mock_executor
+}
+
+#[test]
+fn test_incremental_build_reuses_unchanged_stage() {
+    let temp_dir = TempDir::new().unwrap();
+    common::setup_test_containerfiles(&temp_dir, &["base"]);
+
+    let mut config = create_builder_config(&temp_dir);
+    config.incremental = true;
+
+    let mut mock_executor = create_incremental_mock(&["localhost/trellis-stage-base:"]);
+    mock_executor.expect_podman_build_streaming().times(0);
+
+    let builder = ContainerBuilder::new(&config, Arc::new(mock_executor));
+    let stages = vec!["base".to_string()];
+    let summary = builder
+        .build_multistage_container("stage", "test-rootfs", &stages, BuildType::Rootfs)
+        .unwrap();
+
+    assert_eq!(summary.stages.len(), 1);
+    assert!(summary.stages[0].reused);
+}
+
+#[test]
+fn test_incremental_build_tags_rebuilt_stage_with_content_key() {
+    let temp_dir = TempDir::new().unwrap();
+    common::setup_test_containerfiles(&temp_dir, &["base"]);
+
+    let mut config = create_builder_config(&temp_dir);
+    config.incremental = true;
+
+    let mut mock_executor = create_incremental_mock(&[]);
+    mock_executor
+        .expect_podman_build_streaming()
+        .times(1)
+        .withf(|args: &[String]| {
+            args.iter()
+                .any(|arg| arg.starts_with("localhost/trellis-stage-base:"))
+        })
+        .returning(|_| Ok(create_success_status()));
+
+    let builder = ContainerBuilder::new(&config, Arc::new(mock_executor));
+    let stages = vec!["base".to_string()];
+    let summary = builder
+        .build_multistage_container("stage", "test-rootfs", &stages, BuildType::Rootfs)
+        .unwrap();
+
+    assert!(!summary.stages[0].reused);
+}
+
+#[test]
+fn test_incremental_key_changes_with_containerfile() {
+    let temp_dir = TempDir::new().unwrap();
+    common::setup_test_containerfiles(&temp_dir, &["base"]);
+
+    let mut config = create_builder_config(&temp_dir);
+    config.incremental = true;
+    let stages = vec!["base".to_string()];
+
+    let check = |config: &TrellisConfig| {
+        let builder = ContainerBuilder::new(config, Arc::new(create_incremental_mock(&[])));
+        builder
+            .check_stages("stage", &stages, BuildType::Rootfs)
+            .unwrap()[0]
+            .cache_tag
+            .clone()
+            .unwrap()
+    };
+
+    let before = check(&config);
+    std::fs::write(
+        temp_dir.path().join("Containerfile.base"),
+        "FROM scratch AS base\n",
+    )
+    .unwrap();
+    let after = check(&config);
+
+    assert_ne!(before, after);
+}
+
+#[test]
+fn test_incremental_key_changes_with_build_context() {
+    let temp_dir = TempDir::new().unwrap();
+    common::setup_test_containerfiles(&temp_dir, &["base"]);
+    let copied = temp_dir.path().join("files").join("motd");
+    std::fs::create_dir_all(copied.parent().unwrap()).unwrap();
+    std::fs::write(&copied, "one").unwrap();
+
+    let mut config = create_builder_config(&temp_dir);
+    config.incremental = true;
+    let stages = vec!["base".to_string()];
+
+    let check = |config: &TrellisConfig| {
+        let builder = ContainerBuilder::new(config, Arc::new(create_incremental_mock(&[])));
+        builder
+            .check_stages("stage", &stages, BuildType::Rootfs)
+            .unwrap()[0]
+            .cache_tag
+            .clone()
+            .unwrap()
+    };
+
+    let before = check(&config);
+    std::fs::write(&copied, "two, longer").unwrap();
+    let after = check(&config);
+
+    assert_ne!(before, after);
+}
+
+#[test]
+fn test_preprocess_builds_from_rendered_containerfile() {
+    let temp_dir = TempDir::new().unwrap();
+    std::fs::write(
+        temp_dir.path().join("Containerfile.base"),
+        "FROM {{ rootfs_base }} AS base\nRUN echo {{ greeting }}\n",
+    )
+    .unwrap();
+
+    let mut config = create_builder_config(&temp_dir);
+    config.preprocess = true;
+    config
+        .vars
+        .insert("greeting".to_string(), "hello".to_string());
+
+    let stage_dir = temp_dir.path().to_string_lossy().to_string();
+    let mut mock_executor = create_incremental_mock(&[]);
+    mock_executor
+        .expect_podman_build_streaming()
+        .times(1)
+        .withf(move |args: &[String]| {
+            let file = args
+                .iter()
+                .position(|arg| arg == "-f")
+                .map(|index| std::path::PathBuf::from(&args[index + 1]))
+                .unwrap();
+            // The stage directory stays the build context
+            file.to_string_lossy().contains("trellis-render")
+                && std::fs::read_to_string(file).unwrap()
+                    == "FROM scratch AS base\nRUN echo hello\n"
+                && args.last() == Some(&stage_dir)
+        })
+        .returning(|_| Ok(create_success_status()));
+
+    let builder = ContainerBuilder::new(&config, Arc::new(mock_executor));
+    let stages = vec!["base".to_string()];
+    builder
+        .build_multistage_container("stage", "test-rootfs", &stages, BuildType::Rootfs)
+        .unwrap();
+}
+
+#[test]
+fn test_pull_policy_applies_to_base_image_only() {
+    let temp_dir = TempDir::new().unwrap();
+    common::setup_test_containerfiles(&temp_dir, &["base", "system"]);
+
+    let mut config = create_builder_config(&temp_dir);
+    config.rootfs_base = "quay.io/archlinux/archlinux:latest".to_string();
+    config.pull_policy = Some(PullPolicy::Always);
+
+    let mut mock_executor = create_incremental_mock(&[]);
+    mock_executor
+        .expect_podman_build_streaming()
+        .times(1)
+        .withf(|args: &[String]| {
+            args.contains(&"BASE_IMAGE=quay.io/archlinux/archlinux:latest".to_string())
+                && args.contains(&"--pull=always".to_string())
+        })
+        .returning(|_| Ok(create_success_status()));
+    mock_executor
+        .expect_podman_build_streaming()
+        .times(1)
+        .withf(|args: &[String]| !args.iter().any(|arg| arg.starts_with("--pull")))
+        .returning(|_| Ok(create_success_status()));
+
+    let builder = ContainerBuilder::new(&config, Arc::new(mock_executor));
+    let stages = vec!["base".to_string(), "system".to_string()];
+    builder
+        .build_multistage_container("stage", "test-rootfs", &stages, BuildType::Rootfs)
+        .unwrap();
+}
+
+#[test]
+fn test_build_passes_secrets_by_path() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let temp_dir = TempDir::new().unwrap();
+    common::setup_test_containerfiles(&temp_dir, &["base"]);
+    let src = temp_dir.path().join("repo-token");
+    std::fs::write(&src, "s3cr3t-value").unwrap();
+    std::fs::set_permissions(&src, std::fs::Permissions::from_mode(0o600)).unwrap();
+
+    let mut config = create_builder_config(&temp_dir);
+    config.secrets.insert("repo_token".to_string(), src.clone());
+
+    let expected = format!("id=repo_token,src={}", src.display());
+    let mut mock_executor = create_incremental_mock(&[]);
+    mock_executor
+        .expect_podman_build_streaming()
+        .times(1)
+        .withf(move |args: &[String]| {
+            args.windows(2)
+                .any(|pair| pair[0] == "--secret" && pair[1] == expected)
+                && !args.iter().any(|arg| arg.contains("s3cr3t-value"))
+        })
+        .returning(|_| Ok(create_success_status()));
+
+    let builder = ContainerBuilder::new(&config, Arc::new(mock_executor));
+    let stages = vec!["base".to_string()];
+    builder
+        .build_multistage_container("stage", "test-rootfs", &stages, BuildType::Rootfs)
+        .unwrap();
+}
+
+#[test]
+fn test_build_rejects_world_readable_secret() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let temp_dir = TempDir::new().unwrap();
+    common::setup_test_containerfiles(&temp_dir, &["base"]);
+    let src = temp_dir.path().join("repo-token");
+    std::fs::write(&src, "s3cr3t-value").unwrap();
+    std::fs::set_permissions(&src, std::fs::Permissions::from_mode(0o644)).unwrap();
+
+    let mut config = create_builder_config(&temp_dir);
+    config.secrets.insert("repo_token".to_string(), src);
+
+    let mut mock_executor = create_incremental_mock(&[]);
+    mock_executor.expect_podman_build_streaming().times(0);
+
+    let builder = ContainerBuilder::new(&config, Arc::new(mock_executor));
+    let stages = vec!["base".to_string()];
+    let error = builder
+        .build_multistage_container("stage", "test-rootfs", &stages, BuildType::Rootfs)
+        .unwrap_err();
+    assert!(error.to_string().contains("world-readable"));
+}
+
+#[test]
+fn test_build_uses_security_profile_and_stage_network() {
+    use trellis::config::{NetworkMode, SecurityProfile, StageConfig};
+
+    let temp_dir = TempDir::new().unwrap();
+    common::setup_test_containerfiles(&temp_dir, &["base"]);
+
+    let mut config = create_builder_config(&temp_dir);
+    config.security = SecurityProfile::builtin("hardened").unwrap();
+    config.stage_configs.insert(
+        "base".to_string(),
+        StageConfig {
+            network: Some(NetworkMode::None),
+            ..Default::default()
+        },
+    );
+
+    let mut mock_executor = create_incremental_mock(&[]);
+    mock_executor
+        .expect_podman_build_streaming()
+        .times(1)
+        .withf(|args: &[String]| {
+            args.starts_with(&["--net".to_string(), "none".to_string()])
+                && !args.contains(&"--cap-add".to_string())
+        })
+        .returning(|_| Ok(create_success_status()));
+
+    let builder = ContainerBuilder::new(&config, Arc::new(mock_executor));
+    let stages = vec!["base".to_string()];
+    builder
+        .build_multistage_container("stage", "test-rootfs", &stages, BuildType::Rootfs)
+        .unwrap();
+
+    let log = BuildLog::latest(&config.log_dir).unwrap();
+    let content = std::fs::read_to_string(log.stage_path("base")).unwrap();
+    assert!(content.starts_with("# trellis: security profile 'hardened': network=none,"));
+}
+
+#[test]
+fn test_rootfs_stages_use_configured_layering() {
+    use trellis::config::Layering;
+
+    let temp_dir = TempDir::new().unwrap();
+    common::setup_test_containerfiles(&temp_dir, &["base"]);
+
+    let mut config = create_builder_config(&temp_dir);
+    config.layering = Layering::SquashAll;
+
+    let mut mock_executor = create_incremental_mock(&[]);
+    mock_executor
+        .expect_podman_build_streaming()
+        .times(2)
+        .returning(|args: &[String]| {
+            let is_rootfs = args.contains(&"test-rootfs".to_string());
+            let squash_all = args.contains(&"--squash-all".to_string());
+            let squash = args.contains(&"--squash".to_string());
+            assert!(if is_rootfs {
+                squash_all && !squash
+            } else {
+                squash && !squash_all
+            });
+            Ok(create_success_status())
+        });
+
+    let builder = ContainerBuilder::new(&config, Arc::new(mock_executor));
+    let stages = vec!["base".to_string()];
+    builder
+        .build_multistage_container("stage", "test-rootfs", &stages, BuildType::Rootfs)
+        .unwrap();
+    builder
+        .build_multistage_container("builder", "test-builder", &stages, BuildType::Builder)
+        .unwrap();
+}
+
+#[test]
+fn test_incremental_key_changes_with_layering() {
+    use trellis::config::Layering;
+
+    let temp_dir = TempDir::new().unwrap();
+    common::setup_test_containerfiles(&temp_dir, &["base"]);
+
+    let mut config = create_builder_config(&temp_dir);
+    let stages = vec!["base".to_string()];
+    let check = |config: &TrellisConfig| {
+        let builder = ContainerBuilder::new(config, Arc::new(create_incremental_mock(&[])));
+        builder
+            .check_stages("stage", &stages, BuildType::Rootfs)
+            .unwrap()[0]
+            .cache_tag
+            .clone()
+            .unwrap()
+    };
+
+    let squashed = check(&config);
+    config.layering = Layering::Layered;
+    assert_ne!(squashed, check(&config));
+}
+
+#[test]
+fn test_rechunk_image_runs_build_chunked_oci() {
+    let temp_dir = TempDir::new().unwrap();
+    let config = create_builder_config(&temp_dir);
+
+    let mut mock_executor = MockCommandExecutor::new();
+    mock_executor
+        .expect_podman_run_streaming()
+        .times(1)
+        .withf(|args: &[String]| {
+            args.contains(&"--privileged".to_string())
+                && args.contains(&"quay.io/fedora/fedora-bootc:latest".to_string())
+                && args.contains(&"build-chunked-oci".to_string())
+                && args.contains(&"--from=localhost/test-rootfs".to_string())
+                && args.contains(&"--output=containers-storage:localhost/test-rootfs".to_string())
+        })
+        .returning(|_| Ok(create_success_status()));
+
+    let builder = ContainerBuilder::new(&config, Arc::new(mock_executor));
+    builder.rechunk_image("test-rootfs").unwrap();
+}
+
+#[test]
+fn test_embed_metadata_labels_image_and_writes_build_json() {
+    use std::sync::Mutex;
+    use trellis::trellis::{lockfile::BuildLock, metadata::BuildMetadata};
+
+    let temp_dir = TempDir::new().unwrap();
+    common::setup_test_containerfiles(&temp_dir, &["base", "final"]);
+    let mut config = create_builder_config(&temp_dir);
+    config.rootfs_base = "quay.io/fedora/fedora:41".to_string();
+    let digest = format!("sha256:{}", "a".repeat(64));
+    let digest_label = format!("org.opencontainers.image.base.digest={digest}");
+
+    // The build context is removed afterwards, so read it during the build
+    let context = Arc::new(Mutex::new(None));
+    let captured = context.clone();
+    let mut mock_executor = MockCommandExecutor::new();
+    mock_executor.expect_podman_inspect().returning(move |_| {
+        Ok(create_success_output(&format!(
+            r#"[{{"Id": "0123456789ab", "Digest": "{digest}"}}]"#
+        )))
+    });
+    mock_executor
+        .expect_podman_build_streaming()
+        .times(1)
+        .withf(move |args: &[String]| {
+            args.windows(2).any(|w| w == ["--net", "none"])
+                && args.windows(2).any(|w| w == ["-t", "test-rootfs"])
+                && args.contains(&"dev.trellis.stages=base,final".to_string())
+                && args.contains(&digest_label)
+        })
+        .returning(move |args| {
+            let position = args.iter().position(|arg| arg == "-f").unwrap();
+            let containerfile = std::path::PathBuf::from(&args[position + 1]);
+            let build_json = containerfile.with_file_name("build.json");
+            let lock = containerfile.with_file_name("trellis.lock");
+            *captured.lock().unwrap() = Some((
+                std::fs::read_to_string(&containerfile).unwrap(),
+                std::fs::read_to_string(build_json).unwrap(),
+                std::fs::read_to_string(lock).unwrap(),
+            ));
+            Ok(create_success_status())
+        });
+
+    let builder = ContainerBuilder::new(&config, Arc::new(mock_executor));
+    let stages = vec!["base".to_string(), "final".to_string()];
+    let lock: BuildLock = builder.resolve_lock(&stages, BuildType::Rootfs).unwrap();
+    let metadata = BuildMetadata::new(&config, &lock, "20240501-120000-abcd1234");
+    builder
+        .embed_metadata("test-rootfs", &metadata, &lock)
+        .unwrap();
+
+    let (containerfile, build_json, lockfile) = context.lock().unwrap().take().unwrap();
+    assert!(containerfile.starts_with("FROM localhost/test-rootfs\n"));
+    assert!(containerfile.contains("COPY build.json /usr/share/trellis/build.json"));
+    assert!(containerfile.contains("COPY trellis.lock /usr/share/trellis/trellis.lock"));
+    assert_eq!(toml::from_str::<BuildLock>(&lockfile).unwrap(), lock);
+    assert!(containerfile.contains("test-rootfs 20240501-120000-abcd1234 >> /usr/lib/os-release"));
+
+    let written: BuildMetadata = serde_json::from_str(&build_json).unwrap();
+    assert_eq!(written, metadata);
+    assert_eq!(written.image_id, "test-rootfs");
+    assert_eq!(written.stages, vec!["base", "final"]);
+    assert_eq!(written.containerfiles.len(), 2);
+}
+
+#[test]
+fn test_check_stages_reports_stale_stages() {
+    use trellis::trellis::builder::StageState;
+
+    let temp_dir = TempDir::new().unwrap();
+    common::setup_test_containerfiles(&temp_dir, &["base", "middle", "final"]);
+
+    let config = create_builder_config(&temp_dir);
+    let mock_executor = create_incremental_mock(&["localhost/trellis-stage-base:"]);
+    let builder = ContainerBuilder::new(&config, Arc::new(mock_executor));
+
+    let stages = vec![
+        "base".to_string(),
+        "middle".to_string(),
+        "final".to_string(),
+    ];
+    let statuses = builder
+        .check_stages("stage", &stages, BuildType::Rootfs)
+        .unwrap();
+
+    let states: Vec<StageState> = statuses.iter().map(|status| status.state).collect();
+    assert_eq!(
+        states,
+        vec![
+            StageState::UpToDate,
+            StageState::Changed,
+            StageState::ParentChanged
+        ]
+    );
+}
+
+#[test]
+fn test_build_from_stage_uses_previous_intermediate_image() {
+    let temp_dir = TempDir::new().unwrap();
+    common::setup_test_containerfiles(&temp_dir, &["base", "middle", "final"]);
+
+    let config = create_builder_config(&temp_dir);
+    let mut mock_executor = MockCommandExecutor::new();
+    expect_no_previous_image(&mut mock_executor);
+    mock_executor
+        .expect_execute()
+        .withf(|command, args| {
+            command == "podman" && args == ["image", "exists", "localhost/trellis-stage-base"]
+        })
+        .times(1)
+        .returning(|_, _| Ok(create_success_output("")));
+    mock_executor
+        .expect_podman_build_streaming()
+        .times(1)
+        .withf(|args: &[String]| {
+            args.contains(&"BASE_IMAGE=localhost/trellis-stage-base".to_string())
+                && args.contains(&"trellis-stage-middle".to_string())
+        })
+        .returning(|_| Ok(create_success_status()));
+    mock_executor
+        .expect_podman_build_streaming()
+        .times(1)
+        .withf(|args: &[String]| {
+            args.contains(&"BASE_IMAGE=localhost/trellis-stage-middle".to_string())
+        })
+        .returning(|_| Ok(create_success_status()));
+
+    let builder = ContainerBuilder::new(&config, Arc::new(mock_executor));
+    let stages = vec![
+        "base".to_string(),
+        "middle".to_string(),
+        "final".to_string(),
+    ];
+    let start = builder
+        .resolve_start_stage("stage", &stages, Some("middle"), false)
+        .unwrap();
+    assert_eq!(start, 1);
+
+    let summary = builder
+        .build_multistage_container_from("stage", "test-rootfs", &stages, BuildType::Rootfs, start)
+        .unwrap();
+    let built: Vec<&str> = summary.stages.iter().map(|s| s.name.as_str()).collect();
+    assert_eq!(built, vec!["middle", "final"]);
+}
+
+#[test]
+fn test_build_from_stage_requires_intermediate_image() {
+    let temp_dir = TempDir::new().unwrap();
+    common::setup_test_containerfiles(&temp_dir, &["base", "final"]);
+
+    let config = create_builder_config(&temp_dir);
+    let mut mock_executor = MockCommandExecutor::new();
+    expect_no_previous_image(&mut mock_executor);
+    mock_executor
+        .expect_execute()
+        .returning(|_, _| Ok(create_failure_output("")));
+    mock_executor.expect_podman_build_streaming().times(0);
+
+    let builder = ContainerBuilder::new(&config, Arc::new(mock_executor));
+    let stages = vec!["base".to_string(), "final".to_string()];
+    let result = builder.build_multistage_container_from(
+        "stage",
+        "test-rootfs",
+        &stages,
+        BuildType::Rootfs,
+        1,
+    );
+
+    let error = result.unwrap_err().to_string();
+    assert!(error.contains("trellis-stage-base"));
+    assert!(error.contains("not found"));
+}
+
+#[test]
+fn test_resume_starts_after_last_completed_stage() {
+    let temp_dir = TempDir::new().unwrap();
+    common::setup_test_containerfiles(&temp_dir, &["base", "final"]);
+
+    let config = create_builder_config(&temp_dir);
+    let mut mock_executor = MockCommandExecutor::new();
+    expect_no_previous_image(&mut mock_executor);
+    mock_executor
+        .expect_podman_build_streaming()
+        .times(1)
+        .withf(|args: &[String]| args.contains(&"trellis-stage-base".to_string()))
+        .returning(|_| Ok(create_success_status()));
+    mock_executor
+        .expect_podman_build_streaming()
+        .times(1)
+        .returning(|_| Ok(create_failure_status()));
+
+    let builder = ContainerBuilder::new(&config, Arc::new(mock_executor));
+    let stages = vec!["base".to_string(), "final".to_string()];
+    assert!(builder
+        .build_multistage_container("stage", "test-rootfs", &stages, BuildType::Rootfs)
+        .is_err());
+
+    let start = builder
+        .resolve_start_stage("stage", &stages, None, true)
+        .unwrap();
+    assert_eq!(start, 1);
+
+    let changed_stages = vec!["base".to_string(), "other".to_string()];
+    assert!(builder
+        .resolve_start_stage("stage", &changed_stages, None, true)
+        .is_err());
+}
+
+#[test]
+fn test_resume_without_recorded_build_fails() {
+    let temp_dir = TempDir::new().unwrap();
+    let config = create_builder_config(&temp_dir);
+    let builder = ContainerBuilder::new(&config, Arc::new(MockCommandExecutor::new()));
+    let stages = vec!["base".to_string(), "final".to_string()];
+
+    let result = builder.resolve_start_stage("stage", &stages, None, true);
+    assert!(result
+        .unwrap_err()
+        .to_string()
+        .contains("No interrupted build to resume"));
+}
+
+#[test]
+fn test_resolve_start_stage_by_name() {
+    let temp_dir = TempDir::new().unwrap();
+    let config = create_builder_config(&temp_dir);
+    let builder = ContainerBuilder::new(&config, Arc::new(MockCommandExecutor::new()));
+    let stages = vec!["base".to_string(), "features:gpu".to_string()];
+
+    assert_eq!(
+        builder
+            .resolve_start_stage("stage", &stages, None, false)
+            .unwrap(),
+        0
+    );
+    assert_eq!(
+        builder
+            .resolve_start_stage("stage", &stages, Some("features:gpu"), false)
+            .unwrap(),
+        1
+    );
+    assert_eq!(
+        builder
+            .resolve_start_stage("stage", &stages, Some("gpu"), false)
+            .unwrap(),
+        1
+    );
+    assert!(builder
+        .resolve_start_stage("stage", &stages, Some("missing"), false)
+        .is_err());
+}
+
+fn capture_build_args(
+    mock_executor: &mut MockCommandExecutor,
+) -> Arc<std::sync::Mutex<Vec<Vec<String>>>> {
+    let captured = Arc::new(std::sync::Mutex::new(Vec::new()));
+    let sink = Arc::clone(&captured);
+    mock_executor
+        .expect_podman_build_streaming()
+        .returning(move |args| {
+            sink.lock().unwrap().push(args.to_vec());
+            Ok(create_success_status())
+        });
+    captured
+}
+
+fn has_pair(args: &[String], flag: &str, value: &str) -> bool {
+    args.windows(2)
+        .any(|pair| pair[0] == flag && pair[1] == value)
+}
+
+#[test]
+fn test_stage_config_applies_only_to_matching_stage() {
+    use std::collections::BTreeMap;
+    use trellis::config::{NetworkMode, StageConfig};
+
+    let temp_dir = TempDir::new().unwrap();
+    common::setup_test_containerfiles(&temp_dir, &["base", "final"]);
+    std::fs::write(
+        temp_dir.path().join("Containerfile.final"),
+        "ARG BASE_IMAGE\nFROM ${BASE_IMAGE} AS final-server\nARG FLAVOR\nRUN echo $FLAVOR\n",
+    )
+    .unwrap();
+
+    let mut config = create_builder_config(&temp_dir);
+    config.podman_build_cache = true;
+    config.stage_configs.insert(
+        "final".to_string(),
+        StageConfig {
+            build_args: Some(BTreeMap::from([(
+                "FLAVOR".to_string(),
+                "server".to_string(),
+            )])),
+            mounts: Some(vec![
+                "/srv/data".to_string(),
+                "/opt/src:/src:ro".to_string(),
+            ]),
+            contexts: Some(vec!["assets=/opt/assets".to_string()]),
+            network: Some(NetworkMode::None),
+            no_cache: Some(true),
+            target: Some("final-server".to_string()),
+        },
+    );
+
+    let mut mock_executor = MockCommandExecutor::new();
+    expect_no_previous_image(&mut mock_executor);
+    let captured = capture_build_args(&mut mock_executor);
+    let builder = ContainerBuilder::new(&config, Arc::new(mock_executor));
+    let stages = vec!["base".to_string(), "final".to_string()];
+    builder
+        .build_multistage_container("stage", "test-rootfs", &stages, BuildType::Rootfs)
+        .unwrap();
+
+    let calls = captured.lock().unwrap();
+    let (base, last) = (&calls[0], &calls[1]);
+
+    assert!(has_pair(base, "--net", "host"));
+    assert!(has_pair(base, "--target", "base"));
+    assert!(!base.contains(&"--no-cache".to_string()));
+    assert!(!base.iter().any(|arg| arg.contains("FLAVOR")));
+
+    assert!(has_pair(last, "--net", "none"));
+    assert!(has_pair(last, "--target", "final-server"));
+    assert!(last.contains(&"--no-cache".to_string()));
+    assert!(has_pair(last, "--build-arg", "FLAVOR=server"));
+    assert!(has_pair(last, "-v", "/srv/data:/srv/data"));
+    assert!(has_pair(last, "-v", "/opt/src:/src:ro"));
+    assert!(has_pair(last, "--build-context", "assets=/opt/assets"));
+}
+
+#[test]
+fn test_stage_config_applies_to_builder_stages() {
+    use trellis::config::StageConfig;
+
+    let temp_dir = TempDir::new().unwrap();
+    common::setup_test_containerfiles(&temp_dir, &["base"]);
+
+    let mut config = create_builder_config(&temp_dir);
+    config.stage_configs.insert(
+        "base".to_string(),
+        StageConfig {
+            mounts: Some(vec!["/var/cache/pacman/pkg".to_string()]),
+            ..Default::default()
+        },
+    );
+
+    let mut mock_executor = MockCommandExecutor::new();
+    expect_no_previous_image(&mut mock_executor);
+    let captured = capture_build_args(&mut mock_executor);
+    let builder = ContainerBuilder::new(&config, Arc::new(mock_executor));
+    builder
+        .build_multistage_container(
+            "builder",
+            "test-builder",
+            &["base".to_string()],
+            BuildType::Builder,
+        )
+        .unwrap();
+
+    let calls = captured.lock().unwrap();
+    assert!(has_pair(
+        &calls[0],
+        "-v",
+        "/var/cache/pacman/pkg:/var/cache/pacman/pkg"
+    ));
+}
+
+#[test]
+fn test_stage_config_matches_group_stage_by_stage_name() {
+    use trellis::config::StageConfig;
+
+    let temp_dir = TempDir::new().unwrap();
+    let mut config = create_builder_config(&temp_dir);
+    config.stage_configs.insert(
+        "gpu".to_string(),
+        StageConfig {
+            target: Some("gpu-nvidia".to_string()),
+            ..Default::default()
+        },
+    );
+
+    assert_eq!(
+        config
+            .stage_config("features:gpu")
+            .unwrap()
+            .target
+            .as_deref(),
+        Some("gpu-nvidia")
+    );
+    assert!(config.stage_config("features:audio").is_none());
+}
+
+#[test]
+fn test_quiet_build_writes_stage_logs() {
+    use trellis::trellis::build_log::BuildLog;
+
+    let temp_dir = TempDir::new().unwrap();
+    common::setup_test_containerfiles(&temp_dir, &["base", "final"]);
+
+    let mut config = create_builder_config(&temp_dir);
+    config.quiet = true;
+    let mut mock_executor = MockCommandExecutor::new();
+    expect_no_previous_image(&mut mock_executor);
+    mock_executor
+        .expect_podman_build()
+        .times(1)
+        .returning(|_| Ok(create_success_output("STEP 1/2: FROM scratch\n")));
+    mock_executor
+        .expect_podman_build()
+        .times(1)
+        .returning(|_| Ok(create_failure_output("error: no space left on device\n")));
+
+    let builder = ContainerBuilder::new(&config, Arc::new(mock_executor));
+    let stages = vec!["base".to_string(), "final".to_string()];
+    assert!(builder
+        .build_multistage_container("stage", "test-rootfs", &stages, BuildType::Rootfs)
+        .is_err());
+
+    let log = BuildLog::latest(&config.log_dir).unwrap();
+    assert_eq!(log.stages().unwrap(), vec!["base", "final"]);
+    let base = std::fs::read_to_string(log.find_stage("base").unwrap()).unwrap();
+    assert!(base.contains("STEP 1/2: FROM scratch"));
+    let failed = std::fs::read_to_string(log.find_stage("final").unwrap()).unwrap();
+    assert!(failed.contains("no space left on device"));
+}
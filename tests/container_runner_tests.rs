@@ -8,7 +8,7 @@ use common::{mocks::*, TestVariation};
 use std::sync::Arc;
 use tempfile::TempDir;
 use trellis::{
-    config::TrellisConfig,
+    config::{NetworkMode, SecurityProfile, TrellisConfig},
     trellis::runner::{ContainerCapability, ContainerRunner, PodmanRunCommandBuilder},
 };
 
@@ -18,16 +18,44 @@ fn create_runner_config(temp_dir: &TempDir) -> TrellisConfig {
         builder_tag: "test-builder".to_string(),
         podman_build_cache: false,
         auto_clean: false,
+        incremental: false,
+        preprocess: false,
+        layering: Default::default(),
+        rechunk: false,
+        rechunk_image: "quay.io/fedora/fedora-bootc:latest".to_string(),
         pacman_cache: None,
         aur_cache: None,
         stages_dir: temp_dir.path().to_path_buf(),
+        sources: Vec::new(),
+        sources_dir: temp_dir.path().join("sources"),
         rootfs_stages: vec!["base".to_string()],
         rootfs_base: "scratch".to_string(),
+        pull_policy: None,
         extra_contexts: vec![],
         extra_mounts: vec![],
+        secrets: Default::default(),
         rootfs_tag: "test-rootfs".to_string(),
+        keep_versions: 5,
         hooks_dir: None,
+        state_dir: temp_dir.path().join("state"),
+        log_dir: temp_dir.path().join("logs"),
+        log_retention: 10,
+        lock_dir: Default::default(),
+        rootless: false,
+        storage_dir: Default::default(),
+        privilege_command: None,
+        engine: Default::default(),
+        podman_socket: None,
         quiet: false,
+        profile: None,
+        config_path: Default::default(),
+        stage_configs: Default::default(),
+        vars: Default::default(),
+        security: Default::default(),
+        registry: None,
+        dry_run: false,
+        wait: false,
+        force: false,
     }
 }
 
@@ -380,9 +408,8 @@ fn test_podman_run_command_builder_default() {
 #[test]
 fn test_podman_run_command_builder_chaining() {
     let _builder = PodmanRunCommandBuilder::new()
-        .network_host()
-        .add_capability(ContainerCapability::SysAdmin)
-        .add_capability(ContainerCapability::DacOverride)
+        .network(NetworkMode::Host)
+        .security(&SecurityProfile::default().run)
         .remove_on_exit()
         .interactive()
         .image("test-image")
@@ -390,6 +417,30 @@ fn test_podman_run_command_builder_chaining() {
     // Test passes if no panic occurs during chaining
 }
 
+#[test]
+fn test_run_container_uses_security_profile() {
+    let temp_dir = TempDir::new().unwrap();
+    let mut config = create_runner_config(&temp_dir);
+    config.security = SecurityProfile::builtin("privileged").unwrap();
+
+    let mut mock_executor = MockCommandExecutor::new();
+    mock_executor
+        .expect_execute()
+        .returning(|_, _| Ok(create_success_output("Image exists")));
+    mock_executor
+        .expect_podman_run_streaming()
+        .times(1)
+        .withf(|args: &[String]| {
+            args.starts_with(&["--net".to_string(), "host".to_string()])
+                && args.contains(&"--privileged".to_string())
+                && !args.contains(&"--cap-add".to_string())
+        })
+        .returning(|_| Ok(create_success_status()));
+
+    let runner = ContainerRunner::new(&config, Arc::new(mock_executor));
+    runner.run_container("test-rootfs", &[]).unwrap();
+}
+
 #[test]
 fn test_container_validation_success() {
     let temp_dir = TempDir::new().unwrap();
@@ -14,16 +14,44 @@ fn create_discovery_config(temp_dir: &TempDir) -> TrellisConfig {
         builder_tag: "test-builder".to_string(),
         podman_build_cache: false,
         auto_clean: false,
+        incremental: false,
+        preprocess: false,
+        layering: Default::default(),
+        rechunk: false,
+        rechunk_image: "quay.io/fedora/fedora-bootc:latest".to_string(),
         pacman_cache: None,
         aur_cache: None,
         stages_dir: temp_dir.path().to_path_buf(),
+        sources: Vec::new(),
+        sources_dir: temp_dir.path().join("sources"),
         rootfs_stages: vec!["base".to_string()],
         rootfs_base: "scratch".to_string(),
+        pull_policy: None,
         extra_contexts: vec![],
         extra_mounts: vec![],
+        secrets: Default::default(),
         rootfs_tag: "test-rootfs".to_string(),
+        keep_versions: 5,
         hooks_dir: None,
+        state_dir: temp_dir.path().join("state"),
+        log_dir: temp_dir.path().join("logs"),
+        log_retention: 10,
+        lock_dir: Default::default(),
+        rootless: false,
+        storage_dir: Default::default(),
+        privilege_command: None,
+        engine: Default::default(),
+        podman_socket: None,
         quiet: false,
+        profile: None,
+        config_path: Default::default(),
+        stage_configs: Default::default(),
+        vars: Default::default(),
+        security: Default::default(),
+        registry: None,
+        dry_run: false,
+        wait: false,
+        force: false,
     }
 }
 
@@ -429,3 +457,272 @@ fn test_recursive_search_depth_limit() {
     // We don't assert success/failure here since deep nesting behavior
     // may be implementation-dependent, but it shouldn't panic
 }
+
+fn write_containerfile(temp_dir: &TempDir, group: &str, header: &str) {
+    fs::write(
+        temp_dir.path().join(format!("Containerfile.{group}")),
+        format!("{header}\nFROM alpine AS {group}\nRUN echo {group}\n"),
+    )
+    .unwrap();
+}
+
+fn stage_list(stages: &[&str]) -> Vec<String> {
+    stages.iter().map(|stage| stage.to_string()).collect()
+}
+
+#[test]
+fn test_stage_metadata_parsing() {
+    use trellis::trellis::discovery::StageMetadata;
+
+    let content = "# syntax=docker/dockerfile:1\n\
+                   # trellis: requires=base,gpu conflicts=cosmic\n\
+                   # trellis: requires=network\n\
+                   FROM alpine\n\
+                   # trellis: requires=ignored\n";
+    let metadata = StageMetadata::parse(content).unwrap();
+
+    assert_eq!(metadata.requires, vec!["base", "gpu", "network"]);
+    assert_eq!(metadata.conflicts, vec!["cosmic"]);
+
+    // Preprocessor directives are not metadata
+    let metadata = StageMetadata::parse(
+        "# trellis:include fragments/setup\n# trellis: requires=base\nFROM alpine\n",
+    )
+    .unwrap();
+    assert_eq!(metadata.requires, vec!["base"]);
+
+    assert!(StageMetadata::parse("# trellis: needs=base\nFROM alpine\n").is_err());
+    assert!(StageMetadata::parse("# trellis: requires\nFROM alpine\n").is_err());
+}
+
+#[test]
+fn test_order_stages_sorts_by_requirements() {
+    let temp_dir = TempDir::new().unwrap();
+    write_containerfile(&temp_dir, "base", "");
+    write_containerfile(&temp_dir, "gpu", "# trellis: requires=base");
+    write_containerfile(&temp_dir, "hyprland", "# trellis: requires=base,gpu");
+    write_containerfile(&temp_dir, "tools", "");
+
+    let config = create_discovery_config(&temp_dir);
+    let discovery = ContainerfileDiscovery::new(&config);
+
+    let ordered = discovery
+        .order_stages(&stage_list(&["hyprland", "tools", "gpu", "base"]))
+        .unwrap();
+    assert_eq!(ordered, vec!["tools", "base", "gpu", "hyprland"]);
+
+    // Stages without metadata keep their configured order
+    let ordered = discovery
+        .order_stages(&stage_list(&["tools", "base"]))
+        .unwrap();
+    assert_eq!(ordered, vec!["tools", "base"]);
+}
+
+#[test]
+fn test_order_stages_rejects_cycles() {
+    let temp_dir = TempDir::new().unwrap();
+    write_containerfile(&temp_dir, "base", "");
+    write_containerfile(&temp_dir, "a", "# trellis: requires=b");
+    write_containerfile(&temp_dir, "b", "# trellis: requires=a");
+
+    let config = create_discovery_config(&temp_dir);
+    let discovery = ContainerfileDiscovery::new(&config);
+
+    let error = discovery
+        .order_stages(&stage_list(&["base", "a", "b"]))
+        .unwrap_err()
+        .to_string();
+    assert!(error.contains("Dependency cycle between stages: a, b"));
+}
+
+#[test]
+fn test_order_stages_rejects_conflicts_and_missing_requirements() {
+    let temp_dir = TempDir::new().unwrap();
+    write_containerfile(&temp_dir, "base", "");
+    write_containerfile(&temp_dir, "hyprland", "# trellis: conflicts=cosmic");
+    write_containerfile(&temp_dir, "cosmic", "# trellis: requires=gpu");
+
+    let config = create_discovery_config(&temp_dir);
+    let discovery = ContainerfileDiscovery::new(&config);
+
+    let error = discovery
+        .order_stages(&stage_list(&["base", "hyprland", "cosmic"]))
+        .unwrap_err()
+        .to_string();
+    assert!(error.contains("Stage 'hyprland' conflicts with 'cosmic'"));
+
+    let error = discovery
+        .order_stages(&stage_list(&["base", "cosmic"]))
+        .unwrap_err()
+        .to_string();
+    assert!(error.contains("requires 'gpu', which is not in the stage list"));
+}
+
+#[test]
+fn test_order_stages_matches_group_stage_references() {
+    let temp_dir = TempDir::new().unwrap();
+    write_containerfile(&temp_dir, "base", "");
+    write_containerfile(&temp_dir, "desktop", "# trellis: requires=drivers:gpu");
+    fs::write(
+        temp_dir.path().join("Containerfile.drivers"),
+        "# trellis: requires=base\nFROM alpine AS gpu\n",
+    )
+    .unwrap();
+
+    let config = create_discovery_config(&temp_dir);
+    let discovery = ContainerfileDiscovery::new(&config);
+
+    let ordered = discovery
+        .order_stages(&stage_list(&["desktop", "drivers:gpu", "base"]))
+        .unwrap();
+    assert_eq!(ordered, vec!["base", "drivers:gpu", "desktop"]);
+}
+
+#[test]
+fn test_stage_graph_dot() {
+    let temp_dir = TempDir::new().unwrap();
+    write_containerfile(&temp_dir, "base", "");
+    write_containerfile(
+        &temp_dir,
+        "gpu",
+        "# trellis: requires=base conflicts=nouveau",
+    );
+
+    let config = create_discovery_config(&temp_dir);
+    let discovery = ContainerfileDiscovery::new(&config);
+
+    let dot = discovery
+        .stage_graph_dot(&stage_list(&["base", "gpu"]))
+        .unwrap();
+    assert!(dot.starts_with("digraph stages {"));
+    assert!(dot.contains("    \"base\";\n"));
+    assert!(dot.contains("    \"base\" -> \"gpu\";\n"));
+    assert!(dot.contains("\"gpu\" -> \"nouveau\" [style=dashed"));
+    assert!(dot.ends_with("}\n"));
+}
+
+#[test]
+fn test_lint_reports_problems_with_line_numbers() {
+    use std::collections::BTreeMap;
+    use trellis::{config::StageConfig, trellis::lint::Severity};
+
+    let temp_dir = TempDir::new().unwrap();
+    write_containerfile(&temp_dir, "base", "");
+    fs::write(
+        temp_dir.path().join("Containerfile.desktop"),
+        "FROM ${BASE_IMAGE} AS desktop\n\
+         ARG THEME\n\
+         COPY --from=assets wallpaper.png /usr/share/backgrounds/\n\
+         COPY --from=builder /out /usr/bin/\n\
+         RUN pacman -S --noconfirm sddm \\\n    && mkdir -p /var/lib/sddm\n\
+         RUN echo cached > /var/cache/desktop\n",
+    )
+    .unwrap();
+
+    let mut config = create_discovery_config(&temp_dir);
+    config.extra_contexts = vec!["assets=/srv/assets".to_string()];
+    config.stage_configs.insert(
+        "desktop".to_string(),
+        StageConfig {
+            build_args: Some(BTreeMap::from([(
+                "FLAVOR".to_string(),
+                "plasma".to_string(),
+            )])),
+            ..Default::default()
+        },
+    );
+    let discovery = ContainerfileDiscovery::new(&config);
+
+    let findings = discovery
+        .lint_stages(&stage_list(&["base", "desktop"]))
+        .unwrap();
+    let lines: Vec<String> = findings.iter().map(|f| f.to_string()).collect();
+    let path = temp_dir.path().join("Containerfile.desktop");
+    let path = path.display();
+    assert_eq!(
+        lines,
+        vec![
+            format!("{path}: warning [desktop]: Build arg 'FLAVOR' is passed to the stage but never declared with ARG"),
+            format!("{path}:1: error [desktop]: FROM uses ${{BASE_IMAGE}}, but ARG BASE_IMAGE is not declared before the first FROM"),
+            format!("{path}:2: warning [desktop]: ARG THEME is declared but never used"),
+            format!("{path}:4: warning [desktop]: COPY --from=builder is neither an earlier stage nor a build context and will be pulled as an image"),
+            format!("{path}:5: warning [desktop]: RUN writes to /var/lib/sddm, which bootc only populates on the first installation; use /usr or systemd-tmpfiles"),
+        ]
+    );
+    assert_eq!(findings[1].severity, Severity::Error);
+
+    let error = discovery
+        .validate_stages(&stage_list(&["base", "desktop"]))
+        .unwrap_err()
+        .to_string();
+    assert!(error.starts_with("Containerfile lint failed"));
+    assert!(error.contains("Containerfile.desktop:1: error"));
+    assert!(!error.contains("COPY --from=builder"));
+}
+
+#[test]
+fn test_lint_allows_copy_from_bare_image_names() {
+    use trellis::trellis::lint::Severity;
+
+    let temp_dir = TempDir::new().unwrap();
+    fs::write(
+        temp_dir.path().join("Containerfile.base"),
+        "ARG BASE_IMAGE
+         FROM ${BASE_IMAGE} AS base
+         COPY --from=busybox /bin/busybox /usr/bin/
+         COPY --from=3 /out /usr/bin/
+",
+    )
+    .unwrap();
+
+    let config = create_discovery_config(&temp_dir);
+    let discovery = ContainerfileDiscovery::new(&config);
+
+    let findings = discovery.lint_stages(&stage_list(&["base"])).unwrap();
+    let severities: Vec<(usize, Severity)> = findings
+        .iter()
+        .map(|f| (f.line.unwrap(), f.severity))
+        .collect();
+    assert_eq!(
+        severities,
+        vec![(3, Severity::Warning), (4, Severity::Error)]
+    );
+
+    fs::write(
+        temp_dir.path().join("Containerfile.base"),
+        "ARG BASE_IMAGE
+         FROM ${BASE_IMAGE} AS base
+         COPY --from=busybox /bin/busybox /usr/bin/
+",
+    )
+    .unwrap();
+    let discovery = ContainerfileDiscovery::new(&config);
+    discovery.validate_stages(&stage_list(&["base"])).unwrap();
+}
+
+#[test]
+fn test_lint_checks_group_targets() {
+    let temp_dir = TempDir::new().unwrap();
+    fs::write(
+        temp_dir.path().join("Containerfile.gpu"),
+        "ARG BASE_IMAGE\n\
+         FROM ${BASE_IMAGE} AS nvidia\n\
+         FROM nvidia AS cuda\n\
+         COPY --from=nvidia /usr/lib/libcuda.so /usr/lib/\n",
+    )
+    .unwrap();
+
+    let config = create_discovery_config(&temp_dir);
+    let discovery = ContainerfileDiscovery::new(&config);
+
+    assert!(discovery
+        .lint_stages(&stage_list(&["gpu:nvidia", "gpu:cuda"]))
+        .unwrap()
+        .is_empty());
+
+    let error = discovery
+        .validate_stages(&stage_list(&["gpu:amd"]))
+        .unwrap_err()
+        .to_string();
+    assert!(error.contains("Target 'amd' not found. Stages in this file: nvidia, cuda"));
+}
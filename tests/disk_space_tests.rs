@@ -0,0 +1,171 @@
+//! Tests for DiskSpaceChecker functionality.
+//!
+//! Tests cover shortfalls against the real free space of the test's temporary
+//! directory, with image sizes reported by a mocked podman.
+
+mod common;
+
+use common::mocks::*;
+use std::sync::Arc;
+use tempfile::TempDir;
+use trellis::{
+    config::TrellisConfig,
+    trellis::{
+        builder::BuildType,
+        disk_space::{free_space, DiskSpaceChecker, SpaceRequirement, GIB},
+    },
+};
+
+fn create_disk_space_config(temp_dir: &TempDir) -> TrellisConfig {
+    TrellisConfig {
+        rootfs_base: "quay.io/archlinux/archlinux:latest".to_string(),
+        storage_dir: "/var/lib/containers/storage".into(),
+        ..common::test_config(temp_dir)
+    }
+}
+
+/// Reports `size` bytes for `image` and no other local image.
+fn create_image_size_mock(image: &'static str, size: u64) -> MockCommandExecutor {
+    let mut mock_executor = MockCommandExecutor::new();
+    mock_executor
+        .expect_podman_inspect()
+        .withf(|args: &[String]| args[..2] == ["--type", "image"])
+        .returning(move |args| {
+            if args[2] == image {
+                Ok(create_success_output(&format!(r#"[{{"Size": {size}}}]"#)))
+            } else {
+                Ok(create_failure_output("image not known"))
+            }
+        });
+    mock_executor
+}
+
+#[test]
+fn test_build_refuses_to_start_without_space_for_previous_image() {
+    let temp_dir = TempDir::new().unwrap();
+    let config = create_disk_space_config(&temp_dir);
+    let mock_executor = create_image_size_mock("localhost/test-rootfs", u64::MAX / 4);
+
+    let checker = DiskSpaceChecker::new(&config, Arc::new(mock_executor));
+    let error = checker
+        .check_build("test-rootfs", BuildType::Rootfs)
+        .unwrap_err()
+        .to_string();
+
+    assert!(
+        error.contains("Not enough free space for the build"),
+        "{error}"
+    );
+    assert!(error.contains("/var/lib/containers/storage"), "{error}");
+    assert!(error.contains("short"), "{error}");
+    assert!(error.contains("--force"), "{error}");
+}
+
+#[test]
+fn test_build_estimates_first_build_from_base_image() {
+    let temp_dir = TempDir::new().unwrap();
+    let config = create_disk_space_config(&temp_dir);
+    let mock_executor = create_image_size_mock("quay.io/archlinux/archlinux:latest", u64::MAX / 4);
+
+    let checker = DiskSpaceChecker::new(&config, Arc::new(mock_executor));
+
+    assert!(checker
+        .check_build("test-rootfs", BuildType::Rootfs)
+        .is_err());
+}
+
+#[test]
+fn test_build_without_estimate_checks_only_caches() {
+    let temp_dir = TempDir::new().unwrap();
+    let config = create_disk_space_config(&temp_dir);
+    let mock_executor = create_image_size_mock("localhost/other", u64::MAX / 4);
+
+    let checker = DiskSpaceChecker::new(&config, Arc::new(mock_executor));
+
+    assert!(checker
+        .check_build("test-builder", BuildType::Builder)
+        .is_ok());
+}
+
+#[test]
+fn test_force_starts_despite_shortfall() {
+    let temp_dir = TempDir::new().unwrap();
+    let mut config = create_disk_space_config(&temp_dir);
+    config.force = true;
+    let mock_executor = create_image_size_mock("localhost/test-rootfs", u64::MAX / 4);
+
+    let checker = DiskSpaceChecker::new(&config, Arc::new(mock_executor));
+
+    assert!(checker
+        .check_build("test-rootfs", BuildType::Rootfs)
+        .is_ok());
+}
+
+#[test]
+fn test_requirements_on_one_filesystem_add_up() {
+    let temp_dir = TempDir::new().unwrap();
+    let config = create_disk_space_config(&temp_dir);
+    let half = free_space(temp_dir.path()).unwrap().available / 2 + GIB;
+    let requirements = [
+        SpaceRequirement::new(temp_dir.path().join("storage"), half),
+        SpaceRequirement::new(temp_dir.path().join("cache"), half),
+    ];
+
+    let checker = DiskSpaceChecker::new(&config, Arc::new(MockCommandExecutor::new()));
+    let error = checker
+        .check("build", &requirements[..1])
+        .and_then(|()| checker.check("build", &requirements))
+        .unwrap_err()
+        .to_string();
+
+    assert!(error.contains("storage"), "{error}");
+    assert!(error.contains("cache"), "{error}");
+}
+
+#[test]
+fn test_image_refuses_to_start_without_space_for_image_file() {
+    let temp_dir = TempDir::new().unwrap();
+    let config = create_disk_space_config(&temp_dir);
+    let output_path = temp_dir.path().join("bootable.img");
+    let size_gb = free_space(temp_dir.path()).unwrap().available / GIB + 2;
+
+    let checker = DiskSpaceChecker::new(&config, Arc::new(MockCommandExecutor::new()));
+    let error = checker
+        .check_image(&output_path, size_gb)
+        .unwrap_err()
+        .to_string();
+
+    assert!(
+        error.contains("Not enough free space for the disk image"),
+        "{error}"
+    );
+    assert!(
+        error.contains(&temp_dir.path().display().to_string()),
+        "{error}"
+    );
+}
+
+#[test]
+fn test_image_reuses_existing_image_file() {
+    let temp_dir = TempDir::new().unwrap();
+    let config = create_disk_space_config(&temp_dir);
+    let output_path = temp_dir.path().join("bootable.img");
+    std::fs::write(&output_path, "").unwrap();
+    let size_gb = free_space(temp_dir.path()).unwrap().available / GIB + 2;
+
+    let checker = DiskSpaceChecker::new(&config, Arc::new(MockCommandExecutor::new()));
+
+    assert!(checker.check_image(&output_path, size_gb).is_ok());
+}
+
+#[test]
+fn test_dry_run_skips_checks() {
+    let temp_dir = TempDir::new().unwrap();
+    let mut config = create_disk_space_config(&temp_dir);
+    config.dry_run = true;
+    let output_path = temp_dir.path().join("bootable.img");
+
+    let checker = DiskSpaceChecker::new(&config, Arc::new(MockCommandExecutor::new()));
+
+    assert!(checker.check_image(&output_path, u64::MAX / GIB).is_ok());
+}
@@ -0,0 +1,244 @@
+//! Tests for the pluggable container engine.
+//!
+//! Tests cover selecting the engine, the commands each engine runs, and how builds
+//! adapt to or refuse features an engine does not support.
+
+mod common;
+
+use common::mocks::*;
+use std::sync::Arc;
+use tempfile::TempDir;
+use trellis::{
+    cli::{BuildArgs, Cli, Commands},
+    config::{EngineKind, Layering, PullPolicy, TrellisConfig},
+    trellis::{
+        builder::{BuildType, ContainerBuilder},
+        executor::{CommandExecutor, DryRunCommandExecutor},
+        image_generator::ImageGenerator,
+    },
+};
+
+fn create_engine_config(temp_dir: &TempDir, engine: EngineKind) -> TrellisConfig {
+    TrellisConfig {
+        engine,
+        ..common::test_config(temp_dir)
+    }
+}
+
+fn create_config_file_cli(temp_dir: &TempDir, contents: &str) -> Cli {
+    let config_path = temp_dir.path().join("trellis.toml");
+    std::fs::write(&config_path, contents).unwrap();
+    Cli {
+        command: Commands::Build(BuildArgs::default()),
+        builder_tag: "test-builder".to_string(),
+        podman_build_cache: None,
+        auto_clean: false,
+        incremental: None,
+        layering: None,
+        rechunk: None,
+        pacman_cache: None,
+        aur_cache: None,
+        stages_dir: Some(temp_dir.path().to_path_buf()),
+        extra_contexts: vec![],
+        extra_mounts: vec![],
+        secrets: vec![],
+        rootfs_stages: vec!["base".to_string()],
+        rootfs_base: "scratch".to_string(),
+        rootfs_tag: "test-rootfs".to_string(),
+        builder_stages: vec!["base".to_string()],
+        quiet: false,
+        config_path: Some(config_path),
+        skip_root_check: false,
+        profile: None,
+        dry_run: false,
+        wait: false,
+        no_wait: false,
+        force: false,
+    }
+}
+
+/// Builds the rootfs stage `base`, returning the arguments passed to the build.
+fn build_rootfs(config: &TrellisConfig) -> anyhow::Result<Vec<String>> {
+    let build_args = Arc::new(std::sync::Mutex::new(Vec::new()));
+    let recorded = Arc::clone(&build_args);
+    let mut mock_executor = MockCommandExecutor::new();
+    mock_executor
+        .expect_podman_inspect()
+        .returning(|_| Ok(create_failure_output("image not known")));
+    mock_executor
+        .expect_podman_build_streaming()
+        .returning(move |args| {
+            *recorded.lock().unwrap() = args.to_vec();
+            Ok(create_success_status())
+        });
+
+    let builder = ContainerBuilder::new(config, Arc::new(mock_executor));
+    let stages = vec!["base".to_string()];
+    builder.build_multistage_container("stage", "test-rootfs", &stages, BuildType::Rootfs)?;
+    let args = build_args.lock().unwrap().clone();
+    Ok(args)
+}
+
+#[test]
+fn test_engine_selected_in_config_file() {
+    let temp_dir = TempDir::new().unwrap();
+    let cli = create_config_file_cli(&temp_dir, "[engine]\nkind = \"buildah\"\n");
+    assert_eq!(TrellisConfig::new(cli).unwrap().engine, EngineKind::Buildah);
+
+    let cli = create_config_file_cli(&temp_dir, "");
+    assert_eq!(TrellisConfig::new(cli).unwrap().engine, EngineKind::Podman);
+
+    let cli = create_config_file_cli(&temp_dir, "[engine]\nkind = \"lxc\"\n");
+    assert!(TrellisConfig::new(cli).is_err());
+}
+
+#[test]
+fn test_dry_run_records_engine_command_lines() {
+    let args = |args: &[&str]| args.iter().map(|arg| arg.to_string()).collect::<Vec<_>>();
+
+    let docker = DryRunCommandExecutor::with_engine(EngineKind::Docker);
+    docker
+        .podman_build(&args(&["--net", "host", "--layers=false", "-t", "x"]))
+        .unwrap();
+    docker
+        .podman_command(&args(&["image", "exists", "localhost/x"]))
+        .unwrap();
+    assert_eq!(
+        docker.commands(),
+        [
+            "docker build --network host -t x",
+            "docker image inspect --format '{{.Id}}' localhost/x",
+        ]
+    );
+
+    let buildah = DryRunCommandExecutor::with_engine(EngineKind::Buildah);
+    buildah
+        .podman_build(&args(&["--squash-all", "-t", "x"]))
+        .unwrap();
+    buildah.podman_command(&args(&["tag", "x", "y"])).unwrap();
+    assert_eq!(
+        buildah.commands(),
+        ["buildah build --squash -t x", "podman tag x y"]
+    );
+}
+
+#[test]
+fn test_podman_build_keeps_squash_and_security_options() {
+    let temp_dir = TempDir::new().unwrap();
+    common::setup_test_containerfiles(&temp_dir, &["base"]);
+    let config = create_engine_config(&temp_dir, EngineKind::Podman);
+
+    let args = build_rootfs(&config).unwrap();
+
+    assert!(args.contains(&"--squash".to_string()), "{args:?}");
+    assert!(args.contains(&"--cap-add".to_string()), "{args:?}");
+}
+
+#[test]
+fn test_buildah_build_falls_back_to_layered_for_squash() {
+    let temp_dir = TempDir::new().unwrap();
+    common::setup_test_containerfiles(&temp_dir, &["base"]);
+    let mut config = create_engine_config(&temp_dir, EngineKind::Buildah);
+
+    let args = build_rootfs(&config).unwrap();
+    assert!(
+        !args.iter().any(|arg| arg.starts_with("--squash")),
+        "{args:?}"
+    );
+
+    config.layering = Layering::SquashAll;
+    let args = build_rootfs(&config).unwrap();
+    assert!(args.contains(&"--squash-all".to_string()), "{args:?}");
+}
+
+#[test]
+fn test_docker_build_leaves_out_unsupported_options() {
+    let temp_dir = TempDir::new().unwrap();
+    common::setup_test_containerfiles(&temp_dir, &["base"]);
+    let mut config = create_engine_config(&temp_dir, EngineKind::Docker);
+    config.pacman_cache = Some(temp_dir.path().join("pacman"));
+
+    let args = build_rootfs(&config).unwrap();
+
+    assert!(!args.contains(&"--squash".to_string()), "{args:?}");
+    assert!(!args.contains(&"--cap-add".to_string()), "{args:?}");
+    assert!(!args.contains(&"-v".to_string()), "{args:?}");
+    assert!(args.contains(&"--net".to_string()), "{args:?}");
+}
+
+#[test]
+fn test_docker_build_refuses_mounts() {
+    let temp_dir = TempDir::new().unwrap();
+    common::setup_test_containerfiles(&temp_dir, &["base"]);
+    let mut config = create_engine_config(&temp_dir, EngineKind::Docker);
+    config.extra_mounts = vec![temp_dir.path().join("mnt")];
+
+    let mut mock_executor = MockCommandExecutor::new();
+    mock_executor
+        .expect_podman_inspect()
+        .returning(|_| Ok(create_failure_output("image not known")));
+    mock_executor.expect_podman_build_streaming().times(0);
+
+    let builder = ContainerBuilder::new(&config, Arc::new(mock_executor));
+    let stages = vec!["base".to_string()];
+    let error = builder
+        .build_multistage_container("stage", "test-rootfs", &stages, BuildType::Rootfs)
+        .unwrap_err()
+        .to_string();
+
+    assert!(
+        error.contains("The docker engine does not support volumes in builds"),
+        "{error}"
+    );
+}
+
+#[test]
+fn test_docker_build_refuses_pull_never() {
+    let temp_dir = TempDir::new().unwrap();
+    common::setup_test_containerfiles(&temp_dir, &["base"]);
+    let mut config = create_engine_config(&temp_dir, EngineKind::Docker);
+    config.pull_policy = Some(PullPolicy::Never);
+
+    let mut mock_executor = MockCommandExecutor::new();
+    mock_executor
+        .expect_podman_inspect()
+        .returning(|_| Ok(create_failure_output("image not known")));
+    mock_executor.expect_podman_build_streaming().times(0);
+
+    let builder = ContainerBuilder::new(&config, Arc::new(mock_executor));
+    let stages = vec!["base".to_string()];
+    let error = builder
+        .build_multistage_container("stage", "test-rootfs", &stages, BuildType::Rootfs)
+        .unwrap_err()
+        .to_string();
+
+    assert!(
+        error.contains("The docker engine does not support pull_policy = \"never\""),
+        "{error}"
+    );
+}
+
+#[test]
+fn test_docker_refuses_operations_on_containers_storage() {
+    let temp_dir = TempDir::new().unwrap();
+    common::setup_test_containerfiles(&temp_dir, &["base"]);
+    let mut config = create_engine_config(&temp_dir, EngineKind::Docker);
+    config.rechunk = true;
+
+    let error = build_rootfs(&config).unwrap_err().to_string();
+    assert!(
+        error.contains("`rechunk = true` needs the image in containers-storage"),
+        "{error}"
+    );
+
+    let output_path = temp_dir.path().join("bootable.img");
+    let generator = ImageGenerator::new(&config, Arc::new(MockCommandExecutor::new()));
+    let error = generator
+        .generate_bootable_image("test-rootfs", &output_path, "ext4", Some(4), None)
+        .unwrap_err()
+        .to_string();
+    assert!(
+        error.contains("`trls image` needs the image in containers-storage"),
+        "{error}"
+    );
+}
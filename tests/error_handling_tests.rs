@@ -8,7 +8,7 @@ use common::mocks::*;
 use std::{fs, sync::Arc};
 use tempfile::TempDir;
 use trellis::{
-    cli::{Cli, Commands},
+    cli::{BuildArgs, Cli, Commands},
     config::TrellisConfig,
     trellis::{
         builder::{BuildType, ContainerBuilder},
@@ -26,16 +26,44 @@ fn create_error_test_config(temp_dir: &TempDir) -> TrellisConfig {
         builder_tag: "test-builder".to_string(),
         podman_build_cache: false,
         auto_clean: false,
+        incremental: false,
+        preprocess: false,
+        layering: Default::default(),
+        rechunk: false,
+        rechunk_image: "quay.io/fedora/fedora-bootc:latest".to_string(),
         pacman_cache: None,
         aur_cache: None,
         stages_dir: temp_dir.path().to_path_buf(),
+        sources: Vec::new(),
+        sources_dir: temp_dir.path().join("sources"),
         rootfs_stages: vec!["base".to_string()],
         rootfs_base: "scratch".to_string(),
+        pull_policy: None,
         extra_contexts: vec![],
         extra_mounts: vec![],
+        secrets: Default::default(),
         rootfs_tag: "test-rootfs".to_string(),
+        keep_versions: 5,
         hooks_dir: None,
+        state_dir: temp_dir.path().join("state"),
+        log_dir: temp_dir.path().join("logs"),
+        log_retention: 10,
+        lock_dir: Default::default(),
+        rootless: false,
+        storage_dir: Default::default(),
+        privilege_command: None,
+        engine: Default::default(),
+        podman_socket: None,
         quiet: false,
+        profile: None,
+        config_path: Default::default(),
+        stage_configs: Default::default(),
+        vars: Default::default(),
+        security: Default::default(),
+        registry: None,
+        dry_run: false,
+        wait: false,
+        force: false,
     }
 }
 
@@ -73,9 +101,9 @@ fn test_cleaner_with_malformed_image_output() {
     let executor = Arc::new(mock);
     let cleaner = ImageCleaner::new(&config, executor);
 
-    // Should handle malformed output gracefully
-    let result = cleaner.clean_all();
-    assert!(result.is_ok());
+    // Malformed output is reported instead of cleaning nothing
+    let error = cleaner.clean_all().unwrap_err();
+    assert!(format!("{error:#}").contains("Failed to parse image list"));
 }
 
 #[test]
@@ -138,15 +166,19 @@ fn test_app_with_corrupted_config() {
     fs::write(&config_path, "invalid toml [[[").unwrap();
 
     let cli = Cli {
-        command: Commands::Build,
+        command: Commands::Build(BuildArgs::default()),
         builder_tag: "test".to_string(),
         podman_build_cache: None,
         auto_clean: false,
+        incremental: None,
+        layering: None,
+        rechunk: None,
         pacman_cache: None,
         aur_cache: None,
         stages_dir: Some(temp_dir.path().to_path_buf()),
         extra_contexts: vec![],
         extra_mounts: vec![],
+        secrets: vec![],
         rootfs_stages: vec!["base".to_string()],
         rootfs_base: "scratch".to_string(),
         rootfs_tag: "test-rootfs".to_string(),
@@ -154,6 +186,11 @@ fn test_app_with_corrupted_config() {
         quiet: false,
         config_path: Some(config_path),
         skip_root_check: false,
+        profile: None,
+        dry_run: false,
+        wait: false,
+        no_wait: false,
+        force: false,
     };
 
     let result = TrellisApp::new(cli);
@@ -178,6 +215,8 @@ fn test_trellis_with_all_operations_failing() {
     let config = create_error_test_config(&temp_dir);
 
     let mut mock = MockCommandExecutor::new();
+    mock.expect_podman_inspect()
+        .returning(|_| Err(anyhow::anyhow!("Inspect failed")));
     mock.expect_podman_build_streaming()
         .returning(|_| Err(anyhow::anyhow!("Build failed")));
     mock.expect_podman_run_streaming()
@@ -282,7 +321,7 @@ fn test_cleaner_with_special_character_image_names() {
     ];
 
     let mut mock = MockCommandExecutor::new();
-    let images_output = format_special_images(&special_images);
+    let images_output = format_images_json(&special_images);
     mock.expect_podman_images()
         .returning(move |_| Ok(create_success_output(&images_output)));
     mock.expect_podman_rmi()
@@ -331,15 +370,19 @@ fn test_app_with_invalid_stages_directory() {
     );
 
     let cli = Cli {
-        command: Commands::Build,
+        command: Commands::Build(BuildArgs::default()),
         builder_tag: "test".to_string(),
         podman_build_cache: None,
         auto_clean: false,
+        incremental: None,
+        layering: None,
+        rechunk: None,
         pacman_cache: None,
         aur_cache: None,
         stages_dir: Some(invalid_path.clone()),
         extra_contexts: vec![],
         extra_mounts: vec![],
+        secrets: vec![],
         rootfs_stages: vec!["base".to_string()],
         rootfs_base: "scratch".to_string(),
         rootfs_tag: "test-rootfs".to_string(),
@@ -347,6 +390,11 @@ fn test_app_with_invalid_stages_directory() {
         quiet: false,
         config_path: None,
         skip_root_check: false,
+        profile: None,
+        dry_run: false,
+        wait: false,
+        no_wait: false,
+        force: false,
     };
 
     let result = TrellisApp::new(cli);
@@ -374,6 +422,8 @@ fn test_error_propagation_chain() {
     let config = create_error_test_config(&temp_dir);
 
     let mut mock = MockCommandExecutor::new();
+    mock.expect_podman_inspect()
+        .returning(|_| Ok(create_failure_output("image not known")));
     mock.expect_podman_build_streaming()
         .returning(|_| Err(anyhow::anyhow!("Low level error").context("Mid level context")));
 
@@ -424,11 +474,3 @@ fn test_resource_cleanup_on_early_exit() {
         .to_string()
         .contains("Missing required containerfiles"));
 }
-
-fn format_special_images(images: &[MockImageInfo]) -> String {
-    let mut output = String::new();
-    for image in images {
-        output.push_str(&format!("{}:{}\n", image.repository, image.tag));
-    }
-    output
-}
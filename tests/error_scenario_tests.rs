@@ -4,7 +4,7 @@ use common::*;
 use std::fs;
 use tempfile::TempDir;
 use trellis::{
-    cli::{Cli, Commands},
+    cli::{BuildArgs, Cli, Commands},
     config::TrellisConfig,
     trellis::{
         builder::ContainerBuilder, cleaner::ImageCleaner, discovery::ContainerfileDiscovery,
@@ -14,15 +14,19 @@ use trellis::{
 
 fn create_minimal_cli() -> Cli {
     Cli {
-        command: Commands::Build,
+        command: Commands::Build(BuildArgs::default()),
         builder_tag: "test-builder".to_string(),
         podman_build_cache: None,
         auto_clean: false,
+        incremental: None,
+        layering: None,
+        rechunk: None,
         pacman_cache: None,
         aur_cache: None,
         stages_dir: None,
         extra_contexts: vec![],
         extra_mounts: vec![],
+        secrets: vec![],
         rootfs_stages: vec![],
         rootfs_base: "scratch".to_string(),
         rootfs_tag: "test-rootfs".to_string(),
@@ -30,6 +34,11 @@ fn create_minimal_cli() -> Cli {
         quiet: false,
         config_path: None,
         skip_root_check: false,
+        profile: None,
+        dry_run: false,
+        wait: false,
+        no_wait: false,
+        force: false,
     }
 }
 
@@ -41,16 +50,44 @@ fn test_missing_containerfile_error() {
         builder_tag: "test-builder".to_string(),
         podman_build_cache: false,
         auto_clean: false,
+        incremental: false,
+        preprocess: false,
+        layering: Default::default(),
+        rechunk: false,
+        rechunk_image: "quay.io/fedora/fedora-bootc:latest".to_string(),
         pacman_cache: None,
         aur_cache: None,
         stages_dir: temp_dir.path().to_path_buf(),
+        sources: Vec::new(),
+        sources_dir: temp_dir.path().join("sources"),
         rootfs_stages: vec![],
         rootfs_base: "scratch".to_string(),
+        pull_policy: None,
         extra_contexts: vec![],
         extra_mounts: vec![],
+        secrets: Default::default(),
         rootfs_tag: "test-rootfs".to_string(),
+        keep_versions: 5,
         hooks_dir: None,
+        state_dir: temp_dir.path().join("state"),
+        log_dir: temp_dir.path().join("logs"),
+        log_retention: 10,
+        lock_dir: Default::default(),
+        rootless: false,
+        storage_dir: Default::default(),
+        privilege_command: None,
+        engine: Default::default(),
+        podman_socket: None,
         quiet: false,
+        profile: None,
+        config_path: Default::default(),
+        stage_configs: Default::default(),
+        vars: Default::default(),
+        security: Default::default(),
+        registry: None,
+        dry_run: false,
+        wait: false,
+        force: false,
     };
 
     let discovery = ContainerfileDiscovery::new(&config);
@@ -113,16 +150,44 @@ fn test_nonexistent_cache_directory_parent() {
         builder_tag: "test-builder".to_string(),
         podman_build_cache: false,
         auto_clean: false,
+        incremental: false,
+        preprocess: false,
+        layering: Default::default(),
+        rechunk: false,
+        rechunk_image: "quay.io/fedora/fedora-bootc:latest".to_string(),
         pacman_cache: Some(nonexistent_cache),
         aur_cache: None,
         stages_dir: temp_dir.path().to_path_buf(),
+        sources: Vec::new(),
+        sources_dir: temp_dir.path().join("sources"),
         rootfs_stages: vec!["base".to_string()],
         rootfs_base: "scratch".to_string(),
+        pull_policy: None,
         extra_contexts: vec![],
         extra_mounts: vec![],
+        secrets: Default::default(),
         rootfs_tag: "test-rootfs".to_string(),
+        keep_versions: 5,
         hooks_dir: None,
+        state_dir: temp_dir.path().join("state"),
+        log_dir: temp_dir.path().join("logs"),
+        log_retention: 10,
+        lock_dir: Default::default(),
+        rootless: false,
+        storage_dir: Default::default(),
+        privilege_command: None,
+        engine: Default::default(),
+        podman_socket: None,
         quiet: false,
+        profile: None,
+        config_path: Default::default(),
+        stage_configs: Default::default(),
+        vars: Default::default(),
+        security: Default::default(),
+        registry: None,
+        dry_run: false,
+        wait: false,
+        force: false,
     };
 
     // This test validates that the cache directory creation logic
@@ -157,16 +222,44 @@ fn test_containerfile_discovery_with_symlinks() {
         builder_tag: "test-builder".to_string(),
         podman_build_cache: false,
         auto_clean: false,
+        incremental: false,
+        preprocess: false,
+        layering: Default::default(),
+        rechunk: false,
+        rechunk_image: "quay.io/fedora/fedora-bootc:latest".to_string(),
         pacman_cache: None,
         aur_cache: None,
         stages_dir: temp_dir.path().to_path_buf(),
+        sources: Vec::new(),
+        sources_dir: temp_dir.path().join("sources"),
         rootfs_stages: vec![],
         rootfs_base: "scratch".to_string(),
+        pull_policy: None,
         extra_contexts: vec![],
         extra_mounts: vec![],
+        secrets: Default::default(),
         rootfs_tag: "test-rootfs".to_string(),
+        keep_versions: 5,
         hooks_dir: None,
+        state_dir: temp_dir.path().join("state"),
+        log_dir: temp_dir.path().join("logs"),
+        log_retention: 10,
+        lock_dir: Default::default(),
+        rootless: false,
+        storage_dir: Default::default(),
+        privilege_command: None,
+        engine: Default::default(),
+        podman_socket: None,
         quiet: false,
+        profile: None,
+        config_path: Default::default(),
+        stage_configs: Default::default(),
+        vars: Default::default(),
+        security: Default::default(),
+        registry: None,
+        dry_run: false,
+        wait: false,
+        force: false,
     };
 
     let discovery = ContainerfileDiscovery::new(&config);
@@ -189,16 +282,44 @@ fn test_stage_validation_with_missing_files() {
         builder_tag: "test-builder".to_string(),
         podman_build_cache: false,
         auto_clean: false,
+        incremental: false,
+        preprocess: false,
+        layering: Default::default(),
+        rechunk: false,
+        rechunk_image: "quay.io/fedora/fedora-bootc:latest".to_string(),
         pacman_cache: None,
         aur_cache: None,
         stages_dir: temp_dir.path().to_path_buf(),
+        sources: Vec::new(),
+        sources_dir: temp_dir.path().join("sources"),
         rootfs_stages: vec![],
         rootfs_base: "scratch".to_string(),
+        pull_policy: None,
         extra_contexts: vec![],
         extra_mounts: vec![],
+        secrets: Default::default(),
         rootfs_tag: "test-rootfs".to_string(),
+        keep_versions: 5,
         hooks_dir: None,
+        state_dir: temp_dir.path().join("state"),
+        log_dir: temp_dir.path().join("logs"),
+        log_retention: 10,
+        lock_dir: Default::default(),
+        rootless: false,
+        storage_dir: Default::default(),
+        privilege_command: None,
+        engine: Default::default(),
+        podman_socket: None,
         quiet: false,
+        profile: None,
+        config_path: Default::default(),
+        stage_configs: Default::default(),
+        vars: Default::default(),
+        security: Default::default(),
+        registry: None,
+        dry_run: false,
+        wait: false,
+        force: false,
     };
 
     let discovery = ContainerfileDiscovery::new(&config);
@@ -255,16 +376,44 @@ fn test_readonly_cache_directory() {
         builder_tag: "test-builder".to_string(),
         podman_build_cache: false,
         auto_clean: false,
+        incremental: false,
+        preprocess: false,
+        layering: Default::default(),
+        rechunk: false,
+        rechunk_image: "quay.io/fedora/fedora-bootc:latest".to_string(),
         pacman_cache: Some(cache_dir.clone()),
         aur_cache: None,
         stages_dir: temp_dir.path().to_path_buf(),
+        sources: Vec::new(),
+        sources_dir: temp_dir.path().join("sources"),
         rootfs_stages: vec!["base".to_string()],
         rootfs_base: "scratch".to_string(),
+        pull_policy: None,
         extra_contexts: vec![],
         extra_mounts: vec![],
+        secrets: Default::default(),
         rootfs_tag: "test-rootfs".to_string(),
+        keep_versions: 5,
         hooks_dir: None,
+        state_dir: temp_dir.path().join("state"),
+        log_dir: temp_dir.path().join("logs"),
+        log_retention: 10,
+        lock_dir: Default::default(),
+        rootless: false,
+        storage_dir: Default::default(),
+        privilege_command: None,
+        engine: Default::default(),
+        podman_socket: None,
         quiet: false,
+        profile: None,
+        config_path: Default::default(),
+        stage_configs: Default::default(),
+        vars: Default::default(),
+        security: Default::default(),
+        registry: None,
+        dry_run: false,
+        wait: false,
+        force: false,
     };
 
     // The builder should detect the readonly cache directory
@@ -291,16 +440,44 @@ fn test_image_filtering_logic() {
         builder_tag: "custom-builder".to_string(),
         podman_build_cache: false,
         auto_clean: false,
+        incremental: false,
+        preprocess: false,
+        layering: Default::default(),
+        rechunk: false,
+        rechunk_image: "quay.io/fedora/fedora-bootc:latest".to_string(),
         pacman_cache: None,
         aur_cache: None,
         stages_dir: std::path::PathBuf::from("/tmp"),
+        sources: Vec::new(),
+        sources_dir: std::path::PathBuf::from("/var/cache/trellis/sources"),
         rootfs_stages: vec![],
         rootfs_base: "scratch".to_string(),
+        pull_policy: None,
         extra_contexts: vec![],
         extra_mounts: vec![],
+        secrets: Default::default(),
         rootfs_tag: "custom-rootfs".to_string(),
+        keep_versions: 5,
         hooks_dir: None,
+        state_dir: std::env::temp_dir().join("trellis-test-state"),
+        log_dir: std::env::temp_dir().join("trellis-test-logs"),
+        log_retention: 10,
+        lock_dir: Default::default(),
+        rootless: false,
+        storage_dir: Default::default(),
+        privilege_command: None,
+        engine: Default::default(),
+        podman_socket: None,
         quiet: false,
+        profile: None,
+        config_path: Default::default(),
+        stage_configs: Default::default(),
+        vars: Default::default(),
+        security: Default::default(),
+        registry: None,
+        dry_run: false,
+        wait: false,
+        force: false,
     };
 
     let executor = std::sync::Arc::new(RealCommandExecutor::new());
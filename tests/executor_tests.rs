@@ -283,3 +283,62 @@ mod argument_handling_tests {
         let _ = executor.execute("echo", &args);
     }
 }
+
+#[cfg(test)]
+mod dry_run_command_executor_tests {
+    use trellis::trellis::executor::{CommandExecutor, DryRunCommandExecutor};
+
+    #[test]
+    fn test_records_commands_in_order() {
+        let executor = DryRunCommandExecutor::new();
+        executor
+            .podman_build(&["--tag".to_string(), "test".to_string()])
+            .unwrap();
+        executor
+            .execute(
+                "mount",
+                &["/dev/loop0p3".to_string(), "/tmp/my mount".to_string()],
+            )
+            .unwrap();
+        executor.bootc(&["upgrade".to_string()]).unwrap();
+
+        assert_eq!(
+            executor.commands(),
+            vec![
+                "podman build --tag test",
+                "mount /dev/loop0p3 '/tmp/my mount'",
+                "bootc upgrade",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_synthesizes_successful_answers() {
+        let executor = DryRunCommandExecutor::new();
+
+        let images = executor
+            .podman_images(&[
+                "--filter".to_string(),
+                "reference=localhost/builder".to_string(),
+            ])
+            .unwrap();
+        assert_eq!(
+            String::from_utf8_lossy(&images.stdout).trim(),
+            "localhost/builder:latest"
+        );
+
+        let losetup = executor
+            .execute("losetup", &["--find".to_string(), "--show".to_string()])
+            .unwrap();
+        assert!(losetup.status.success());
+        assert!(!losetup.stdout.is_empty());
+
+        assert!(executor
+            .podman_build_streaming(&["--tag".to_string(), "test".to_string()])
+            .unwrap()
+            .success());
+        assert!(executor
+            .check_command_in_container("builder", "pacstrap")
+            .unwrap());
+    }
+}
@@ -15,16 +15,44 @@ fn create_cleaner_config(temp_dir: &TempDir) -> TrellisConfig {
         builder_tag: "test-builder".to_string(),
         podman_build_cache: false,
         auto_clean: false,
+        incremental: false,
+        preprocess: false,
+        layering: Default::default(),
+        rechunk: false,
+        rechunk_image: "quay.io/fedora/fedora-bootc:latest".to_string(),
         pacman_cache: None,
         aur_cache: None,
         stages_dir: temp_dir.path().to_path_buf(),
+        sources: Vec::new(),
+        sources_dir: temp_dir.path().join("sources"),
         rootfs_stages: vec!["base".to_string()],
         rootfs_base: "scratch".to_string(),
+        pull_policy: None,
         extra_contexts: vec![],
         extra_mounts: vec![],
+        secrets: Default::default(),
         rootfs_tag: "test-rootfs".to_string(),
+        keep_versions: 5,
         hooks_dir: None,
+        state_dir: temp_dir.path().join("state"),
+        log_dir: temp_dir.path().join("logs"),
+        log_retention: 10,
+        lock_dir: Default::default(),
+        rootless: false,
+        storage_dir: Default::default(),
+        privilege_command: None,
+        engine: Default::default(),
+        podman_socket: None,
         quiet: false,
+        profile: None,
+        config_path: Default::default(),
+        stage_configs: Default::default(),
+        vars: Default::default(),
+        security: Default::default(),
+        registry: None,
+        dry_run: false,
+        wait: false,
+        force: false,
     }
 }
 
@@ -132,7 +160,7 @@ fn test_clean_with_rmi_command_failure() {
         MockImageInfo::new("abc123", "localhost/test-builder", "latest"),
         MockImageInfo::new("def456", "localhost/trellis-stage-base", "latest"),
     ];
-    let images_output = format_images_output(&images);
+    let images_output = format_images_json(&images);
 
     mock_executor
         .expect_podman_images()
@@ -168,7 +196,7 @@ fn test_image_filtering_trellis_images() {
         MockImageInfo::new("jkl012", "localhost/trellis-stage-tools", "latest"),
         MockImageInfo::new("mno345", "docker.io/ubuntu", "latest"), // Non-trellis image
     ];
-    let images_output = format_images_output(&images);
+    let images_output = format_images_json(&images);
 
     let mut mock_executor = MockCommandExecutor::new();
     mock_executor
@@ -197,7 +225,7 @@ fn test_image_filtering_non_trellis_images() {
         MockImageInfo::new("def456", "quay.io/fedora", "39"),
         MockImageInfo::new("ghi789", "registry.example.com/app", "v1.0"),
     ];
-    let images_output = format_images_output(&images);
+    let images_output = format_images_json(&images);
 
     let mut mock_executor = MockCommandExecutor::new();
     mock_executor
@@ -224,7 +252,7 @@ fn test_custom_builder_and_rootfs_tags() {
         MockImageInfo::new("def456", "localhost/custom-rootfs", "latest"),
         MockImageInfo::new("ghi789", "localhost/trellis-stage-base", "latest"),
     ];
-    let images_output = format_images_output(&images);
+    let images_output = format_images_json(&images);
 
     let mut mock_executor = MockCommandExecutor::new();
     mock_executor
@@ -255,7 +283,7 @@ fn test_batch_image_removal() {
         MockImageInfo::new("jkl012", "localhost/trellis-stage-tools", "latest"),
         MockImageInfo::new("mno345", "localhost/trellis-stage-final", "latest"),
     ];
-    let images_output = format_images_output(&images);
+    let images_output = format_images_json(&images);
 
     let mut mock_executor = MockCommandExecutor::new();
     mock_executor
@@ -286,7 +314,7 @@ fn test_clean_mode_full_vs_auto() {
         MockImageInfo::new("def456", "localhost/test-rootfs", "latest"),
         MockImageInfo::new("ghi789", "localhost/trellis-stage-intermediate", "latest"),
     ];
-    let images_output = format_images_output(&images);
+    let images_output = format_images_json(&images);
 
     let mut mock_executor = MockCommandExecutor::new();
     mock_executor
@@ -315,11 +343,9 @@ fn test_empty_image_list() {
     let config = create_cleaner_config(&temp_dir);
 
     let mut mock_executor = MockCommandExecutor::new();
-    mock_executor.expect_podman_images().returning(|_| {
-        Ok(create_success_output(
-            "REPOSITORY\tTAG\tIMAGE ID\tCREATED\tSIZE\n",
-        ))
-    });
+    mock_executor
+        .expect_podman_images()
+        .returning(|_| Ok(create_success_output("[]\n")));
 
     let executor = Arc::new(mock_executor);
     let cleaner = ImageCleaner::new(&config, executor);
@@ -343,8 +369,8 @@ fn test_malformed_image_list() {
     let executor = Arc::new(mock_executor);
     let cleaner = ImageCleaner::new(&config, executor);
 
-    let result = cleaner.clean_all();
-    assert!(result.is_ok()); // Should handle gracefully
+    let error = cleaner.clean_all().unwrap_err();
+    assert!(format!("{error:#}").contains("Failed to parse image list"));
 }
 
 #[test]
@@ -368,14 +394,6 @@ fn test_images_command_stderr_output() {
         .contains("Failed to list images"));
 }
 
-fn format_images_output(images: &[MockImageInfo]) -> String {
-    let mut output = String::new();
-    for image in images {
-        output.push_str(&format!("{}:{}\n", image.repository, image.tag));
-    }
-    output
-}
-
 #[test]
 fn test_batch_removal_with_fallback_to_individual() {
     let temp_dir = TempDir::new().unwrap();
@@ -386,7 +404,7 @@ fn test_batch_removal_with_fallback_to_individual() {
         MockImageInfo::new("abc123", "localhost/test-builder", "latest"),
         MockImageInfo::new("def456", "localhost/trellis-stage-base", "latest"),
     ];
-    let images_output = format_images_output(&images);
+    let images_output = format_images_json(&images);
 
     let mut mock_executor = MockCommandExecutor::new();
     mock_executor
@@ -427,7 +445,7 @@ fn test_single_image_removal_success() {
         "localhost/trellis-stage-base",
         "latest",
     )];
-    let images_output = format_images_output(&images);
+    let images_output = format_images_json(&images);
 
     let mut mock_executor = MockCommandExecutor::new();
     mock_executor
@@ -457,7 +475,7 @@ fn test_single_image_removal_failure() {
         "localhost/trellis-stage-base",
         "latest",
     )];
-    let images_output = format_images_output(&images);
+    let images_output = format_images_json(&images);
 
     let mut mock_executor = MockCommandExecutor::new();
     mock_executor
@@ -486,7 +504,7 @@ fn test_batch_removal_command_error_fallback() {
         MockImageInfo::new("abc123", "localhost/test-builder", "latest"),
         MockImageInfo::new("def456", "localhost/trellis-stage-base", "latest"),
     ];
-    let images_output = format_images_output(&images);
+    let images_output = format_images_json(&images);
 
     let mut mock_executor = MockCommandExecutor::new();
     mock_executor
@@ -529,7 +547,7 @@ fn test_clean_mode_auto_preserves_final_tags() {
         MockImageInfo::new("ghi789", "localhost/trellis-stage-intermediate", "latest"), // Should be removed
         MockImageInfo::new("jkl012", "localhost/trellis-builder-temp", "latest"), // Should be removed
     ];
-    let images_output = format_images_output(&images);
+    let images_output = format_images_json(&images);
 
     let mut mock_executor = MockCommandExecutor::new();
     mock_executor
@@ -552,3 +570,137 @@ fn test_clean_mode_auto_preserves_final_tags() {
     let result = cleaner.auto_clean();
     assert!(result.is_ok());
 }
+
+#[test]
+fn test_auto_clean_preserves_content_keyed_images_when_incremental() {
+    let temp_dir = TempDir::new().unwrap();
+    let mut config = create_cleaner_config(&temp_dir);
+    config.auto_clean = true;
+    config.incremental = true;
+
+    let mut mock_executor = MockCommandExecutor::new();
+    let images_output = format_images_json(&[
+        MockImageInfo::new("abc123", "localhost/trellis-stage-base", "latest"),
+        MockImageInfo::new("def456", "localhost/trellis-stage-base", "0123456789abcdef"),
+    ]);
+    mock_executor
+        .expect_podman_images()
+        .returning(move |_| Ok(create_success_output(&images_output)));
+    mock_executor
+        .expect_podman_rmi()
+        .times(1)
+        .withf(|args: &[String]| {
+            args.contains(&"localhost/trellis-stage-base:latest".to_string())
+                && !args.contains(&"localhost/trellis-stage-base:0123456789abcdef".to_string())
+        })
+        .returning(|_| Ok(create_success_output("")));
+
+    let cleaner = ImageCleaner::new(&config, Arc::new(mock_executor));
+    assert!(cleaner.auto_clean().is_ok());
+}
+
+/// Lists four versions of the rootfs image, with `latest` pointing at the oldest.
+fn versioned_images_output() -> String {
+    r#"[
+        {"Id": "aaaa00000001", "Names": ["localhost/test-rootfs:latest", "localhost/test-rootfs:20240101-100000-0000000a"], "Created": 1704103200},
+        {"Id": "dddd00000004", "Names": ["localhost/test-rootfs:20240104-100000-0000000d"], "Created": 1704362400},
+        {"Id": "cccc00000003", "Names": ["localhost/test-rootfs:20240103-100000-0000000c"], "Created": 1704276000},
+        {"Id": "bbbb00000002", "Names": ["localhost/test-rootfs:20240102-100000-0000000b"], "Created": 1704189600},
+        {"Id": "eeee00000005", "Names": ["localhost/test-rootfs:testing"], "Created": 1704448800}
+    ]"#
+    .to_string()
+}
+
+#[test]
+fn test_prune_versions_keeps_newest_and_latest() {
+    let temp_dir = TempDir::new().unwrap();
+    let mut config = create_cleaner_config(&temp_dir);
+    config.keep_versions = 2;
+
+    let mut mock_executor = MockCommandExecutor::new();
+    mock_executor
+        .expect_podman_images()
+        .returning(|_| Ok(create_success_output(&versioned_images_output())));
+    mock_executor
+        .expect_podman_rmi()
+        .times(1)
+        .withf(|args: &[String]| args == ["-f", "localhost/test-rootfs:20240102-100000-0000000b"])
+        .returning(|_| Ok(create_success_output("")));
+
+    let cleaner = ImageCleaner::new(&config, Arc::new(mock_executor));
+    assert!(cleaner.prune_versions().is_ok());
+}
+
+#[test]
+fn test_auto_clean_preserves_versions() {
+    let temp_dir = TempDir::new().unwrap();
+    let mut config = create_cleaner_config(&temp_dir);
+    config.auto_clean = true;
+
+    let mut mock_executor = MockCommandExecutor::new();
+    let images_output = format_images_json(&[
+        MockImageInfo::new("abc123", "localhost/trellis-stage-base", "latest"),
+        MockImageInfo::new(
+            "def456",
+            "localhost/test-rootfs",
+            "20240101-100000-0000000a",
+        ),
+    ]);
+    mock_executor
+        .expect_podman_images()
+        .returning(move |_| Ok(create_success_output(&images_output)));
+    mock_executor
+        .expect_podman_rmi()
+        .times(1)
+        .withf(|args: &[String]| args == ["-f", "localhost/trellis-stage-base:latest"])
+        .returning(|_| Ok(create_success_output("")));
+
+    let cleaner = ImageCleaner::new(&config, Arc::new(mock_executor));
+    assert!(cleaner.auto_clean().is_ok());
+}
+
+#[test]
+fn test_versions_list_and_retag() {
+    use trellis::trellis::versions::RootfsVersions;
+
+    let temp_dir = TempDir::new().unwrap();
+    let config = create_cleaner_config(&temp_dir);
+
+    let mut mock_executor = MockCommandExecutor::new();
+    mock_executor
+        .expect_podman_images()
+        .returning(|_| Ok(create_success_output(&versioned_images_output())));
+    mock_executor
+        .expect_execute()
+        .times(1)
+        .withf(|command, args| {
+            command == "podman"
+                && args
+                    == [
+                        "tag",
+                        "localhost/test-rootfs:20240103-100000-0000000c",
+                        "localhost/test-rootfs:latest",
+                    ]
+        })
+        .returning(|_, _| Ok(create_success_output("")));
+
+    let versions = RootfsVersions::new(&config, Arc::new(mock_executor));
+    let listed = versions.list().unwrap();
+    let names: Vec<&str> = listed.iter().map(|v| v.version.as_str()).collect();
+    assert_eq!(
+        names,
+        vec![
+            "20240104-100000-0000000d",
+            "20240103-100000-0000000c",
+            "20240102-100000-0000000b",
+            "20240101-100000-0000000a",
+        ]
+    );
+    assert!(listed[3].latest && !listed[0].latest);
+    assert_eq!(listed[0].created, 1_704_362_400);
+
+    let error = versions.retag("2024010").unwrap_err().to_string();
+    assert!(error.contains("is ambiguous"));
+    assert!(versions.retag("20240201").is_err());
+    assert_eq!(versions.retag("20240103").unwrap().image_id, "cccc00000003");
+}
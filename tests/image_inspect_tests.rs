@@ -0,0 +1,71 @@
+//! Tests for ImageInspector functionality.
+//!
+//! Tests cover combining podman inspect and history output into layer information.
+
+mod common;
+
+use common::mocks::*;
+use std::sync::Arc;
+use trellis::trellis::inspect::{ImageInspector, Layer};
+
+const INSPECT_JSON: &str = r#"[{
+    "Id": "0123456789abcdef0123456789abcdef",
+    "Size": 1300000000,
+    "RootFS": {"Type": "layers", "Layers": ["sha256:aaa", "sha256:bbb"]}
+}]"#;
+
+const HISTORY_JSON: &str = r#"[
+    {"id": "0123456789ab", "created": "2024-01-02T00:00:00Z", "CreatedBy": "/bin/sh -c #(nop) LABEL stage=final", "size": 0, "comment": ""},
+    {"id": "<missing>", "created": "2024-01-02T00:00:00Z", "CreatedBy": "/bin/sh -c pacman -Syu --noconfirm", "size": 500000000, "comment": ""},
+    {"id": "<missing>", "created": "2024-01-01T00:00:00Z", "CreatedBy": "/bin/sh -c pacstrap /mnt base", "size": 800000000, "comment": ""}
+]"#;
+
+#[test]
+fn test_inspect_reports_layers_oldest_first() {
+    let mut mock_executor = MockCommandExecutor::new();
+    mock_executor
+        .expect_podman_inspect()
+        .returning(|_| Ok(create_success_output(INSPECT_JSON)));
+    mock_executor
+        .expect_execute()
+        .withf(|command: &str, args: &[String]| command == "podman" && args[0] == "history")
+        .returning(|_, _| Ok(create_success_output(HISTORY_JSON)));
+
+    let inspector = ImageInspector::new(Arc::new(mock_executor));
+    let layers = inspector.layers("localhost/test-rootfs").unwrap();
+
+    assert_eq!(layers.layer_count, 2);
+    assert_eq!(
+        layers.layers,
+        vec![
+            Layer {
+                size: 800_000_000,
+                created_by: "/bin/sh -c pacstrap /mnt base".to_string(),
+            },
+            Layer {
+                size: 500_000_000,
+                created_by: "/bin/sh -c pacman -Syu --noconfirm".to_string(),
+            },
+        ]
+    );
+
+    let report = layers.to_string();
+    assert!(report.contains("ID:     0123456789ab\n"));
+    assert!(report.contains("Size:   1.3 GB\n"));
+    assert!(report.contains("Layers: 2\n"));
+    assert!(report.contains("1        800.0 MB  pacstrap /mnt base\n"));
+}
+
+#[test]
+fn test_inspect_missing_image_fails() {
+    let mut mock_executor = MockCommandExecutor::new();
+    mock_executor
+        .expect_podman_inspect()
+        .returning(|_| Ok(create_failure_output("no such image")));
+
+    let inspector = ImageInspector::new(Arc::new(mock_executor));
+    let error = inspector.layers("localhost/missing").unwrap_err();
+    assert!(error
+        .to_string()
+        .contains("Image not found: localhost/missing"));
+}
@@ -319,3 +319,297 @@ fn test_no_warning_without_root_password() {
         "Security warning should not appear when --root-password is not used, got stderr: '{stderr_str}'"
     );
 }
+
+#[test]
+fn test_stages_graph_dot() {
+    let temp_dir = TempDir::new().unwrap();
+    std::fs::write(
+        temp_dir.path().join("Containerfile.base"),
+        "FROM alpine AS base\n",
+    )
+    .unwrap();
+    std::fs::write(
+        temp_dir.path().join("Containerfile.gpu"),
+        "# trellis: requires=base\nARG BASE_IMAGE\nFROM ${BASE_IMAGE} AS gpu\n",
+    )
+    .unwrap();
+
+    let mut cmd = Command::cargo_bin("trls").unwrap();
+    cmd.arg("--skip-root-check");
+    cmd.arg("--stages-dir")
+        .arg(temp_dir.path())
+        .arg("--rootfs-stages")
+        .arg("gpu,base")
+        .args(["stages", "graph", "--format", "dot"]);
+
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("\"base\" -> \"gpu\";"))
+        .stdout(predicate::str::contains("Successful").not());
+}
+
+#[test]
+fn test_lint_command_reports_findings() {
+    let temp_dir = TempDir::new().unwrap();
+    std::fs::write(
+        temp_dir.path().join("Containerfile.base"),
+        "FROM alpine AS base\n",
+    )
+    .unwrap();
+    std::fs::write(
+        temp_dir.path().join("Containerfile.tools"),
+        "ARG BASE_IMAGE\nFROM ${BASE_IMAGE} AS tools\nCOPY motd /var/lib/motd\n",
+    )
+    .unwrap();
+
+    let mut cmd = Command::cargo_bin("trls").unwrap();
+    cmd.arg("--skip-root-check");
+    cmd.arg("--stages-dir")
+        .arg(temp_dir.path())
+        .args(["lint", "base", "tools"]);
+    cmd.assert().success().stdout(predicate::str::contains(
+        "Containerfile.tools:3: warning [tools]: COPY writes to /var/lib/motd",
+    ));
+
+    let mut cmd = Command::cargo_bin("trls").unwrap();
+    cmd.arg("--skip-root-check");
+    cmd.arg("--stages-dir")
+        .arg(temp_dir.path())
+        .args(["lint", "base", "tools:extras"]);
+    cmd.assert()
+        .failure()
+        .stdout(predicate::str::contains(
+            "error [tools:extras]: Target 'extras' not found. Stages in this file: tools",
+        ))
+        .stderr(predicate::str::contains(
+            "Containerfile lint failed: 1 error(s), 0 warning(s)",
+        ));
+}
+
+#[test]
+fn test_dry_run_build_prints_commands() {
+    let temp_dir = TempDir::new().unwrap();
+    std::fs::write(
+        temp_dir.path().join("Containerfile.base"),
+        "FROM alpine AS base\n",
+    )
+    .unwrap();
+
+    let mut cmd = Command::cargo_bin("trls").unwrap();
+    cmd.arg("--skip-root-check").arg("--dry-run");
+    cmd.arg("--stages-dir")
+        .arg(temp_dir.path())
+        .arg("--rootfs-stages")
+        .arg("base")
+        .arg("build");
+
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("[dry-run] podman build"))
+        .stdout(predicate::str::contains("BASE_IMAGE=scratch"));
+    assert!(!temp_dir.path().join("trellis.lock").exists());
+}
+
+#[test]
+fn test_logs_lists_builds_and_prints_stage_log() {
+    let temp_dir = TempDir::new().unwrap();
+    let log_dir = temp_dir.path().join("logs");
+    let build_dir = log_dir.join("20240101-120000-0123abcd");
+    std::fs::create_dir_all(&build_dir).unwrap();
+    std::fs::write(build_dir.join("base.log"), "STEP 1/2: FROM scratch\n").unwrap();
+
+    let config_path = temp_dir.path().join("trellis.toml");
+    std::fs::write(
+        &config_path,
+        format!("[environment]\nlog_dir = \"{}\"\n", log_dir.display()),
+    )
+    .unwrap();
+
+    let mut cmd = Command::cargo_bin("trls").unwrap();
+    cmd.arg("--skip-root-check")
+        .arg("--config-path")
+        .arg(&config_path)
+        .arg("--stages-dir")
+        .arg(temp_dir.path())
+        .arg("logs");
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("20240101-120000-0123abcd\tbase"))
+        .stdout(predicate::str::contains("Successful").not());
+
+    let mut cmd = Command::cargo_bin("trls").unwrap();
+    cmd.arg("--skip-root-check")
+        .arg("--config-path")
+        .arg(&config_path)
+        .arg("--stages-dir")
+        .arg(temp_dir.path())
+        .args(["logs", "--build", "20240101", "--stage", "base"]);
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::diff("STEP 1/2: FROM scratch\n"));
+}
+
+#[test]
+fn test_history_and_show() {
+    let temp_dir = TempDir::new().unwrap();
+    let state_dir = temp_dir.path().join("state");
+    let history_dir = state_dir.join("history");
+    std::fs::create_dir_all(&history_dir).unwrap();
+    for (id, base_seconds) in [
+        ("20240101-120000-0123abcd", 60.0),
+        ("20240102-120000-4567abcd", 120.0),
+    ] {
+        let entry = serde_json::json!({
+            "id": id,
+            "operation": "build",
+            "started": 1_704_110_400,
+            "finished": 1_704_110_530,
+            "stages": ["base"],
+            "stage_timings": [{"name": "base", "seconds": base_seconds, "reused": false}],
+            "success": true,
+            "error": null,
+            "image_id": "sha256:abc",
+            "log_id": id,
+            "config": {"rootfs_tag": "trellis-rootfs"}
+        });
+        std::fs::write(history_dir.join(format!("{id}.json")), entry.to_string()).unwrap();
+    }
+
+    let config_path = temp_dir.path().join("trellis.toml");
+    std::fs::write(
+        &config_path,
+        format!("[environment]\nstate_dir = \"{}\"\n", state_dir.display()),
+    )
+    .unwrap();
+
+    let mut cmd = Command::cargo_bin("trls").unwrap();
+    cmd.arg("--skip-root-check")
+        .arg("--config-path")
+        .arg(&config_path)
+        .arg("--stages-dir")
+        .arg(temp_dir.path())
+        .arg("history");
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("20240101-120000-0123abcd  build"))
+        .stdout(predicate::str::contains("success (slower: base)"))
+        .stdout(predicate::str::contains("Successful").not());
+
+    let mut cmd = Command::cargo_bin("trls").unwrap();
+    cmd.arg("--skip-root-check")
+        .arg("--config-path")
+        .arg(&config_path)
+        .arg("--stages-dir")
+        .arg(temp_dir.path())
+        .args(["show", "20240102"]);
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("Image:      sha256:abc"))
+        .stdout(predicate::str::contains("  base  2m00s"))
+        .stdout(predicate::str::contains(
+            "Stage 'base' took 2m00s, 100% longer than in build 20240101-120000-0123abcd",
+        ));
+}
+
+#[test]
+fn test_render_prints_preprocessed_containerfile() {
+    let temp_dir = TempDir::new().unwrap();
+    std::fs::write(
+        temp_dir.path().join("Containerfile.base"),
+        "# trellis:if stage == \"base\"\nFROM {{ rootfs_base }}\n# trellis:endif\n",
+    )
+    .unwrap();
+
+    let mut cmd = Command::cargo_bin("trls").unwrap();
+    cmd.arg("--skip-root-check")
+        .arg("--stages-dir")
+        .arg(temp_dir.path())
+        .arg("--rootfs-base")
+        .arg("alpine")
+        .args(["render", "base"]);
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::diff("FROM alpine\n"));
+}
+
+#[test]
+fn test_sources_update_checks_out_file_repository() {
+    let temp_dir = TempDir::new().unwrap();
+    let repo = temp_dir.path().join("repo");
+    let stages_dir = temp_dir.path().join("stages");
+    std::fs::create_dir_all(repo.join("stages")).unwrap();
+    std::fs::create_dir_all(&stages_dir).unwrap();
+
+    let git = |args: &[&str]| {
+        let status = std::process::Command::new("git")
+            .args([
+                "-c",
+                "user.name=trellis",
+                "-c",
+                "user.email=trellis@example.com",
+            ])
+            .arg("-C")
+            .arg(&repo)
+            .args(args)
+            .status()
+            .unwrap();
+        assert!(status.success());
+    };
+    let commit_containerfile = |content: &str| {
+        std::fs::write(repo.join("stages/Containerfile.extra"), content).unwrap();
+        git(&["add", "."]);
+        git(&["commit", "--quiet", "-m", content]);
+    };
+    git(&["init", "--quiet", "--initial-branch=main"]);
+    commit_containerfile("FROM first\n");
+
+    let config_path = temp_dir.path().join("trellis.toml");
+    std::fs::write(
+        &config_path,
+        format!(
+            "[environment]\nstages_dir = \"{}\"\nsources_dir = \"{}\"\n\n\
+             [[sources]]\ngit = \"file://{}\"\nref = \"main\"\nsubdir = \"stages\"\n",
+            stages_dir.display(),
+            temp_dir.path().join("sources").display(),
+            repo.display()
+        ),
+    )
+    .unwrap();
+    let trls = |args: &[&str]| {
+        let mut cmd = Command::cargo_bin("trls").unwrap();
+        cmd.arg("--skip-root-check")
+            .arg("--config-path")
+            .arg(&config_path)
+            .args(args);
+        cmd
+    };
+
+    // Builds fetch missing sources, reporting progress on stderr
+    trls(&["render", "extra"])
+        .assert()
+        .success()
+        .stdout(predicate::str::diff("FROM first\n"))
+        .stderr(predicate::str::contains("Fetching source"));
+
+    // Branch refs stay at the fetched commit until updated
+    commit_containerfile("FROM second\n");
+    trls(&["render", "extra"])
+        .assert()
+        .success()
+        .stdout(predicate::str::diff("FROM first\n"));
+    trls(&["sources", "update"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Source 'repo' is at"));
+    trls(&["render", "extra"])
+        .assert()
+        .success()
+        .stdout(predicate::str::diff("FROM second\n"));
+
+    // The stages directory takes precedence over sources
+    std::fs::write(stages_dir.join("Containerfile.extra"), "FROM local\n").unwrap();
+    trls(&["render", "extra"])
+        .assert()
+        .success()
+        .stdout(predicate::str::diff("FROM local\n"));
+}
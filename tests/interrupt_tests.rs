@@ -0,0 +1,234 @@
+//! Tests for cancellation of builds, quick updates and image generation.
+//!
+//! Tests cover the cleanup of partial state after SIGINT and waits on the podman
+//! API. The interruption flag is process-wide, so every test runs serially and
+//! clears it.
+
+mod common;
+
+use anyhow::anyhow;
+use common::mocks::*;
+use serial_test::serial;
+use std::{
+    os::unix::net::UnixListener,
+    sync::{Arc, Mutex},
+    thread,
+    time::Duration,
+};
+use tempfile::TempDir;
+use trellis::{
+    config::TrellisConfig,
+    trellis::{
+        builder::{BuildType, ContainerBuilder},
+        image_generator::ImageGenerator,
+        interrupt::{self, Interrupted},
+        libpod::LibpodClient,
+        runner::ContainerRunner,
+    },
+};
+
+fn create_interrupt_config(temp_dir: &TempDir) -> TrellisConfig {
+    TrellisConfig {
+        rootfs_stages: vec![
+            "base".to_string(),
+            "system".to_string(),
+            "final".to_string(),
+        ],
+        ..common::test_config(temp_dir)
+    }
+}
+
+/// Simulates Ctrl-C while a command runs, as the real executor reports it.
+fn interrupted_command() -> anyhow::Result<std::process::ExitStatus> {
+    interrupt::interrupt(libc::SIGINT);
+    Err(anyhow!(Interrupted {
+        signal: libc::SIGINT
+    }))
+}
+
+#[test]
+#[serial]
+fn test_interrupted_build_removes_stage_tags() {
+    interrupt::reset();
+    let temp_dir = TempDir::new().unwrap();
+    common::setup_test_containerfiles(&temp_dir, &["base", "system", "final"]);
+    let config = create_interrupt_config(&temp_dir);
+
+    let mut mock_executor = MockCommandExecutor::new();
+    mock_executor
+        .expect_podman_inspect()
+        .returning(|_| Ok(create_failure_output("image not known")));
+    mock_executor
+        .expect_podman_build_streaming()
+        .times(1)
+        .withf(|args: &[String]| args.contains(&"trellis-stage-base".to_string()))
+        .returning(|_| Ok(create_success_status()));
+    mock_executor
+        .expect_podman_build_streaming()
+        .times(1)
+        .withf(|args: &[String]| args.contains(&"trellis-stage-system".to_string()))
+        .returning(|_| interrupted_command());
+    mock_executor
+        .expect_podman_rmi()
+        .times(1)
+        .withf(|args: &[String]| args == ["--ignore", "trellis-stage-system"])
+        .returning(|_| Ok(create_success_output("")));
+
+    let builder = ContainerBuilder::new(&config, Arc::new(mock_executor));
+    let error = builder
+        .build_multistage_container(
+            "stage",
+            "test-rootfs",
+            &config.rootfs_stages,
+            BuildType::Rootfs,
+        )
+        .unwrap_err();
+
+    assert!(error.downcast_ref::<Interrupted>().is_some(), "{error:#}");
+    interrupt::reset();
+}
+
+#[test]
+#[serial]
+fn test_interrupted_quick_update_removes_container() {
+    interrupt::reset();
+    let temp_dir = TempDir::new().unwrap();
+    let config = create_interrupt_config(&temp_dir);
+
+    let removed = Arc::new(Mutex::new(Vec::new()));
+    let removed_clone = Arc::clone(&removed);
+    let mut mock_executor = MockCommandExecutor::new();
+    mock_executor
+        .expect_execute()
+        .returning(move |command, args| {
+            if command == "podman" && args[0] == "rm" {
+                removed_clone.lock().unwrap().push(args.to_vec());
+            }
+            Ok(create_success_output(""))
+        });
+    mock_executor
+        .expect_check_command_in_container()
+        .returning(|_, _| Ok(true));
+    mock_executor
+        .expect_podman_run_streaming()
+        .times(1)
+        .returning(|_| interrupted_command());
+    mock_executor.expect_podman_commit().times(0);
+
+    let runner = ContainerRunner::new(&config, Arc::new(mock_executor));
+    let error = runner.quick_update_rootfs().unwrap_err();
+
+    assert!(error.downcast_ref::<Interrupted>().is_some(), "{error:#}");
+    let removed = removed.lock().unwrap();
+    assert_eq!(removed.len(), 1);
+    assert_eq!(removed[0][..2], ["rm", "--force"]);
+    assert!(removed[0][2].starts_with("trellis-quick-update-"));
+    interrupt::reset();
+}
+
+#[test]
+#[serial]
+fn test_interrupted_injection_unmounts_and_detaches_in_reverse_order() {
+    interrupt::reset();
+    let temp_dir = TempDir::new().unwrap();
+    let mut config = create_interrupt_config(&temp_dir);
+    // Keeps the test from writing into the mount point
+    config.dry_run = true;
+
+    let commands = Arc::new(Mutex::new(Vec::new()));
+    let commands_clone = Arc::clone(&commands);
+    let mut mock_executor = MockCommandExecutor::new();
+    mock_executor.expect_execute().returning(move |command, _| {
+        commands_clone.lock().unwrap().push(command.to_string());
+        match command {
+            "losetup" => Ok(create_success_output("/dev/loop7\n")),
+            "cp" => {
+                interrupt::interrupt(libc::SIGINT);
+                Err(anyhow!(Interrupted {
+                    signal: libc::SIGINT
+                }))
+            }
+            _ => Ok(create_success_output("")),
+        }
+    });
+
+    let generator = ImageGenerator::new(&config, Arc::new(mock_executor));
+    let disk_image = temp_dir.path().join("disk.img");
+    let error = generator
+        .inject_configuration_to_disk(&disk_image, None)
+        .unwrap_err();
+
+    assert!(error.downcast_ref::<Interrupted>().is_some(), "{error:#}");
+    assert_eq!(
+        *commands.lock().unwrap(),
+        ["losetup", "mount", "cp", "umount", "losetup"]
+    );
+    let mount_point = std::env::temp_dir().join(format!("trellis-mount-{}", std::process::id()));
+    assert!(!mount_point.exists());
+    interrupt::reset();
+}
+
+#[test]
+#[serial]
+fn test_interrupted_generation_removes_image_file() {
+    interrupt::reset();
+    let temp_dir = TempDir::new().unwrap();
+    let config = create_interrupt_config(&temp_dir);
+
+    let mut mock_executor = MockCommandExecutor::new();
+    mock_executor
+        .expect_podman_images()
+        .returning(|_| Ok(create_success_output("localhost/test-rootfs:latest\n")));
+    mock_executor
+        .expect_execute()
+        .returning(|command, args| match command {
+            "fallocate" => {
+                std::fs::write(&args[2], "").unwrap();
+                Ok(create_success_output(""))
+            }
+            _ => Ok(create_success_output("/usr/bin/mkfs.fat\n")),
+        });
+    mock_executor
+        .expect_podman_run_streaming()
+        .times(1)
+        .returning(|_| interrupted_command());
+
+    let generator = ImageGenerator::new(&config, Arc::new(mock_executor));
+    let output_path = temp_dir.path().join("bootable.img");
+    let error = generator
+        .generate_bootable_image(
+            "localhost/test-rootfs:latest",
+            &output_path,
+            "ext4",
+            Some(4),
+            None,
+        )
+        .unwrap_err();
+
+    assert!(error.downcast_ref::<Interrupted>().is_some(), "{error:#}");
+    assert!(!output_path.exists());
+    interrupt::reset();
+}
+
+#[test]
+#[serial]
+fn test_interrupt_ends_wait_for_podman_api() {
+    interrupt::reset();
+    let temp_dir = TempDir::new().unwrap();
+    let socket = temp_dir.path().join("podman.sock");
+    // Accepts the request but never answers, like a stuck podman.service
+    let listener = UnixListener::bind(&socket).unwrap();
+    let connection = thread::spawn(move || listener.accept().map(|(stream, _)| stream));
+    thread::spawn(|| {
+        thread::sleep(Duration::from_millis(200));
+        interrupt::interrupt(libc::SIGINT);
+    });
+
+    let error = LibpodClient::new(&socket)
+        .image_exists("localhost/x")
+        .unwrap_err();
+
+    assert!(error.downcast_ref::<Interrupted>().is_some(), "{error:#}");
+    interrupt::reset();
+    drop(connection);
+}
@@ -0,0 +1,430 @@
+//! Tests for the libpod REST API executor.
+//!
+//! Tests run the executor against a stand-in API server on a Unix socket in a
+//! temporary directory, which records the requests and serves canned responses.
+
+mod common;
+
+use common::mocks::*;
+use std::{
+    io::{BufRead, BufReader, Write},
+    os::unix::net::UnixListener,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    thread,
+};
+use tempfile::TempDir;
+use trellis::{
+    cli::{BuildArgs, Cli, Commands},
+    config::TrellisConfig,
+    trellis::{
+        builder::{BuildType, ContainerBuilder},
+        cleaner::ImageCleaner,
+        executor::CommandExecutor,
+        libpod::{LibpodClient, LibpodCommandExecutor},
+    },
+};
+
+const IMAGES: &str = r#"[
+    {"Id": "111111111111aaaaaaaa", "RepoTags": ["localhost/trellis-stage-base:latest"], "Created": 1704067200, "Digest": "sha256:aa"},
+    {"Id": "222222222222bbbbbbbb", "RepoTags": ["localhost/trellis-builder:latest", "localhost/test-builder:latest"], "Created": 1704153600, "Digest": "sha256:bb"},
+    {"Id": "333333333333cccccccc", "RepoTags": null, "Created": 1704240000, "Digest": "sha256:cc"}
+]"#;
+
+/// Stand-in for podman's API socket.
+struct StandInServer {
+    socket: PathBuf,
+    requests: Arc<Mutex<Vec<String>>>,
+    _temp_dir: TempDir,
+}
+
+impl StandInServer {
+    /// Serves `respond(method, path)` as `(status, body)` for every request.
+    fn start<F>(respond: F) -> Self
+    where
+        F: Fn(&str, &str) -> (u16, String) + Send + 'static,
+    {
+        let temp_dir = TempDir::new().unwrap();
+        let socket = temp_dir.path().join("podman.sock");
+        let listener = UnixListener::bind(&socket).unwrap();
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let recorded = Arc::clone(&requests);
+
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let Ok(mut stream) = stream else { break };
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut request_line = String::new();
+                reader.read_line(&mut request_line).unwrap();
+                let mut header = String::new();
+                while reader.read_line(&mut header).unwrap() > 2 {
+                    header.clear();
+                }
+
+                let mut parts = request_line.split_whitespace();
+                let method = parts.next().unwrap_or_default().to_string();
+                let path = parts.next().unwrap_or_default().to_string();
+                recorded.lock().unwrap().push(format!("{method} {path}"));
+
+                let (status, body) = respond(&method, &path);
+                let _ = write!(
+                    stream,
+                    "HTTP/1.1 {status} Status\r\nContent-Type: application/json\r\n\
+                     Content-Length: {}\r\n\r\n{body}",
+                    body.len()
+                );
+            }
+        });
+
+        Self {
+            socket,
+            requests,
+            _temp_dir: temp_dir,
+        }
+    }
+
+    fn requests(&self) -> Vec<String> {
+        self.requests.lock().unwrap().clone()
+    }
+
+    fn executor(&self, fallback: MockCommandExecutor) -> LibpodCommandExecutor {
+        LibpodCommandExecutor::new(LibpodClient::new(&self.socket), Arc::new(fallback))
+    }
+}
+
+fn args(args: &[&str]) -> Vec<String> {
+    args.iter().map(|arg| arg.to_string()).collect()
+}
+
+fn stdout(output: &std::process::Output) -> String {
+    String::from_utf8_lossy(&output.stdout).to_string()
+}
+
+fn create_test_config(temp_dir: &TempDir) -> TrellisConfig {
+    TrellisConfig {
+        quiet: true,
+        ..common::test_config(temp_dir)
+    }
+}
+
+fn create_config_file_cli(temp_dir: &TempDir, contents: &str) -> Cli {
+    let config_path = temp_dir.path().join("trellis.toml");
+    std::fs::write(&config_path, contents).unwrap();
+    Cli {
+        command: Commands::Build(BuildArgs::default()),
+        builder_tag: "test-builder".to_string(),
+        podman_build_cache: None,
+        auto_clean: false,
+        incremental: None,
+        layering: None,
+        rechunk: None,
+        pacman_cache: None,
+        aur_cache: None,
+        stages_dir: Some(temp_dir.path().to_path_buf()),
+        extra_contexts: vec![],
+        extra_mounts: vec![],
+        secrets: vec![],
+        rootfs_stages: vec!["base".to_string()],
+        rootfs_base: "scratch".to_string(),
+        rootfs_tag: "test-rootfs".to_string(),
+        builder_stages: vec!["base".to_string()],
+        quiet: false,
+        config_path: Some(config_path),
+        skip_root_check: false,
+        profile: None,
+        dry_run: false,
+        wait: false,
+        no_wait: false,
+        force: false,
+    }
+}
+
+#[test]
+fn test_api_selected_in_config_file() {
+    let temp_dir = TempDir::new().unwrap();
+    let cli = create_config_file_cli(
+        &temp_dir,
+        "[engine]\napi = true\nsocket = \"unix:///tmp/podman.sock\"\n",
+    );
+    assert_eq!(
+        TrellisConfig::new(cli).unwrap().podman_socket,
+        Some(PathBuf::from("/tmp/podman.sock"))
+    );
+
+    let cli = create_config_file_cli(&temp_dir, "[engine]\napi = true\n");
+    assert_eq!(
+        TrellisConfig::new(cli).unwrap().podman_socket,
+        Some(PathBuf::from("/run/podman/podman.sock"))
+    );
+
+    let cli = create_config_file_cli(&temp_dir, "[engine]\nsocket = \"/tmp/podman.sock\"\n");
+    assert_eq!(TrellisConfig::new(cli).unwrap().podman_socket, None);
+}
+
+#[test]
+fn test_images_listed_from_api() {
+    let server = StandInServer::start(|_, _| (200, IMAGES.to_string()));
+    let executor = server.executor(MockCommandExecutor::new());
+
+    let images = executor.list_images(None).unwrap();
+
+    let listed: Vec<(&str, Vec<&str>, i64)> = images
+        .iter()
+        .map(|image| {
+            (
+                image.id.as_str(),
+                image.references().collect(),
+                image.created,
+            )
+        })
+        .collect();
+    assert_eq!(
+        listed,
+        [
+            (
+                "111111111111aaaaaaaa",
+                vec!["localhost/trellis-stage-base:latest"],
+                1704067200
+            ),
+            (
+                "222222222222bbbbbbbb",
+                vec![
+                    "localhost/trellis-builder:latest",
+                    "localhost/test-builder:latest"
+                ],
+                1704153600
+            ),
+            ("333333333333cccccccc", vec![], 1704240000),
+        ]
+    );
+    assert_eq!(server.requests(), ["GET /v4.0.0/libpod/images/json"]);
+}
+
+#[test]
+fn test_images_reference_filter_sent_to_api() {
+    let server = StandInServer::start(|_, _| (200, "[]".to_string()));
+    let executor = server.executor(MockCommandExecutor::new());
+
+    let images = executor
+        .list_images(Some("localhost/test-builder"))
+        .unwrap();
+
+    assert!(images.is_empty());
+    assert_eq!(
+        server.requests(),
+        ["GET /v4.0.0/libpod/images/json?filters=%7B%22reference%22%3A%5B%22localhost%2Ftest-builder%22%5D%7D"]
+    );
+}
+
+#[test]
+fn test_images_and_inspect_commands_fall_back_to_podman() {
+    let server = StandInServer::start(|_, _| (200, IMAGES.to_string()));
+    let mut fallback = MockCommandExecutor::new();
+    fallback
+        .expect_podman_images()
+        .times(1)
+        .returning(|_| Ok(create_success_output("from podman\n")));
+    fallback
+        .expect_podman_inspect()
+        .times(1)
+        .returning(|_| Ok(create_success_output("from podman\n")));
+    let executor = server.executor(fallback);
+
+    let output = executor
+        .podman_images(&args(&["--format", "{{.Repository}}:{{.Tag}}"]))
+        .unwrap();
+    assert_eq!(stdout(&output), "from podman\n");
+    let output = executor
+        .podman_inspect(&args(&["--format", "{{.Digest}}", "localhost/x"]))
+        .unwrap();
+    assert_eq!(stdout(&output), "from podman\n");
+
+    assert!(server.requests().is_empty());
+}
+
+#[test]
+fn test_inspect_image_and_missing_image() {
+    let server = StandInServer::start(|_, path| {
+        if path.starts_with("/v4.0.0/libpod/images/localhost/test-rootfs:latest/json") {
+            (
+                200,
+                r#"{"Id": "abc", "Digest": "sha256:aa", "Size": 1024, "Labels": {}}"#.to_string(),
+            )
+        } else {
+            (
+                404,
+                r#"{"cause": "image not known", "message": "x: image not known", "response": 404}"#
+                    .to_string(),
+            )
+        }
+    });
+    let executor = server.executor(MockCommandExecutor::new());
+
+    let image = executor
+        .inspect_image("localhost/test-rootfs:latest")
+        .unwrap()
+        .unwrap();
+    assert_eq!(image.size, 1024);
+
+    assert!(executor
+        .inspect_image("localhost/missing")
+        .unwrap()
+        .is_none());
+}
+
+#[test]
+fn test_stage_lookups_served_by_api() {
+    let digest = format!("sha256:{}", "a".repeat(64));
+    let inspect = format!(
+        r#"{{"Id": "444444444444dddddddd", "Digest": "{digest}", "RepoDigests": ["quay.io/fedora/fedora@{digest}"], "Size": 1024}}"#
+    );
+    let server = StandInServer::start(move |_, path| {
+        if path.starts_with("/v4.0.0/libpod/images/quay.io/fedora/fedora:41/json") {
+            (200, inspect.clone())
+        } else {
+            (
+                404,
+                r#"{"cause": "image not known", "message": "x: image not known", "response": 404}"#
+                    .to_string(),
+            )
+        }
+    });
+    let temp_dir = TempDir::new().unwrap();
+    common::setup_test_containerfiles(&temp_dir, &["base"]);
+    let config = TrellisConfig {
+        incremental: true,
+        rootfs_base: "quay.io/fedora/fedora:41".to_string(),
+        ..create_test_config(&temp_dir)
+    };
+    let mut fallback = MockCommandExecutor::new();
+    fallback.expect_podman_inspect().times(0);
+    fallback.expect_podman_images().times(0);
+    let builder = ContainerBuilder::new(&config, Arc::new(server.executor(fallback)));
+
+    let statuses = builder
+        .check_stages("stage", &["base".to_string()], BuildType::Rootfs)
+        .unwrap();
+    assert!(statuses[0].cache_tag.is_some());
+    assert_eq!(
+        builder.resolve_base_digest(&config.rootfs_base).unwrap(),
+        format!("sha256:{}", "a".repeat(64))
+    );
+    assert_eq!(
+        builder.resolve_image_id("localhost/missing"),
+        "localhost/missing"
+    );
+    assert!(server
+        .requests()
+        .contains(&"GET /v4.0.0/libpod/images/quay.io/fedora/fedora:41/json".to_string()));
+}
+
+#[test]
+fn test_rmi_through_api() {
+    let server = StandInServer::start(|_, _| {
+        (
+            200,
+            r#"{"Deleted": ["1111"], "Untagged": ["localhost/x:latest"], "Errors": [], "ExitCode": 0}"#
+                .to_string(),
+        )
+    });
+    let executor = server.executor(MockCommandExecutor::new());
+
+    let output = executor
+        .podman_rmi(&args(&["-f", "localhost/x:latest"]))
+        .unwrap();
+
+    assert!(output.status.success());
+    assert_eq!(stdout(&output), "localhost/x:latest\n1111\n");
+    assert_eq!(
+        server.requests(),
+        ["DELETE /v4.0.0/libpod/images/remove?images=localhost%2Fx%3Alatest&force=true&ignore=false"]
+    );
+}
+
+#[test]
+fn test_exists_and_container_removal() {
+    let server = StandInServer::start(|method, path| match (method, path) {
+        (_, "/v4.0.0/libpod/images/localhost/x/exists") => (204, String::new()),
+        ("DELETE", "/v4.0.0/libpod/containers/trellis-run?force=true") => (200, "[]".to_string()),
+        _ => (404, r#"{"message": "no such object"}"#.to_string()),
+    });
+    let executor = server.executor(MockCommandExecutor::new());
+
+    assert!(executor.image_exists("localhost/x").unwrap());
+    assert!(!executor.image_exists("localhost/y").unwrap());
+
+    let exists = |args: &[&str]| executor.podman_command(&self::args(args)).unwrap();
+    assert!(!exists(&["container", "exists", "trellis-run"])
+        .status
+        .success());
+    assert!(exists(&["rm", "--force", "trellis-run"]).status.success());
+}
+
+#[test]
+fn test_builds_and_other_commands_fall_back_to_podman() {
+    let server = StandInServer::start(|_, _| (500, String::new()));
+    let mut fallback = MockCommandExecutor::new();
+    fallback
+        .expect_podman_build_streaming()
+        .times(1)
+        .returning(|_| Ok(create_success_status()));
+    fallback
+        .expect_execute()
+        .withf(|command, args| command == "podman" && args[0] == "tag")
+        .times(1)
+        .returning(|_, _| Ok(create_success_output("")));
+    let executor = server.executor(fallback);
+
+    executor
+        .podman_build_streaming(&args(&["-t", "localhost/x"]))
+        .unwrap();
+    executor
+        .podman_command(&args(&["tag", "localhost/x", "localhost/y"]))
+        .unwrap();
+
+    assert!(server.requests().is_empty());
+}
+
+#[test]
+fn test_clean_all_through_api() {
+    let server = StandInServer::start(|method, _| match method {
+        "DELETE" => (
+            200,
+            r#"{"Deleted": ["1111", "2222"], "ExitCode": 0}"#.to_string(),
+        ),
+        _ => (200, IMAGES.to_string()),
+    });
+    let temp_dir = TempDir::new().unwrap();
+    let config = create_test_config(&temp_dir);
+    let cleaner = ImageCleaner::new(
+        &config,
+        Arc::new(server.executor(MockCommandExecutor::new())),
+    );
+
+    cleaner.clean_all().unwrap();
+
+    let requests = server.requests();
+    assert!(
+        requests.contains(
+            &"DELETE /v4.0.0/libpod/images/remove?images=localhost%2Ftrellis-stage-base%3Alatest&images=localhost%2Ftrellis-builder%3Alatest&images=localhost%2Ftest-builder%3Alatest&force=true&ignore=false"
+                .to_string()
+        ),
+        "{requests:?}"
+    );
+}
+
+#[test]
+fn test_unreachable_socket_reports_how_to_enable_api() {
+    let temp_dir = TempDir::new().unwrap();
+    let executor = LibpodCommandExecutor::new(
+        LibpodClient::new(temp_dir.path().join("missing.sock")),
+        Arc::new(MockCommandExecutor::new()),
+    );
+
+    let error = executor.image_exists("localhost/x").unwrap_err();
+
+    assert!(
+        format!("{error:#}").contains("systemctl enable --now podman.socket"),
+        "{error:#}"
+    );
+}
@@ -244,6 +244,44 @@ fn test_quick_update_cleanup_on_commit_failure() -> Result<()> {
     Ok(())
 }
 
+/// Test that no cleanup runs if podman could not create the container.
+#[test]
+fn test_quick_update_skips_cleanup_without_container() -> Result<()> {
+    let mut mock_executor = MockCommandExecutor::new();
+
+    let removed = std::sync::Arc::new(std::sync::Mutex::new(false));
+    let removed_clone = removed.clone();
+    mock_executor
+        .expect_execute()
+        .returning(move |command, args| match (command, args[0].as_str()) {
+            ("podman", "container") => Ok(create_failure_output("")),
+            ("podman", "rm") => {
+                *removed_clone.lock().unwrap() = true;
+                Ok(create_success_output(""))
+            }
+            _ => Ok(create_success_output("")),
+        });
+    mock_executor
+        .expect_check_command_in_container()
+        .returning(|_, command| Ok(command == "topgrade"));
+
+    // podman fails before creating the container, e.g. on a name conflict
+    mock_executor.expect_podman_run().returning(|_| {
+        Ok(create_failure_output(
+            "the container name is already in use",
+        ))
+    });
+
+    let config = create_test_config()?;
+    let runner = ContainerRunner::new(&config, Arc::new(mock_executor));
+
+    let result = runner.quick_update_rootfs();
+    assert!(result.unwrap_err().to_string().contains("already in use"));
+    assert!(!*removed.lock().unwrap());
+
+    Ok(())
+}
+
 /// Helper function to create a test configuration.
 fn create_test_config() -> Result<TrellisConfig> {
     use trellis::cli::{Cli, Commands};
@@ -258,11 +296,15 @@ fn create_test_config() -> Result<TrellisConfig> {
         builder_tag: "test-builder".to_string(),
         podman_build_cache: None,
         auto_clean: false,
+        incremental: None,
+        layering: None,
+        rechunk: None,
         pacman_cache: None,
         aur_cache: None,
         stages_dir: Some(stages_dir),
         extra_contexts: vec![],
         extra_mounts: vec![],
+        secrets: vec![],
         rootfs_stages: vec!["stage1".to_string()],
         rootfs_base: "scratch".to_string(),
         rootfs_tag: "test-rootfs".to_string(),
@@ -270,6 +312,11 @@ fn create_test_config() -> Result<TrellisConfig> {
         quiet: true,
         config_path: None,
         skip_root_check: true,
+        profile: None,
+        dry_run: false,
+        wait: false,
+        no_wait: false,
+        force: false,
     };
 
     // Keep the temp_dir alive for the duration of the config
@@ -0,0 +1,149 @@
+//! Tests for RegistryPusher functionality.
+//!
+//! Tests cover the pushed references, push options and the metadata image.
+
+mod common;
+
+use common::mocks::*;
+use std::sync::{Arc, Mutex};
+use tempfile::TempDir;
+use trellis::{
+    config::{Registry, RegistryDestination},
+    trellis::registry::RegistryPusher,
+};
+
+const VERSIONED_IMAGES: &str = r#"[
+    {"Id": "aaaa00000001", "Names": ["localhost/test-rootfs:20240101-100000-0000000a"], "Created": 1704103200},
+    {"Id": "bbbb00000002", "Names": ["localhost/test-rootfs:latest", "localhost/test-rootfs:20240102-100000-0000000b"], "Created": 1704189600}
+]"#;
+
+/// Records the arguments of every `podman push` and the Containerfiles of builds,
+/// and accepts everything else.
+fn recording_executor(
+    pushes: Arc<Mutex<Vec<Vec<String>>>>,
+    containerfiles: Arc<Mutex<Vec<String>>>,
+) -> MockCommandExecutor {
+    let mut mock_executor = MockCommandExecutor::new();
+    mock_executor
+        .expect_podman_images()
+        .returning(|_| Ok(create_success_output(VERSIONED_IMAGES)));
+    mock_executor.expect_podman_build().returning(move |args| {
+        let position = args.iter().position(|arg| arg == "-f").unwrap();
+        let containerfile = std::fs::read_to_string(&args[position + 1]).unwrap();
+        containerfiles.lock().unwrap().push(containerfile);
+        Ok(create_success_output(""))
+    });
+    mock_executor
+        .expect_podman_rmi()
+        .returning(|_| Ok(create_success_output("")));
+    mock_executor
+        .expect_execute()
+        .withf(|command, args| command == "podman" && args[0] == "push")
+        .returning(move |_, args| {
+            pushes.lock().unwrap().push(args.to_vec());
+            Ok(create_success_output(""))
+        });
+    mock_executor
+}
+
+#[test]
+fn test_push_to_registry_with_versions_and_metadata() {
+    let temp_dir = TempDir::new().unwrap();
+    let config = common::test_config(&temp_dir);
+    let pushes = Arc::new(Mutex::new(Vec::new()));
+    let containerfiles = Arc::new(Mutex::new(Vec::new()));
+
+    let pusher = RegistryPusher::new(
+        &config,
+        Arc::new(recording_executor(
+            Arc::clone(&pushes),
+            Arc::clone(&containerfiles),
+        )),
+    );
+    let registry = Registry {
+        repository: Some("os/rootfs".to_string()),
+        tls_verify: false,
+        sign_by: Some("releases@example.com".to_string()),
+        ..Registry::new(RegistryDestination::parse("localhost:5000").unwrap())
+    };
+    let pushed = pusher.push(&registry).unwrap();
+
+    assert_eq!(
+        pushed,
+        vec![
+            "docker://localhost:5000/os/rootfs:latest",
+            "docker://localhost:5000/os/rootfs:20240102-100000-0000000b",
+            "docker://localhost:5000/os/rootfs:20240101-100000-0000000a",
+            "docker://localhost:5000/os/rootfs:20240102-100000-0000000b-metadata",
+        ]
+    );
+
+    let pushes = pushes.lock().unwrap();
+    assert_eq!(
+        pushes[0],
+        vec![
+            "push",
+            "--tls-verify=false",
+            "--sign-by",
+            "releases@example.com",
+            "localhost/test-rootfs:latest",
+            "docker://localhost:5000/os/rootfs:latest",
+        ]
+    );
+    // The metadata image is built locally under a temporary tag, from the files
+    // embedded in the pushed version
+    assert!(pushes[3][4].starts_with("localhost/test-rootfs-metadata:"));
+    assert_eq!(
+        containerfiles.lock().unwrap().as_slice(),
+        [
+            "FROM localhost/test-rootfs:20240102-100000-0000000b AS build\n\
+          FROM scratch\n\
+          COPY --from=build /usr/share/trellis/ /\n"
+        ]
+    );
+}
+
+#[test]
+fn test_push_to_oci_layout_without_metadata() {
+    let temp_dir = TempDir::new().unwrap();
+    let mut config = common::test_config(&temp_dir);
+    config.keep_versions = 0;
+    let pushes = Arc::new(Mutex::new(Vec::new()));
+
+    let pusher = RegistryPusher::new(
+        &config,
+        Arc::new(recording_executor(Arc::clone(&pushes), Arc::default())),
+    );
+    let registry = Registry {
+        attach_metadata: false,
+        ..pusher.registry(Some("oci:/srv/oci")).unwrap()
+    };
+    let pushed = pusher.push(&registry).unwrap();
+
+    assert_eq!(pushed, vec!["oci:/srv/oci/test-rootfs:latest"]);
+    assert_eq!(
+        pushes.lock().unwrap()[0],
+        vec![
+            "push",
+            "localhost/test-rootfs:latest",
+            "oci:/srv/oci/test-rootfs:latest"
+        ]
+    );
+}
+
+#[test]
+fn test_push_requires_destination_and_rejects_signed_oci() {
+    let temp_dir = TempDir::new().unwrap();
+    let config = common::test_config(&temp_dir);
+    let pusher = RegistryPusher::new(&config, Arc::new(MockCommandExecutor::new()));
+
+    let error = pusher.registry(None).unwrap_err().to_string();
+    assert!(error.contains("No registry configured"));
+
+    let registry = Registry {
+        sign_by: Some("releases@example.com".to_string()),
+        ..pusher.registry(Some("oci:/srv/oci")).unwrap()
+    };
+    let error = pusher.push(&registry).unwrap_err().to_string();
+    assert!(error.contains("Signing requires a registry destination"));
+}
@@ -0,0 +1,216 @@
+//! Tests for rootless podman mode.
+//!
+//! Tests cover the `rootless` setting, the storage and cache handling of rootless
+//! builds, and the operations handed off to root or refused.
+
+mod common;
+
+use common::mocks::*;
+use std::sync::{Arc, Mutex};
+use tempfile::TempDir;
+use trellis::{
+    cli::{BuildArgs, Cli, Commands},
+    config::{Config, Rootless, TrellisConfig},
+    trellis::{
+        builder::{BuildType, ContainerBuilder},
+        image_generator::ImageGenerator,
+        rootless,
+        runner::ContainerRunner,
+    },
+};
+
+fn create_rootless_config(temp_dir: &TempDir) -> TrellisConfig {
+    TrellisConfig {
+        rootless: true,
+        storage_dir: "/home/builder/.local/share/containers/storage".into(),
+        privilege_command: Some("sudo".to_string()),
+        ..common::test_config(temp_dir)
+    }
+}
+
+fn create_config_file_cli(temp_dir: &TempDir, environment: &str) -> Cli {
+    let config_path = temp_dir.path().join("trellis.toml");
+    std::fs::write(&config_path, format!("[environment]\n{environment}\n")).unwrap();
+    Cli {
+        command: Commands::Build(BuildArgs::default()),
+        builder_tag: "test-builder".to_string(),
+        podman_build_cache: None,
+        auto_clean: false,
+        incremental: None,
+        layering: None,
+        rechunk: None,
+        pacman_cache: None,
+        aur_cache: None,
+        stages_dir: Some(temp_dir.path().to_path_buf()),
+        extra_contexts: vec![],
+        extra_mounts: vec![],
+        secrets: vec![],
+        rootfs_stages: vec!["base".to_string()],
+        rootfs_base: "scratch".to_string(),
+        rootfs_tag: "test-rootfs".to_string(),
+        builder_stages: vec!["base".to_string()],
+        quiet: false,
+        config_path: Some(config_path),
+        skip_root_check: false,
+        profile: None,
+        dry_run: false,
+        wait: false,
+        no_wait: false,
+        force: false,
+    }
+}
+
+#[test]
+fn test_rootless_setting_parsing() {
+    let parse = |value: &str| {
+        toml::from_str::<Config>(&format!("[environment]\nrootless = {value}\n"))
+            .map(|config| config.environment.unwrap().rootless.unwrap())
+    };
+
+    assert_eq!(parse("true").unwrap(), Rootless::On);
+    assert_eq!(parse("false").unwrap(), Rootless::Off);
+    assert_eq!(parse("\"auto\"").unwrap(), Rootless::Auto);
+    let error = parse("\"sometimes\"").unwrap_err().to_string();
+    assert!(
+        error.contains("expected true, false or \"auto\""),
+        "{error}"
+    );
+}
+
+#[test]
+fn test_rootless_auto_follows_the_effective_user() {
+    let temp_dir = TempDir::new().unwrap();
+    let cli = create_config_file_cli(&temp_dir, "rootless = \"auto\"");
+
+    let config = TrellisConfig::new(cli).unwrap();
+
+    assert_eq!(config.rootless, !rootless::is_root());
+    if !config.rootless {
+        assert_eq!(
+            config.storage_dir,
+            std::path::Path::new("/var/lib/containers/storage")
+        );
+        assert_eq!(config.lock_dir, std::path::Path::new("/run/trellis"));
+        assert_eq!(config.pacman_cache, None);
+    } else {
+        assert!(config.pacman_cache.unwrap().ends_with("trellis/pacman"));
+        assert!(config.aur_cache.unwrap().ends_with("trellis/aur"));
+    }
+}
+
+#[test]
+fn test_explicit_directories_apply_in_rootless_mode() {
+    let temp_dir = TempDir::new().unwrap();
+    let cli = create_config_file_cli(
+        &temp_dir,
+        "rootless = \"auto\"\nstate_dir = \"/srv/trellis/state\"",
+    );
+
+    let config = TrellisConfig::new(cli).unwrap();
+
+    assert_eq!(config.state_dir, std::path::Path::new("/srv/trellis/state"));
+}
+
+#[test]
+fn test_rootless_rechunk_mounts_user_storage() {
+    let temp_dir = TempDir::new().unwrap();
+    let config = create_rootless_config(&temp_dir);
+
+    let mut mock_executor = MockCommandExecutor::new();
+    mock_executor
+        .expect_podman_run_streaming()
+        .times(1)
+        .withf(|args: &[String]| {
+            args.contains(
+                &"/home/builder/.local/share/containers/storage:/var/lib/containers/storage"
+                    .to_string(),
+            ) && !args.contains(&"/var/lib/containers:/var/lib/containers".to_string())
+        })
+        .returning(|_| Ok(create_success_status()));
+
+    let builder = ContainerBuilder::new(&config, Arc::new(mock_executor));
+    builder.rechunk_image("test-rootfs").unwrap();
+}
+
+#[test]
+fn test_rootless_build_creates_caches_as_the_invoking_user() {
+    let temp_dir = TempDir::new().unwrap();
+    common::setup_test_containerfiles(&temp_dir, &["base"]);
+    let mut config = create_rootless_config(&temp_dir);
+    let cache_dir = temp_dir.path().join("pacman");
+    config.pacman_cache = Some(cache_dir.clone());
+    let volume = format!("{}:/var/cache/pacman/pkg", cache_dir.display());
+
+    let mut mock_executor = MockCommandExecutor::new();
+    mock_executor
+        .expect_podman_inspect()
+        .returning(|_| Ok(create_failure_output("image not known")));
+    mock_executor.expect_execute().times(0);
+    mock_executor
+        .expect_podman_build_streaming()
+        .times(1)
+        .withf(move |args: &[String]| args.contains(&volume))
+        .returning(|_| Ok(create_success_status()));
+
+    let builder = ContainerBuilder::new(&config, Arc::new(mock_executor));
+    let stages = vec!["base".to_string()];
+    builder
+        .build_multistage_container("stage", "test-rootfs", &stages, BuildType::Rootfs)
+        .unwrap();
+    assert!(cache_dir.is_dir());
+}
+
+#[test]
+fn test_rootless_update_hands_bootc_upgrade_off_to_root() {
+    let temp_dir = TempDir::new().unwrap();
+    let config = create_rootless_config(&temp_dir);
+
+    let commands = Arc::new(Mutex::new(Vec::new()));
+    let recorded = Arc::clone(&commands);
+    let mut mock_executor = MockCommandExecutor::new();
+    mock_executor
+        .expect_bootc()
+        .withf(|args: &[String]| args == ["--version"])
+        .returning(|_| Ok(create_success_output("bootc 1.1.0")));
+    mock_executor.expect_bootc_streaming().times(0);
+    mock_executor
+        .expect_execute()
+        .returning(move |command, args| {
+            let mut line = vec![command.to_string()];
+            line.extend(args.iter().cloned());
+            recorded.lock().unwrap().push(line);
+            Ok(create_success_output(""))
+        });
+
+    let runner = ContainerRunner::new(&config, Arc::new(mock_executor));
+    runner.run_bootc_upgrade().unwrap();
+
+    let commands = commands.lock().unwrap();
+    assert_eq!(commands.len(), 3, "{commands:?}");
+    assert_eq!(commands[0][..3], ["podman", "save", "--output"]);
+    assert_eq!(commands[0][4], "localhost/test-rootfs:latest");
+    assert_eq!(commands[1][..4], ["sudo", "podman", "load", "--input"]);
+    assert_eq!(commands[1][4], commands[0][3]);
+    assert_eq!(commands[2], ["sudo", "bootc", "upgrade"]);
+    assert!(!std::path::Path::new(&commands[0][3]).exists());
+}
+
+#[test]
+fn test_rootless_image_generation_is_refused() {
+    let temp_dir = TempDir::new().unwrap();
+    let config = create_rootless_config(&temp_dir);
+    let output_path = temp_dir.path().join("bootable.img");
+
+    let generator = ImageGenerator::new(&config, Arc::new(MockCommandExecutor::new()));
+    let error = generator
+        .generate_bootable_image("test-rootfs", &output_path, "ext4", Some(4), None)
+        .unwrap_err()
+        .to_string();
+
+    assert!(error.contains("`trls image` needs root"), "{error}");
+    assert!(
+        error.contains("podman image scp localhost/test-rootfs:latest root@localhost::"),
+        "{error}"
+    );
+    assert!(!output_path.exists());
+}
@@ -0,0 +1,117 @@
+//! Tests for the run lock.
+//!
+//! Tests cover shared and exclusive locking against a lock held by another process.
+
+mod common;
+
+use std::{ffi::CString, fs::File, io::Read, os::fd::FromRawFd, time::Duration};
+use tempfile::TempDir;
+use trellis::{
+    config::TrellisConfig,
+    trellis::run_lock::{LockMode, RunLocker},
+};
+
+fn create_lock_config(temp_dir: &TempDir) -> TrellisConfig {
+    TrellisConfig {
+        lock_dir: temp_dir.path().join("run"),
+        ..common::test_config(temp_dir)
+    }
+}
+
+/// Process holding the run lock until it is killed.
+struct Holder {
+    pid: libc::pid_t,
+}
+
+impl Holder {
+    /// Forks a process that takes the lock at `path` in `mode` and holds it.
+    fn spawn(path: &std::path::Path, mode: LockMode) -> Self {
+        let path = CString::new(path.to_str().unwrap()).unwrap();
+        let lock_type = match mode {
+            LockMode::Shared => libc::F_RDLCK,
+            LockMode::Exclusive => libc::F_WRLCK,
+        };
+        let mut fds = [0; 2];
+        assert_eq!(unsafe { libc::pipe(fds.as_mut_ptr()) }, 0);
+
+        let pid = unsafe { libc::fork() };
+        assert!(pid >= 0);
+        if pid == 0 {
+            // Only async-signal-safe calls between fork and _exit
+            unsafe {
+                let fd = libc::open(path.as_ptr(), libc::O_RDWR | libc::O_CREAT, 0o644);
+                let mut lock: libc::flock = std::mem::zeroed();
+                lock.l_type = lock_type as libc::c_short;
+                lock.l_whence = libc::SEEK_SET as libc::c_short;
+                let locked = fd >= 0 && libc::fcntl(fd, libc::F_SETLK, &lock) == 0;
+                libc::write(fds[1], [u8::from(locked)].as_ptr().cast(), 1);
+                loop {
+                    libc::pause();
+                }
+            }
+        }
+
+        unsafe { libc::close(fds[1]) };
+        let mut ready = [0u8; 1];
+        let mut pipe = unsafe { File::from_raw_fd(fds[0]) };
+        pipe.read_exact(&mut ready).unwrap();
+        assert_eq!(ready[0], 1, "holder failed to take the lock");
+        Self { pid }
+    }
+}
+
+impl Drop for Holder {
+    fn drop(&mut self) {
+        unsafe {
+            libc::kill(self.pid, libc::SIGKILL);
+            libc::waitpid(self.pid, std::ptr::null_mut(), 0);
+        }
+    }
+}
+
+#[test]
+fn test_exclusive_lock_names_holder() {
+    let temp_dir = TempDir::new().unwrap();
+    let config = create_lock_config(&temp_dir);
+    let locker = RunLocker::new(&config);
+    drop(locker.acquire(LockMode::Shared).unwrap());
+
+    let holder = Holder::spawn(&locker.path(), LockMode::Exclusive);
+
+    for mode in [LockMode::Shared, LockMode::Exclusive] {
+        let error = locker.acquire(mode).unwrap_err().to_string();
+        assert!(error.contains(&format!("PID {}", holder.pid)), "{error}");
+        assert!(error.contains("--wait"), "{error}");
+    }
+}
+
+#[test]
+fn test_shared_locks_coexist() {
+    let temp_dir = TempDir::new().unwrap();
+    let config = create_lock_config(&temp_dir);
+    let locker = RunLocker::new(&config);
+    drop(locker.acquire(LockMode::Shared).unwrap());
+
+    let _holder = Holder::spawn(&locker.path(), LockMode::Shared);
+
+    assert!(locker.acquire(LockMode::Shared).is_ok());
+    assert!(locker.acquire(LockMode::Exclusive).is_err());
+}
+
+#[test]
+fn test_wait_takes_lock_after_holder_exits() {
+    let temp_dir = TempDir::new().unwrap();
+    let mut config = create_lock_config(&temp_dir);
+    config.wait = true;
+    let locker = RunLocker::new(&config);
+    drop(locker.acquire(LockMode::Shared).unwrap());
+
+    let holder = Holder::spawn(&locker.path(), LockMode::Exclusive);
+    let release = std::thread::spawn(move || {
+        std::thread::sleep(Duration::from_millis(200));
+        drop(holder);
+    });
+
+    assert!(locker.acquire(LockMode::Exclusive).is_ok());
+    release.join().unwrap();
+}
@@ -8,7 +8,7 @@ use common::mocks::*;
 use std::sync::Arc;
 use tempfile::TempDir;
 use trellis::{
-    cli::{Cli, Commands},
+    cli::{BuildArgs, Cli, Commands},
     TrellisApp,
 };
 
@@ -18,11 +18,15 @@ fn create_test_cli_with_command(command: Commands) -> Cli {
         builder_tag: "test-builder".to_string(),
         podman_build_cache: None,
         auto_clean: false,
+        incremental: None,
+        layering: None,
+        rechunk: None,
         pacman_cache: None,
         aur_cache: None,
         stages_dir: None,
         extra_contexts: vec![],
         extra_mounts: vec![],
+        secrets: vec![],
         rootfs_stages: vec!["base".to_string()],
         rootfs_base: "scratch".to_string(),
         rootfs_tag: "test-rootfs".to_string(),
@@ -30,13 +34,18 @@ fn create_test_cli_with_command(command: Commands) -> Cli {
         quiet: false,
         config_path: None,
         skip_root_check: false,
+        profile: None,
+        dry_run: false,
+        wait: false,
+        no_wait: false,
+        force: false,
     }
 }
 
 #[test]
 fn test_trellis_app_creation_success() {
     let temp_dir = TempDir::new().unwrap();
-    let mut cli = create_test_cli_with_command(Commands::Build);
+    let mut cli = create_test_cli_with_command(Commands::Build(BuildArgs::default()));
     cli.stages_dir = Some(temp_dir.path().to_path_buf());
     let app = TrellisApp::new(cli);
     assert!(app.is_ok());
@@ -45,7 +54,7 @@ fn test_trellis_app_creation_success() {
 #[test]
 fn test_trellis_app_with_custom_executor() {
     let temp_dir = TempDir::new().unwrap();
-    let mut cli = create_test_cli_with_command(Commands::Build);
+    let mut cli = create_test_cli_with_command(Commands::Build(BuildArgs::default()));
     cli.stages_dir = Some(temp_dir.path().to_path_buf());
     let executor = Arc::new(MockScenarios::all_success());
     let app = TrellisApp::with_executor(cli, executor);
@@ -57,7 +66,7 @@ fn test_build_command_execution() {
     let temp_dir = TempDir::new().unwrap();
     common::setup_test_containerfiles(&temp_dir, &["base"]);
 
-    let mut cli = create_test_cli_with_command(Commands::Build);
+    let mut cli = create_test_cli_with_command(Commands::Build(BuildArgs::default()));
     cli.stages_dir = Some(temp_dir.path().to_path_buf());
 
     let executor = Arc::new(MockScenarios::all_success());
@@ -143,7 +152,7 @@ fn test_build_with_empty_stages_fails() {
 
     let temp_dir = TempDir::new().unwrap();
 
-    let mut cli = create_test_cli_with_command(Commands::Build);
+    let mut cli = create_test_cli_with_command(Commands::Build(BuildArgs::default()));
     cli.config_path = Some(temp_config_path);
     cli.stages_dir = Some(temp_dir.path().to_path_buf());
     cli.rootfs_stages = vec![]; // Empty stages should fail
@@ -189,7 +198,7 @@ fn test_build_with_command_failure() {
     let temp_dir = TempDir::new().unwrap();
     common::setup_test_containerfiles(&temp_dir, &["base"]);
 
-    let mut cli = create_test_cli_with_command(Commands::Build);
+    let mut cli = create_test_cli_with_command(Commands::Build(BuildArgs::default()));
     cli.stages_dir = Some(temp_dir.path().to_path_buf());
 
     let executor = Arc::new(MockScenarios::build_failures());
@@ -209,7 +218,7 @@ fn test_build_with_missing_containerfiles() {
     let temp_dir = TempDir::new().unwrap();
     // Don't create any containerfiles
 
-    let mut cli = create_test_cli_with_command(Commands::Build);
+    let mut cli = create_test_cli_with_command(Commands::Build(BuildArgs::default()));
     cli.config_path = Some(temp_config_path);
     cli.stages_dir = Some(temp_dir.path().to_path_buf());
 
@@ -226,7 +235,7 @@ fn test_build_with_missing_containerfiles() {
 fn test_configuration_validation_integration() {
     let temp_dir = TempDir::new().unwrap();
 
-    let mut cli = create_test_cli_with_command(Commands::Build);
+    let mut cli = create_test_cli_with_command(Commands::Build(BuildArgs::default()));
     cli.stages_dir = Some(temp_dir.path().to_path_buf());
     cli.builder_tag = "test-rootfs".to_string(); // Same as rootfs_tag - should fail validation
     cli.rootfs_tag = "test-rootfs".to_string();
@@ -246,7 +255,7 @@ fn test_multi_stage_build_success() {
     let temp_dir = TempDir::new().unwrap();
     common::setup_test_containerfiles(&temp_dir, &["base", "tools", "final"]);
 
-    let mut cli = create_test_cli_with_command(Commands::Build);
+    let mut cli = create_test_cli_with_command(Commands::Build(BuildArgs::default()));
     cli.stages_dir = Some(temp_dir.path().to_path_buf());
     cli.rootfs_stages = vec!["base".to_string(), "tools".to_string(), "final".to_string()];
 
@@ -262,7 +271,7 @@ fn test_auto_clean_integration() {
     let temp_dir = TempDir::new().unwrap();
     common::setup_test_containerfiles(&temp_dir, &["base"]);
 
-    let mut cli = create_test_cli_with_command(Commands::Build);
+    let mut cli = create_test_cli_with_command(Commands::Build(BuildArgs::default()));
     cli.stages_dir = Some(temp_dir.path().to_path_buf());
     cli.auto_clean = true;
 
@@ -286,7 +295,7 @@ fn test_cache_configuration_applied() {
     let pacman_cache = temp_dir.path().join("pacman");
     let aur_cache = temp_dir.path().join("aur");
 
-    let mut cli = create_test_cli_with_command(Commands::Build);
+    let mut cli = create_test_cli_with_command(Commands::Build(BuildArgs::default()));
     cli.config_path = Some(temp_config_path);
     cli.stages_dir = Some(temp_dir.path().to_path_buf());
     cli.pacman_cache = Some(pacman_cache.clone());
@@ -304,7 +313,7 @@ fn test_extra_contexts_and_mounts() {
     let temp_dir = TempDir::new().unwrap();
     common::setup_test_containerfiles(&temp_dir, &["base"]);
 
-    let mut cli = create_test_cli_with_command(Commands::Build);
+    let mut cli = create_test_cli_with_command(Commands::Build(BuildArgs::default()));
     cli.stages_dir = Some(temp_dir.path().to_path_buf());
     cli.extra_contexts = vec!["context1=/tmp".to_string()];
     cli.extra_mounts = vec!["mount1=/opt".to_string().into()];
@@ -321,7 +330,7 @@ fn test_custom_rootfs_base() {
     let temp_dir = TempDir::new().unwrap();
     common::setup_test_containerfiles(&temp_dir, &["base"]);
 
-    let mut cli = create_test_cli_with_command(Commands::Build);
+    let mut cli = create_test_cli_with_command(Commands::Build(BuildArgs::default()));
     cli.stages_dir = Some(temp_dir.path().to_path_buf());
     cli.rootfs_base = "fedora:39".to_string();
 
@@ -342,12 +351,21 @@ fn test_build_command_with_quiet_flag() {
     let temp_dir = TempDir::new().unwrap();
     common::setup_test_containerfiles(&temp_dir, &["base"]);
 
-    let mut cli = create_test_cli_with_command(Commands::Build);
+    let mut cli = create_test_cli_with_command(Commands::Build(BuildArgs::default()));
     cli.config_path = Some(temp_config_path);
     cli.stages_dir = Some(temp_dir.path().to_path_buf());
     cli.quiet = true; // Test CLI quiet flag
 
     let mut mock_executor = MockCommandExecutor::new();
+    // Image digest and ID lookups for the lockfile
+    mock_executor
+        .expect_podman_inspect()
+        .returning(|_| Ok(create_success_output("sha256:abc123")));
+    // Versioned tag of the rootfs image
+    mock_executor
+        .expect_execute()
+        .withf(|command, args| command == "podman" && args[0] == "tag")
+        .returning(|_, _| Ok(create_success_output("")));
     mock_executor
         .expect_podman_build() // Should use non-streaming
         .returning(|_| Ok(create_success_output("Build completed")));
@@ -358,10 +376,13 @@ fn test_build_command_with_quiet_flag() {
                 .iter()
                 .any(|arg| arg.contains("reference=localhost/test-builder"))
         {
-            Ok(create_success_output("localhost/test-builder:latest\n"))
+            Ok(create_success_output(&format_images_json(&[
+                MockImageInfo::new("abc123", "localhost/test-builder", "latest"),
+            ])))
         } else {
             Ok(create_success_output(
-                "REPOSITORY\tTAG\tIMAGE ID\tCREATED\tSIZE\n",
+                "[]
+",
             ))
         }
     });
@@ -390,6 +411,9 @@ fn test_build_builder_command_with_quiet_flag() {
     cli.quiet = true;
 
     let mut mock_executor = MockCommandExecutor::new();
+    mock_executor
+        .expect_podman_inspect()
+        .returning(|_| Ok(create_failure_output("image not known")));
     mock_executor
         .expect_podman_build()
         .returning(|_| Ok(create_success_output("Build completed")));
@@ -400,10 +424,13 @@ fn test_build_builder_command_with_quiet_flag() {
                 .iter()
                 .any(|arg| arg.contains("reference=localhost/test-builder"))
         {
-            Ok(create_success_output("localhost/test-builder:latest\n"))
+            Ok(create_success_output(&format_images_json(&[
+                MockImageInfo::new("abc123", "localhost/test-builder", "latest"),
+            ])))
         } else {
             Ok(create_success_output(
-                "REPOSITORY\tTAG\tIMAGE ID\tCREATED\tSIZE\n",
+                "[]
+",
             ))
         }
     });
@@ -464,7 +491,16 @@ fn test_update_command_with_quiet_flag() {
     cli.quiet = true;
 
     let mut mock_executor = MockCommandExecutor::new();
+    // Image digest and ID lookups for the lockfile
+    mock_executor
+        .expect_podman_inspect()
+        .returning(|_| Ok(create_success_output("sha256:abc123")));
     // Build stage
+    // Versioned tag of the rootfs image
+    mock_executor
+        .expect_execute()
+        .withf(|command, args| command == "podman" && args[0] == "tag")
+        .returning(|_, _| Ok(create_success_output("")));
     mock_executor
         .expect_podman_build()
         .returning(|_| Ok(create_success_output("Build completed")));
@@ -475,10 +511,13 @@ fn test_update_command_with_quiet_flag() {
                 .iter()
                 .any(|arg| arg.contains("reference=localhost/test-builder"))
         {
-            Ok(create_success_output("localhost/test-builder:latest\n"))
+            Ok(create_success_output(&format_images_json(&[
+                MockImageInfo::new("abc123", "localhost/test-builder", "latest"),
+            ])))
         } else {
             Ok(create_success_output(
-                "REPOSITORY\tTAG\tIMAGE ID\tCREATED\tSIZE\n",
+                "[]
+",
             ))
         }
     });
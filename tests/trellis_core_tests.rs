@@ -6,7 +6,7 @@ mod common;
 
 use common::{mocks::*, TestVariation};
 use mockall::predicate;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 use tempfile::TempDir;
 use trellis::{config::TrellisConfig, trellis::Trellis};
 
@@ -16,16 +16,44 @@ fn create_test_config(temp_dir: &TempDir) -> TrellisConfig {
         builder_tag: "test-builder".to_string(),
         podman_build_cache: false,
         auto_clean: false,
+        incremental: false,
+        preprocess: false,
+        layering: Default::default(),
+        rechunk: false,
+        rechunk_image: "quay.io/fedora/fedora-bootc:latest".to_string(),
         pacman_cache: None,
         aur_cache: None,
         stages_dir: temp_dir.path().to_path_buf(),
+        sources: Vec::new(),
+        sources_dir: temp_dir.path().join("sources"),
         rootfs_stages: vec!["base".to_string(), "final".to_string()],
         rootfs_base: "scratch".to_string(),
+        pull_policy: None,
         extra_contexts: vec![],
         extra_mounts: vec![],
+        secrets: Default::default(),
         rootfs_tag: "test-rootfs".to_string(),
+        keep_versions: 5,
         hooks_dir: None,
+        state_dir: temp_dir.path().join("state"),
+        log_dir: temp_dir.path().join("logs"),
+        log_retention: 10,
+        lock_dir: Default::default(),
+        rootless: false,
+        storage_dir: Default::default(),
+        privilege_command: None,
+        engine: Default::default(),
+        podman_socket: None,
         quiet: false,
+        profile: None,
+        config_path: Default::default(),
+        stage_configs: Default::default(),
+        vars: Default::default(),
+        security: Default::default(),
+        registry: None,
+        dry_run: false,
+        wait: false,
+        force: false,
     }
 }
 
@@ -63,20 +91,32 @@ fn test_build_rootfs_container_success_impl(variation: TestVariation) {
 
     let executor = if variation.quiet {
         let mut mock_executor = MockCommandExecutor::new();
+        // Image digest and ID lookups for the lockfile
+        mock_executor
+            .expect_podman_inspect()
+            .returning(|_| Ok(create_success_output("sha256:abc123")));
         mock_executor
             .expect_podman_build()
-            .times(2) // Two stages
+            .times(3) // Two stages plus build metadata
             .returning(|_| Ok(create_success_output("Build completed")));
+        mock_executor
+            .expect_execute()
+            .withf(|command, args| command == "podman" && args[0] == "tag")
+            .times(1) // Versioned rootfs tag
+            .returning(|_, _| Ok(create_success_output("")));
         mock_executor.expect_podman_images().returning(|args| {
             if args.iter().any(|arg| arg.contains("--filter"))
                 && args
                     .iter()
                     .any(|arg| arg.contains("reference=localhost/test-builder"))
             {
-                Ok(create_success_output("localhost/test-builder:latest\n"))
+                Ok(create_success_output(&format_images_json(&[
+                    MockImageInfo::new("abc123", "localhost/test-builder", "latest"),
+                ])))
             } else {
                 Ok(create_success_output(
-                    "REPOSITORY\tTAG\tIMAGE ID\tCREATED\tSIZE\n",
+                    "[]
+",
                 ))
             }
         });
@@ -157,11 +197,20 @@ fn test_update_operation_success_impl(variation: TestVariation) {
 
     let executor = if variation.quiet {
         let mut mock_executor = MockCommandExecutor::new();
+        // Image digest and ID lookups for the lockfile
+        mock_executor
+            .expect_podman_inspect()
+            .returning(|_| Ok(create_success_output("sha256:abc123")));
         // Build operations in quiet mode
         mock_executor
             .expect_podman_build()
-            .times(2) // Two stages
+            .times(3) // Two stages plus build metadata
             .returning(|_| Ok(create_success_output("Build completed")));
+        mock_executor
+            .expect_execute()
+            .withf(|command, args| command == "podman" && args[0] == "tag")
+            .times(1) // Versioned rootfs tag
+            .returning(|_, _| Ok(create_success_output("")));
         // Images listing and builder container check
         mock_executor.expect_podman_images().returning(|args| {
             if args.iter().any(|arg| arg.contains("--filter"))
@@ -169,10 +218,13 @@ fn test_update_operation_success_impl(variation: TestVariation) {
                     .iter()
                     .any(|arg| arg.contains("reference=localhost/test-builder"))
             {
-                Ok(create_success_output("localhost/test-builder:latest\n"))
+                Ok(create_success_output(&format_images_json(&[
+                    MockImageInfo::new("abc123", "localhost/test-builder", "latest"),
+                ])))
             } else {
                 Ok(create_success_output(
-                    "REPOSITORY\tTAG\tIMAGE ID\tCREATED\tSIZE\n",
+                    "[]
+",
                 ))
             }
         });
@@ -217,6 +269,10 @@ fn test_build_rootfs_container_failure_impl(variation: TestVariation) {
 
     let executor = if variation.quiet {
         let mut mock_executor = MockCommandExecutor::new();
+        // Image digest and ID lookups for the lockfile
+        mock_executor
+            .expect_podman_inspect()
+            .returning(|_| Ok(create_success_output("sha256:abc123")));
         mock_executor
             .expect_podman_build()
             .returning(|_| Ok(create_failure_output("Build failed")));
@@ -226,10 +282,13 @@ fn test_build_rootfs_container_failure_impl(variation: TestVariation) {
                     .iter()
                     .any(|arg| arg.contains("reference=localhost/test-builder"))
             {
-                Ok(create_success_output("localhost/test-builder:latest\n"))
+                Ok(create_success_output(&format_images_json(&[
+                    MockImageInfo::new("abc123", "localhost/test-builder", "latest"),
+                ])))
             } else {
                 Ok(create_success_output(
-                    "REPOSITORY\tTAG\tIMAGE ID\tCREATED\tSIZE\n",
+                    "[]
+",
                 ))
             }
         });
@@ -497,6 +556,9 @@ fn test_error_propagation_from_builder() {
     let config = create_test_config(&temp_dir);
 
     let mut mock_executor = MockCommandExecutor::new();
+    mock_executor
+        .expect_podman_inspect()
+        .returning(|_| Ok(create_failure_output("image not known")));
     mock_executor
         .expect_podman_build_streaming()
         .returning(|_| Err(anyhow::anyhow!("Build command failed")));
@@ -523,6 +585,9 @@ fn test_error_propagation_from_cleaner() {
     config.auto_clean = true;
 
     let mut mock_executor = MockCommandExecutor::new();
+    mock_executor
+        .expect_podman_inspect()
+        .returning(|_| Ok(create_failure_output("image not known")));
     mock_executor
         .expect_podman_build_streaming()
         .returning(|_| Ok(create_success_status()));
@@ -598,9 +663,17 @@ fn test_update_failure_in_bootc_phase() {
     let config = create_test_config(&temp_dir);
 
     let mut mock_executor = MockCommandExecutor::new();
+    // Image digest and ID lookups for the lockfile
+    mock_executor
+        .expect_podman_inspect()
+        .returning(|_| Ok(create_success_output("sha256:abc123")));
     mock_executor
         .expect_podman_build_streaming()
         .returning(|_| Ok(create_success_status()));
+    mock_executor
+        .expect_execute()
+        .withf(|command, args| command == "podman" && args[0] == "tag")
+        .returning(|_, _| Ok(create_success_output("")));
     mock_executor.expect_podman_images().returning(|args| {
         if args.iter().any(|arg| arg.contains("--filter"))
             && args
@@ -608,11 +681,14 @@ fn test_update_failure_in_bootc_phase() {
                 .any(|arg| arg.contains("reference=localhost/test-builder"))
         {
             // Return builder container exists for the check
-            Ok(create_success_output("localhost/test-builder:latest\n"))
+            Ok(create_success_output(&format_images_json(&[
+                MockImageInfo::new("abc123", "localhost/test-builder", "latest"),
+            ])))
         } else {
             // Return empty results for general image listing
             Ok(create_success_output(
-                "REPOSITORY\tTAG\tIMAGE ID\tCREATED\tSIZE\n",
+                "[]
+",
             ))
         }
     });
@@ -641,3 +717,361 @@ fn test_update_failure_in_bootc_phase() {
         .to_string()
         .contains("Failed to execute bootc upgrade"));
 }
+
+#[test]
+fn test_build_rootfs_container_writes_lockfile() {
+    use trellis::trellis::lockfile::BuildLock;
+
+    let temp_dir = TempDir::new().unwrap();
+    common::setup_test_containerfiles(&temp_dir, &["base", "final"]);
+
+    let config = create_test_config(&temp_dir);
+    let executor = Arc::new(MockScenarios::all_success());
+    let user_interaction = create_default_user_interaction();
+    let trellis = Trellis::new(&config, executor, user_interaction);
+
+    trellis.build_rootfs_container().unwrap();
+
+    let lock = BuildLock::load(&BuildLock::path(temp_dir.path(), None))
+        .unwrap()
+        .expect("lockfile should be written after a build");
+    assert_eq!(lock.rootfs_base, "scratch");
+    let names: Vec<&str> = lock.stages.iter().map(|s| s.name.as_str()).collect();
+    assert_eq!(names, vec!["base", "final"]);
+    assert_eq!(
+        lock.stages[0].containerfile,
+        std::path::PathBuf::from("Containerfile.base")
+    );
+    assert_eq!(lock.stages[1].target, "final");
+}
+
+#[test]
+fn test_profiles_write_separate_lockfiles() {
+    use trellis::trellis::lockfile::BuildLock;
+
+    let temp_dir = TempDir::new().unwrap();
+    common::setup_test_containerfiles(&temp_dir, &["base", "final"]);
+
+    for (profile, stages) in [("a", vec!["base"]), ("b", vec!["base", "final"])] {
+        let mut config = create_test_config(&temp_dir);
+        config.profile = Some(profile.to_string());
+        config.rootfs_stages = stages.iter().map(|s| s.to_string()).collect();
+        let trellis = Trellis::new(
+            &config,
+            Arc::new(MockScenarios::all_success()),
+            create_default_user_interaction(),
+        );
+        trellis.build_rootfs_container().unwrap();
+    }
+
+    let lock_a = BuildLock::load(&BuildLock::path(temp_dir.path(), Some("a")))
+        .unwrap()
+        .unwrap();
+    let lock_b = BuildLock::load(&BuildLock::path(temp_dir.path(), Some("b")))
+        .unwrap()
+        .unwrap();
+    assert_eq!(lock_a.stages.len(), 1);
+    assert_eq!(lock_b.stages.len(), 2);
+    assert!(!BuildLock::path(temp_dir.path(), None).exists());
+}
+
+#[test]
+fn test_locked_build_fails_when_containerfile_changes() {
+    use trellis::cli::BuildArgs;
+
+    let temp_dir = TempDir::new().unwrap();
+    common::setup_test_containerfiles(&temp_dir, &["base", "final"]);
+
+    let config = create_test_config(&temp_dir);
+    let executor = Arc::new(MockScenarios::all_success());
+    let user_interaction = create_default_user_interaction();
+    let trellis = Trellis::new(&config, executor, user_interaction);
+
+    let locked = BuildArgs {
+        locked: true,
+        ..Default::default()
+    };
+    let result = trellis.build_rootfs_container_with(&locked);
+    assert!(result
+        .unwrap_err()
+        .to_string()
+        .contains("requires an existing lockfile"));
+
+    trellis.build_rootfs_container().unwrap();
+    assert!(trellis.build_rootfs_container_with(&locked).is_ok());
+
+    std::fs::write(
+        temp_dir.path().join("Containerfile.final"),
+        "FROM alpine\nRUN echo changed\n",
+    )
+    .unwrap();
+    let error = trellis
+        .build_rootfs_container_with(&locked)
+        .unwrap_err()
+        .to_string();
+    assert!(error.contains("final: Containerfile.final content changed"));
+}
+
+/// Returns a mock whose base image is only inspectable once a build pulled it,
+/// with `digest`, or never if `digest` is `None`.
+fn create_base_pull_mock(
+    digest: Option<&str>,
+    builds: Arc<Mutex<Vec<Vec<String>>>>,
+) -> MockCommandExecutor {
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    let pulled = Arc::new(AtomicBool::new(false));
+    let built = Arc::clone(&pulled);
+    let digest = digest.map(str::to_string);
+    let mut mock = MockCommandExecutor::new();
+    mock.expect_podman_build_streaming().returning(move |args| {
+        built.store(true, Ordering::SeqCst);
+        builds.lock().unwrap().push(args.to_vec());
+        Ok(create_success_status())
+    });
+    mock.expect_podman_images().returning(|_| {
+        Ok(create_success_output(&format_images_json(&[
+            MockImageInfo::new("abc123", "localhost/test-builder", "latest"),
+        ])))
+    });
+    mock.expect_podman_inspect().returning(move |args| {
+        if !args.last().is_some_and(|arg| arg.starts_with("quay.io/")) {
+            return Ok(create_success_output(r#"[{"Id": "0123456789ab"}]"#));
+        }
+        match &digest {
+            Some(digest) if pulled.load(Ordering::SeqCst) => Ok(create_success_output(&format!(
+                r#"[{{"Id": "0123456789ab", "Digest": "{digest}"}}]"#
+            ))),
+            _ => Ok(create_failure_output("image not known")),
+        }
+    });
+    mock.expect_execute()
+        .returning(|_, _| Ok(create_success_output("")));
+    mock
+}
+
+#[test]
+fn test_lockfile_records_base_digest_pulled_by_build() {
+    use trellis::trellis::lockfile::BuildLock;
+
+    let temp_dir = TempDir::new().unwrap();
+    common::setup_test_containerfiles(&temp_dir, &["base", "final"]);
+    let mut config = create_test_config(&temp_dir);
+    config.rootfs_base = "quay.io/fedora/fedora:41".to_string();
+    let digest = format!("sha256:{}", "b".repeat(64));
+
+    let builds = Arc::new(Mutex::new(Vec::new()));
+    let executor = Arc::new(create_base_pull_mock(Some(&digest), Arc::clone(&builds)));
+    let trellis = Trellis::new(&config, executor, create_default_user_interaction());
+    trellis.build_rootfs_container().unwrap();
+
+    let lock = BuildLock::load(&BuildLock::path(temp_dir.path(), None))
+        .unwrap()
+        .unwrap();
+    assert_eq!(lock.base_digest, digest);
+
+    // The embedded metadata carries the digest resolved after the build
+    let builds = builds.lock().unwrap();
+    let metadata_build = builds.last().unwrap();
+    assert!(metadata_build.contains(&format!("org.opencontainers.image.base.digest={digest}")));
+}
+
+#[test]
+fn test_build_fails_when_base_digest_cannot_be_resolved() {
+    use trellis::trellis::lockfile::BuildLock;
+
+    let temp_dir = TempDir::new().unwrap();
+    common::setup_test_containerfiles(&temp_dir, &["base", "final"]);
+    let mut config = create_test_config(&temp_dir);
+    config.rootfs_base = "quay.io/fedora/fedora:41".to_string();
+
+    let executor = Arc::new(create_base_pull_mock(None, Arc::default()));
+    let trellis = Trellis::new(&config, executor, create_default_user_interaction());
+
+    let error = trellis.build_rootfs_container().unwrap_err().to_string();
+    assert!(error.contains("not in local storage"), "{error}");
+    assert!(BuildLock::load(&BuildLock::path(temp_dir.path(), None))
+        .unwrap()
+        .is_none());
+}
+
+#[test]
+fn test_dry_run_build_records_commands_without_side_effects() {
+    use trellis::trellis::{executor::DryRunCommandExecutor, lockfile::BuildLock};
+
+    let temp_dir = TempDir::new().unwrap();
+    common::setup_test_containerfiles(&temp_dir, &["base", "final"]);
+
+    let mut config = create_test_config(&temp_dir);
+    config.dry_run = true;
+    config.incremental = true;
+    let executor = Arc::new(DryRunCommandExecutor::new());
+    let user_interaction = create_default_user_interaction();
+    let trellis = Trellis::new(&config, executor.clone(), user_interaction);
+
+    trellis.build_rootfs_container().unwrap();
+
+    let builds: Vec<String> = executor
+        .commands()
+        .into_iter()
+        .filter(|c| c.starts_with("podman build"))
+        .collect();
+    // Metadata is not embedded, since that writes a build context
+    assert_eq!(builds.len(), 2);
+    let base_file = temp_dir.path().join("Containerfile.base");
+    assert!(builds[0].contains(&format!("-f {}", base_file.display())));
+    assert!(builds[0].contains("--build-arg BASE_IMAGE=scratch"));
+    assert!(builds[1].contains("BASE_IMAGE=localhost/trellis-stage-base"));
+    assert!(builds[1].contains("-t test-rootfs"));
+
+    assert!(BuildLock::load(&BuildLock::path(temp_dir.path(), None))
+        .unwrap()
+        .is_none());
+    assert!(!config.state_dir.exists());
+}
+
+#[test]
+fn test_dry_run_does_not_write_rendered_containerfiles() {
+    use trellis::trellis::executor::DryRunCommandExecutor;
+
+    let temp_dir = TempDir::new().unwrap();
+    common::setup_test_containerfiles(&temp_dir, &["base", "final"]);
+
+    let mut config = create_test_config(&temp_dir);
+    config.dry_run = true;
+    config.preprocess = true;
+    let executor = Arc::new(DryRunCommandExecutor::new());
+    let trellis = Trellis::new(&config, executor.clone(), create_default_user_interaction());
+
+    trellis.build_rootfs_container().unwrap();
+
+    let builds: Vec<String> = executor
+        .commands()
+        .into_iter()
+        .filter(|c| c.starts_with("podman build"))
+        .collect();
+    let base_file = temp_dir.path().join("Containerfile.base");
+    assert!(builds[0].contains(&format!("-f {}", base_file.display())));
+    assert!(builds.iter().all(|build| !build.contains("trellis-render")));
+}
+
+#[test]
+fn test_dry_run_image_generation_creates_no_mount_point() {
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use trellis::trellis::image_generator::ImageGenerator;
+
+    let temp_dir = TempDir::new().unwrap();
+    let mut config = create_test_config(&temp_dir);
+    config.dry_run = true;
+
+    // The mount point is removed afterwards, so check it when mounting
+    let mount_point_created = Arc::new(AtomicBool::new(false));
+    let created = Arc::clone(&mount_point_created);
+    let mut mock = MockCommandExecutor::new();
+    mock.expect_podman_images()
+        .returning(|_| Ok(create_success_output("localhost/test-rootfs:latest\n")));
+    mock.expect_podman_run_streaming()
+        .returning(|_| Ok(create_success_status()));
+    mock.expect_podman_run()
+        .returning(|_| Ok(create_success_output("")));
+    mock.expect_execute().returning(move |command, args| {
+        if command == "mount" {
+            let mount_point = std::path::Path::new(args.last().unwrap());
+            created.store(mount_point.exists(), Ordering::SeqCst);
+        }
+        Ok(create_success_output("/dev/loop0"))
+    });
+    let generator = ImageGenerator::new(&config, Arc::new(mock));
+
+    let output_path = temp_dir.path().join("bootable.img");
+    generator
+        .generate_bootable_image("test-rootfs", &output_path, "ext4", Some(4), None)
+        .unwrap();
+
+    assert!(!mount_point_created.load(Ordering::SeqCst));
+    assert!(!output_path.exists());
+}
+
+#[test]
+fn test_dry_run_install_skips_filesystem_tool_check() {
+    use trellis::trellis::{executor::DryRunCommandExecutor, image_generator::ImageGenerator};
+
+    let temp_dir = TempDir::new().unwrap();
+    let mut config = create_test_config(&temp_dir);
+    config.dry_run = true;
+    let executor = Arc::new(DryRunCommandExecutor::new());
+    let generator = ImageGenerator::new(&config, executor.clone());
+
+    generator
+        .install_bootable_system("test-rootfs", &temp_dir.path().join("bootable.img"), "ext4")
+        .unwrap();
+
+    let commands = executor.commands();
+    assert!(commands.iter().any(|c| c.contains("which mkfs.fat")));
+    assert!(commands.iter().any(|c| c.contains("bootc install")));
+}
+
+#[test]
+fn test_builder_build_records_history_entry() {
+    use trellis::trellis::history::{History, Operation};
+
+    let temp_dir = TempDir::new().unwrap();
+    common::setup_test_containerfiles(&temp_dir, &["base"]);
+
+    let config = create_test_config(&temp_dir);
+    let executor = Arc::new(MockScenarios::all_success());
+    let trellis = Trellis::new(&config, executor, create_default_user_interaction());
+
+    trellis.build_builder_container().unwrap();
+
+    let entries = History::new(&config.state_dir).entries().unwrap();
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0].operation, Operation::BuildBuilder);
+    assert_eq!(entries[0].stages, vec!["base"]);
+    assert_eq!(entries[0].stage_timings.len(), 1);
+    assert!(entries[0].log_id.is_some());
+    assert!(entries[0].success);
+}
+
+#[test]
+fn test_build_records_history_entry() {
+    use trellis::trellis::history::{History, Operation};
+
+    let temp_dir = TempDir::new().unwrap();
+    common::setup_test_containerfiles(&temp_dir, &["base", "final"]);
+
+    let config = create_test_config(&temp_dir);
+    let executor = Arc::new(MockScenarios::all_success());
+    let user_interaction = create_default_user_interaction();
+    let trellis = Trellis::new(&config, executor, user_interaction);
+
+    trellis.build_rootfs_container().unwrap();
+    std::fs::remove_file(temp_dir.path().join("Containerfile.final")).unwrap();
+    assert!(trellis.build_rootfs_container().is_err());
+
+    let entries = History::new(&config.state_dir).entries().unwrap();
+    assert_eq!(entries.len(), 2);
+
+    // Both entries may share a start second, so don't rely on their order
+    let success = entries.iter().find(|e| e.success).unwrap();
+    assert_eq!(success.operation, Operation::Build);
+    assert!(success.success);
+    assert_eq!(success.stages, vec!["base", "final"]);
+    let timed: Vec<&str> = success
+        .stage_timings
+        .iter()
+        .map(|t| t.name.as_str())
+        .collect();
+    assert_eq!(timed, vec!["base", "final"]);
+    assert_eq!(success.log_id.as_deref(), Some(success.id.as_str()));
+    assert!(success.image_id.is_some());
+    assert_eq!(success.config["rootfs_tag"], "test-rootfs");
+
+    let failure = entries.iter().find(|e| !e.success).unwrap();
+    assert!(failure.image_id.is_none());
+    assert!(failure
+        .error
+        .as_deref()
+        .unwrap()
+        .contains("Missing required containerfiles"));
+}
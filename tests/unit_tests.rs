@@ -5,7 +5,7 @@ use tempfile::TempDir;
 
 use common::mocks::create_default_user_interaction;
 use trellis::{
-    cli::{Cli, Commands},
+    cli::{BuildArgs, Cli, Commands},
     config::{Config, TrellisConfig},
     trellis::{
         builder::{BuildType, ContainerBuilder},
@@ -17,15 +17,19 @@ use trellis::{
 
 fn create_test_cli() -> Cli {
     Cli {
-        command: Commands::Build,
+        command: Commands::Build(BuildArgs::default()),
         builder_tag: "test-builder".to_string(),
         podman_build_cache: None,
         auto_clean: false,
+        incremental: None,
+        layering: None,
+        rechunk: None,
         pacman_cache: None,
         aur_cache: None,
         stages_dir: None,
         extra_contexts: vec![],
         extra_mounts: vec![],
+        secrets: vec![],
         rootfs_stages: vec![],
         rootfs_base: "scratch".to_string(),
         rootfs_tag: "test-rootfs".to_string(),
@@ -33,6 +37,11 @@ fn create_test_cli() -> Cli {
         quiet: false,
         config_path: None,
         skip_root_check: false,
+        profile: None,
+        dry_run: false,
+        wait: false,
+        no_wait: false,
+        force: false,
     }
 }
 
@@ -42,16 +51,44 @@ fn create_test_config(temp_dir: &TempDir) -> TrellisConfig {
         builder_tag: "test-builder".to_string(),
         podman_build_cache: false,
         auto_clean: false,
+        incremental: false,
+        preprocess: false,
+        layering: Default::default(),
+        rechunk: false,
+        rechunk_image: "quay.io/fedora/fedora-bootc:latest".to_string(),
         pacman_cache: None,
         aur_cache: None,
         stages_dir: temp_dir.path().to_path_buf(),
+        sources: Vec::new(),
+        sources_dir: temp_dir.path().join("sources"),
         rootfs_stages: vec!["base".to_string(), "final".to_string()],
         rootfs_base: "scratch".to_string(),
+        pull_policy: None,
         extra_contexts: vec![],
         extra_mounts: vec![],
+        secrets: Default::default(),
         rootfs_tag: "test-rootfs".to_string(),
+        keep_versions: 5,
         hooks_dir: None,
+        state_dir: temp_dir.path().join("state"),
+        log_dir: temp_dir.path().join("logs"),
+        log_retention: 10,
+        lock_dir: Default::default(),
+        rootless: false,
+        storage_dir: Default::default(),
+        privilege_command: None,
+        engine: Default::default(),
+        podman_socket: None,
         quiet: false,
+        profile: None,
+        config_path: Default::default(),
+        stage_configs: Default::default(),
+        vars: Default::default(),
+        security: Default::default(),
+        registry: None,
+        dry_run: false,
+        wait: false,
+        force: false,
     }
 }
 
@@ -231,6 +268,172 @@ builder_tag = "test"
     assert_eq!(trellis_config.rootfs_base, "scratch");
 }
 
+fn create_profile_config(temp_dir: &TempDir) -> std::path::PathBuf {
+    let config_path = temp_dir.path().join("trellis.toml");
+    let config_content = r#"
+[build]
+rootfs_stages = ["base", "desktop"]
+rootfs_base = "archlinux:latest"
+rootfs_tag = "file-rootfs"
+
+[profiles.server]
+rootfs_stages = ["base", "server"]
+rootfs_tag = "server-rootfs"
+extra_mounts = ["/srv/data"]
+"#;
+    fs::write(&config_path, config_content).unwrap();
+    config_path
+}
+
+#[test]
+fn test_profile_overrides_build_section() {
+    let temp_dir = TempDir::new().unwrap();
+    let mut cli = create_test_cli();
+    cli.config_path = Some(create_profile_config(&temp_dir));
+    cli.stages_dir = Some(temp_dir.path().to_path_buf());
+    cli.rootfs_tag = trellis::trellis::constants::containers::DEFAULT_ROOTFS_TAG.to_string();
+    cli.profile = Some("server".to_string());
+
+    let config = TrellisConfig::new(cli).unwrap();
+
+    assert_eq!(config.rootfs_stages, vec!["base", "server"]);
+    assert_eq!(config.rootfs_tag, "server-rootfs");
+    assert_eq!(
+        config.extra_mounts,
+        vec![std::path::PathBuf::from("/srv/data")]
+    );
+    // Values the profile does not set fall back to [build]
+    assert_eq!(config.rootfs_base, "archlinux:latest");
+    assert_eq!(config.profile.as_deref(), Some("server"));
+}
+
+#[test]
+fn test_cli_overrides_profile() {
+    let temp_dir = TempDir::new().unwrap();
+    let mut cli = create_test_cli();
+    cli.config_path = Some(create_profile_config(&temp_dir));
+    cli.stages_dir = Some(temp_dir.path().to_path_buf());
+    cli.rootfs_stages = vec!["base".to_string(), "recovery".to_string()];
+    cli.profile = Some("server".to_string());
+
+    let config = TrellisConfig::new(cli).unwrap();
+
+    assert_eq!(config.rootfs_stages, vec!["base", "recovery"]);
+    assert_eq!(config.rootfs_tag, "test-rootfs");
+}
+
+#[test]
+fn test_unknown_profile_fails() {
+    let temp_dir = TempDir::new().unwrap();
+    let mut cli = create_test_cli();
+    cli.config_path = Some(create_profile_config(&temp_dir));
+    cli.stages_dir = Some(temp_dir.path().to_path_buf());
+    cli.profile = Some("desktop".to_string());
+
+    let error = TrellisConfig::new(cli).unwrap_err().to_string();
+    assert!(error.contains("Profile 'desktop' not found"));
+    assert!(error.contains("server"));
+}
+
+#[test]
+fn test_secrets_merge_config_and_cli() {
+    let temp_dir = TempDir::new().unwrap();
+    let config_path = temp_dir.path().join("trellis.toml");
+    fs::write(
+        &config_path,
+        r#"
+[build.secrets]
+repo_token = "/etc/trellis/secrets/repo-token"
+signing_key = "/etc/trellis/secrets/signing.key"
+"#,
+    )
+    .unwrap();
+
+    let mut cli = create_test_cli();
+    cli.config_path = Some(config_path);
+    cli.stages_dir = Some(temp_dir.path().to_path_buf());
+    cli.secrets = vec!["id=repo_token,src=/run/secrets/token".to_string()];
+    let config = TrellisConfig::new(cli).unwrap();
+
+    assert_eq!(
+        config.secrets.get("repo_token"),
+        Some(&std::path::PathBuf::from("/run/secrets/token"))
+    );
+    assert_eq!(
+        config.secrets.get("signing_key"),
+        Some(&std::path::PathBuf::from(
+            "/etc/trellis/secrets/signing.key"
+        ))
+    );
+
+    let mut cli = create_test_cli();
+    cli.stages_dir = Some(temp_dir.path().to_path_buf());
+    cli.secrets = vec!["repo_token=/run/secrets/token".to_string()];
+    let error = TrellisConfig::new(cli).unwrap_err().to_string();
+    assert!(error.contains("expected id=ID,src=PATH"));
+}
+
+#[test]
+fn test_stage_config_parsing() {
+    use trellis::config::NetworkMode;
+
+    let temp_dir = TempDir::new().unwrap();
+    let config_path = temp_dir.path().join("trellis.toml");
+    fs::write(
+        &config_path,
+        r#"
+[stages.base]
+network = "none"
+no_cache = true
+
+[stages."features:gpu"]
+target = "gpu-nvidia"
+build_args = { DRIVER = "nvidia" }
+mounts = ["/opt/firmware"]
+"#,
+    )
+    .unwrap();
+
+    let mut cli = create_test_cli();
+    cli.config_path = Some(config_path);
+    cli.stages_dir = Some(temp_dir.path().to_path_buf());
+    let config = TrellisConfig::new(cli).unwrap();
+
+    let base = config.stage_config("base").unwrap();
+    assert_eq!(base.network, Some(NetworkMode::None));
+    assert_eq!(base.no_cache, Some(true));
+
+    let gpu = config.stage_config("features:gpu").unwrap();
+    assert_eq!(gpu.target.as_deref(), Some("gpu-nvidia"));
+    assert_eq!(
+        gpu.build_args
+            .as_ref()
+            .unwrap()
+            .get("DRIVER")
+            .map(String::as_str),
+        Some("nvidia")
+    );
+    assert!(config.stage_config("final").is_none());
+}
+
+#[test]
+fn test_stage_config_rejects_base_image_override() {
+    let temp_dir = TempDir::new().unwrap();
+    let config_path = temp_dir.path().join("trellis.toml");
+    fs::write(
+        &config_path,
+        "[stages.base]\nbuild_args = { BASE_IMAGE = \"alpine\" }\n",
+    )
+    .unwrap();
+
+    let mut cli = create_test_cli();
+    cli.config_path = Some(config_path);
+    cli.stages_dir = Some(temp_dir.path().to_path_buf());
+
+    let error = TrellisConfig::new(cli).unwrap_err().to_string();
+    assert!(error.contains("[stages.base] cannot set BASE_IMAGE"));
+}
+
 // Discovery tests
 #[test]
 fn test_find_containerfile_in_subdir() {
@@ -319,15 +522,19 @@ fn test_find_containerfile_recursive_search() {
 fn test_trellis_app_creation() {
     let temp_dir = TempDir::new().unwrap();
     let cli = Cli {
-        command: Commands::Build,
+        command: Commands::Build(BuildArgs::default()),
         builder_tag: "test-builder".to_string(),
         podman_build_cache: None,
         auto_clean: false,
+        incremental: None,
+        layering: None,
+        rechunk: None,
         pacman_cache: None,
         aur_cache: None,
         stages_dir: Some(temp_dir.path().to_path_buf()),
         extra_contexts: vec![],
         extra_mounts: vec![],
+        secrets: vec![],
         rootfs_stages: vec!["base".to_string()],
         rootfs_base: "scratch".to_string(),
         rootfs_tag: "test-rootfs".to_string(),
@@ -335,6 +542,11 @@ fn test_trellis_app_creation() {
         quiet: false,
         config_path: None,
         skip_root_check: false,
+        profile: None,
+        dry_run: false,
+        wait: false,
+        no_wait: false,
+        force: false,
     };
 
     let app = TrellisApp::new(cli);
@@ -347,15 +559,19 @@ fn test_auto_clean_config() {
 
     // Test CLI override of auto-clean
     let cli = Cli {
-        command: Commands::Build,
+        command: Commands::Build(BuildArgs::default()),
         builder_tag: "test-builder".to_string(),
         podman_build_cache: None,
         auto_clean: true,
+        incremental: None,
+        layering: None,
+        rechunk: None,
         pacman_cache: None,
         aur_cache: None,
         stages_dir: Some(temp_dir.path().to_path_buf()),
         extra_contexts: vec![],
         extra_mounts: vec![],
+        secrets: vec![],
         rootfs_stages: vec!["base".to_string()],
         rootfs_base: "scratch".to_string(),
         rootfs_tag: "test-rootfs".to_string(),
@@ -363,6 +579,11 @@ fn test_auto_clean_config() {
         quiet: false,
         config_path: None,
         skip_root_check: false,
+        profile: None,
+        dry_run: false,
+        wait: false,
+        no_wait: false,
+        force: false,
     };
 
     let config = TrellisConfig::new(cli).unwrap();
@@ -413,16 +634,44 @@ fn test_rootfs_base_functionality_first_stage() {
         builder_tag: "test-builder".to_string(),
         podman_build_cache: false,
         auto_clean: false,
+        incremental: false,
+        preprocess: false,
+        layering: Default::default(),
+        rechunk: false,
+        rechunk_image: "quay.io/fedora/fedora-bootc:latest".to_string(),
         pacman_cache: None,
         aur_cache: None,
         stages_dir: temp_dir.path().to_path_buf(),
+        sources: Vec::new(),
+        sources_dir: temp_dir.path().join("sources"),
         rootfs_stages: vec!["base".to_string()],
         rootfs_base: "ubuntu:22.04".to_string(),
+        pull_policy: None,
         extra_contexts: vec![],
         extra_mounts: vec![],
+        secrets: Default::default(),
         rootfs_tag: "test-rootfs".to_string(),
+        keep_versions: 5,
         hooks_dir: None,
+        state_dir: temp_dir.path().join("state"),
+        log_dir: temp_dir.path().join("logs"),
+        log_retention: 10,
+        lock_dir: Default::default(),
+        rootless: false,
+        storage_dir: Default::default(),
+        privilege_command: None,
+        engine: Default::default(),
+        podman_socket: None,
         quiet: false,
+        profile: None,
+        config_path: Default::default(),
+        stage_configs: Default::default(),
+        vars: Default::default(),
+        security: Default::default(),
+        registry: None,
+        dry_run: false,
+        wait: false,
+        force: false,
     };
 
     let executor = std::sync::Arc::new(RealCommandExecutor::new());
@@ -445,16 +694,44 @@ fn test_rootfs_base_functionality_subsequent_stages() {
         builder_tag: "test-builder".to_string(),
         podman_build_cache: false,
         auto_clean: false,
+        incremental: false,
+        preprocess: false,
+        layering: Default::default(),
+        rechunk: false,
+        rechunk_image: "quay.io/fedora/fedora-bootc:latest".to_string(),
         pacman_cache: None,
         aur_cache: None,
         stages_dir: temp_dir.path().to_path_buf(),
+        sources: Vec::new(),
+        sources_dir: temp_dir.path().join("sources"),
         rootfs_stages: vec!["base".to_string(), "tools".to_string()],
         rootfs_base: "alpine:latest".to_string(),
+        pull_policy: None,
         extra_contexts: vec![],
         extra_mounts: vec![],
+        secrets: Default::default(),
         rootfs_tag: "test-rootfs".to_string(),
+        keep_versions: 5,
         hooks_dir: None,
+        state_dir: temp_dir.path().join("state"),
+        log_dir: temp_dir.path().join("logs"),
+        log_retention: 10,
+        lock_dir: Default::default(),
+        rootless: false,
+        storage_dir: Default::default(),
+        privilege_command: None,
+        engine: Default::default(),
+        podman_socket: None,
         quiet: false,
+        profile: None,
+        config_path: Default::default(),
+        stage_configs: Default::default(),
+        vars: Default::default(),
+        security: Default::default(),
+        registry: None,
+        dry_run: false,
+        wait: false,
+        force: false,
     };
 
     let executor = std::sync::Arc::new(RealCommandExecutor::new());
@@ -476,16 +753,44 @@ fn test_rootfs_base_with_default_scratch() {
         builder_tag: "test-builder".to_string(),
         podman_build_cache: false,
         auto_clean: false,
+        incremental: false,
+        preprocess: false,
+        layering: Default::default(),
+        rechunk: false,
+        rechunk_image: "quay.io/fedora/fedora-bootc:latest".to_string(),
         pacman_cache: None,
         aur_cache: None,
         stages_dir: temp_dir.path().to_path_buf(),
+        sources: Vec::new(),
+        sources_dir: temp_dir.path().join("sources"),
         rootfs_stages: vec!["base".to_string()],
         rootfs_base: "scratch".to_string(), // Default value
+        pull_policy: None,
         extra_contexts: vec![],
         extra_mounts: vec![],
+        secrets: Default::default(),
         rootfs_tag: "test-rootfs".to_string(),
+        keep_versions: 5,
         hooks_dir: None,
+        state_dir: temp_dir.path().join("state"),
+        log_dir: temp_dir.path().join("logs"),
+        log_retention: 10,
+        lock_dir: Default::default(),
+        rootless: false,
+        storage_dir: Default::default(),
+        privilege_command: None,
+        engine: Default::default(),
+        podman_socket: None,
         quiet: false,
+        profile: None,
+        config_path: Default::default(),
+        stage_configs: Default::default(),
+        vars: Default::default(),
+        security: Default::default(),
+        registry: None,
+        dry_run: false,
+        wait: false,
+        force: false,
     };
 
     let executor = std::sync::Arc::new(RealCommandExecutor::new());
@@ -514,16 +819,44 @@ fn test_rootfs_base_with_custom_images() {
             builder_tag: "test-builder".to_string(),
             podman_build_cache: false,
             auto_clean: false,
+            incremental: false,
+            preprocess: false,
+            layering: Default::default(),
+            rechunk: false,
+            rechunk_image: "quay.io/fedora/fedora-bootc:latest".to_string(),
             pacman_cache: None,
             aur_cache: None,
             stages_dir: temp_dir.path().to_path_buf(),
+            sources: Vec::new(),
+            sources_dir: temp_dir.path().join("sources"),
             rootfs_stages: vec!["base".to_string()],
             rootfs_base: base_image_value.to_string(),
+            pull_policy: None,
             extra_contexts: vec![],
             extra_mounts: vec![],
+            secrets: Default::default(),
             rootfs_tag: "test-rootfs".to_string(),
+            keep_versions: 5,
             hooks_dir: None,
+            state_dir: temp_dir.path().join("state"),
+            log_dir: temp_dir.path().join("logs"),
+            log_retention: 10,
+            lock_dir: Default::default(),
+            rootless: false,
+            storage_dir: Default::default(),
+            privilege_command: None,
+            engine: Default::default(),
+            podman_socket: None,
             quiet: false,
+            profile: None,
+            config_path: Default::default(),
+            stage_configs: Default::default(),
+            vars: Default::default(),
+            security: Default::default(),
+            registry: None,
+            dry_run: false,
+            wait: false,
+            force: false,
         };
 
         let executor = std::sync::Arc::new(RealCommandExecutor::new());
@@ -535,3 +868,120 @@ fn test_rootfs_base_with_custom_images() {
         );
     }
 }
+
+#[test]
+fn test_utc_time_from_unix() {
+    use trellis::trellis::common::UtcTime;
+
+    assert_eq!(UtcTime::from_unix(0).to_string(), "1970-01-01 00:00:00 UTC");
+    // Leap day in a year divisible by 400
+    assert_eq!(
+        UtcTime::from_unix(951_827_696).to_string(),
+        "2000-02-29 12:34:56 UTC"
+    );
+}
+
+#[test]
+fn test_preprocess_includes_vars_and_conditionals() {
+    use trellis::trellis::preprocess::Preprocessor;
+
+    let temp_dir = TempDir::new().unwrap();
+    fs::create_dir_all(temp_dir.path().join("fragments")).unwrap();
+    fs::write(
+        temp_dir.path().join("fragments/setup"),
+        "RUN echo {{ mirror }}\n",
+    )
+    .unwrap();
+    let containerfile = temp_dir.path().join("Containerfile.base");
+    fs::write(
+        &containerfile,
+        "FROM {{ rootfs_base }}\n\
+         # trellis:include fragments/setup\n\
+         # trellis:if profile == \"desktop\"\n\
+         RUN install-desktop\n\
+         # trellis:else\n\
+         RUN install-server {{ stage }}\n\
+         # trellis:endif\n\
+         RUN podman inspect --format '{{.Id}}'\n",
+    )
+    .unwrap();
+
+    let mut config = create_test_config(&temp_dir);
+    config
+        .vars
+        .insert("mirror".to_string(), "https://mirror.example".to_string());
+    let rendered = Preprocessor::new(&config, "base")
+        .render_file(&containerfile)
+        .unwrap();
+    assert_eq!(
+        rendered,
+        "FROM scratch\n\
+         RUN echo https://mirror.example\n\
+         RUN install-server base\n\
+         RUN podman inspect --format '{{.Id}}'\n"
+    );
+
+    config.profile = Some("desktop".to_string());
+    let rendered = Preprocessor::new(&config, "base")
+        .render_file(&containerfile)
+        .unwrap();
+    assert!(rendered.contains("RUN install-desktop\n"));
+    assert!(!rendered.contains("install-server"));
+}
+
+#[test]
+fn test_preprocess_includes_relative_to_source_checkout() {
+    use trellis::{config::StageSource, trellis::preprocess::Preprocessor};
+
+    let temp_dir = TempDir::new().unwrap();
+    let mut config = create_test_config(&temp_dir);
+    config.sources_dir = temp_dir.path().join("sources");
+    config.sources = vec![StageSource {
+        name: "shared".to_string(),
+        git: "https://example.com/shared.git".to_string(),
+        reference: "main".to_string(),
+        subdir: Some("stages".into()),
+    }];
+
+    let checkout = config.sources_dir.join("shared/stages");
+    fs::create_dir_all(checkout.join("fragments")).unwrap();
+    fs::write(checkout.join("fragments/setup"), "RUN echo shared\n").unwrap();
+    fs::create_dir_all(temp_dir.path().join("fragments")).unwrap();
+    fs::write(temp_dir.path().join("fragments/setup"), "RUN echo local\n").unwrap();
+
+    let containerfile = checkout.join("Containerfile.base");
+    fs::write(
+        &containerfile,
+        "FROM scratch\n# trellis:include fragments/setup\n",
+    )
+    .unwrap();
+
+    let rendered = Preprocessor::new(&config, "base")
+        .render_file(&containerfile)
+        .unwrap();
+    assert_eq!(rendered, "FROM scratch\nRUN echo shared\n");
+}
+
+#[test]
+fn test_preprocess_reports_errors_with_location() {
+    use trellis::trellis::preprocess::Preprocessor;
+
+    let temp_dir = TempDir::new().unwrap();
+    let config = create_test_config(&temp_dir);
+    let preprocessor = Preprocessor::new(&config, "base");
+
+    let unknown = temp_dir.path().join("Containerfile.unknown");
+    fs::write(&unknown, "FROM scratch\nRUN echo {{ missing }}\n").unwrap();
+    let error = format!("{:#}", preprocessor.render_file(&unknown).unwrap_err());
+    assert!(error.contains("Containerfile.unknown:2"), "{error}");
+    assert!(error.contains("Unknown variable 'missing'"), "{error}");
+
+    let unbalanced = temp_dir.path().join("Containerfile.unbalanced");
+    fs::write(&unbalanced, "# trellis:if stage\nFROM scratch\n").unwrap();
+    assert!(preprocessor.render_file(&unbalanced).is_err());
+
+    let cycle = temp_dir.path().join("Containerfile.cycle");
+    fs::write(&cycle, "# trellis:include Containerfile.cycle\n").unwrap();
+    let error = format!("{:#}", preprocessor.render_file(&cycle).unwrap_err());
+    assert!(error.contains("Include cycle"), "{error}");
+}